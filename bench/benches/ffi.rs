@@ -0,0 +1,90 @@
+//! Benchmarks of the conversions between collectd's structures and Rust's. Plugin authors can
+//! benchmark their own callbacks the same way with the fixtures from `collectd_plugin::testing`.
+
+#[macro_use]
+extern crate criterion;
+extern crate collectd_plugin;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+
+use collectd_plugin::de::from_collectd;
+use collectd_plugin::testing::{self, ConfigFixture, ValueListFixture};
+use collectd_plugin::{ConfigItem, OwnedConfigItem, OwnedConfigValue, RecvValueList, Value,
+                      ValueListBuilder};
+use criterion::Criterion;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+#[allow(dead_code)]
+struct Config {
+    host: String,
+    port: u16,
+    report_relative: Option<bool>,
+    tags: Vec<String>,
+}
+
+fn option(key: &str, values: Vec<OwnedConfigValue>) -> OwnedConfigItem {
+    OwnedConfigItem {
+        key: String::from(key),
+        values: values,
+        children: vec![],
+    }
+}
+
+fn config() -> OwnedConfigItem {
+    let s = |x: &str| OwnedConfigValue::String(String::from(x));
+    OwnedConfigItem {
+        key: String::from("Plugin"),
+        values: vec![s("myplugin")],
+        children: vec![
+            option("Host", vec![s("localhost")]),
+            option("Port", vec![OwnedConfigValue::Number(2003.0)]),
+            option("ReportRelative", vec![OwnedConfigValue::Boolean(true)]),
+            option("Tags", vec![s("a"), s("b"), s("c")]),
+        ],
+    }
+}
+
+fn submit(c: &mut Criterion) {
+    let values = [Value::Gauge(0.5), Value::Gauge(0.75), Value::Gauge(1.0)];
+    c.bench_function("submit", move |b| {
+        b.iter(|| {
+            ValueListBuilder::new("myplugin", "load")
+                .values(&values)
+                .plugin_instance("cpu0")
+                .submit()
+                .unwrap();
+
+            // The stub records every dispatched value, which shouldn't accumulate between
+            // iterations
+            testing::clear();
+        })
+    });
+}
+
+fn receive(c: &mut Criterion) {
+    let values = [Value::Gauge(0.5), Value::Derive(10), Value::Counter(20)];
+    let fixture = ValueListFixture::new("myplugin", "mytype", &values).type_instance("idle");
+    c.bench_function("receive", move |b| {
+        b.iter(|| RecvValueList::from(fixture.data_set(), fixture.value_list()).unwrap())
+    });
+}
+
+fn config_item(c: &mut Criterion) {
+    let fixture = ConfigFixture::new(&config());
+    c.bench_function("config_item", move |b| {
+        b.iter(|| unsafe { ConfigItem::from(fixture.item()) }.unwrap())
+    });
+}
+
+fn deserialize(c: &mut Criterion) {
+    let config = config();
+    c.bench_function("deserialize", move |b| {
+        let item = config.as_config_item();
+        b.iter(|| from_collectd::<Config>(&item.children).unwrap())
+    });
+}
+
+criterion_group!(benches, submit, receive, config_item, deserialize);
+criterion_main!(benches);
@@ -0,0 +1 @@
+//! Criterion benchmarks for the FFI glue, see `benches/`
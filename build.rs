@@ -1,19 +1,248 @@
 use std::env;
 use std::path::PathBuf;
 
+/// The collectd releases that bindings are shipped for. Struct layouts (eg: `value_list_t`) and
+/// registration signatures differ between them, so exactly one must be chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CollectdVersion {
+    V54,
+    V55,
+    V57,
+}
+
+impl CollectdVersion {
+    fn cfg(&self) -> &'static str {
+        match *self {
+            CollectdVersion::V54 => "collectd_54",
+            CollectdVersion::V55 => "collectd_55",
+            CollectdVersion::V57 => "collectd_57",
+        }
+    }
+
+    #[cfg_attr(not(feature = "bindgen"), allow(dead_code))]
+    fn define(&self) -> &'static str {
+        match *self {
+            CollectdVersion::V54 => "-DCOLLECTD_54",
+            CollectdVersion::V55 => "-DCOLLECTD_55",
+            CollectdVersion::V57 => "-DCOLLECTD_57",
+        }
+    }
+
+    /// Location of the plugin header relative to an include directory
+    #[cfg_attr(not(feature = "bindgen"), allow(dead_code))]
+    fn header(&self) -> &'static str {
+        match *self {
+            CollectdVersion::V54 => "collectd/core/plugin.h",
+            CollectdVersion::V55 | CollectdVersion::V57 => "collectd/core/daemon/plugin.h",
+        }
+    }
+
+    #[cfg_attr(feature = "bindgen", allow(dead_code))]
+    fn bindings(&self) -> &'static str {
+        match *self {
+            CollectdVersion::V54 => "src/bindings-54.rs",
+            CollectdVersion::V55 => "src/bindings-55.rs",
+            CollectdVersion::V57 => "src/bindings-57.rs",
+        }
+    }
+}
+
 fn main() {
+    let version = collectd_version();
+
+    // Downstream code (and our own) keys off of these cfgs instead of the features so that the
+    // default version (when no feature is given) is consistent everywhere
+    println!("cargo:rustc-check-cfg=cfg(collectd_54, collectd_55, collectd_57)");
+    println!("cargo:rustc-cfg={}", version.cfg());
+
+    // Bindgen's layout tests in the shipped bindings encode x86_64's type sizes
+    if cfg!(not(feature = "bindgen")) && env::var("CARGO_CFG_TARGET_POINTER_WIDTH").ok()
+        != Some(String::from("64"))
+    {
+        println!(
+            "cargo:warning=The shipped collectd bindings were generated for 64 bit targets, \
+             enable the bindgen feature to generate bindings for this target"
+        );
+    }
+
     // Write the bindings to the $OUT_DIR/bindings.rs file.
     let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
-    bindings(out_path.join("bindings.rs"));
+    bindings(out_path.join("bindings.rs"), version);
 }
 
+/// Determines the collectd version from the enabled cargo features. Enabling several versions is
+/// an error as silently picking one would compile against the wrong struct layouts.
+fn collectd_version() -> CollectdVersion {
+    let features = [
+        ("CARGO_FEATURE_COLLECTD_54", CollectdVersion::V54),
+        ("CARGO_FEATURE_COLLECTD_55", CollectdVersion::V55),
+        ("CARGO_FEATURE_COLLECTD_57", CollectdVersion::V57),
+    ];
+
+    let enabled: Vec<CollectdVersion> = features
+        .iter()
+        .filter(|&&(var, _)| env::var_os(var).is_some())
+        .map(|&(_, version)| version)
+        .collect();
+
+    match enabled.len() {
+        0 => {
+            println!(
+                "cargo:warning=No collectd version feature enabled (collectd-54, collectd-55, \
+                 collectd-57), defaulting to collectd 5.7 bindings"
+            );
+            CollectdVersion::V57
+        }
+        1 => enabled[0],
+        _ => panic!(
+            "Only one collectd version feature may be enabled at a time, received: {:?}",
+            enabled
+        ),
+    }
+}
+
+/// Include arguments for clang that locate collectd's headers. The search order is:
+///
+/// 1. `COLLECTD_PATH`, which may either point to a collectd source tree (`./configure` must have
+///    been ran so that `src/config.h` exists) or to an include directory
+/// 2. `COLLECTD_PREFIX`, the prefix collectd was installed to (eg: `/opt/collectd`)
+/// 3. pkg-config's include directory for `libcollectdclient`
+/// 4. Standard locations used by Debian's `collectd-dev`, Fedora's `collectd-devel`, FreeBSD's
+///    ports, Homebrew, and collectd's default install prefix
 #[cfg(feature = "bindgen")]
-fn bindings(loc: PathBuf) {
+fn collectd_include_args(version: CollectdVersion) -> Vec<String> {
+    println!("cargo:rerun-if-env-changed=COLLECTD_PATH");
+    println!("cargo:rerun-if-env-changed=COLLECTD_PREFIX");
+
+    let sysroot = sysroot();
+
+    if let Some(path) = env::var_os("COLLECTD_PATH").map(PathBuf::from) {
+        let src = path.join("src");
+        return if src.is_dir() {
+            vec![
+                String::from("-DCOLLECTD_SRC"),
+                format!("-I{}", src.display()),
+                format!("-I{}", src.join("daemon").display()),
+            ]
+        } else {
+            vec![format!("-I{}", path.display())]
+        };
+    }
+
+    let mut candidates = Vec::new();
+    if let Some(prefix) = env::var_os("COLLECTD_PREFIX").map(PathBuf::from) {
+        candidates.push(prefix.join("include"));
+    }
+
+    // The host's pkg-config knows nothing of the target's headers
+    if sysroot.is_none() {
+        if let Some(dir) = pkg_config_include_dir() {
+            candidates.push(dir);
+        }
+    }
+
+    // Homebrew installs to /usr/local on Intel and /opt/homebrew on Apple Silicon, while FreeBSD's
+    // ports install to /usr/local
+    let dirs = [
+        "usr/include",
+        "usr/local/include",
+        "opt/homebrew/include",
+        "opt/collectd/include",
+    ];
+
+    for dir in &dirs {
+        let root = sysroot.clone().unwrap_or_else(|| PathBuf::from("/"));
+        candidates.push(root.join(dir));
+    }
+
+    let header = version.header();
+    match candidates.iter().find(|dir| dir.join(header).is_file()) {
+        Some(dir) => vec![format!("-I{}", dir.display())],
+        None => {
+            let searched: Vec<String> = candidates
+                .iter()
+                .map(|x| format!("  {}", x.join(header).display()))
+                .collect();
+            panic!(
+                "Unable to locate collectd's headers. Searched:\n{}\n\
+                 Install collectd's development headers (`apt install collectd-dev` on \
+                 Debian/Ubuntu or `dnf install collectd-devel` on Fedora/RHEL), or set \
+                 COLLECTD_PREFIX to collectd's install prefix, or set COLLECTD_PATH to a \
+                 configured collectd source tree",
+                searched.join("\n")
+            );
+        }
+    }
+}
+
+/// The root directory of the target's filesystem when cross compiling, given by
+/// `COLLECTD_SYSROOT`. Standard header locations are searched relative to the sysroot.
+#[cfg(feature = "bindgen")]
+fn sysroot() -> Option<PathBuf> {
+    println!("cargo:rerun-if-env-changed=COLLECTD_SYSROOT");
+    env::var_os("COLLECTD_SYSROOT").map(PathBuf::from)
+}
+
+/// Clang arguments for generating bindings for the target instead of the host, so that the
+/// target's C library headers (eg: musl's) and type sizes are used
+#[cfg(feature = "bindgen")]
+fn target_args() -> Vec<String> {
+    let mut args = Vec::new();
+    let target = env::var("TARGET").unwrap();
+    if target != env::var("HOST").unwrap() {
+        args.push(format!("--target={}", target));
+    }
+
+    if let Some(root) = sysroot() {
+        args.push(format!("--sysroot={}", root.display()));
+    }
+
+    args
+}
+
+/// Asks pkg-config where the collectd client library's headers were installed, which will be
+/// alongside the daemon's headers. Absence of pkg-config is not an error.
+#[cfg(feature = "bindgen")]
+fn pkg_config_include_dir() -> Option<PathBuf> {
+    use std::process::Command;
+
+    let output = Command::new("pkg-config")
+        .args(&["--variable=includedir", "libcollectdclient"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let dir = String::from_utf8(output.stdout).ok()?;
+    let dir = dir.trim();
+    if dir.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(dir))
+    }
+}
+
+#[cfg(feature = "bindgen")]
+fn bindings(loc: PathBuf, version: CollectdVersion) {
     extern crate bindgen;
-    let bindings_builder = bindgen::Builder::default()
+    println!("cargo:rerun-if-changed=wrapper.h");
+    let bindings = bindgen::Builder::default()
         .header("wrapper.h")
         .clang_arg("-DHAVE_CONFIG_H")
+        .clang_arg(version.define())
+        .clang_args(collectd_include_args(version))
+        .clang_args(target_args())
         .rust_target(bindgen::RustTarget::Stable_1_19)
+        // Only generate collectd's API (and the types it references), as the C library's
+        // internals differ between glibc, musl, and the BSDs
+        .whitelist_function("(plugin|meta_data|fc|uc)_.*")
+        .whitelist_type("(cdtime|data_set|data_source|value|value_list|identifier|user_data)_t")
+        .whitelist_type("(meta_data|notification|notification_meta|oconfig_item|oconfig_value)_t")
+        .whitelist_type("(match_proc|target_proc|plugin_.*)_t")
+        .whitelist_var("(DS_TYPE|LOG|NOTIF|MD_TYPE|OCONFIG_TYPE|FC_MATCH|FC_TARGET)_.*")
+        .whitelist_var("DATA_MAX_NAME_LEN|NOTIF_MAX_MSG_LEN|interval_g|timeout_g")
         .blacklist_type("FP_NAN")
         .blacklist_type("FP_INFINITE")
         .blacklist_type("FP_ZERO")
@@ -21,18 +250,7 @@ fn bindings(loc: PathBuf) {
         .blacklist_type("FP_NORMAL")
         .blacklist_type("max_align_t")
         .blacklist_type("hostname_g")
-        .blacklist_type("module_register");
-
-    #[cfg(feature = "collectd-57")]
-    let bindings_builder = bindings_builder.clang_arg("-DCOLLECTD_57");
-
-    #[cfg(feature = "collectd-55")]
-    let bindings_builder = bindings_builder.clang_arg("-DCOLLECTD_55");
-
-    #[cfg(feature = "collectd-54")]
-    let bindings_builder = bindings_builder.clang_arg("-DCOLLECTD_54");
-
-    let bindings = bindings_builder
+        .blacklist_type("module_register")
         .generate()
         .expect("Unable to generate bindings");
 
@@ -42,18 +260,9 @@ fn bindings(loc: PathBuf) {
 }
 
 #[cfg(not(feature = "bindgen"))]
-fn bindings(loc: PathBuf) {
+fn bindings(loc: PathBuf, version: CollectdVersion) {
     use std::fs;
 
-    // Default to using collectd-57 bindings
-    #[allow(unused_variables)]
-    let path = PathBuf::from("src/bindings-57.rs");
-
-    #[cfg(feature = "collectd-55")]
-    let path = PathBuf::from("src/bindings-55.rs");
-
-    #[cfg(feature = "collectd-54")]
-    let path = PathBuf::from("src/bindings-54.rs");
-
-    fs::copy(path, loc).expect("File to copy");
+    println!("cargo:rerun-if-changed={}", version.bindings());
+    fs::copy(version.bindings(), loc).expect("File to copy");
 }
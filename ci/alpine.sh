@@ -0,0 +1,31 @@
+#!/bin/sh
+
+# Builds the load plugin against musl and verifies that Alpine's collectd loads it. Intended to be
+# ran within an alpine docker container from the root of the repo.
+
+set -eu
+
+apk add --no-cache collectd collectd-csv rust cargo
+
+# Rust links musl statically by default, which rules out producing a cdylib
+export RUSTFLAGS="-C target-feature=-crt-static"
+cargo build --all --features collectd-57
+
+cp target/debug/libloadrust.so /usr/lib/collectd/loadrust.so
+
+cat <<EOF2 | tee /etc/collectd/collectd.conf
+Hostname "localhost"
+LoadPlugin loadrust
+LoadPlugin csv
+<Plugin csv>
+  DataDir "/var/lib/collectd/csv"
+  StoreRates false
+</Plugin>
+<Plugin loadrust>
+</Plugin>
+EOF2
+
+collectd -C /etc/collectd/collectd.conf
+sleep 15
+
+grep 'epoch,shortterm,midterm,longterm' /var/lib/collectd/csv/localhost/loadrust/load*
@@ -11,6 +11,9 @@ cargo build --all --features "$VERSION bindgen"
 cargo test --all --features "$VERSION bindgen"
 cargo test-junit --name TestResults-bindgen --features "$VERSION bindgen"
 
+# Tests that load plugins into the installed collectd
+cargo test --all --features $VERSION -- --ignored
+
 cp target/debug/libloadrust.so /usr/lib/collectd/loadrust.so
 
 cat <<EOF | tee /etc/collectd/collectd.conf
@@ -0,0 +1,333 @@
+//! Derive macros for [collectd-plugin](https://docs.rs/collectd-plugin). Enable the crate's
+//! `derive` feature instead of depending on this crate directly.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::meta::ParseNestedMeta;
+use syn::{Attribute, Data, DeriveInput, Expr, ExprLit, Fields, Lit, LitStr, Meta, Token};
+
+/// Implements `collectd_plugin::CollectdConfig` for a struct with named fields, describing each
+/// field by the key that collectd's config uses for it (honoring serde's `rename` and
+/// `rename_all`), its type, its doc comment, and the value given with
+/// `#[collectd(default = "..")]`. Fields that serde skips are left out.
+#[proc_macro_derive(CollectdConfig, attributes(collectd))]
+pub fn derive_collectd_config(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => return Err(error(input, "CollectdConfig requires named fields")),
+        },
+        _ => return Err(error(input, "CollectdConfig can only be derived for structs")),
+    };
+
+    let rename_all = container_rename_all(&input.attrs)?;
+    let mut entries = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let name = ident.to_string();
+        let name = name.trim_start_matches("r#");
+        let mut key = match rename_all {
+            Some(ref rule) => rename(rule, name).map_err(|e| error(input, &e))?,
+            None => String::from(name),
+        };
+
+        let mut skip = false;
+        let mut default = None;
+        for attr in &field.attrs {
+            if attr.path().is_ident("serde") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("rename") {
+                        let s: LitStr = meta.value()?.parse()?;
+                        key = s.value();
+                    } else if meta.path.is_ident("skip")
+                        || meta.path.is_ident("skip_deserializing")
+                    {
+                        skip = true;
+                    } else {
+                        ignore(&meta)?;
+                    }
+                    Ok(())
+                })?;
+            } else if attr.path().is_ident("collectd") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("default") {
+                        let s: LitStr = meta.value()?.parse()?;
+                        default = Some(s.value());
+                        Ok(())
+                    } else {
+                        Err(meta.error("unknown collectd attribute, expected `default`"))
+                    }
+                })?;
+            }
+        }
+
+        if skip {
+            continue;
+        }
+
+        let ty = &field.ty;
+        let type_ = type_name(quote!(#ty).to_string());
+        let doc = match doc_comment(&field.attrs) {
+            Some(doc) => quote!(Some(#doc)),
+            None => quote!(None),
+        };
+        let default = match default {
+            Some(default) => quote!(Some(#default)),
+            None => quote!(None),
+        };
+
+        entries.push(quote! {
+            ::collectd_plugin::ConfigField {
+                key: #key,
+                type_: #type_,
+                doc: #doc,
+                default: #default,
+            }
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::collectd_plugin::CollectdConfig for #name #ty_generics
+            #where_clause
+        {
+            fn fields() -> Vec<::collectd_plugin::ConfigField> {
+                vec![#(#entries),*]
+            }
+        }
+    })
+}
+
+/// Implements `collectd_plugin::TypeInstance`, `Display`, and `FromStr` for an enum of unit
+/// variants, each written as its lowercased name (eg: `SoftIrq` is `softirq`). Rename all variants
+/// with one of serde's rules in `#[collectd(rename_all = "..")]`, or a single variant with
+/// `#[collectd(rename = "..")]`.
+#[proc_macro_derive(TypeInstance, attributes(collectd))]
+pub fn derive_type_instance(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand_type_instance(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_type_instance(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let variants = match input.data {
+        Data::Enum(ref data) => &data.variants,
+        _ => return Err(error(input, "TypeInstance can only be derived for enums")),
+    };
+
+    let mut rename_all = None;
+    for attr in input.attrs.iter().filter(|x| x.path().is_ident("collectd")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                let s: LitStr = meta.value()?.parse()?;
+                rename_all = Some(s.value());
+                Ok(())
+            } else {
+                Err(meta.error("unknown collectd attribute, expected `rename_all`"))
+            }
+        })?;
+    }
+
+    let mut idents = Vec::new();
+    let mut names = Vec::new();
+    for variant in variants {
+        match variant.fields {
+            Fields::Unit => {}
+            _ => {
+                let msg = "TypeInstance variants can't have fields";
+                return Err(syn::Error::new_spanned(&variant.ident, msg));
+            }
+        }
+
+        let ident = variant.ident.to_string();
+        let ident = ident.trim_start_matches("r#");
+        let mut name = match rename_all.as_deref() {
+            None | Some("lowercase") => ident.to_lowercase(),
+            Some("UPPERCASE") => ident.to_uppercase(),
+            Some(rule) => rename(rule, &snake_case(ident)).map_err(|e| error(input, &e))?,
+        };
+
+        for attr in variant.attrs.iter().filter(|x| x.path().is_ident("collectd")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    let s: LitStr = meta.value()?.parse()?;
+                    name = s.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown collectd attribute, expected `rename`"))
+                }
+            })?;
+        }
+
+        idents.push(&variant.ident);
+        names.push(name);
+    }
+
+    let name = &input.ident;
+    let enum_name = name.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let variants = idents.iter().map(|ident| quote!(#name::#ident));
+    let arms = idents.iter().zip(names.iter()).map(|(ident, s)| quote!(#name::#ident => #s));
+    Ok(quote! {
+        impl #impl_generics ::collectd_plugin::TypeInstance for #name #ty_generics
+            #where_clause
+        {
+            fn as_str(&self) -> &'static str {
+                match *self {
+                    #(#arms),*
+                }
+            }
+
+            fn instances() -> &'static [Self] {
+                &[#(#variants),*]
+            }
+        }
+
+        impl #impl_generics ::std::fmt::Display for #name #ty_generics #where_clause {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                f.write_str(::collectd_plugin::TypeInstance::as_str(self))
+            }
+        }
+
+        impl #impl_generics ::std::str::FromStr for #name #ty_generics #where_clause {
+            type Err = ::collectd_plugin::ParseInstanceError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                ::collectd_plugin::TypeInstance::from_instance(s)
+                    .ok_or_else(|| ::collectd_plugin::ParseInstanceError::new(#enum_name, s))
+            }
+        }
+
+        impl<'a> From<#name> for &'a str {
+            fn from(instance: #name) -> &'a str {
+                ::collectd_plugin::TypeInstance::as_str(&instance)
+            }
+        }
+    })
+}
+
+fn error(input: &DeriveInput, msg: &str) -> syn::Error {
+    syn::Error::new_spanned(&input.ident, msg)
+}
+
+/// Consumes a serde attribute that doesn't affect the sample
+fn ignore(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|nested| ignore(&nested))?;
+    }
+    Ok(())
+}
+
+fn container_rename_all(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    let mut rule = None;
+    for attr in attrs.iter().filter(|x| x.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                let s: LitStr = meta.value()?.parse()?;
+                rule = Some(s.value());
+            } else {
+                ignore(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(rule)
+}
+
+/// Applies one of serde's `rename_all` rules to a snake case field name
+fn rename(rule: &str, name: &str) -> Result<String, String> {
+    let capitalize = |word: &str| {
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(c) => c.to_uppercase().chain(chars).collect::<String>(),
+            None => String::new(),
+        }
+    };
+
+    let renamed = match rule {
+        "lowercase" | "snake_case" => String::from(name),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => name.to_uppercase(),
+        "kebab-case" => name.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => name.to_uppercase().replace('_', "-"),
+        "PascalCase" => name.split('_').map(capitalize).collect(),
+        "camelCase" => {
+            let pascal: String = name.split('_').map(capitalize).collect();
+            let mut chars = pascal.chars();
+            match chars.next() {
+                Some(c) => c.to_lowercase().chain(chars).collect(),
+                None => pascal,
+            }
+        }
+        _ => return Err(format!("unknown rename_all rule: {}", rule)),
+    };
+    Ok(renamed)
+}
+
+/// Splits a Pascal case variant name into snake case words (eg: `SlabRecl` is `slab_recl`)
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_uppercase() && prev_lower {
+            snake.push('_');
+        }
+        prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+/// Tightens the spacing that `quote` puts between type tokens (eg: `Option < u16 >`)
+fn type_name(tokens: String) -> String {
+    tokens
+        .replace(" < ", "<")
+        .replace("< ", "<")
+        .replace(" >", ">")
+        .replace(" , ", ", ")
+        .replace(" :: ", "::")
+        .replace(":: ", "::")
+        .replace("& ", "&")
+}
+
+/// The lines of the field's doc comment, joined by spaces
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter_map(|attr| match attr.meta {
+            Meta::NameValue(ref nv) if nv.path.is_ident("doc") => match nv.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(ref s),
+                    ..
+                }) => Some(String::from(s.value().trim())),
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|x| !x.is_empty())
+        .collect();
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join(" "))
+    }
+}
@@ -0,0 +1,56 @@
+//! Runs arbitrary configuration trees through `from_collectd` for common struct shapes. The
+//! deserializer may reject any of them, but it must never panic. Once configuration text can be
+//! parsed, a target that feeds raw text through the parser belongs alongside this one.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate collectd_plugin;
+extern crate collectd_plugin_fuzz;
+#[macro_use]
+extern crate serde_derive;
+
+use collectd_plugin::de::from_collectd;
+use collectd_plugin_fuzz::config_items;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Flat {
+    host: Option<String>,
+    port: Option<u16>,
+    enabled: Option<bool>,
+    ratio: Option<f64>,
+    letter: Option<char>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+#[serde(deny_unknown_fields)]
+struct Strict {
+    host: String,
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Server {
+    name: String,
+    weight: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Nested {
+    inner: Option<Flat>,
+    servers: Vec<Server>,
+    tags: Option<Vec<String>>,
+}
+
+fuzz_target!(|data: &[u8]| {
+    let items = config_items(data);
+    let _ = from_collectd::<Flat>(&items);
+    let _ = from_collectd::<Strict>(&items);
+    let _ = from_collectd::<Nested>(&items);
+    let _ = from_collectd::<Vec<bool>>(&items);
+    let _ = from_collectd::<String>(&items);
+});
@@ -0,0 +1,90 @@
+//! Builds collectd configuration trees out of arbitrary fuzzer input. Keys are mostly drawn from
+//! the field names of the structs under test so that the fuzzer spends its time in the
+//! deserializer instead of tripping over unknown fields.
+
+extern crate collectd_plugin;
+
+use collectd_plugin::{ConfigItem, ConfigValue};
+use std::str;
+
+/// Keys that the fuzz target's structs know about
+const KEYS: &[&str] = &[
+    "Host", "Port", "Enabled", "Ratio", "Tags", "Letter", "Inner", "Servers", "Name", "Weight",
+];
+
+/// Deeper trees than this don't exercise anything new and only make each run slower
+const MAX_DEPTH: usize = 4;
+
+struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&first, rest) = self.data.split_first()?;
+        self.data = rest;
+        Some(first)
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(head)
+    }
+
+    /// A known key most of the time, otherwise a (possibly empty) string from the input
+    fn string(&mut self) -> Option<&'a str> {
+        let choice = self.byte()? as usize;
+        if choice < 200 {
+            Some(KEYS[choice % KEYS.len()])
+        } else {
+            let len = (self.byte()? % 16) as usize;
+            str::from_utf8(self.bytes(len)?).ok()
+        }
+    }
+
+    fn value(&mut self) -> Option<ConfigValue<'a>> {
+        match self.byte()? % 3 {
+            0 => self.string().map(ConfigValue::String),
+            1 => Some(ConfigValue::Boolean(self.byte()? & 1 == 1)),
+            _ => {
+                let mut bits = [0u8; 8];
+                bits.copy_from_slice(self.bytes(8)?);
+                Some(ConfigValue::Number(f64::from_bits(u64::from_le_bytes(bits))))
+            }
+        }
+    }
+
+    fn item(&mut self, depth: usize) -> Option<ConfigItem<'a>> {
+        let key = self.string()?;
+        let counts = self.byte()?;
+        let values = (0..counts % 4).filter_map(|_| self.value()).collect();
+        let children = if depth < MAX_DEPTH {
+            (0..(counts >> 4) % 4)
+                .filter_map(|_| self.item(depth + 1))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Some(ConfigItem {
+            key: key,
+            values: values,
+            children: children,
+        })
+    }
+}
+
+/// Top level configuration items built from the input until it is exhausted
+pub fn config_items(data: &[u8]) -> Vec<ConfigItem> {
+    let mut input = Input { data: data };
+    let mut items = Vec::new();
+    while let Some(item) = input.item(0) {
+        items.push(item);
+    }
+    items
+}
@@ -1,15 +1,13 @@
 #[macro_use]
 extern crate collectd_plugin;
-extern crate failure;
 extern crate num_cpus;
 extern crate libc;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
-use collectd_plugin::{ConfigItem, Plugin, PluginCapabilities, PluginManager, PluginRegistration,
-                      Value, ValueListBuilder};
-use failure::Error;
+use collectd_plugin::{ConfigItem, Error, Plugin, PluginCapabilities, PluginManager,
+                      PluginRegistration, Value, ValueListBuilder};
 
 /// Our plugin will look for a ReportRelative True / False in the collectd config. Unknown
 /// properties will cause a collectd failure as that means there is probably a typo.
@@ -68,7 +66,7 @@ fn get_load() -> Result<[f64; 3], Error> {
 
     unsafe {
         if libc::getloadavg(load.as_mut_ptr(), 3) != 3 {
-            Err(failure::err_msg("load: getloadavg failed"))
+            Err("load: getloadavg failed".into())
         } else {
             Ok(load)
         }
@@ -76,6 +74,8 @@ fn get_load() -> Result<[f64; 3], Error> {
 }
 
 impl Plugin for AbsoluteLoadPlugin {
+    type Error = Error;
+
     fn capabilities(&self) -> PluginCapabilities {
         PluginCapabilities::READ
     }
@@ -94,6 +94,8 @@ impl Plugin for AbsoluteLoadPlugin {
 }
 
 impl Plugin for RelativeLoadPlugin {
+    type Error = Error;
+
     fn capabilities(&self) -> PluginCapabilities {
         PluginCapabilities::READ
     }
@@ -0,0 +1,20 @@
+extern crate collectd_test_runner;
+
+use collectd_test_runner::Runner;
+
+// Requires collectd (with the csv and logfile plugins) to be installed, so ran with `--ignored`
+#[test]
+#[ignore]
+fn test_reports_load_in_collectd() {
+    let output = Runner::new("loadrust", Runner::cdylib("loadrust"))
+        .config("ReportRelative true")
+        .intervals(2)
+        .run()
+        .unwrap();
+
+    assert!(output.errors().is_empty(), "{}", output.log);
+    let files = output.csv("loadrust", "load");
+    assert_eq!(files.len(), 1, "{}", output.log);
+    assert_eq!(files[0].columns, vec!["shortterm", "midterm", "longterm"]);
+    assert!(!files[0].rows.is_empty());
+}
@@ -0,0 +1,9 @@
+use std::env;
+
+fn main() {
+    // collectd's symbols (eg: plugin_log, hostname_g) are only resolved once collectd loads the
+    // plugin. ELF platforms allow this by default, but macOS' linker must be told to defer them.
+    if env::var("CARGO_CFG_TARGET_OS").map(|os| os == "macos").unwrap_or(false) {
+        println!("cargo:rustc-cdylib-link-arg=-Wl,-undefined,dynamic_lookup");
+    }
+}
@@ -1,10 +1,8 @@
 #[macro_use]
 extern crate collectd_plugin;
-extern crate failure;
 
-use collectd_plugin::{ConfigItem, Plugin, PluginCapabilities, PluginManager, PluginRegistration,
-                      Value, ValueListBuilder};
-use failure::Error;
+use collectd_plugin::{ConfigItem, Error, Plugin, PluginCapabilities, PluginManager,
+                      PluginRegistration, Value, ValueListBuilder};
 
 #[derive(Default)]
 struct MyPlugin;
@@ -26,6 +24,8 @@ impl PluginManager for MyPlugin {
 }
 
 impl Plugin for MyPlugin {
+    type Error = Error;
+
     // We define that our plugin will only be reporting / submitting values to writers
     fn capabilities(&self) -> PluginCapabilities {
         PluginCapabilities::READ
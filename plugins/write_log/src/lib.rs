@@ -1,12 +1,11 @@
 #[macro_use]
 extern crate collectd_plugin;
-extern crate failure;
 extern crate itertools;
 
-use collectd_plugin::{collectd_log, ConfigItem, LogLevel, Plugin, PluginCapabilities,
-                      PluginManager, PluginRegistration, RecvValueList};
-use failure::Error;
+use collectd_plugin::{ConfigItem, Error, Plugin, PluginCapabilities, PluginManager,
+                      PluginRegistration, RecvValueList};
 use itertools::Itertools;
+use std::time::UNIX_EPOCH;
 
 #[derive(Default)]
 struct TestWritePlugin;
@@ -17,13 +16,14 @@ impl PluginManager for TestWritePlugin {
     }
 
     fn plugins(config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
-        let line = format!("Received configuration of {:?}", config);
-        collectd_log(LogLevel::Info, &line);
+        collectd_info!("Received configuration of {:?}", config);
         Ok(PluginRegistration::Single(Box::new(TestWritePlugin)))
     }
 }
 
 impl Plugin for TestWritePlugin {
+    type Error = Error;
+
     fn capabilities(&self) -> PluginCapabilities {
         PluginCapabilities::WRITE
     }
@@ -34,18 +34,17 @@ impl Plugin for TestWritePlugin {
             .map(|v| format!("{} - {}", v.name, v.value))
             .join(", ");
 
-        let line = format!(
+        collectd_info!(
             "plugin_instance: {}, plugin: {}, type: {}, type_instance: {}, host: {}, time: {}, interval: {} seconds, values: {}",
             list.plugin_instance.unwrap_or("<none>"),
             list.plugin,
             list.type_,
             list.type_instance.unwrap_or("<none>"),
             list.host,
-            list.time,
-            list.interval.num_seconds(),
+            list.time.duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0),
+            list.interval.as_secs(),
             values,
         );
-        collectd_log(LogLevel::Info, &line);
         Ok(())
     }
 }
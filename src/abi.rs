@@ -0,0 +1,132 @@
+//! Guards against loading a plugin into a collectd daemon whose struct layouts differ from the
+//! bindings that the plugin was compiled with. Such a mismatch doesn't fail at load time, instead
+//! collectd crashes or reads garbage once values are dispatched.
+//!
+//! The size of text fields (`DATA_MAX_NAME_LEN`) is the layout difference between releases, and
+//! it can be observed at runtime through the size of the `hostname_g` symbol that collectd exports.
+
+use bindings::{ARR_LENGTH, COLLECTD_VERSION};
+use errors::AbiMismatch;
+
+/// A marker embedded in the compiled library recording the collectd release that it targets, so
+/// that tools (eg: `strings myplugin.so | grep collectd-rust-plugin`) can identify it
+#[cfg(collectd_54)]
+#[no_mangle]
+#[used]
+pub static COLLECTD_RUST_PLUGIN_ABI: [u8; 30] = *b"collectd-rust-plugin abi 5.4\0\0";
+
+#[cfg(collectd_55)]
+#[no_mangle]
+#[used]
+pub static COLLECTD_RUST_PLUGIN_ABI: [u8; 30] = *b"collectd-rust-plugin abi 5.5\0\0";
+
+#[cfg(collectd_57)]
+#[no_mangle]
+#[used]
+pub static COLLECTD_RUST_PLUGIN_ABI: [u8; 30] = *b"collectd-rust-plugin abi 5.7\0\0";
+
+/// Verifies that the daemon that loaded the plugin has text fields of the same length as the
+/// bindings. If the length can't be determined (eg: the platform doesn't support `dladdr1`), the
+/// check passes.
+pub fn verify_abi() -> Result<(), AbiMismatch> {
+    match daemon_name_len() {
+        Some(actual) if actual != ARR_LENGTH => Err(AbiMismatch {
+            compiled: COLLECTD_VERSION,
+            expected: ARR_LENGTH,
+            actual: actual,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The size of collectd's `hostname_g` as recorded in the daemon's dynamic symbol table
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn daemon_name_len() -> Option<usize> {
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::ptr;
+    use bindings::hostname_g;
+
+    #[repr(C)]
+    struct DlInfo {
+        dli_fname: *const c_char,
+        dli_fbase: *mut c_void,
+        dli_sname: *const c_char,
+        dli_saddr: *mut c_void,
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[repr(C)]
+    struct ElfSym {
+        st_name: u32,
+        st_info: u8,
+        st_other: u8,
+        st_shndx: u16,
+        st_value: u64,
+        st_size: u64,
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[repr(C)]
+    struct ElfSym {
+        st_name: u32,
+        st_value: u32,
+        st_size: u32,
+        st_info: u8,
+        st_other: u8,
+        st_shndx: u16,
+    }
+
+    const RTLD_DL_SYMENT: c_int = 1;
+
+    #[link(name = "dl")]
+    extern "C" {
+        fn dladdr1(
+            addr: *const c_void,
+            info: *mut DlInfo,
+            extra_info: *mut *mut c_void,
+            flags: c_int,
+        ) -> c_int;
+    }
+
+    unsafe {
+        let mut info = DlInfo {
+            dli_fname: ptr::null(),
+            dli_fbase: ptr::null_mut(),
+            dli_sname: ptr::null(),
+            dli_saddr: ptr::null_mut(),
+        };
+        let mut sym: *mut c_void = ptr::null_mut();
+        let addr = &hostname_g as *const _ as *const c_void;
+        if dladdr1(addr, &mut info, &mut sym, RTLD_DL_SYMENT) == 0 || sym.is_null() {
+            return None;
+        }
+
+        // Only trust the symbol if it is actually hostname_g and not a neighbor
+        if info.dli_saddr as *const c_void != addr {
+            return None;
+        }
+
+        let sym = &*(sym as *const ElfSym);
+        match sym.st_size as usize {
+            0 => None,
+            n => Some(n),
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn daemon_name_len() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_abi_in_executable() {
+        // The test executable doesn't export hostname_g dynamically, so the check can't determine
+        // the size and must not report a false mismatch
+        assert!(verify_abi().is_ok());
+    }
+}
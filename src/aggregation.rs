@@ -0,0 +1,519 @@
+//! A Rust equivalent of collectd's `aggregation` plugin: value lists of a type are grouped by some
+//! of their fields and consolidated (summed, averaged, etc) across the fields that aren't. For
+//! instance, grouping `cpu` values by host and type instance yields the total of each CPU state
+//! across every core.
+//!
+//! ```
+//! use collectd_plugin::aggregation::{Aggregator, Calculate, GroupBy};
+//!
+//! let aggregator = Aggregator::new("percent")
+//!     .plugin("cpu")
+//!     .group_by(GroupBy::Host)
+//!     .group_by(GroupBy::TypeInstance)
+//!     .calculate(Calculate::Average)
+//!     .calculate(Calculate::Max);
+//! ```
+//!
+//! A write plugin adds each value list it receives, and the aggregates are submitted on every
+//! read. Like the `aggregation` plugin, the aggregates are named after their group: the plugin
+//! instance is made of the plugin (when grouped by or filtered to one), the plugin instance (when
+//! grouped by), and the function, with other fields left empty (or `global` for the host). The
+//! above yields `web-1/aggregation-cpu-average/percent-idle`.
+//!
+//! Counters and derives are aggregated by their rates, and the aggregated rates are integrated
+//! back into counters and derives.
+
+use api::{CdTime, RecvValueList, Value, ValueListBuilder};
+use errors::Error;
+use identifier::Identifier;
+use network::ValueList;
+use rates::RateTracker;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// A field that value lists are grouped by
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum GroupBy {
+    Host,
+    Plugin,
+    PluginInstance,
+    TypeInstance,
+}
+
+/// A function that consolidates the values of a group
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Calculate {
+    Sum,
+    Average,
+    Min,
+    Max,
+    /// The number of values
+    Num,
+    /// The population standard deviation
+    Stddev,
+}
+
+impl Calculate {
+    /// The name appended to the plugin instance of the aggregate
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Calculate::Sum => "sum",
+            Calculate::Average => "average",
+            Calculate::Min => "min",
+            Calculate::Max => "max",
+            Calculate::Num => "num",
+            Calculate::Stddev => "stddev",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Source {
+    time: SystemTime,
+    rates: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+struct Group {
+    host: String,
+
+    /// The plugin instance of the aggregates, before the function is appended
+    plugin_instance: String,
+    type_instance: String,
+
+    /// The kind of each data source, from the latest value list
+    kinds: Vec<Value>,
+    interval: Duration,
+    sources: HashMap<Identifier, Source>,
+}
+
+/// Integrates an aggregated rate back into a counter or derive
+#[derive(Debug, Clone, Copy)]
+struct Integrated {
+    value: f64,
+    time: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Summary {
+    num: u64,
+    sum: f64,
+    squares: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Summary {
+    fn add(&mut self, x: f64) {
+        if self.num == 0 || x < self.min {
+            self.min = x;
+        }
+        if self.num == 0 || x > self.max {
+            self.max = x;
+        }
+        self.num += 1;
+        self.sum += x;
+        self.squares += x * x;
+    }
+
+    fn calculate(&self, func: Calculate) -> f64 {
+        let num = self.num as f64;
+        match func {
+            Calculate::Sum => self.sum,
+            Calculate::Average => self.sum / num,
+            Calculate::Min => self.min,
+            Calculate::Max => self.max,
+            Calculate::Num => num,
+            Calculate::Stddev => {
+                let mean = self.sum / num;
+                (self.squares / num - mean * mean).max(0.0).sqrt()
+            }
+        }
+    }
+}
+
+/// Consolidates the value lists of one type. Value lists that match the filters (the type and any
+/// of the plugin, host, and instances that are set) are aggregated.
+#[derive(Debug, Clone)]
+pub struct Aggregator {
+    type_: String,
+    host: Option<String>,
+    plugin: Option<String>,
+    plugin_instance: Option<String>,
+    type_instance: Option<String>,
+    group_by: Vec<GroupBy>,
+    functions: Vec<Calculate>,
+    window: Duration,
+    groups: HashMap<String, Group>,
+    rates: RateTracker,
+    integrated: HashMap<(String, Calculate), Vec<Integrated>>,
+}
+
+impl Aggregator {
+    pub fn new(type_: &str) -> Self {
+        Aggregator {
+            type_: String::from(type_),
+            host: None,
+            plugin: None,
+            plugin_instance: None,
+            type_instance: None,
+            group_by: Vec::new(),
+            functions: Vec::new(),
+            window: Duration::from_secs(20),
+            groups: HashMap::new(),
+            rates: RateTracker::new(),
+            integrated: HashMap::new(),
+        }
+    }
+
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(String::from(host));
+        self
+    }
+
+    pub fn plugin(mut self, plugin: &str) -> Self {
+        self.plugin = Some(String::from(plugin));
+        self
+    }
+
+    pub fn plugin_instance(mut self, instance: &str) -> Self {
+        self.plugin_instance = Some(String::from(instance));
+        self
+    }
+
+    pub fn type_instance(mut self, instance: &str) -> Self {
+        self.type_instance = Some(String::from(instance));
+        self
+    }
+
+    /// Keeps value lists that differ in this field in separate groups
+    pub fn group_by(mut self, field: GroupBy) -> Self {
+        if !self.group_by.contains(&field) {
+            self.group_by.push(field);
+        }
+        self
+    }
+
+    /// Adds a function to aggregate each group with
+    pub fn calculate(mut self, func: Calculate) -> Self {
+        if !self.functions.contains(&func) {
+            self.functions.push(func);
+        }
+        self
+    }
+
+    /// Only values received within this long of an aggregation are part of it, so that value
+    /// lists that stop being reported (eg: a removed disk) drop out. Defaults to twenty seconds,
+    /// which is two of collectd's default intervals.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    fn matches(&self, list: &RecvValueList) -> bool {
+        fn field(expected: &Option<String>, actual: Option<&str>) -> bool {
+            expected
+                .as_ref()
+                .map_or(true, |x| x == actual.unwrap_or(""))
+        }
+
+        self.type_ == list.type_ && field(&self.host, Some(list.host))
+            && field(&self.plugin, Some(list.plugin))
+            && field(&self.plugin_instance, list.plugin_instance)
+            && field(&self.type_instance, list.type_instance)
+    }
+
+    /// Adds the value list to its group, returning whether it was of interest to the aggregator
+    pub fn add(&mut self, list: &RecvValueList) -> bool {
+        if !self.matches(list) {
+            return false;
+        }
+
+        let grouped = |field| self.group_by.contains(&field);
+        let host = if grouped(GroupBy::Host) {
+            String::from(list.host)
+        } else {
+            String::from("global")
+        };
+
+        // The plugin is named when it's known to be the same for the whole group
+        let mut prefix = Vec::new();
+        if grouped(GroupBy::Plugin) || self.plugin.is_some() {
+            prefix.push(list.plugin);
+        }
+        if grouped(GroupBy::PluginInstance) {
+            prefix.extend(list.plugin_instance.filter(|x| !x.is_empty()));
+        }
+        let plugin_instance = prefix.join("-");
+        let type_instance = if grouped(GroupBy::TypeInstance) {
+            String::from(list.type_instance.unwrap_or(""))
+        } else {
+            String::new()
+        };
+        let key = format!("{}/{}/{}", host, plugin_instance, type_instance);
+
+        let source = Identifier::from(list);
+        let values: Vec<Value> = list.values.iter().map(|x| x.value).collect();
+        let rates = match self.rates.rates(&source, list.time, &values) {
+            Some(rates) => rates,
+            None => return true,
+        };
+
+        let group = self.groups.entry(key).or_insert_with(|| Group {
+            host: host,
+            plugin_instance: plugin_instance,
+            type_instance: type_instance,
+            kinds: Vec::new(),
+            interval: list.interval,
+            sources: HashMap::new(),
+        });
+
+        // A type's data sources don't change, so any list with a different number is an error
+        if !group.kinds.is_empty() && group.kinds.len() != values.len() {
+            return true;
+        }
+
+        group.kinds = values;
+        group.interval = list.interval;
+        group.sources.insert(
+            source,
+            Source {
+                time: list.time,
+                rates: rates,
+            },
+        );
+        true
+    }
+
+    /// Aggregates every group as of the given time. Groups without values in the window are
+    /// skipped, as are counters and derives until they have been aggregated twice (their rate
+    /// needs integrating over time).
+    pub fn aggregate(&mut self, now: SystemTime) -> Vec<ValueList> {
+        let oldest = now - self.window;
+        let mut result = Vec::new();
+        let mut keys: Vec<String> = self.groups.keys().cloned().collect();
+        keys.sort();
+
+        for key in keys {
+            let group = self.groups.get_mut(&key).unwrap();
+            group.sources.retain(|_, x| x.time >= oldest);
+
+            let mut summaries = vec![Summary::default(); group.kinds.len()];
+            for source in group.sources.values() {
+                for (summary, &rate) in summaries.iter_mut().zip(source.rates.iter()) {
+                    if !rate.is_nan() {
+                        summary.add(rate);
+                    }
+                }
+            }
+
+            if summaries.is_empty() || summaries.iter().any(|x| x.num == 0) {
+                continue;
+            }
+
+            for &func in &self.functions {
+                let rates: Vec<f64> = summaries.iter().map(|x| x.calculate(func)).collect();
+                let state = self.integrated.entry((key.clone(), func)).or_insert_with(Vec::new);
+                let values = match integrate(state, &group.kinds, &rates, now) {
+                    Some(values) => values,
+                    None => continue,
+                };
+
+                let plugin_instance = if group.plugin_instance.is_empty() {
+                    String::from(func.name())
+                } else {
+                    format!("{}-{}", group.plugin_instance, func.name())
+                };
+
+                result.push(ValueList {
+                    host: group.host.clone(),
+                    plugin: String::from("aggregation"),
+                    plugin_instance: Some(plugin_instance),
+                    type_: self.type_.clone(),
+                    type_instance: Some(group.type_instance.clone()).filter(|x| !x.is_empty()),
+                    time: CdTime::from(now),
+                    interval: CdTime::from(group.interval),
+                    values: values,
+                });
+            }
+        }
+
+        self.groups.retain(|_, x| !x.sources.is_empty());
+        result
+    }
+
+    /// Aggregates every group and submits the results to collectd. Call from a read callback.
+    pub fn submit(&mut self) -> Result<(), Error> {
+        for list in self.aggregate(SystemTime::now()) {
+            let mut builder = ValueListBuilder::new(list.plugin.as_str(), list.type_.as_str())
+                .host(list.host.as_str())
+                .time(list.time)
+                .interval(list.interval)
+                .values(&list.values);
+            if let Some(ref instance) = list.plugin_instance {
+                builder = builder.plugin_instance(instance.as_str());
+            }
+            if let Some(ref instance) = list.type_instance {
+                builder = builder.type_instance(instance.as_str());
+            }
+
+            builder.submit()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts aggregated rates into values of each data source's kind. Counters and derives
+/// accumulate the rate over the elapsed time, and absolutes are the rate over the elapsed time,
+/// so that neither has a value the first time.
+fn integrate(
+    state: &mut Vec<Integrated>,
+    kinds: &[Value],
+    rates: &[f64],
+    now: SystemTime,
+) -> Option<Vec<Value>> {
+    if state.len() != rates.len() {
+        *state = rates
+            .iter()
+            .map(|&rate| Integrated {
+                value: rate,
+                time: now,
+            })
+            .collect();
+
+        if kinds.iter().all(|x| match *x {
+            Value::Gauge(_) => true,
+            _ => false,
+        }) {
+            return Some(rates.iter().map(|&x| Value::Gauge(x)).collect());
+        }
+        return None;
+    }
+
+    let mut values = Vec::with_capacity(rates.len());
+    for ((entry, kind), &rate) in state.iter_mut().zip(kinds).zip(rates) {
+        let elapsed = now.duration_since(entry.time).unwrap_or(Duration::from_secs(0));
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        entry.value += rate * secs;
+        entry.time = now;
+        values.push(match *kind {
+            Value::Gauge(_) => Value::Gauge(rate),
+            Value::Counter(_) => Value::Counter(entry.value as u64),
+            Value::Derive(_) => Value::Derive(entry.value as i64),
+            Value::Absolute(_) => Value::Absolute((rate * secs) as u64),
+        });
+    }
+
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::ValueReport;
+    use std::time::UNIX_EPOCH;
+    use testing;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn list<'a>(host: &'a str, cpu: &'a str, secs: u64, value: Value) -> RecvValueList<'a> {
+        RecvValueList {
+            values: smallvec![ValueReport {
+                name: "value",
+                value: value,
+                min: 0.0,
+                max: 0.0,
+            }],
+            plugin_instance: Some(cpu),
+            plugin: "cpu",
+            type_: "percent",
+            type_instance: Some("idle"),
+            host: host,
+            time: at(secs),
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn test_aggregate_gauges() {
+        let mut aggregator = Aggregator::new("percent")
+            .plugin("cpu")
+            .group_by(GroupBy::Host)
+            .group_by(GroupBy::TypeInstance)
+            .calculate(Calculate::Average)
+            .calculate(Calculate::Num)
+            .calculate(Calculate::Stddev);
+
+        assert!(aggregator.add(&list("web-1", "0", 10, Value::Gauge(20.0))));
+        assert!(aggregator.add(&list("web-1", "1", 10, Value::Gauge(40.0))));
+        aggregator.add(&list("web-2", "0", 10, Value::Gauge(90.0)));
+        assert!(!aggregator.add(&RecvValueList {
+            plugin: "memory",
+            ..list("web-1", "0", 10, Value::Gauge(1.0))
+        }));
+
+        let lists = aggregator.aggregate(at(15));
+        let summary: Vec<_> = lists
+            .iter()
+            .map(|x| {
+                (
+                    x.host.as_str(),
+                    x.plugin_instance.as_ref().unwrap().as_str(),
+                    x.values[0],
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("web-1", "cpu-average", Value::Gauge(30.0)),
+                ("web-1", "cpu-num", Value::Gauge(2.0)),
+                ("web-1", "cpu-stddev", Value::Gauge(10.0)),
+                ("web-2", "cpu-average", Value::Gauge(90.0)),
+                ("web-2", "cpu-num", Value::Gauge(1.0)),
+                ("web-2", "cpu-stddev", Value::Gauge(0.0)),
+            ]
+        );
+        assert_eq!(lists[0].plugin, "aggregation");
+        assert_eq!(lists[0].type_instance, Some(String::from("idle")));
+
+        // Values that are older than the window drop out
+        aggregator.add(&list("web-1", "0", 20, Value::Gauge(60.0)));
+        assert_eq!(aggregator.aggregate(at(35)).len(), 3);
+
+        testing::reset();
+        aggregator.add(&RecvValueList {
+            time: SystemTime::now(),
+            ..list("web-3", "0", 0, Value::Gauge(5.0))
+        });
+        aggregator.submit().unwrap();
+        assert_eq!(testing::dispatched().len(), 3);
+        testing::assert_submitted("aggregation", "percent", &[Value::Gauge(5.0)]);
+    }
+
+    #[test]
+    fn test_aggregate_derives() {
+        let mut aggregator = Aggregator::new("percent")
+            .group_by(GroupBy::PluginInstance)
+            .calculate(Calculate::Sum);
+
+        for &(secs, a, b) in &[(0, 0, 0), (10, 100, 50), (20, 200, 100)] {
+            aggregator.add(&list("web-1", "0", secs, Value::Derive(a)));
+            aggregator.add(&list("web-2", "0", secs, Value::Derive(b)));
+            if secs == 10 {
+                // The rate is known, but needs integrating over time
+                assert!(aggregator.aggregate(at(10)).is_empty());
+            }
+        }
+
+        let lists = aggregator.aggregate(at(20));
+        assert_eq!(lists[0].host, "global");
+        assert_eq!(lists[0].plugin, "aggregation");
+        assert_eq!(lists[0].plugin_instance, Some(String::from("0-sum")));
+        assert_eq!(lists[0].type_instance, None);
+        assert_eq!(lists[0].values, vec![Value::Derive(165)]);
+    }
+}
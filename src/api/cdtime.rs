@@ -10,21 +10,52 @@
 //! comparison / subtraction works.
 
 use bindings::cdtime_t;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "chrono")]
 use chrono::prelude::*;
-use chrono::Duration;
 
-/// `CdTime` allows for ergonomic interop between collectd's `cdtime_t` and chrono's `Duration` and
-/// `DateTime`. The single field represents epoch nanoseconds.
+/// `CdTime` allows for ergonomic interop between collectd's `cdtime_t` and std's `Duration` and
+/// `SystemTime` (and chrono's `Duration` and `DateTime` when the `chrono` feature is enabled). The
+/// single field represents epoch nanoseconds.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct CdTime(pub u64);
 
+impl From<SystemTime> for CdTime {
+    /// Times before the epoch are clamped to the epoch
+    fn from(t: SystemTime) -> Self {
+        CdTime::from(t.duration_since(UNIX_EPOCH).unwrap_or_else(|_| Duration::new(0, 0)))
+    }
+}
+
+impl From<CdTime> for SystemTime {
+    fn from(v: CdTime) -> SystemTime {
+        UNIX_EPOCH + Duration::from(v)
+    }
+}
+
+impl From<Duration> for CdTime {
+    fn from(d: Duration) -> Self {
+        CdTime(d.as_secs() * 1_000_000_000 + u64::from(d.subsec_nanos()))
+    }
+}
+
+impl From<CdTime> for Duration {
+    fn from(v: CdTime) -> Self {
+        let CdTime(ns) = v;
+        Duration::new(ns / 1_000_000_000, (ns % 1_000_000_000) as u32)
+    }
+}
+
+#[cfg(feature = "chrono")]
 impl<Tz: TimeZone> From<DateTime<Tz>> for CdTime {
     fn from(dt: DateTime<Tz>) -> Self {
-        let nanos = (dt.timestamp() as u64) + u64::from(dt.timestamp_subsec_nanos());
-        CdTime(nanos_to_collectd(nanos))
+        let secs = dt.timestamp() as u64;
+        CdTime(secs * 1_000_000_000 + u64::from(dt.timestamp_subsec_nanos()))
     }
 }
 
+#[cfg(feature = "chrono")]
 impl From<CdTime> for DateTime<Utc> {
     fn from(v: CdTime) -> DateTime<Utc> {
         let CdTime(ns) = v;
@@ -34,16 +65,18 @@ impl From<CdTime> for DateTime<Utc> {
     }
 }
 
-impl From<Duration> for CdTime {
-    fn from(d: Duration) -> Self {
+#[cfg(feature = "chrono")]
+impl From<::chrono::Duration> for CdTime {
+    fn from(d: ::chrono::Duration) -> Self {
         CdTime(d.num_nanoseconds().unwrap() as u64)
     }
 }
 
-impl From<CdTime> for Duration {
+#[cfg(feature = "chrono")]
+impl From<CdTime> for ::chrono::Duration {
     fn from(v: CdTime) -> Self {
         let CdTime(ns) = v;
-        Duration::nanoseconds(ns as i64)
+        ::chrono::Duration::nanoseconds(ns as i64)
     }
 }
 
@@ -95,10 +128,29 @@ mod tests {
     fn test_collectd_to_duration() {
         let v: cdtime_t = nanos_to_collectd(1_000_000_000);
         let dur = Duration::from(CdTime::from(v));
+        assert_eq!(dur, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_collectd_to_system_time() {
+        let v: cdtime_t = nanos_to_collectd(1_500_000_000);
+        let t: SystemTime = CdTime::from(v).into();
+        assert_eq!(t, UNIX_EPOCH + Duration::from_millis(1500));
+
+        let back: cdtime_t = CdTime::from(t).into();
+        assert_eq!(back, v);
+    }
+
+    #[test]
+    #[cfg(feature = "chrono")]
+    fn test_collectd_to_chrono_duration() {
+        let v: cdtime_t = nanos_to_collectd(1_000_000_000);
+        let dur = ::chrono::Duration::from(CdTime::from(v));
         assert_eq!(dur.num_seconds(), 1);
     }
 
     #[test]
+    #[cfg(feature = "chrono")]
     fn test_collectd_to_datetime() {
         let v: cdtime_t = nanos_to_collectd(1_000_000_000);
         let dt: DateTime<Utc> = CdTime::from(v).into();
@@ -0,0 +1,118 @@
+use bindings::{plugin_ctx_t, plugin_get_ctx, plugin_set_ctx};
+use std::thread;
+use std::time::Duration;
+use super::CdTime;
+
+/// collectd's context for the current thread, which holds the interval of the plugin whose
+/// callback is running. collectd attributes values dispatched without an explicit interval to the
+/// context's interval, so threads spawned by a plugin should inherit the context of the callback
+/// that spawned them (see `spawn`), otherwise their values are attributed the global interval.
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadContext(plugin_ctx_t);
+
+impl ThreadContext {
+    /// The context of the current thread
+    pub fn current() -> ThreadContext {
+        ThreadContext(unsafe { plugin_get_ctx() })
+    }
+
+    /// The interval of the plugin that the context belongs to, or `None` if the thread didn't
+    /// originate from a callback
+    pub fn interval(&self) -> Option<Duration> {
+        match self.0.interval {
+            0 => None,
+            x => Some(CdTime::from(x).into()),
+        }
+    }
+
+    /// Makes this the context of the current thread, returning the thread's previous context
+    pub fn install(self) -> ThreadContext {
+        ThreadContext(unsafe { plugin_set_ctx(self.0) })
+    }
+
+    /// Spawns a thread that runs within this context
+    pub fn spawn<F, T>(self, f: F) -> thread::JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let ctx = self.0.interval;
+        thread::spawn(move || {
+            let mut context = ThreadContext::current();
+            (context.0).interval = ctx;
+            context.install();
+            f()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{Value, ValueListBuilder};
+    use stub;
+
+    #[test]
+    fn test_spawn_inherits_context() {
+        assert_eq!(ThreadContext::current().interval(), None);
+
+        let mut context = ThreadContext::current();
+        (context.0).interval = CdTime::from(Duration::from_secs(5)).into();
+        let previous = context.install();
+        assert_eq!(previous.interval(), None);
+        assert_eq!(
+            ThreadContext::current().interval(),
+            Some(Duration::from_secs(5))
+        );
+
+        let interval = ThreadContext::current()
+            .spawn(|| ThreadContext::current().interval())
+            .join()
+            .unwrap();
+        assert_eq!(interval, Some(Duration::from_secs(5)));
+
+        // Threads spawned without the context don't have an interval
+        let interval = thread::spawn(|| ThreadContext::current().interval())
+            .join()
+            .unwrap();
+        assert_eq!(interval, None);
+        previous.install();
+    }
+
+    #[test]
+    fn test_submit_uses_context_interval() {
+        stub::reset();
+        let values = [Value::Gauge(1.0)];
+        ValueListBuilder::new("myplugin", "gauge")
+            .values(&values)
+            .submit()
+            .unwrap();
+
+        let mut context = ThreadContext::current();
+        (context.0).interval = CdTime::from(Duration::from_secs(60)).into();
+        let previous = context.install();
+        ValueListBuilder::new("myplugin", "gauge")
+            .values(&values)
+            .submit()
+            .unwrap();
+        ValueListBuilder::new("myplugin", "gauge")
+            .values(&values)
+            .interval(Duration::from_secs(1))
+            .submit()
+            .unwrap();
+        previous.install();
+
+        let intervals: Vec<Duration> = stub::dispatched()
+            .iter()
+            .map(|x| CdTime::from(x.interval).into())
+            .collect();
+        assert_eq!(
+            intervals,
+            vec![
+                Duration::from_secs(10),
+                Duration::from_secs(60),
+                Duration::from_secs(1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use bindings::{hostname_g, interval_g, timeout_g};
+use std::ffi::CStr;
+use std::time::Duration;
+use super::CdTime;
+
+/// collectd's daemon-wide options (`Interval`, `Hostname`, and `Timeout`), which are only final
+/// once the configuration has been read, so they should be queried at init or later.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GlobalConfig {
+    /// The default interval between reads, and the interval attributed to dispatched values
+    /// without one
+    pub interval: Duration,
+
+    /// The host attributed to dispatched values without one
+    pub hostname: String,
+
+    /// The number of intervals after which collectd considers a value missing
+    pub timeout: u32,
+}
+
+impl GlobalConfig {
+    /// Reads the options from collectd's globals
+    pub fn current() -> GlobalConfig {
+        unsafe {
+            GlobalConfig {
+                interval: CdTime::from(interval_g).into(),
+                hostname: CStr::from_ptr(hostname_g.as_ptr())
+                    .to_string_lossy()
+                    .into_owned(),
+                timeout: timeout_g.max(0) as u32,
+            }
+        }
+    }
+
+    /// How long until collectd considers a value missing, which bounds how long values are worth
+    /// buffering (eg: for a write plugin that batches values)
+    pub fn missing_after(&self) -> Duration {
+        self.interval * self.timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_global_config() {
+        // The stub leaves collectd's hostname empty and the rest at collectd's defaults
+        let config = GlobalConfig::current();
+        assert_eq!(
+            config,
+            GlobalConfig {
+                interval: Duration::from_secs(10),
+                hostname: String::new(),
+                timeout: 2,
+            }
+        );
+        assert_eq!(config.missing_after(), Duration::from_secs(20));
+    }
+}
@@ -0,0 +1,319 @@
+//! # `MetaData`
+//!
+//! Collectd can attach a list of typed key-value pairs to value lists and notifications. The list
+//! is an opaque `meta_data_t` that is only manipulated through the `meta_data_*` functions, so
+//! `MetaData` owns one of these lists and destroys it when dropped.
+
+use bindings::{meta_data_add_boolean, meta_data_add_double, meta_data_add_signed_int,
+               meta_data_add_string, meta_data_add_unsigned_int, meta_data_clone,
+               meta_data_create, meta_data_delete, meta_data_destroy, meta_data_exists,
+               meta_data_get_boolean, meta_data_get_double, meta_data_get_signed_int,
+               meta_data_get_string, meta_data_get_unsigned_int, meta_data_t, meta_data_toc,
+               meta_data_type};
+use constants::{MD_TYPE_BOOLEAN, MD_TYPE_DOUBLE, MD_TYPE_SIGNED_INT, MD_TYPE_STRING,
+                MD_TYPE_UNSIGNED_INT};
+use errors::MetaDataError;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::slice;
+
+extern "C" {
+    // Strings returned by `meta_data_get_string` and `meta_data_toc` are allocated by collectd
+    // and must be released by the caller
+    fn free(ptr: *mut c_void);
+}
+
+/// A single metadata value
+#[derive(Debug, PartialEq, Clone)]
+pub enum MetaValue {
+    String(String),
+    SignedInt(i64),
+    UnsignedInt(u64),
+    Double(f64),
+    Boolean(bool),
+}
+
+impl<'a> From<&'a str> for MetaValue {
+    fn from(x: &'a str) -> MetaValue {
+        MetaValue::String(String::from(x))
+    }
+}
+
+impl From<String> for MetaValue {
+    fn from(x: String) -> MetaValue {
+        MetaValue::String(x)
+    }
+}
+
+impl From<i64> for MetaValue {
+    fn from(x: i64) -> MetaValue {
+        MetaValue::SignedInt(x)
+    }
+}
+
+impl From<u64> for MetaValue {
+    fn from(x: u64) -> MetaValue {
+        MetaValue::UnsignedInt(x)
+    }
+}
+
+impl From<f64> for MetaValue {
+    fn from(x: f64) -> MetaValue {
+        MetaValue::Double(x)
+    }
+}
+
+impl From<bool> for MetaValue {
+    fn from(x: bool) -> MetaValue {
+        MetaValue::Boolean(x)
+    }
+}
+
+/// An owned collectd metadata list
+#[derive(Debug)]
+pub struct MetaData {
+    ptr: *mut meta_data_t,
+}
+
+impl MetaData {
+    /// Creates an empty metadata list
+    pub fn new() -> Result<MetaData, MetaDataError> {
+        let ptr = unsafe { meta_data_create() };
+        if ptr.is_null() {
+            Err(MetaDataError::Allocation)
+        } else {
+            Ok(MetaData { ptr: ptr })
+        }
+    }
+
+    /// Takes ownership of a metadata list allocated by collectd. The list will be destroyed when
+    /// the returned `MetaData` is dropped, so the pointer must not be used afterwards.
+    pub unsafe fn from_raw(ptr: *mut meta_data_t) -> Option<MetaData> {
+        if ptr.is_null() {
+            None
+        } else {
+            Some(MetaData { ptr: ptr })
+        }
+    }
+
+    /// Releases ownership of the list, for handing it to collectd (eg: as `value_list_t.meta`)
+    pub fn into_raw(self) -> *mut meta_data_t {
+        let ptr = self.ptr;
+        ::std::mem::forget(self);
+        ptr
+    }
+
+    pub fn as_ptr(&self) -> *mut meta_data_t {
+        self.ptr
+    }
+
+    pub fn add_string(&mut self, key: &str, value: &str) -> Result<(), MetaDataError> {
+        let k = CString::new(key)?;
+        let v = CString::new(value)?;
+        let res = unsafe { meta_data_add_string(self.ptr, k.as_ptr(), v.as_ptr()) };
+        check("add_string", key, res)
+    }
+
+    pub fn add_signed_int(&mut self, key: &str, value: i64) -> Result<(), MetaDataError> {
+        let k = CString::new(key)?;
+        let res = unsafe { meta_data_add_signed_int(self.ptr, k.as_ptr(), value) };
+        check("add_signed_int", key, res)
+    }
+
+    pub fn add_unsigned_int(&mut self, key: &str, value: u64) -> Result<(), MetaDataError> {
+        let k = CString::new(key)?;
+        let res = unsafe { meta_data_add_unsigned_int(self.ptr, k.as_ptr(), value) };
+        check("add_unsigned_int", key, res)
+    }
+
+    pub fn add_double(&mut self, key: &str, value: f64) -> Result<(), MetaDataError> {
+        let k = CString::new(key)?;
+        let res = unsafe { meta_data_add_double(self.ptr, k.as_ptr(), value) };
+        check("add_double", key, res)
+    }
+
+    pub fn add_boolean(&mut self, key: &str, value: bool) -> Result<(), MetaDataError> {
+        let k = CString::new(key)?;
+        let res = unsafe { meta_data_add_boolean(self.ptr, k.as_ptr(), value) };
+        check("add_boolean", key, res)
+    }
+
+    /// Adds the value under the key, replacing any existing value
+    pub fn add(&mut self, key: &str, value: &MetaValue) -> Result<(), MetaDataError> {
+        match *value {
+            MetaValue::String(ref x) => self.add_string(key, x),
+            MetaValue::SignedInt(x) => self.add_signed_int(key, x),
+            MetaValue::UnsignedInt(x) => self.add_unsigned_int(key, x),
+            MetaValue::Double(x) => self.add_double(key, x),
+            MetaValue::Boolean(x) => self.add_boolean(key, x),
+        }
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        match CString::new(key) {
+            Ok(k) => unsafe { meta_data_exists(self.ptr, k.as_ptr()) != 0 },
+            Err(_) => false,
+        }
+    }
+
+    /// Removes the key, returning whether it was present
+    pub fn delete(&mut self, key: &str) -> bool {
+        match CString::new(key) {
+            Ok(k) => unsafe { meta_data_delete(self.ptr, k.as_ptr()) == 0 },
+            Err(_) => false,
+        }
+    }
+
+    /// Retrieves the value stored under the key, or `None` if the key is absent
+    pub fn get(&self, key: &str) -> Result<Option<MetaValue>, MetaDataError> {
+        let k = CString::new(key)?;
+        unsafe {
+            let value = match meta_data_type(self.ptr, k.as_ptr()) {
+                0 => return Ok(None),
+                MD_TYPE_STRING => {
+                    let mut s: *mut c_char = ptr::null_mut();
+                    check("get_string", key, meta_data_get_string(self.ptr, k.as_ptr(), &mut s))?;
+                    MetaValue::String(take_string(s))
+                }
+                MD_TYPE_SIGNED_INT => {
+                    let mut x = 0i64;
+                    check(
+                        "get_signed_int",
+                        key,
+                        meta_data_get_signed_int(self.ptr, k.as_ptr(), &mut x),
+                    )?;
+                    MetaValue::SignedInt(x)
+                }
+                MD_TYPE_UNSIGNED_INT => {
+                    let mut x = 0u64;
+                    check(
+                        "get_unsigned_int",
+                        key,
+                        meta_data_get_unsigned_int(self.ptr, k.as_ptr(), &mut x),
+                    )?;
+                    MetaValue::UnsignedInt(x)
+                }
+                MD_TYPE_DOUBLE => {
+                    let mut x = 0f64;
+                    check("get_double", key, meta_data_get_double(self.ptr, k.as_ptr(), &mut x))?;
+                    MetaValue::Double(x)
+                }
+                MD_TYPE_BOOLEAN => {
+                    let mut x = false;
+                    check(
+                        "get_boolean",
+                        key,
+                        meta_data_get_boolean(self.ptr, k.as_ptr(), &mut x),
+                    )?;
+                    MetaValue::Boolean(x)
+                }
+                t => return Err(MetaDataError::UnknownType(String::from(key), t)),
+            };
+
+            Ok(Some(value))
+        }
+    }
+
+    /// Lists the keys present, in collectd's order
+    pub fn keys(&self) -> Result<Vec<String>, MetaDataError> {
+        unsafe {
+            let mut toc: *mut *mut c_char = ptr::null_mut();
+            let len = meta_data_toc(self.ptr, &mut toc);
+            if len < 0 {
+                return Err(MetaDataError::Call("toc", String::new(), len));
+            }
+
+            let keys = if toc.is_null() {
+                Vec::new()
+            } else {
+                slice::from_raw_parts(toc, len as usize)
+                    .iter()
+                    .map(|&x| take_string(x))
+                    .collect()
+            };
+
+            free(toc as *mut c_void);
+            Ok(keys)
+        }
+    }
+}
+
+impl Clone for MetaData {
+    fn clone(&self) -> MetaData {
+        let ptr = unsafe { meta_data_clone(self.ptr) };
+        if ptr.is_null() {
+            panic!("meta_data_clone failed to allocate");
+        }
+        MetaData { ptr: ptr }
+    }
+}
+
+impl Drop for MetaData {
+    fn drop(&mut self) {
+        unsafe { meta_data_destroy(self.ptr) };
+    }
+}
+
+fn check(func: &'static str, key: &str, res: c_int) -> Result<(), MetaDataError> {
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(MetaDataError::Call(func, String::from(key), res))
+    }
+}
+
+/// Copies and frees a string allocated by collectd
+unsafe fn take_string(s: *mut c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+
+    let res = CStr::from_ptr(s).to_string_lossy().into_owned();
+    free(s as *mut c_void);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_data_round_trip() {
+        let mut meta = MetaData::new().unwrap();
+        meta.add_string("host", "localhost").unwrap();
+        meta.add_signed_int("offset", -3).unwrap();
+        meta.add_unsigned_int("count", 10).unwrap();
+        meta.add_double("ratio", 0.5).unwrap();
+        meta.add_boolean("enabled", true).unwrap();
+
+        assert_eq!(
+            meta.get("host").unwrap(),
+            Some(MetaValue::String(String::from("localhost")))
+        );
+        assert_eq!(meta.get("offset").unwrap(), Some(MetaValue::SignedInt(-3)));
+        assert_eq!(meta.get("count").unwrap(), Some(MetaValue::UnsignedInt(10)));
+        assert_eq!(meta.get("ratio").unwrap(), Some(MetaValue::Double(0.5)));
+        assert_eq!(meta.get("enabled").unwrap(), Some(MetaValue::Boolean(true)));
+        assert_eq!(meta.get("missing").unwrap(), None);
+
+        let mut keys = meta.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["count", "enabled", "host", "offset", "ratio"]);
+    }
+
+    #[test]
+    fn test_meta_data_replace_delete_clone() {
+        let mut meta = MetaData::new().unwrap();
+        meta.add("a", &MetaValue::SignedInt(1)).unwrap();
+        meta.add("a", &MetaValue::Boolean(false)).unwrap();
+        assert_eq!(meta.get("a").unwrap(), Some(MetaValue::Boolean(false)));
+
+        let copy = meta.clone();
+        assert!(meta.delete("a"));
+        assert!(!meta.delete("a"));
+        assert!(!meta.exists("a"));
+        assert!(copy.exists("a"));
+        assert!(meta.keys().unwrap().is_empty());
+    }
+}
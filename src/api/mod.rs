@@ -1,40 +1,209 @@
-use bindings::{data_set_t, hostname_g, plugin_dispatch_values, plugin_log, value_list_t, value_t,
-               ARR_LENGTH, DS_TYPE_ABSOLUTE, DS_TYPE_COUNTER, DS_TYPE_DERIVE, DS_TYPE_GAUGE,
+use bindings::{data_set_t, hostname_g, interval_g, plugin_dispatch_values,
+               plugin_get_interval, plugin_log, plugin_write, value_list_t, value_t, ARR_LENGTH,
                LOG_DEBUG, LOG_ERR, LOG_INFO, LOG_NOTICE, LOG_WARNING};
-use std::os::raw::c_char;
+use constants::DataSourceType;
+use smallvec::SmallVec;
+use std::os::raw::{c_char, c_int};
 use std::ptr;
 use std::slice;
-use chrono::prelude::*;
-use chrono::Duration;
-use std::ffi::{CStr, CString};
-use failure::{Error, ResultExt};
-use errors::{ArrayError, SubmitError};
+use std::ffi::{CStr, CString, NulError};
+use errors::{ArrayError, Error, ReceiveError, SubmitError, UnknownLogLevel};
+use std::convert::TryFrom;
 use std::fmt;
-use std::str::Utf8Error;
+use std::str::{FromStr, Utf8Error};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
 pub use self::cdtime::CdTime;
-pub use self::oconfig::{ConfigItem, ConfigValue};
+pub use self::context::ThreadContext;
+pub use self::global::GlobalConfig;
+pub use self::meta::{MetaData, MetaValue};
+pub use self::notification::{AlertTracker, NotificationBuilder, RecvNotification};
+pub use self::oconfig::{ConfigAccumulator, ConfigItem, ConfigValue, OwnedConfigItem,
+                        OwnedConfigValue};
 
 mod cdtime;
+mod context;
+mod global;
+mod meta;
+mod notification;
 mod oconfig;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-#[repr(u32)]
+/// Severity of a log message. Levels are ordered from most to least severe, so `Error` is the
+/// smallest and `Debug` the largest.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub enum LogLevel {
-    Error = LOG_ERR,
-    Warning = LOG_WARNING,
-    Notice = LOG_NOTICE,
-    Info = LOG_INFO,
-    Debug = LOG_DEBUG,
+    Error,
+    Warning,
+    Notice,
+    Info,
+    Debug,
+
+    /// A severity that collectd doesn't define, which is ordered as less severe than `Debug`
+    Unknown(u32),
+}
+
+impl LogLevel {
+    /// The syslog severity that collectd uses for this level
+    pub fn as_raw(&self) -> u32 {
+        match *self {
+            LogLevel::Error => LOG_ERR,
+            LogLevel::Warning => LOG_WARNING,
+            LogLevel::Notice => LOG_NOTICE,
+            LogLevel::Info => LOG_INFO,
+            LogLevel::Debug => LOG_DEBUG,
+            LogLevel::Unknown(x) => x,
+        }
+    }
+}
+
+impl TryFrom<u32> for LogLevel {
+    type Error = UnknownLogLevel;
+
+    /// Converts one of collectd's severities, failing on severities that aren't defined. Log
+    /// callbacks receive whatever severity another plugin logged with, so they should fall back
+    /// to `LogLevel::Unknown` instead of failing.
+    fn try_from(x: u32) -> Result<Self, UnknownLogLevel> {
+        match x {
+            LOG_ERR => Ok(LogLevel::Error),
+            LOG_WARNING => Ok(LogLevel::Warning),
+            LOG_NOTICE => Ok(LogLevel::Notice),
+            LOG_INFO => Ok(LogLevel::Info),
+            LOG_DEBUG => Ok(LogLevel::Debug),
+            _ => Err(UnknownLogLevel(x.to_string())),
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = UnknownLogLevel;
+
+    /// Parses log levels the same way that collectd's logfile and syslog plugins do (case
+    /// insensitive), with `err` and `warn` accepted as aliases.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" | "err" => Ok(LogLevel::Error),
+            "warning" | "warn" => Ok(LogLevel::Warning),
+            "notice" => Ok(LogLevel::Notice),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(UnknownLogLevel(String::from(s))),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            LogLevel::Error => "error",
+            LogLevel::Warning => "warning",
+            LogLevel::Notice => "notice",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Unknown(_) => "unknown",
+        };
+        f.write_str(s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for LogLevel {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        let s: &str = ::serde::Deserialize::deserialize(deserializer)?;
+        s.parse().map_err(::serde::de::Error::custom)
+    }
 }
 
+/// Drops log messages that are less severe than a threshold, so log plugins can honor a
+/// `LogLevel` option from their config. Defaults to `info` like collectd's logfile plugin.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-#[repr(u32)]
-#[allow(dead_code)]
-enum ValueType {
-    Counter = DS_TYPE_COUNTER,
-    Gauge = DS_TYPE_GAUGE,
-    Derive = DS_TYPE_DERIVE,
-    Absolute = DS_TYPE_ABSOLUTE,
+pub struct LogFilter {
+    threshold: LogLevel,
+}
+
+impl LogFilter {
+    pub fn new(threshold: LogLevel) -> Self {
+        LogFilter {
+            threshold: threshold,
+        }
+    }
+
+    /// The least severe level that will be allowed through the filter
+    pub fn threshold(&self) -> LogLevel {
+        self.threshold
+    }
+
+    /// Returns true if a message of the given level should be logged
+    pub fn allows(&self, lvl: LogLevel) -> bool {
+        lvl <= self.threshold
+    }
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        LogFilter::new(LogLevel::Info)
+    }
+}
+
+impl From<LogLevel> for LogFilter {
+    fn from(lvl: LogLevel) -> Self {
+        LogFilter::new(lvl)
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = UnknownLogLevel;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(LogFilter::new)
+    }
+}
+
+/// Selects the value lists that a write plugin is interested in, so that the others are dropped
+/// before they are decoded. A list must come from one of the plugins and be of one the types
+/// given, where not giving any plugins (or types) allows all of them.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct WriteFilter {
+    plugins: Vec<String>,
+    types: Vec<String>,
+}
+
+impl WriteFilter {
+    /// A filter that allows every value list
+    pub fn new() -> Self {
+        WriteFilter::default()
+    }
+
+    /// Allows value lists from the plugin
+    pub fn plugin(mut self, plugin: &str) -> Self {
+        self.plugins.push(String::from(plugin));
+        self
+    }
+
+    /// Allows value lists of the type
+    pub fn type_(mut self, type_: &str) -> Self {
+        self.types.push(String::from(type_));
+        self
+    }
+
+    /// Returns if a value list of the plugin and type passes the filter
+    pub fn allows(&self, plugin: &str, type_: &str) -> bool {
+        (self.plugins.is_empty() || self.plugins.iter().any(|x| x == plugin))
+            && (self.types.is_empty() || self.types.iter().any(|x| x == type_))
+    }
+
+    /// Returns if the value list that collectd passed to a write callback passes the filter
+    pub fn allows_raw(&self, list: &value_list_t) -> bool {
+        match (from_array(&list.plugin), from_array(&list.type_)) {
+            (Ok(plugin), Ok(type_)) => self.allows(plugin, type_),
+
+            // Let the write callback report the encoding error
+            _ => true,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -65,6 +234,18 @@ pub enum Value {
     Absolute(u64),
 }
 
+impl Value {
+    /// The kind of data source that the value belongs to
+    pub fn data_source_type(&self) -> DataSourceType {
+        match *self {
+            Value::Counter(_) => DataSourceType::Counter,
+            Value::Gauge(_) => DataSourceType::Gauge,
+            Value::Derive(_) => DataSourceType::Derive,
+            Value::Absolute(_) => DataSourceType::Absolute,
+        }
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -88,23 +269,52 @@ impl Into<value_t> for Value {
     }
 }
 
+/// A received value along with its data source's definition from types.db
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct ValueReport<'a> {
+    /// Name of the data source
     pub name: &'a str,
+
+    /// The value, whose variant is the data source's type
     pub value: Value,
+
+    /// Smallest expected value (or rate, for non-gauges), which is NaN if unbounded
     pub min: f64,
+
+    /// Largest expected value (or rate, for non-gauges), which is NaN if unbounded
     pub max: f64,
 }
 
+impl<'a> ValueReport<'a> {
+    pub fn data_source_type(&self) -> DataSourceType {
+        self.value.data_source_type()
+    }
+
+    /// Returns if the number is within the data source's bounds, ignoring bounds that are NaN.
+    /// The bounds of a gauge apply to its value, and those of other types apply to their rate, so
+    /// pass the rate (eg: from `RateTracker`) for non-gauges. collectd's own write plugins write
+    /// out of range numbers as unknown (NaN).
+    pub fn in_range(&self, x: f64) -> bool {
+        (self.min.is_nan() || x >= self.min) && (self.max.is_nan() || x <= self.max)
+    }
+}
+
+/// Number of values a value list holds before spilling to the heap. Most types have one to four
+/// data sources.
+pub const INLINE_VALUES: usize = 4;
+
+/// A value list received by a write callback. Text fields borrow from collectd's buffers and up
+/// to `INLINE_VALUES` values are stored inline, so receiving a typical list doesn't allocate. Use
+/// `to_owned` to keep the list beyond the callback.
 #[derive(Debug, PartialEq, Clone)]
 pub struct RecvValueList<'a> {
-    pub values: Vec<ValueReport<'a>>,
+    pub values: SmallVec<[ValueReport<'a>; INLINE_VALUES]>,
     pub plugin_instance: Option<&'a str>,
     pub plugin: &'a str,
     pub type_: &'a str,
     pub type_instance: Option<&'a str>,
     pub host: &'a str,
-    pub time: DateTime<Utc>,
+    pub time: SystemTime,
     pub interval: Duration,
 }
 
@@ -112,70 +322,167 @@ impl<'a> RecvValueList<'a> {
     pub fn from<'b>(
         set: &'b data_set_t,
         list: &'b value_list_t,
-    ) -> Result<RecvValueList<'b>, Error> {
-        let p = from_array(&list.plugin).context("Plugin could not be parsed")?;
+    ) -> Result<RecvValueList<'b>, ReceiveError> {
+        let p = from_array(&list.plugin)
+            .map_err(|e| ReceiveError::Utf8(String::from("Plugin"), e))?;
+        let field = |arr: &'b [c_char; ARR_LENGTH], name: &str| {
+            from_array(arr)
+                .map_err(|e| ReceiveError::Utf8(format!("For plugin: {}, {}", p, name), e))
+        };
         let ds_len = length(set.ds_num);
         let list_len = length(list.values_len);
 
-        let values: Result<Vec<ValueReport>, Error> = unsafe {
-            slice::from_raw_parts(list.values, list_len)
-        }.iter()
-            .zip(unsafe { slice::from_raw_parts(set.ds, ds_len) })
-            .map(|(val, source)| unsafe {
-                let v = match ::std::mem::transmute(source.type_) {
-                    ValueType::Gauge => Value::Gauge(val.gauge),
-                    ValueType::Counter => Value::Counter(val.counter),
-                    ValueType::Derive => Value::Derive(val.derive),
-                    ValueType::Absolute => Value::Absolute(val.absolute),
-                };
-
-                let name = from_array(&source.name).with_context(|_e| {
-                    format!("For plugin: {}, data source name could not be decoded", p)
-                })?;
-
-                Ok(ValueReport {
-                    name: name,
-                    value: v,
-                    min: source.min,
-                    max: source.max,
-                })
-            })
-            .collect();
+        let raw = unsafe { slice::from_raw_parts(list.values, list_len) };
+        let sources = unsafe { slice::from_raw_parts(set.ds, ds_len) };
+        let mut values = SmallVec::with_capacity(list_len.min(ds_len));
+        for (val, source) in raw.iter().zip(sources) {
+            let v = unsafe {
+                match DataSourceType::from_raw(source.type_) {
+                    Some(DataSourceType::Gauge) => Value::Gauge(val.gauge),
+                    Some(DataSourceType::Counter) => Value::Counter(val.counter),
+                    Some(DataSourceType::Derive) => Value::Derive(val.derive),
+                    Some(DataSourceType::Absolute) => Value::Absolute(val.absolute),
+                    None => {
+                        return Err(ReceiveError::UnknownDataSourceType(
+                            String::from(p),
+                            source.type_,
+                        ))
+                    }
+                }
+            };
+
+            values.push(ValueReport {
+                name: field(&source.name, "data source name")?,
+                value: v,
+                min: source.min,
+                max: source.max,
+            });
+        }
 
-        assert!(list.time > 0);
-        assert!(list.interval > 0);
+        if list.time == 0 {
+            return Err(ReceiveError::MissingTime(String::from(p), "time"));
+        }
+
+        if list.interval == 0 {
+            return Err(ReceiveError::MissingTime(String::from(p), "interval"));
+        }
 
         Ok(RecvValueList {
-            values: values?,
-            plugin_instance: empty_to_none(from_array(&list.plugin_instance).with_context(|_e| {
-                format!("For plugin: {}, plugin instance could not be decoded", p)
-            })?),
+            values: values,
+            plugin_instance: empty_to_none(field(&list.plugin_instance, "plugin instance")?),
             plugin: p,
-            type_: from_array(&list.type_)
-                .with_context(|_e| format!("For plugin: {}, type could not be decoded", p))?,
-            type_instance: empty_to_none(from_array(&list.type_instance).with_context(|_e| {
-                format!("For plugin: {}, type instance could not be decoded", p)
-            })?),
-            host: from_array(&list.host)
-                .with_context(|_e| format!("For plugin: {}, host could not be decoded", p))?,
+            type_: field(&list.type_, "type")?,
+            type_instance: empty_to_none(field(&list.type_instance, "type instance")?),
+            host: field(&list.host, "host")?,
             time: CdTime::from(list.time).into(),
             interval: CdTime::from(list.interval).into(),
         })
     }
+
+    /// How long ago the values were collected, which is zero for values from the future (eg: when
+    /// the clocks of the reporting hosts disagree)
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.time)
+            .unwrap_or_else(|_| Duration::from_secs(0))
+    }
+
+    /// Whether collectd would consider the values missing by now, which is once `timeout`
+    /// intervals have passed without an update (see `GlobalConfig::timeout`)
+    pub fn is_missing(&self, timeout: u32) -> bool {
+        self.age() > self.interval * timeout
+    }
+
+    /// When the values were collected, as a chrono `DateTime`
+    #[cfg(feature = "chrono")]
+    pub fn datetime(&self) -> ::chrono::DateTime<::chrono::Utc> {
+        CdTime::from(self.time).into()
+    }
+
+    /// Copies the list out of collectd's buffers
+    pub fn to_owned(&self) -> OwnedRecvValueList {
+        OwnedRecvValueList {
+            values: self.values
+                .iter()
+                .map(|x| OwnedValueReport {
+                    name: String::from(x.name),
+                    value: x.value,
+                    min: x.min,
+                    max: x.max,
+                })
+                .collect(),
+            plugin_instance: self.plugin_instance.map(String::from),
+            plugin: String::from(self.plugin),
+            type_: String::from(self.type_),
+            type_instance: self.type_instance.map(String::from),
+            host: String::from(self.host),
+            time: self.time,
+            interval: self.interval,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedValueReport {
+    pub name: String,
+    pub value: Value,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// An owned copy of a received value list, for plugins that queue lists (eg: to write them in
+/// batches from another thread)
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedRecvValueList {
+    pub values: SmallVec<[OwnedValueReport; INLINE_VALUES]>,
+    pub plugin_instance: Option<String>,
+    pub plugin: String,
+    pub type_: String,
+    pub type_instance: Option<String>,
+    pub host: String,
+    pub time: SystemTime,
+    pub interval: Duration,
+}
+
+impl OwnedRecvValueList {
+    /// Borrows the list, for passing to code (like the formatters) that accepts `RecvValueList`
+    pub fn as_recv(&self) -> RecvValueList {
+        RecvValueList {
+            values: self.values
+                .iter()
+                .map(|x| ValueReport {
+                    name: &x.name,
+                    value: x.value,
+                    min: x.min,
+                    max: x.max,
+                })
+                .collect(),
+            plugin_instance: self.plugin_instance.as_ref().map(|x| x.as_str()),
+            plugin: &self.plugin,
+            type_: &self.type_,
+            type_instance: self.type_instance.as_ref().map(|x| x.as_str()),
+            host: &self.host,
+            time: self.time,
+            interval: self.interval,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 struct ValueList<'a> {
-    values: &'a [Value],
+    values: SmallVec<[Value; INLINE_VALUES]>,
     plugin_instance: Option<&'a str>,
     plugin: &'a str,
     type_: &'a str,
     type_instance: Option<&'a str>,
     host: Option<&'a str>,
-    time: Option<DateTime<Utc>>,
-    interval: Option<Duration>,
+    time: Option<CdTime>,
+    interval: Option<CdTime>,
 }
 
+/// Builds a value list and submits it to collectd. A builder can be kept and submitted again
+/// (eg: every read) after replacing its values with `clear_values` and `values`, which saves
+/// re-validating the identifier of the list for every submission.
 #[derive(Debug, PartialEq, Clone)]
 pub struct ValueListBuilder<'a> {
     list: ValueList<'a>,
@@ -185,7 +492,7 @@ impl<'a> ValueListBuilder<'a> {
     pub fn new<T: Into<&'a str>, U: Into<&'a str>>(plugin: T, type_: U) -> ValueListBuilder<'a> {
         ValueListBuilder {
             list: ValueList {
-                values: &[],
+                values: SmallVec::new(),
                 plugin_instance: None,
                 plugin: plugin.into(),
                 type_: type_.into(),
@@ -198,8 +505,29 @@ impl<'a> ValueListBuilder<'a> {
     }
 
     /// A set of observed values that belong to the same plugin and type instance
-    pub fn values(mut self, values: &'a [Value]) -> ValueListBuilder<'a> {
-        self.list.values = values;
+    pub fn values(mut self, values: &[Value]) -> ValueListBuilder<'a> {
+        self.list.values.clear();
+        self.list.values.extend_from_slice(values);
+        self
+    }
+
+    /// Removes the values and the time from the builder so that it can be reused for the next
+    /// submission. The identifier and the interval are kept.
+    pub fn clear_values(&mut self) -> &mut ValueListBuilder<'a> {
+        self.list.values.clear();
+        self.list.time = None;
+        self
+    }
+
+    /// Adds an observed value to a builder that is being reused
+    pub fn push_value(&mut self, value: Value) -> &mut ValueListBuilder<'a> {
+        self.list.values.push(value);
+        self
+    }
+
+    /// Sets the time of a builder that is being reused (see `time`)
+    pub fn set_time<T: Into<CdTime>>(&mut self, dt: T) -> &mut ValueListBuilder<'a> {
+        self.list.time = Some(dt.into());
         self
     }
 
@@ -227,74 +555,147 @@ impl<'a> ValueListBuilder<'a> {
 
     /// The timestamp at which the value was collected. Overrides the default time, which is when
     /// collectd receives the values from `submit`. Use only if there is a significant delay is
-    /// metrics gathering or if submitting values from the past.
-    pub fn time(mut self, dt: DateTime<Utc>) -> ValueListBuilder<'a> {
-        self.list.time = Some(dt);
+    /// metrics gathering or if submitting values from the past. Accepts a `SystemTime` (or a
+    /// chrono `DateTime` with the `chrono` feature).
+    pub fn time<T: Into<CdTime>>(mut self, dt: T) -> ValueListBuilder<'a> {
+        self.list.time = Some(dt.into());
         self
     }
 
     /// The interval in which new values are to be expected. This is typically handled at a global
-    /// or plugin level. Use at your own discretion.
-    pub fn interval(mut self, interval: Duration) -> ValueListBuilder<'a> {
-        self.list.interval = Some(interval);
+    /// or plugin level: when not set, the interval of the calling thread's context (see
+    /// `ThreadContext`) is used, falling back to collectd's global interval. Accepts a std
+    /// `Duration` (or a chrono `Duration` with the `chrono` feature).
+    pub fn interval<T: Into<CdTime>>(mut self, interval: T) -> ValueListBuilder<'a> {
+        self.list.interval = Some(interval.into());
         self
     }
 
-    /// Submits the observed values to collectd and returns errors if encountered
-    pub fn submit(self) -> Result<(), Error> {
-        let mut v: Vec<value_t> = self.list.values.into_iter().map(|&x| x.into()).collect();
+    /// Submits the observed values to collectd and returns errors if encountered. The builder is
+    /// left as is, so it can be submitted again.
+    pub fn submit(&self) -> Result<(), Error> {
+        // collectd fills in the time of values dispatched without one
+        match self.with_list(CdTime(0), |list| unsafe { plugin_dispatch_values(list) })? {
+            0 => Ok(()),
+            i => Err(SubmitError::DispatchError(i).into()),
+        }
+    }
+
+    /// Hands the observed values to the named write plugin only (eg: `write_tsdb`) instead of
+    /// dispatching them to every write plugin, so that a routing plugin can direct some value
+    /// lists to a single destination. The values skip collectd's cache and filter chain, and
+    /// the time defaults to now, as collectd only fills in the time of dispatched values.
+    pub fn write_to(&self, plugin: &str) -> Result<(), Error> {
+        let name = CString::new(plugin).map_err(|e| SubmitError::InvalidString("plugin", e))?;
+        let now = CdTime::from(SystemTime::now());
+        let res = self.with_list(now, |list| unsafe {
+            // collectd looks up the data set of the list's type when it isn't given
+            plugin_write(name.as_ptr(), ptr::null(), list)
+        });
+
+        match res? {
+            0 => Ok(()),
+            i => Err(SubmitError::WriteError(String::from(plugin), i).into()),
+        }
+    }
+
+    /// Converts the builder into collectd's value list, which borrows the values for the duration
+    /// of the closure
+    fn with_list<F>(&self, default_time: CdTime, f: F) -> Result<c_int, Error>
+    where
+        F: FnOnce(&value_list_t) -> c_int,
+    {
+        let mut v: SmallVec<[value_t; INLINE_VALUES]> =
+            self.list.values.iter().map(|&x| x.into()).collect();
         let plugin_instance = self.list
             .plugin_instance
-            .map(|x| to_array_res(x).context("plugin_instance"))
-            .unwrap_or_else(|| Ok([0i8; ARR_LENGTH]))?;
+            .map(|x| to_array_res(x).map_err(|e| SubmitError::field("plugin_instance", e)))
+            .unwrap_or_else(|| Ok([0; ARR_LENGTH]))?;
 
         let type_instance = self.list
             .type_instance
-            .map(|x| to_array_res(x).context("type_instance"))
-            .unwrap_or_else(|| Ok([0i8; ARR_LENGTH]))?;
+            .map(|x| to_array_res(x).map_err(|e| SubmitError::field("type_instance", e)))
+            .unwrap_or_else(|| Ok([0; ARR_LENGTH]))?;
 
         // In collectd 5.7, it is no longer required to supply hostname_g for default hostname,
         // an empty array will get replaced with the hostname. However, since we're collectd 5.5
         // compatible, we use hostname_g in both circumstances, as it is not harmful
         let host = self.list
             .host
-            .map(|x| to_array_res(x).context("host"))
+            .map(|x| to_array_res(x).map_err(|e| SubmitError::field("host", e)))
             .unwrap_or_else(|| unsafe { Ok(hostname_g) })?;
 
-        #[cfg(feature = "collectd-57")]
+        #[cfg(collectd_57)]
         let len = v.len();
 
-        #[cfg(not(feature = "collectd-57"))]
+        #[cfg(not(collectd_57))]
         let len = v.len() as i32;
 
         let list = value_list_t {
             values: v.as_mut_ptr(),
             values_len: len,
             plugin_instance: plugin_instance,
-            plugin: to_array_res(self.list.plugin)?,
-            type_: to_array_res(self.list.type_)?,
+            plugin: to_array_res(self.list.plugin).map_err(|e| SubmitError::field("plugin", e))?,
+            type_: to_array_res(self.list.type_).map_err(|e| SubmitError::field("type", e))?,
             type_instance: type_instance,
             host: host,
-            time: self.list.time.map(CdTime::from).unwrap_or(CdTime(0)).into(),
+            time: self.list.time.unwrap_or(default_time).into(),
             interval: self.list
                 .interval
-                .map(CdTime::from)
-                .unwrap_or(CdTime(0))
-                .into(),
+                .map(|x| x.into())
+                .unwrap_or_else(|| unsafe { plugin_get_interval() }),
             meta: ptr::null_mut(),
         };
 
-        match unsafe { plugin_dispatch_values(&list) } {
-            0 => Ok(()),
-            i => Err(SubmitError::DispatchError(i).into()),
-        }
+        Ok(f(&list))
+    }
+}
+
+/// Submits a single gauge value, which is shorthand for a `ValueListBuilder` with one value
+///
+/// # Examples
+///
+/// ```no_run
+/// # use collectd_plugin::submit_gauge;
+/// submit_gauge("myplugin", "temperature", Some("cpu0"), 45.5).unwrap();
+/// ```
+pub fn submit_gauge(
+    plugin: &str,
+    type_: &str,
+    type_instance: Option<&str>,
+    value: f64,
+) -> Result<(), Error> {
+    submit_one(plugin, type_, type_instance, Value::Gauge(value))
+}
+
+/// Submits a single derive value, which is shorthand for a `ValueListBuilder` with one value
+pub fn submit_derive(
+    plugin: &str,
+    type_: &str,
+    type_instance: Option<&str>,
+    value: i64,
+) -> Result<(), Error> {
+    submit_one(plugin, type_, type_instance, Value::Derive(value))
+}
+
+fn submit_one(
+    plugin: &str,
+    type_: &str,
+    type_instance: Option<&str>,
+    value: Value,
+) -> Result<(), Error> {
+    let values = [value];
+    let builder = ValueListBuilder::new(plugin, type_).values(&values);
+    match type_instance {
+        Some(x) => builder.type_instance(x).submit(),
+        None => builder.submit(),
     }
 }
 
 /// Collectd stores textual data in fixed sized arrays, so this function will convert a string
 /// slice into array compatible with collectd's text fields. Be aware that `ARR_LENGTH` is 64
 /// before collectd 5.7
-fn to_array_res(s: &str) -> Result<[c_char; ARR_LENGTH], ArrayError> {
+pub fn to_array_res(s: &str) -> Result<[c_char; ARR_LENGTH], ArrayError> {
     let value = CString::new(s)?;
     let data = value.as_bytes_with_nul();
     if data.len() > ARR_LENGTH {
@@ -321,10 +722,8 @@ fn to_array_res(s: &str) -> Result<[c_char; ARR_LENGTH], ArrayError> {
 /// assert_eq!(Ok("hi"), from_array(&name));
 /// ```
 pub fn from_array(s: &[c_char; ARR_LENGTH]) -> Result<&str, Utf8Error> {
-    unsafe {
-        let a = s as *const [i8; ARR_LENGTH] as *const i8;
-        CStr::from_ptr(a).to_str()
-    }
+    // `c_char` is unsigned on ARM, so don't assume it's an i8
+    unsafe { CStr::from_ptr(s.as_ptr()).to_str() }
 }
 
 /// Returns if the string is empty or not
@@ -348,34 +747,123 @@ pub fn empty_to_none(s: &str) -> Option<&str> {
 }
 
 /// Sends message and log level to collectd. Collectd configuration determines if a level is logged
-/// and where it is delivered.
-///
-/// # Panics
-///
-/// If a message containing a null character is given as a message this function will panic.
+/// and where it is delivered. Null characters, which would cut the message short, are stripped
+/// (eg: from a plugin name included in the message).
 pub fn collectd_log(lvl: LogLevel, message: &str) {
-    let cs = CString::new(message).expect("Collectd log to not contain nulls");
+    let cs = match CString::new(message) {
+        Ok(x) => x,
+        Err(_) => CString::new(message.replace('\0', "")).unwrap_or_default(),
+    };
     unsafe {
-        plugin_log(lvl as i32, cs.as_ptr());
+        plugin_log(lvl.as_raw() as i32, cs.as_ptr());
     }
 }
 
-#[cfg(feature = "collectd-57")]
+// The plugin manager's context, which is recorded on `module_register`. As each plugin is
+// compiled into its own shared library, there is only ever one context per copy of this crate.
+static PLUGIN_CONTEXT: OnceLock<PluginContext> = OnceLock::new();
+
+/// Values that are computed once when collectd loads the plugin, so that callbacks needn't
+/// recompute them
+#[derive(Debug)]
+pub struct PluginContext {
+    name: &'static str,
+    c_name: CString,
+
+    /// collectd's global interval as a raw `cdtime_t`, or zero if the plugin isn't initialized
+    interval: AtomicU64,
+}
+
+impl PluginContext {
+    /// The name the plugin manager registered with collectd
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The name as a C string, for passing to collectd's registration functions
+    pub fn c_name(&self) -> &CStr {
+        &self.c_name
+    }
+
+    /// collectd's global `Interval`, which is only known once the configuration has been read
+    /// (ie: at init), so this is `None` when called any earlier
+    pub fn interval(&self) -> Option<Duration> {
+        match self.interval.load(Ordering::Acquire) {
+            0 => None,
+            x => Some(CdTime::from(x).into()),
+        }
+    }
+
+    /// The interval that collectd attributes to values dispatched from the current thread: the
+    /// interval of the callback's context, falling back to the global interval
+    pub fn effective_interval(&self) -> Option<Duration> {
+        ThreadContext::current().interval().or_else(|| self.interval())
+    }
+}
+
+#[doc(hidden)]
+pub fn register_plugin_name(name: &'static str) -> Result<(), NulError> {
+    let context = PluginContext {
+        name: name,
+        c_name: CString::new(name)?,
+        interval: AtomicU64::new(0),
+    };
+
+    // collectd only loads the plugin once, so should `module_register` be invoked again (eg: from
+    // tests) the first context is kept
+    let _ = PLUGIN_CONTEXT.set(context);
+    Ok(())
+}
+
+/// Records collectd's global interval in the plugin's context
+#[doc(hidden)]
+pub fn register_default_interval() {
+    if let Some(context) = plugin_context() {
+        context
+            .interval
+            .store(unsafe { interval_g }, Ordering::Release);
+    }
+}
+
+/// The context of the plugin that was registered with collectd, if registration has occurred
+pub fn plugin_context() -> Option<&'static PluginContext> {
+    PLUGIN_CONTEXT.get()
+}
+
+/// The name of the plugin that was registered with collectd, if registration has occurred
+pub fn plugin_name() -> Option<&'static str> {
+    plugin_context().map(|x| x.name)
+}
+
+/// Formats the message and logs it at the given level, prefixed by the plugin's name. Like
+/// `collectd_log`, null characters are stripped. Prefer the `collectd_error!`,
+/// `collectd_warning!`, `collectd_notice!`, `collectd_info!`, and `collectd_debug!` macros over
+/// calling this directly.
+pub fn collectd_log_args(lvl: LogLevel, args: fmt::Arguments) {
+    let msg = match plugin_name() {
+        Some(name) => format!("{}: {}", name, args),
+        None => fmt::format(args),
+    };
+
+    collectd_log(lvl, &msg);
+}
+
+#[cfg(collectd_57)]
 pub fn length(len: usize) -> usize {
     len
 }
 
-#[cfg(not(feature = "collectd-57"))]
+#[cfg(not(collectd_57))]
 pub fn length(len: i32) -> usize {
     len as usize
 }
 
-#[cfg(feature = "collectd-57")]
+#[cfg(collectd_57)]
 pub fn get_default_interval() -> u64 {
     0
 }
 
-#[cfg(not(feature = "collectd-57"))]
+#[cfg(not(collectd_57))]
 pub fn get_default_interval<T>() -> *const T {
     use std::ptr;
     ptr::null()
@@ -388,6 +876,55 @@ mod tests {
     use bindings::data_source_t;
     use self::cdtime::nanos_to_collectd;
 
+    #[test]
+    fn test_log_level_ordering() {
+        assert!(LogLevel::Error < LogLevel::Warning);
+        assert!(LogLevel::Notice < LogLevel::Info);
+        assert!(LogLevel::Info < LogLevel::Debug);
+        assert!(LogLevel::Debug < LogLevel::Unknown(0));
+    }
+
+    #[test]
+    fn test_log_level_from_raw() {
+        assert_eq!(LogLevel::try_from(LOG_ERR), Ok(LogLevel::Error));
+        assert_eq!(LogLevel::try_from(LOG_DEBUG), Ok(LogLevel::Debug));
+        assert_eq!(LogLevel::try_from(42), Err(UnknownLogLevel(String::from("42"))));
+        assert_eq!(LogLevel::Warning.as_raw(), LOG_WARNING);
+        assert_eq!(LogLevel::Unknown(42).as_raw(), 42);
+        assert_eq!(LogLevel::Unknown(42).to_string(), "unknown");
+    }
+
+    #[test]
+    fn test_log_level_from_str() {
+        assert_eq!("info".parse::<LogLevel>().unwrap(), LogLevel::Info);
+        assert_eq!("Debug".parse::<LogLevel>().unwrap(), LogLevel::Debug);
+        assert_eq!("err".parse::<LogLevel>().unwrap(), LogLevel::Error);
+        assert_eq!("WARNING".parse::<LogLevel>().unwrap(), LogLevel::Warning);
+        assert!("verbose".parse::<LogLevel>().is_err());
+    }
+
+    #[test]
+    fn test_log_filter() {
+        let filter: LogFilter = "notice".parse().unwrap();
+        assert!(filter.allows(LogLevel::Error));
+        assert!(filter.allows(LogLevel::Notice));
+        assert!(!filter.allows(LogLevel::Info));
+        assert!(!filter.allows(LogLevel::Debug));
+        assert_eq!(LogFilter::default().threshold(), LogLevel::Info);
+    }
+
+    #[test]
+    fn test_write_filter() {
+        let filter = WriteFilter::new();
+        assert!(filter.allows("cpu", "percent"));
+
+        let filter = WriteFilter::new().plugin("cpu").plugin("memory").type_("percent");
+        assert!(filter.allows("cpu", "percent"));
+        assert!(filter.allows("memory", "percent"));
+        assert!(!filter.allows("memory", "bytes"));
+        assert!(!filter.allows("df", "percent"));
+    }
+
     #[test]
     fn test_to_array() {
         let actual = to_array_res("Hi");
@@ -401,6 +938,24 @@ mod tests {
         assert!(actual.is_err());
     }
 
+    #[test]
+    fn test_submit_invalid_string() {
+        let values = [Value::Gauge(1.0)];
+        let err = ValueListBuilder::new("my\0plugin", "gauge")
+            .values(&values)
+            .submit()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "plugin contains a null character: nul byte found in provided data at position: 2"
+        );
+
+        ::stub::reset();
+        collectd_log(LogLevel::Info, "my\0plugin: hello");
+        assert_eq!(::stub::logged()[0].message, "myplugin: hello");
+        ::stub::reset();
+    }
+
     #[test]
     fn test_to_array_res_too_long() {
         let actual = to_array_res(
@@ -422,7 +977,7 @@ mod tests {
 
         let val = data_source_t {
             name: name,
-            type_: DS_TYPE_GAUGE as i32,
+            type_: ::constants::DS_TYPE_GAUGE,
             min: 10.0,
             max: 11.0,
         };
@@ -450,11 +1005,18 @@ mod tests {
             meta: ptr::null_mut(),
         };
 
+        let unset = value_list_t { time: 0, ..list_t };
+        let err = RecvValueList::from(&conv, &unset).unwrap_err();
+        assert_eq!(err.to_string(), "For plugin: hi, value list has no time");
+        let unset = value_list_t { interval: 0, ..list_t };
+        let err = RecvValueList::from(&conv, &unset).unwrap_err();
+        assert_eq!(err.to_string(), "For plugin: hi, value list has no interval");
+
         let actual = RecvValueList::from(&conv, &list_t).unwrap();
         assert_eq!(
             actual,
             RecvValueList {
-                values: vec![
+                values: smallvec![
                     ValueReport {
                         name: "hi",
                         value: Value::Gauge(3.0),
@@ -467,9 +1029,40 @@ mod tests {
                 type_: "ho",
                 type_instance: None,
                 host: "ho",
-                time: Utc.ymd(1970, 1, 1).and_hms(0, 0, 1),
-                interval: Duration::seconds(1),
+                time: ::std::time::UNIX_EPOCH + Duration::from_secs(1),
+                interval: Duration::from_secs(1),
             }
         );
+
+        // The values were collected in 1970, so they are long gone
+        assert!(actual.age() > Duration::from_secs(1_000_000_000));
+        assert!(actual.is_missing(2));
+        #[cfg(feature = "chrono")]
+        assert_eq!(actual.datetime().timestamp(), 1);
+        let fresh = RecvValueList {
+            time: SystemTime::now() + Duration::from_secs(60),
+            ..actual.clone()
+        };
+        assert_eq!(fresh.age(), Duration::from_secs(0));
+        assert!(!fresh.is_missing(2));
+
+        // The strings are only copied when asked to be
+        assert_eq!(actual.plugin.as_ptr(), list_t.plugin.as_ptr() as *const u8);
+        assert!(!actual.values.spilled());
+        assert_eq!(actual.values[0].data_source_type(), DataSourceType::Gauge);
+        assert!(actual.values[0].in_range(10.5));
+        assert!(!actual.values[0].in_range(3.0));
+        assert!(!actual.values[0].in_range(11.5));
+
+        let unbounded = ValueReport {
+            min: f64::NAN,
+            max: f64::NAN,
+            ..actual.values[0]
+        };
+        assert!(unbounded.in_range(-1e9));
+        let owned = actual.to_owned();
+        assert_eq!(owned.values[0].name, "hi");
+        assert_eq!(owned.plugin_instance, Some(String::from("ho")));
+        assert_eq!(owned.as_recv(), actual);
     }
 }
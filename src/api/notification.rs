@@ -0,0 +1,427 @@
+use bindings::{hostname_g, notification_meta_t, notification_t, plugin_dispatch_notification,
+               plugin_notification_meta_add_boolean, plugin_notification_meta_add_double,
+               plugin_notification_meta_add_signed_int, plugin_notification_meta_add_string,
+               plugin_notification_meta_add_unsigned_int, plugin_notification_meta_free,
+               ARR_LENGTH};
+use constants::{Severity, NM_TYPE_BOOLEAN, NM_TYPE_DOUBLE, NM_TYPE_SIGNED_INT, NM_TYPE_STRING,
+                NM_TYPE_UNSIGNED_INT, NOTIF_MAX_MSG_LEN};
+use errors::{Error, MetaDataError, ReceiveError, SubmitError};
+use identifier::Identifier;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::time::SystemTime;
+use super::{empty_to_none, from_array, to_array_res, CdTime, MetaValue, RecvValueList};
+
+/// A notification received from collectd
+#[derive(Debug, PartialEq, Clone)]
+pub struct RecvNotification<'a> {
+    pub severity: Severity,
+    pub time: SystemTime,
+    pub message: &'a str,
+    pub host: &'a str,
+    pub plugin: &'a str,
+    pub plugin_instance: Option<&'a str>,
+    pub type_: Option<&'a str>,
+    pub type_instance: Option<&'a str>,
+
+    /// Typed key-value pairs attached to the notification (eg: a runbook URL). Should a key
+    /// appear more than once, the last value is kept.
+    pub meta: HashMap<String, MetaValue>,
+}
+
+impl<'a> RecvNotification<'a> {
+    pub fn from<'b>(n: &'b notification_t) -> Result<RecvNotification<'b>, ReceiveError> {
+        let field = |arr: &'b [c_char; ARR_LENGTH], name: &str| {
+            from_array(arr).map_err(|e| ReceiveError::Utf8(format!("Notification {}", name), e))
+        };
+
+        let severity = Severity::from_raw(n.severity)
+            .ok_or_else(|| ReceiveError::UnknownSeverity(n.severity))?;
+        let message = unsafe { CStr::from_ptr(n.message.as_ptr()) }
+            .to_str()
+            .map_err(|e| ReceiveError::Utf8(String::from("Notification message"), e))?;
+
+        Ok(RecvNotification {
+            severity: severity,
+            time: CdTime::from(n.time).into(),
+            message: message,
+            host: field(&n.host, "host")?,
+            plugin: field(&n.plugin, "plugin")?,
+            plugin_instance: empty_to_none(field(&n.plugin_instance, "plugin instance")?),
+            type_: empty_to_none(field(&n.type_, "type")?),
+            type_instance: empty_to_none(field(&n.type_instance, "type instance")?),
+            meta: unsafe { read_meta(n.meta)? },
+        })
+    }
+}
+
+/// Walks collectd's linked list of notification metadata
+unsafe fn read_meta(
+    mut meta: *const notification_meta_t,
+) -> Result<HashMap<String, MetaValue>, ReceiveError> {
+    let mut result = HashMap::new();
+    while let Some(entry) = meta.as_ref() {
+        let key = String::from(
+            from_array(&entry.name)
+                .map_err(|e| ReceiveError::Utf8(String::from("Notification meta key"), e))?,
+        );
+
+        let value = match entry.type_ as c_int {
+            NM_TYPE_STRING => {
+                let s = CStr::from_ptr(entry.nm_value.nm_string).to_str().map_err(|e| {
+                    ReceiveError::Utf8(format!("Notification meta value of {}", key), e)
+                })?;
+                MetaValue::String(String::from(s))
+            }
+            NM_TYPE_SIGNED_INT => MetaValue::SignedInt(entry.nm_value.nm_signed_int),
+            NM_TYPE_UNSIGNED_INT => MetaValue::UnsignedInt(entry.nm_value.nm_unsigned_int),
+            NM_TYPE_DOUBLE => MetaValue::Double(entry.nm_value.nm_double),
+            NM_TYPE_BOOLEAN => MetaValue::Boolean(entry.nm_value.nm_boolean),
+            t => return Err(MetaDataError::UnknownType(key, t).into()),
+        };
+
+        result.insert(key, value);
+        meta = entry.next;
+    }
+
+    Ok(result)
+}
+
+/// Attaches metadata to the notification with the `plugin_notification_meta_add_*` functions
+unsafe fn add_meta(n: &mut notification_t, key: &str, value: &MetaValue) -> Result<(), Error> {
+    let k = CString::new(key).map_err(MetaDataError::from)?;
+    let (func, res) = match *value {
+        MetaValue::String(ref x) => {
+            let v = CString::new(x.as_str()).map_err(MetaDataError::from)?;
+            let res = plugin_notification_meta_add_string(n, k.as_ptr(), v.as_ptr());
+            ("add_string", res)
+        }
+        MetaValue::SignedInt(x) => (
+            "add_signed_int",
+            plugin_notification_meta_add_signed_int(n, k.as_ptr(), x),
+        ),
+        MetaValue::UnsignedInt(x) => (
+            "add_unsigned_int",
+            plugin_notification_meta_add_unsigned_int(n, k.as_ptr(), x),
+        ),
+        MetaValue::Double(x) => (
+            "add_double",
+            plugin_notification_meta_add_double(n, k.as_ptr(), x),
+        ),
+        MetaValue::Boolean(x) => (
+            "add_boolean",
+            plugin_notification_meta_add_boolean(n, k.as_ptr(), x),
+        ),
+    };
+
+    match res {
+        0 => Ok(()),
+        code => Err(MetaDataError::Call(func, String::from(key), code).into()),
+    }
+}
+
+/// Builds a notification (eg: a threshold being crossed) to dispatch to collectd, which passes it
+/// to every plugin with a notification callback
+#[derive(Debug, PartialEq, Clone)]
+pub struct NotificationBuilder<'a> {
+    severity: Severity,
+    message: &'a str,
+    plugin: &'a str,
+    plugin_instance: Option<&'a str>,
+    type_: Option<&'a str>,
+    type_instance: Option<&'a str>,
+    host: Option<&'a str>,
+    time: Option<CdTime>,
+    meta: Vec<(&'a str, MetaValue)>,
+}
+
+impl<'a> NotificationBuilder<'a> {
+    pub fn new<T: Into<&'a str>>(plugin: T, severity: Severity, message: &'a str) -> Self {
+        NotificationBuilder {
+            severity: severity,
+            message: message,
+            plugin: plugin.into(),
+            plugin_instance: None,
+            type_: None,
+            type_instance: None,
+            host: None,
+            time: None,
+            meta: Vec::new(),
+        }
+    }
+
+    /// A failure notification (`NOTIF_FAILURE`)
+    pub fn failure<T: Into<&'a str>>(plugin: T, message: &'a str) -> Self {
+        NotificationBuilder::new(plugin, Severity::Failure, message)
+    }
+
+    /// A warning notification (`NOTIF_WARNING`)
+    pub fn warning<T: Into<&'a str>>(plugin: T, message: &'a str) -> Self {
+        NotificationBuilder::new(plugin, Severity::Warning, message)
+    }
+
+    /// A notification that a previous warning or failure has cleared (`NOTIF_OKAY`)
+    pub fn okay<T: Into<&'a str>>(plugin: T, message: &'a str) -> Self {
+        NotificationBuilder::new(plugin, Severity::Okay, message)
+    }
+
+    /// A notification about the value list that triggered it, which carries the list's host,
+    /// plugin, type, instances, and time
+    pub fn for_list(list: &RecvValueList<'a>, severity: Severity, message: &'a str) -> Self {
+        NotificationBuilder {
+            plugin_instance: list.plugin_instance,
+            type_: Some(list.type_),
+            type_instance: list.type_instance,
+            host: Some(list.host),
+            time: Some(CdTime::from(list.time)),
+            ..NotificationBuilder::new(list.plugin, severity, message)
+        }
+    }
+
+    pub fn plugin_instance<T: Into<&'a str>>(mut self, plugin_instance: T) -> Self {
+        self.plugin_instance = Some(plugin_instance.into());
+        self
+    }
+
+    pub fn type_<T: Into<&'a str>>(mut self, type_: T) -> Self {
+        self.type_ = Some(type_.into());
+        self
+    }
+
+    pub fn type_instance<T: Into<&'a str>>(mut self, type_instance: T) -> Self {
+        self.type_instance = Some(type_instance.into());
+        self
+    }
+
+    /// Overrides the machine's hostname that the notification is about
+    pub fn host<T: Into<&'a str>>(mut self, host: T) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// When the notification occurred, defaulting to when it is dispatched
+    pub fn time<T: Into<CdTime>>(mut self, dt: T) -> Self {
+        self.time = Some(dt.into());
+        self
+    }
+
+    /// Attaches a typed key-value pair, which collectd passes along to notification plugins
+    pub fn meta<T: Into<MetaValue>>(mut self, key: &'a str, value: T) -> Self {
+        self.meta.push((key, value.into()));
+        self
+    }
+
+    /// Dispatches the notification to collectd. Messages longer than collectd allows are
+    /// truncated, as collectd does for its own notifications.
+    pub fn dispatch(self) -> Result<(), Error> {
+        let field = |name: &'static str, value: Option<&str>| match value {
+            Some(x) => to_array_res(x).map_err(|e| SubmitError::field(name, e)),
+            None => Ok([0; ARR_LENGTH]),
+        };
+
+        let host = match self.host {
+            Some(_) => field("host", self.host)?,
+            None => unsafe { hostname_g },
+        };
+
+        let mut n = notification_t {
+            severity: self.severity.as_raw(),
+            time: self.time
+                .unwrap_or_else(|| CdTime::from(SystemTime::now()))
+                .into(),
+            message: message_array(self.message),
+            host: host,
+            plugin: field("plugin", Some(self.plugin))?,
+            plugin_instance: field("plugin_instance", self.plugin_instance)?,
+            type_: field("type", self.type_)?,
+            type_instance: field("type_instance", self.type_instance)?,
+            meta: ptr::null_mut(),
+        };
+
+        // The metadata is allocated by collectd and must be freed whether or not it's dispatched
+        let res = self.meta
+            .iter()
+            .map(|&(key, ref value)| unsafe { add_meta(&mut n, key, value) })
+            .collect::<Result<Vec<()>, Error>>()
+            .map(|_| unsafe { plugin_dispatch_notification(&n) });
+        if !n.meta.is_null() {
+            unsafe { plugin_notification_meta_free(n.meta) };
+        }
+
+        match res? {
+            0 => Ok(()),
+            i => Err(SubmitError::NotificationError(i).into()),
+        }
+    }
+}
+
+/// Remembers the severity of every value list that a plugin checks, so that a notification is
+/// only dispatched when the severity changes: when an alert is raised, escalated, or cleared.
+/// Value lists that are okay when first seen don't need a notification.
+#[derive(Debug, Default, Clone)]
+pub struct AlertTracker {
+    states: HashMap<Identifier, Severity>,
+}
+
+impl AlertTracker {
+    pub fn new() -> Self {
+        AlertTracker::default()
+    }
+
+    /// Records the severity of the value list, returning the notification to dispatch if it
+    /// differs from the list's previous severity
+    pub fn update<'b>(
+        &mut self,
+        list: &RecvValueList<'b>,
+        severity: Severity,
+        message: &'b str,
+    ) -> Option<NotificationBuilder<'b>> {
+        let previous = self.states.insert(Identifier::from(list), severity);
+        match (previous, severity) {
+            (None, Severity::Okay) => None,
+            (Some(x), _) if x == severity => None,
+            _ => Some(NotificationBuilder::for_list(list, severity, message)),
+        }
+    }
+
+    /// The last severity recorded for the identifier
+    pub fn severity(&self, id: &Identifier) -> Option<Severity> {
+        self.states.get(id).cloned()
+    }
+
+    /// Forgets the severity of the identifier (eg: the device it belonged to went away)
+    pub fn remove(&mut self, id: &Identifier) -> Option<Severity> {
+        self.states.remove(id)
+    }
+}
+
+/// Copies the message into collectd's message field, truncating it at a character boundary and
+/// dropping anything after an interior nul
+fn message_array(message: &str) -> [c_char; NOTIF_MAX_MSG_LEN] {
+    let message = message.split('\0').next().unwrap_or("");
+    let mut end = message.len().min(NOTIF_MAX_MSG_LEN - 1);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let mut arr = [0; NOTIF_MAX_MSG_LEN];
+    for (dst, &src) in arr.iter_mut().zip(message[..end].as_bytes()) {
+        *dst = src as c_char;
+    }
+    arr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{Value, ValueReport};
+    use std::time::{Duration, UNIX_EPOCH};
+    use stub;
+
+    #[test]
+    fn test_dispatch() {
+        stub::reset();
+        NotificationBuilder::new("cpu", Severity::Warning, "cpu is busy")
+            .plugin_instance("0")
+            .type_("percent")
+            .host("web-1")
+            .time(CdTime(1_500_000_000_000_000_000))
+            .meta("runbook", "https://wiki/cpu")
+            .meta("cores", 8u64)
+            .meta("load", MetaValue::Double(1.5))
+            .meta("paged", true)
+            .dispatch()
+            .unwrap();
+
+        let long = "é".repeat(200);
+        NotificationBuilder::new("cpu", Severity::Okay, &long)
+            .dispatch()
+            .unwrap();
+
+        let notifs = stub::notifications();
+        assert_eq!(notifs.len(), 2);
+        assert_eq!(notifs[0].severity, Severity::Warning);
+        assert_eq!(notifs[0].message, "cpu is busy");
+        assert_eq!(notifs[0].plugin_instance, Some(String::from("0")));
+        assert_eq!(notifs[0].type_, Some(String::from("percent")));
+        assert_eq!(notifs[0].type_instance, None);
+        assert_eq!(notifs[0].host, Some(String::from("web-1")));
+        assert_eq!(CdTime::from(notifs[0].time), CdTime(1_500_000_000_000_000_000));
+        assert_eq!(notifs[1].message, "é".repeat(127));
+        assert!(notifs[1].meta.is_empty());
+
+        let meta = &notifs[0].meta;
+        assert_eq!(meta.len(), 4);
+        assert_eq!(
+            meta.get("runbook"),
+            Some(&MetaValue::String(String::from("https://wiki/cpu")))
+        );
+        assert_eq!(meta.get("cores"), Some(&MetaValue::UnsignedInt(8)));
+        assert_eq!(meta.get("load"), Some(&MetaValue::Double(1.5)));
+        assert_eq!(meta.get("paged"), Some(&MetaValue::Boolean(true)));
+    }
+
+    #[test]
+    fn test_alert_transitions() {
+        stub::reset();
+        let list = |cpu: &'static str, secs: u64| RecvValueList {
+            values: smallvec![ValueReport {
+                name: "value",
+                value: Value::Gauge(99.0),
+                min: 0.0,
+                max: 100.0,
+            }],
+            plugin_instance: Some(cpu),
+            plugin: "cpu",
+            type_: "percent",
+            type_instance: Some("busy"),
+            host: "web-1",
+            time: UNIX_EPOCH + Duration::from_secs(secs),
+            interval: Duration::from_secs(10),
+        };
+
+        let mut alerts = AlertTracker::new();
+        let mut check = |cpu, secs, severity| {
+            if let Some(n) = alerts.update(&list(cpu, secs), severity, "cpu changed state") {
+                n.dispatch().unwrap();
+            }
+        };
+
+        check("0", 10, Severity::Okay);
+        check("0", 20, Severity::Warning);
+        check("0", 30, Severity::Warning);
+        check("1", 30, Severity::Failure);
+        check("0", 40, Severity::Failure);
+        check("0", 50, Severity::Okay);
+        check("0", 60, Severity::Okay);
+
+        let notifs = stub::notifications();
+        let seen: Vec<(Option<String>, Severity, u64)> = notifs
+            .iter()
+            .map(|n| {
+                let time: SystemTime = CdTime::from(n.time).into();
+                let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+                (n.plugin_instance.clone(), n.severity, secs)
+            })
+            .collect();
+        assert_eq!(
+            seen,
+            vec![
+                (Some(String::from("0")), Severity::Warning, 20),
+                (Some(String::from("1")), Severity::Failure, 30),
+                (Some(String::from("0")), Severity::Failure, 40),
+                (Some(String::from("0")), Severity::Okay, 50),
+            ]
+        );
+        assert_eq!(notifs[0].plugin, "cpu");
+        assert_eq!(notifs[0].type_, Some(String::from("percent")));
+        assert_eq!(notifs[0].type_instance, Some(String::from("busy")));
+        assert_eq!(notifs[0].host, Some(String::from("web-1")));
+        assert_eq!(
+            alerts.severity(&"web-1/cpu-1/percent-busy".parse().unwrap()),
+            Some(Severity::Failure)
+        );
+    }
+}
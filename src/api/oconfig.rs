@@ -1,6 +1,6 @@
-use bindings::{oconfig_item_t, oconfig_value_t, oconfig_value_s__bindgen_ty_1,
-               OCONFIG_TYPE_BOOLEAN, OCONFIG_TYPE_NUMBER, OCONFIG_TYPE_STRING};
-use failure::{Error, ResultExt};
+use bindings::{oconfig_item_t, oconfig_value_t, oconfig_value_s__bindgen_ty_1};
+use constants::{OCONFIG_TYPE_BOOLEAN, OCONFIG_TYPE_NUMBER, OCONFIG_TYPE_STRING};
+use errors::ConfigError;
 use std::ffi::CStr;
 use std::slice;
 
@@ -19,43 +19,43 @@ pub struct ConfigItem<'a> {
 }
 
 impl<'a> ConfigValue<'a> {
-    pub unsafe fn from(value: &oconfig_value_t) -> Result<ConfigValue, Error> {
+    pub unsafe fn from(value: &oconfig_value_t) -> Result<ConfigValue, ConfigError> {
         match value.value {
             oconfig_value_s__bindgen_ty_1 { string }
-                if value.type_ == OCONFIG_TYPE_STRING as i32 =>
+                if value.type_ == OCONFIG_TYPE_STRING =>
             {
                 Ok(ConfigValue::String(CStr::from_ptr(string)
                     .to_str()
-                    .context("failed to decode config value string")?))
+                    .map_err(|e| ConfigError::Utf8("config value string", e))?))
             }
             oconfig_value_s__bindgen_ty_1 { number }
-                if value.type_ == OCONFIG_TYPE_NUMBER as i32 =>
+                if value.type_ == OCONFIG_TYPE_NUMBER =>
             {
                 Ok(ConfigValue::Number(number))
             }
             oconfig_value_s__bindgen_ty_1 { boolean }
-                if value.type_ == OCONFIG_TYPE_BOOLEAN as i32 =>
+                if value.type_ == OCONFIG_TYPE_BOOLEAN =>
             {
                 Ok(ConfigValue::Boolean(boolean != 0))
             }
-            _ => Err(format_err!("Unrecognized value: {}", value.type_)),
+            _ => Err(ConfigError::UnknownType(value.type_)),
         }
     }
 }
 
 impl<'a> ConfigItem<'a> {
-    pub unsafe fn from<'b>(item: &'b oconfig_item_t) -> Result<ConfigItem<'b>, Error> {
+    pub unsafe fn from<'b>(item: &'b oconfig_item_t) -> Result<ConfigItem<'b>, ConfigError> {
         let key = CStr::from_ptr(item.key)
             .to_str()
-            .context("failed to decode config item key")?;
+            .map_err(|e| ConfigError::Utf8("config item key", e))?;
 
-        let values: Result<Vec<ConfigValue<'b>>, Error> =
+        let values: Result<Vec<ConfigValue<'b>>, ConfigError> =
             slice::from_raw_parts(item.values, item.values_num as usize)
                 .iter()
                 .map(|x| ConfigValue::from(x))
                 .collect();
 
-        let children: Result<Vec<ConfigItem<'b>>, Error> =
+        let children: Result<Vec<ConfigItem<'b>>, ConfigError> =
             slice::from_raw_parts(item.children, item.children_num as usize)
                 .iter()
                 .map(|x| ConfigItem::from(x))
@@ -68,3 +68,184 @@ impl<'a> ConfigItem<'a> {
         })
     }
 }
+
+/// An owned counterpart to `ConfigValue` for when configuration needs to outlive the callback that
+/// it was received in (collectd frees the configuration tree once all config callbacks are done).
+#[derive(Debug, PartialEq, Clone)]
+pub enum OwnedConfigValue {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+}
+
+/// An owned counterpart to `ConfigItem`
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedConfigItem {
+    pub key: String,
+    pub values: Vec<OwnedConfigValue>,
+    pub children: Vec<OwnedConfigItem>,
+}
+
+impl<'a> ConfigValue<'a> {
+    /// Copies the value so that it no longer borrows from collectd
+    pub fn to_owned_value(&self) -> OwnedConfigValue {
+        match *self {
+            ConfigValue::Number(x) => OwnedConfigValue::Number(x),
+            ConfigValue::Boolean(x) => OwnedConfigValue::Boolean(x),
+            ConfigValue::String(x) => OwnedConfigValue::String(String::from(x)),
+        }
+    }
+}
+
+impl<'a> ConfigItem<'a> {
+    /// Copies the item and all of its children so that they no longer borrow from collectd
+    pub fn to_owned_item(&self) -> OwnedConfigItem {
+        OwnedConfigItem {
+            key: String::from(self.key),
+            values: self.values.iter().map(|x| x.to_owned_value()).collect(),
+            children: self.children.iter().map(|x| x.to_owned_item()).collect(),
+        }
+    }
+}
+
+impl OwnedConfigValue {
+    pub fn as_config_value(&self) -> ConfigValue {
+        match *self {
+            OwnedConfigValue::Number(x) => ConfigValue::Number(x),
+            OwnedConfigValue::Boolean(x) => ConfigValue::Boolean(x),
+            OwnedConfigValue::String(ref x) => ConfigValue::String(x.as_str()),
+        }
+    }
+}
+
+impl OwnedConfigItem {
+    pub fn as_config_item(&self) -> ConfigItem {
+        ConfigItem {
+            key: self.key.as_str(),
+            values: self.values.iter().map(|x| x.as_config_value()).collect(),
+            children: self.children.iter().map(|x| x.as_config_item()).collect(),
+        }
+    }
+}
+
+/// Collectd will invoke a plugin's config callback once for every `<Plugin>` block that matches
+/// the plugin's name. `ConfigAccumulator` gathers each of these blocks so that they can be handed
+/// to the plugin manager together at initialization instead of the last block winning.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ConfigAccumulator {
+    blocks: Vec<OwnedConfigItem>,
+}
+
+impl ConfigAccumulator {
+    pub fn new() -> Self {
+        ConfigAccumulator::default()
+    }
+
+    /// Records a `<Plugin>` block received from collectd
+    pub fn add(&mut self, block: &ConfigItem) {
+        self.blocks.push(block.to_owned_item());
+    }
+
+    /// Records an option received through collectd's simple config interface, which joins the
+    /// option's values into a single string (eg: `Port 80 443` is received as `80.000000
+    /// 443.000000`), as a block containing the option with the string as its value
+    pub fn add_option(&mut self, key: &str, value: &str) {
+        self.blocks.push(OwnedConfigItem {
+            key: String::from("Plugin"),
+            values: vec![],
+            children: vec![
+                OwnedConfigItem {
+                    key: String::from(key),
+                    values: vec![OwnedConfigValue::String(String::from(value))],
+                    children: vec![],
+                },
+            ],
+        });
+    }
+
+    /// Returns true if no blocks have been recorded
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// The recorded `<Plugin>` blocks in the order that collectd passed them
+    pub fn blocks(&self) -> &[OwnedConfigItem] {
+        &self.blocks[..]
+    }
+
+    /// Concatenates the children of every recorded block (in order) as if they had been written
+    /// in a single `<Plugin>` block
+    pub fn merged(&self) -> Vec<ConfigItem> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.children.iter().map(|x| x.as_config_item()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(key: &str, value: f64) -> OwnedConfigItem {
+        OwnedConfigItem {
+            key: String::from("Plugin"),
+            values: vec![OwnedConfigValue::String(String::from("myplugin"))],
+            children: vec![
+                OwnedConfigItem {
+                    key: String::from(key),
+                    values: vec![OwnedConfigValue::Number(value)],
+                    children: vec![],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_owned_round_trip() {
+        let item = ConfigItem {
+            key: "Node",
+            values: vec![ConfigValue::String("example"), ConfigValue::Boolean(true)],
+            children: vec![
+                ConfigItem {
+                    key: "Port",
+                    values: vec![ConfigValue::Number(2003.0)],
+                    children: vec![],
+                },
+            ],
+        };
+
+        let owned = item.to_owned_item();
+        assert_eq!(owned.key, "Node");
+        assert_eq!(owned.as_config_item(), item);
+    }
+
+    #[test]
+    fn test_accumulator_merges_blocks_in_order() {
+        let first = block("Port", 80.0);
+        let second = block("Timeout", 5.0);
+
+        let mut acc = ConfigAccumulator::new();
+        assert!(acc.is_empty());
+        acc.add(&first.as_config_item());
+        acc.add(&second.as_config_item());
+
+        assert_eq!(acc.blocks().len(), 2);
+        let merged = acc.merged();
+        assert_eq!(
+            merged,
+            vec![
+                ConfigItem {
+                    key: "Port",
+                    values: vec![ConfigValue::Number(80.0)],
+                    children: vec![],
+                },
+                ConfigItem {
+                    key: "Timeout",
+                    values: vec![ConfigValue::Number(5.0)],
+                    children: vec![],
+                },
+            ]
+        );
+    }
+}
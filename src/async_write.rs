@@ -0,0 +1,328 @@
+//! Write plugins for HTTP backends (Prometheus remote write, InfluxDB) are most naturally written
+//! against an async client, but collectd invokes write callbacks synchronously from its write
+//! threads. `AsyncWritePlugin` bridges the two: write callbacks queue a copy of each value list,
+//! and a dedicated thread that owns a tokio runtime batches the lists and awaits the plugin's
+//! `write_batch` future for each batch. A flush waits for every queued list to be written.
+
+use api::{OwnedRecvValueList, RecvValueList};
+use errors::{Error, ErrorChain};
+use identifier::Identifier;
+use plugins::{Plugin, PluginCapabilities};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Builder, Runtime};
+
+/// The future that writes a batch of value lists
+pub type BatchFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
+
+/// Writes batches of value lists asynchronously. Batches are written one at a time, in the order
+/// that collectd wrote the lists, so the next batch isn't started until the future of the last
+/// one completes.
+///
+/// ```ignore
+/// impl BatchWriter for InfluxWriter {
+///     fn write_batch(&mut self, batch: Vec<OwnedRecvValueList>) -> BatchFuture {
+///         let client = self.client.clone();
+///         Box::pin(async move { client.write(line_protocol(&batch)).await })
+///     }
+/// }
+/// ```
+pub trait BatchWriter: Send + 'static {
+    fn write_batch(&mut self, batch: Vec<OwnedRecvValueList>) -> BatchFuture;
+}
+
+enum Message {
+    List(OwnedRecvValueList),
+
+    /// Write the current batch and report how it went
+    Flush(mpsc::Sender<Result<(), Error>>),
+}
+
+/// Configures how value lists are queued and batched before spawning an `AsyncWritePlugin`
+#[derive(Debug, Clone)]
+pub struct AsyncWriteBuilder {
+    batch_size: usize,
+    batch_delay: Duration,
+    queue_capacity: usize,
+}
+
+impl Default for AsyncWriteBuilder {
+    fn default() -> Self {
+        AsyncWriteBuilder::new()
+    }
+}
+
+impl AsyncWriteBuilder {
+    /// Batches of up to 500 lists, written at least every second, from a queue of 10,000 lists
+    pub fn new() -> Self {
+        AsyncWriteBuilder {
+            batch_size: 500,
+            batch_delay: Duration::from_secs(1),
+            queue_capacity: 10_000,
+        }
+    }
+
+    /// The most lists written in a single batch
+    pub fn batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+
+    /// The longest that a list waits for its batch to fill up before the batch is written anyway
+    pub fn batch_delay(mut self, delay: Duration) -> Self {
+        self.batch_delay = delay;
+        self
+    }
+
+    /// The number of lists that can wait to be batched. Once the queue is full (eg: the backend
+    /// is slower than collectd's write rate), write callbacks fail instead of blocking collectd's
+    /// write threads.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity.max(1);
+        self
+    }
+
+    /// Starts the runtime and its thread, which writes batches with the given writer
+    pub fn spawn<W: BatchWriter>(self, writer: W) -> io::Result<AsyncWritePlugin> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let (sender, receiver) = mpsc::sync_channel(self.queue_capacity);
+        let worker = Worker {
+            writer: writer,
+            runtime: runtime,
+            batch: Vec::new(),
+            batch_size: self.batch_size,
+            batch_delay: self.batch_delay,
+        };
+
+        let handle = thread::Builder::new()
+            .name(String::from("collectd-async-write"))
+            .spawn(move || worker.run(receiver))?;
+
+        Ok(AsyncWritePlugin {
+            sender: Some(sender),
+            worker: Some(handle),
+        })
+    }
+}
+
+/// A write plugin that hands value lists to a `BatchWriter` running on a tokio runtime. When the
+/// plugin is dropped at shutdown, the lists still queued are written before the runtime stops.
+pub struct AsyncWritePlugin {
+    sender: Option<SyncSender<Message>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl AsyncWritePlugin {
+    /// Spawns the plugin with the default batching
+    pub fn spawn<W: BatchWriter>(writer: W) -> io::Result<Self> {
+        AsyncWriteBuilder::new().spawn(writer)
+    }
+
+    fn sender(&self) -> Result<&SyncSender<Message>, Error> {
+        self.sender
+            .as_ref()
+            .ok_or_else(|| Error::from("async writer has stopped"))
+    }
+}
+
+impl Plugin for AsyncWritePlugin {
+    type Error = Error;
+
+    fn capabilities(&self) -> PluginCapabilities {
+        PluginCapabilities::WRITE | PluginCapabilities::FLUSH
+    }
+
+    fn write_values<'a>(&mut self, list: RecvValueList<'a>) -> Result<(), Error> {
+        match self.sender()?.try_send(Message::List(list.to_owned())) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(Error::from("write queue is full, dropping values")),
+            Err(TrySendError::Disconnected(_)) => Err(Error::from("async writer has stopped")),
+        }
+    }
+
+    /// Waits for every list queued so far to be written, returning the error of the last batch
+    fn flush(
+        &mut self,
+        _timeout: Option<Duration>,
+        _identifier: Option<Identifier>,
+    ) -> Result<(), Error> {
+        let (reply, done) = mpsc::channel();
+        self.sender()?
+            .send(Message::Flush(reply))
+            .map_err(|_| Error::from("async writer has stopped"))?;
+        done.recv()
+            .unwrap_or_else(|_| Err(Error::from("async writer has stopped")))
+    }
+}
+
+impl Drop for AsyncWritePlugin {
+    fn drop(&mut self) {
+        // Disconnecting the queue tells the worker to write what's left and exit
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+struct Worker<W> {
+    writer: W,
+    runtime: Runtime,
+    batch: Vec<OwnedRecvValueList>,
+    batch_size: usize,
+    batch_delay: Duration,
+}
+
+impl<W: BatchWriter> Worker<W> {
+    fn run(mut self, receiver: Receiver<Message>) {
+        // When the oldest list of the batch is due to be written
+        let mut deadline: Option<Instant> = None;
+        loop {
+            let message = match deadline {
+                Some(at) => receiver.recv_timeout(at.saturating_duration_since(Instant::now())),
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match message {
+                Ok(Message::List(list)) => {
+                    if self.batch.is_empty() {
+                        deadline = Some(Instant::now() + self.batch_delay);
+                    }
+
+                    self.batch.push(list);
+                    if self.batch.len() >= self.batch_size {
+                        self.write_logged();
+                        deadline = None;
+                    }
+                }
+                Ok(Message::Flush(reply)) => {
+                    let _ = reply.send(self.write());
+                    deadline = None;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.write_logged();
+                    deadline = None;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.write_logged();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) -> Result<(), Error> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        let batch = ::std::mem::replace(&mut self.batch, Vec::new());
+        self.runtime.block_on(self.writer.write_batch(batch))
+    }
+
+    /// Writes the batch when no flush is waiting on the outcome
+    fn write_logged(&mut self) {
+        let lists = self.batch.len();
+        if let Err(ref e) = self.write() {
+            collectd_error!("unable to write {} value lists: {}", lists, ErrorChain(&**e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{Value, ValueReport};
+    use std::future;
+    use std::sync::{Arc, Mutex};
+    use std::time::UNIX_EPOCH;
+    use stub;
+
+    struct Recorder {
+        batches: Arc<Mutex<Vec<Vec<String>>>>,
+        fail: bool,
+    }
+
+    impl BatchWriter for Recorder {
+        fn write_batch(&mut self, batch: Vec<OwnedRecvValueList>) -> BatchFuture {
+            let names = batch.into_iter().map(|x| x.type_instance.unwrap()).collect();
+            self.batches.lock().unwrap().push(names);
+            if self.fail {
+                Box::pin(future::ready(Err(Error::from("connection refused"))))
+            } else {
+                Box::pin(future::ready(Ok(())))
+            }
+        }
+    }
+
+    fn write(plugin: &mut AsyncWritePlugin, type_instance: &str) -> Result<(), Error> {
+        let list = RecvValueList {
+            values: smallvec![ValueReport {
+                name: "value",
+                value: Value::Gauge(1.0),
+                min: 0.0,
+                max: 100.0,
+            }],
+            plugin_instance: None,
+            plugin: "memory",
+            type_: "memory",
+            type_instance: Some(type_instance),
+            host: "localhost",
+            time: UNIX_EPOCH,
+            interval: Duration::from_secs(10),
+        };
+        plugin.write_values(list)
+    }
+
+    #[test]
+    fn test_batches_written() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let writer = Recorder {
+            batches: batches.clone(),
+            fail: false,
+        };
+        let mut plugin = AsyncWriteBuilder::new()
+            .batch_size(2)
+            .batch_delay(Duration::from_secs(3600))
+            .spawn(writer)
+            .unwrap();
+
+        for instance in &["used", "free", "cached"] {
+            write(&mut plugin, instance).unwrap();
+        }
+        plugin.flush(None, None).unwrap();
+        assert_eq!(
+            *batches.lock().unwrap(),
+            vec![vec!["used", "free"], vec!["cached"]]
+        );
+
+        // Dropping the plugin writes what is still queued
+        write(&mut plugin, "slab").unwrap();
+        drop(plugin);
+        assert_eq!(batches.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_flush_reports_errors() {
+        stub::reset();
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let writer = Recorder {
+            batches: batches.clone(),
+            fail: true,
+        };
+        let mut plugin = AsyncWritePlugin::spawn(writer).unwrap();
+
+        // Nothing is queued, so nothing is written
+        plugin.flush(None, None).unwrap();
+        assert!(batches.lock().unwrap().is_empty());
+
+        write(&mut plugin, "used").unwrap();
+        let err = plugin.flush(None, None).unwrap_err();
+        assert_eq!(err.to_string(), "connection refused");
+        stub::reset();
+    }
+}
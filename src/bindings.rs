@@ -4,6 +4,17 @@
 #![allow(dead_code)]
 #![cfg_attr(feature = "cargo-clippy", allow(clippy))]
 
+/// The collectd release that these bindings were generated against, as chosen by the
+/// `collectd-54`, `collectd-55`, or `collectd-57` features (defaulting to 5.7)
+#[cfg(collectd_54)]
+pub const COLLECTD_VERSION: &'static str = "5.4";
+
+#[cfg(collectd_55)]
+pub const COLLECTD_VERSION: &'static str = "5.5";
+
+#[cfg(collectd_57)]
+pub const COLLECTD_VERSION: &'static str = "5.7";
+
 // In collectd 5.7 the max length of textual information was extended to 128 characters from 64
 pub const ARR_LENGTH: usize = DATA_MAX_NAME_LEN as usize;
 
@@ -17,4 +28,114 @@ extern "C" {
     pub static mut hostname_g: [::std::os::raw::c_char; ARR_LENGTH];
 }
 
+// collectd's value cache is declared in utils_cache.h (not plugin.h), so its iterator, which
+// collectd 5.7 introduced, isn't part of the generated bindings
+#[cfg(collectd_57)]
+#[repr(C)]
+pub struct uc_iter_t {
+    _private: [u8; 0],
+}
+
+#[cfg(collectd_57)]
+extern "C" {
+    pub fn uc_get_iterator() -> *mut uc_iter_t;
+    pub fn uc_iterator_next(
+        iter: *mut uc_iter_t,
+        ret_name: *mut *mut ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+    pub fn uc_iterator_destroy(iter: *mut uc_iter_t);
+    pub fn uc_iterator_get_time(iter: *mut uc_iter_t, ret_time: *mut cdtime_t)
+        -> ::std::os::raw::c_int;
+    pub fn uc_iterator_get_interval(
+        iter: *mut uc_iter_t,
+        ret_interval: *mut cdtime_t,
+    ) -> ::std::os::raw::c_int;
+    pub fn uc_iterator_get_values(
+        iter: *mut uc_iter_t,
+        ret_values: *mut *mut value_t,
+        ret_num: *mut usize,
+    ) -> ::std::os::raw::c_int;
+}
+
+// The rates that the value cache computed for a value list (also from utils_cache.h), which are
+// returned as a malloc'd array of one gauge per data source, or null if the list isn't cached
+extern "C" {
+    pub fn uc_get_rate(ds: *const data_set_t, vl: *const value_list_t) -> *mut gauge_t;
+}
+
+// The values that the value cache holds for an identifier (eg: `host/plugin/type`), which are
+// copied into a malloc'd array
+extern "C" {
+    pub fn uc_get_value_by_name(
+        name: *const ::std::os::raw::c_char,
+        ret_values: *mut *mut value_t,
+        ret_values_num: *mut usize,
+    ) -> ::std::os::raw::c_int;
+}
+
+// collectd's filter chain (filter_chain.h) registers matches and targets with these callbacks,
+// whose `destroy` returns an int (unlike the destroy callbacks of plugin.h)
+pub const FC_MATCH_NO_MATCH: ::std::os::raw::c_int = 0;
+pub const FC_MATCH_MATCHES: ::std::os::raw::c_int = 1;
+pub const FC_TARGET_CONTINUE: ::std::os::raw::c_int = 0;
+pub const FC_TARGET_STOP: ::std::os::raw::c_int = 1;
+pub const FC_TARGET_RETURN: ::std::os::raw::c_int = 2;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct match_proc_s {
+    pub create: Option<
+        unsafe extern "C" fn(
+            ci: *const oconfig_item_t,
+            user_data: *mut *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub destroy: Option<
+        unsafe extern "C" fn(user_data: *mut *mut ::std::os::raw::c_void) -> ::std::os::raw::c_int,
+    >,
+    pub match_: Option<
+        unsafe extern "C" fn(
+            ds: *const data_set_t,
+            vl: *const value_list_t,
+            meta: *mut *mut notification_meta_t,
+            user_data: *mut *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+}
+pub type match_proc_t = match_proc_s;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct target_proc_s {
+    pub create: Option<
+        unsafe extern "C" fn(
+            ci: *const oconfig_item_t,
+            user_data: *mut *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub destroy: Option<
+        unsafe extern "C" fn(user_data: *mut *mut ::std::os::raw::c_void) -> ::std::os::raw::c_int,
+    >,
+    pub invoke: Option<
+        unsafe extern "C" fn(
+            ds: *const data_set_t,
+            vl: *mut value_list_t,
+            meta: *mut *mut notification_meta_t,
+            user_data: *mut *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+}
+pub type target_proc_t = target_proc_s;
+
+extern "C" {
+    pub fn fc_register_match(
+        name: *const ::std::os::raw::c_char,
+        proc_: match_proc_t,
+    ) -> ::std::os::raw::c_int;
+    pub fn fc_register_target(
+        name: *const ::std::os::raw::c_char,
+        proc_: target_proc_t,
+    ) -> ::std::os::raw::c_int;
+}
+
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
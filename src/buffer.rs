@@ -0,0 +1,244 @@
+//! Write plugins commonly buffer values in their write callback and send them in batches from
+//! their flush callback. collectd calls the write callback from each of its write threads, so a
+//! `Mutex<Vec<_>>` buffer is contended by every one of them. `WriteBuffer` is a lock-free
+//! alternative: writers push onto an atomic linked list and the flusher takes the entire list in
+//! a single swap.
+
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+struct Node<T> {
+    item: T,
+    next: *mut Node<T>,
+}
+
+/// A multi-producer buffer that is drained all at once, with triggers for when the buffer has
+/// grown large or old enough to be flushed
+pub struct WriteBuffer<T> {
+    head: AtomicPtr<Node<T>>,
+    len: AtomicUsize,
+
+    /// Nanoseconds since `created` of when the oldest buffered item was pushed, plus one so
+    /// that zero denotes an empty buffer
+    oldest: AtomicU64,
+    created: Instant,
+    max_len: usize,
+    max_age: Option<Duration>,
+}
+
+unsafe impl<T: Send> Send for WriteBuffer<T> {}
+unsafe impl<T: Send> Sync for WriteBuffer<T> {}
+
+impl<T> WriteBuffer<T> {
+    /// A buffer that is due to be flushed once it holds `max_len` items
+    pub fn new(max_len: usize) -> Self {
+        WriteBuffer {
+            head: AtomicPtr::new(ptr::null_mut()),
+            len: AtomicUsize::new(0),
+            oldest: AtomicU64::new(0),
+            created: Instant::now(),
+            max_len: max_len,
+            max_age: None,
+        }
+    }
+
+    /// The buffer is also due to be flushed once its oldest item has waited this long
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Adds an item to the buffer and returns if the buffer is now due to be flushed
+    pub fn push(&self, item: T) -> bool {
+        let node = Box::into_raw(Box::new(Node {
+            item: item,
+            next: ptr::null_mut(),
+        }));
+
+        // Counting before linking the node means a drain never sees more nodes than are counted
+        let len = self.len.fetch_add(1, Ordering::AcqRel) + 1;
+        let stamp = self.stamp(Instant::now());
+
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            unsafe { (*node).next = head };
+            match self.head
+                .compare_exchange_weak(head, node, Ordering::SeqCst, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(actual) => head = actual,
+            }
+        }
+
+        // Stamping after linking means a drain that resets the stamp either sees this node and
+        // re-stamps the buffer, or resets before this stamp lands
+        let _ = self.oldest
+            .compare_exchange(0, stamp, Ordering::SeqCst, Ordering::Acquire);
+
+        len >= self.max_len || self.aged(stamp)
+    }
+
+    /// Returns if the buffer is full or holds an item older than the max age. Call in a flush
+    /// callback or periodically to catch buffers that stopped receiving values.
+    pub fn is_due(&self) -> bool {
+        !self.is_empty()
+            && (self.len() >= self.max_len || self.aged(self.stamp(Instant::now())))
+    }
+
+    /// Takes every buffered item, in the order they were pushed
+    pub fn drain(&self) -> Vec<T> {
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::AcqRel);
+
+        // Items pushed since the swap stay buffered, so they need a stamp of their own
+        self.oldest.store(0, Ordering::SeqCst);
+        if !self.head.load(Ordering::SeqCst).is_null() {
+            let stamp = self.stamp(Instant::now());
+            let _ = self.oldest
+                .compare_exchange(0, stamp, Ordering::SeqCst, Ordering::Acquire);
+        }
+
+        let mut items = Vec::new();
+        while !node.is_null() {
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next;
+            items.push(boxed.item);
+        }
+
+        self.len.fetch_sub(items.len(), Ordering::AcqRel);
+        items.reverse();
+        items
+    }
+
+    /// Number of buffered items. With concurrent writers, this may count items that are about
+    /// to be buffered.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn stamp(&self, now: Instant) -> u64 {
+        let elapsed = now.duration_since(self.created);
+        elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos()) + 1
+    }
+
+    fn aged(&self, now: u64) -> bool {
+        let oldest = self.oldest.load(Ordering::Acquire);
+        match self.max_age {
+            Some(age) if oldest != 0 => {
+                let age = age.as_secs() * 1_000_000_000 + u64::from(age.subsec_nanos());
+                now.saturating_sub(oldest) >= age
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<T> Drop for WriteBuffer<T> {
+    fn drop(&mut self) {
+        self.drain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_drain_in_order() {
+        let buffer = WriteBuffer::new(3);
+        assert!(!buffer.push(1));
+        assert!(!buffer.push(2));
+        assert!(!buffer.is_due());
+        assert!(buffer.push(3));
+        assert!(buffer.is_due());
+        assert_eq!(buffer.len(), 3);
+
+        assert_eq!(buffer.drain(), vec![1, 2, 3]);
+        assert!(buffer.is_empty());
+        assert!(!buffer.is_due());
+        assert_eq!(buffer.drain(), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn test_max_age() {
+        let buffer = WriteBuffer::new(100).max_age(Duration::from_secs(0));
+        assert!(!buffer.is_due());
+        assert!(buffer.push("a"));
+        assert!(buffer.is_due());
+        buffer.drain();
+        assert!(!buffer.is_due());
+
+        let buffer = WriteBuffer::new(100).max_age(Duration::from_secs(3600));
+        assert!(!buffer.push("a"));
+        assert!(!buffer.is_due());
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let buffer = Arc::new(WriteBuffer::new(usize::MAX));
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let buffer = buffer.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        buffer.push((t, i));
+                    }
+                })
+            })
+            .collect();
+
+        // Drain while the writers are running to exercise a swap racing with pushes
+        let mut items = Vec::new();
+        while items.len() < 4000 {
+            items.extend(buffer.drain());
+        }
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(items.len(), 4000);
+        assert!(buffer.is_empty());
+
+        // Each writer's items are drained in the order they were pushed
+        for t in 0..4 {
+            let mine: Vec<_> = items.iter().filter(|x| x.0 == t).map(|x| x.1).collect();
+            assert_eq!(mine, (0..1000).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_concurrent_push_and_drain_keep_age() {
+        // With a max age of zero, any buffered item is due, unless a drain racing with a push
+        // lost track of when the items left behind were pushed
+        let buffer = Arc::new(WriteBuffer::new(usize::MAX).max_age(Duration::from_secs(0)));
+        for _ in 0..200 {
+            let writers: Vec<_> = (0..2)
+                .map(|_| {
+                    let buffer = buffer.clone();
+                    thread::spawn(move || {
+                        for i in 0..50 {
+                            buffer.push(i);
+                        }
+                    })
+                })
+                .collect();
+
+            let mut drained = 0;
+            for _ in 0..20 {
+                drained += buffer.drain().len();
+            }
+
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            assert!(buffer.is_empty() || buffer.is_due());
+            assert_eq!(drained + buffer.drain().len(), 100);
+        }
+    }
+}
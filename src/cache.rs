@@ -0,0 +1,206 @@
+//! collectd keeps the latest values of every identifier it has received in its cache, which the
+//! unixsock plugin reports with `LISTVAL` and `GETVAL`. `CacheIter` walks the cache so that a
+//! plugin can do the same, for instance to dump a snapshot of everything collectd knows about or
+//! to write out values on demand. The cache iterator was introduced in collectd 5.7.
+
+use api::{CdTime, Value};
+use bindings::{plugin_get_ds, uc_get_iterator, uc_iter_t, uc_iterator_destroy,
+               uc_iterator_get_interval, uc_iterator_get_time, uc_iterator_get_values,
+               uc_iterator_next, value_t};
+use constants::DataSourceType;
+use errors::Error;
+use identifier::Identifier;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::slice;
+use std::time::{Duration, SystemTime};
+
+extern "C" {
+    fn free(ptr: *mut c_void);
+}
+
+/// The latest values of an identifier in collectd's cache
+#[derive(Debug, PartialEq, Clone)]
+pub struct CacheEntry {
+    pub identifier: Identifier,
+    pub time: SystemTime,
+    pub interval: Duration,
+
+    /// Values in the order of the type's data sources. Counters and derives are the raw values
+    /// that were last dispatched, not rates.
+    pub values: Vec<Value>,
+}
+
+/// Iterates over collectd's cache. collectd holds the cache's lock until the iterator is dropped,
+/// and dispatching values updates the cache, so values must not be dispatched while iterating
+/// (see `cache_snapshot` to copy the cache instead).
+pub struct CacheIter {
+    iter: *mut uc_iter_t,
+}
+
+impl CacheIter {
+    pub fn new() -> Result<CacheIter, Error> {
+        let iter = unsafe { uc_get_iterator() };
+        if iter.is_null() {
+            Err(Error::from("uc_get_iterator returned an error"))
+        } else {
+            Ok(CacheIter { iter: iter })
+        }
+    }
+
+    unsafe fn entry(&self, name: *const c_char) -> Result<CacheEntry, Error> {
+        let name = CStr::from_ptr(name).to_str()?;
+        let identifier: Identifier = name.parse()?;
+
+        let mut time = 0;
+        call("uc_iterator_get_time", uc_iterator_get_time(self.iter, &mut time))?;
+        let mut interval = 0;
+        call("uc_iterator_get_interval", uc_iterator_get_interval(self.iter, &mut interval))?;
+
+        let mut raw: *mut value_t = ptr::null_mut();
+        let mut len = 0;
+        call("uc_iterator_get_values", uc_iterator_get_values(self.iter, &mut raw, &mut len))?;
+        let values = decode(&identifier, raw, len);
+        free(raw as *mut c_void);
+
+        Ok(CacheEntry {
+            identifier: identifier,
+            time: CdTime::from(time).into(),
+            interval: CdTime::from(interval).into(),
+            values: values?,
+        })
+    }
+}
+
+impl Iterator for CacheIter {
+    type Item = Result<CacheEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut name: *mut c_char = ptr::null_mut();
+        unsafe {
+            if uc_iterator_next(self.iter, &mut name) != 0 || name.is_null() {
+                return None;
+            }
+            Some(self.entry(name))
+        }
+    }
+}
+
+impl Drop for CacheIter {
+    fn drop(&mut self) {
+        unsafe { uc_iterator_destroy(self.iter) };
+    }
+}
+
+/// Copies every entry of collectd's cache, releasing the cache's lock before returning
+pub fn cache_snapshot() -> Result<Vec<CacheEntry>, Error> {
+    CacheIter::new()?.collect()
+}
+
+fn call(name: &str, code: c_int) -> Result<(), Error> {
+    match code {
+        0 => Ok(()),
+        code => Err(Error::from(format!("{} returned an error: {}", name, code))),
+    }
+}
+
+/// Interprets the cached values with the data sources of the identifier's type
+unsafe fn decode(id: &Identifier, raw: *const value_t, len: usize) -> Result<Vec<Value>, Error> {
+    if raw.is_null() {
+        return Ok(Vec::new());
+    }
+
+    let type_ = CString::new(id.type_.as_str())?;
+    let set = match plugin_get_ds(type_.as_ptr()).as_ref() {
+        Some(x) => x,
+        None => return Err(Error::from(format!("unknown type: {}", id.type_))),
+    };
+
+    let raw = slice::from_raw_parts(raw, len);
+    let sources = slice::from_raw_parts(set.ds, set.ds_num);
+    raw.iter()
+        .zip(sources)
+        .map(|(val, source)| match DataSourceType::from_raw(source.type_) {
+            Some(DataSourceType::Gauge) => Ok(Value::Gauge(val.gauge)),
+            Some(DataSourceType::Counter) => Ok(Value::Counter(val.counter)),
+            Some(DataSourceType::Derive) => Ok(Value::Derive(val.derive)),
+            Some(DataSourceType::Absolute) => Ok(Value::Absolute(val.absolute)),
+            None => Err(Error::from(format!(
+                "For type: {}, unrecognized data source type: {}",
+                id.type_, source.type_
+            ))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::ValueListBuilder;
+    use std::time::UNIX_EPOCH;
+    use stub;
+
+    #[test]
+    fn test_cache_snapshot() {
+        stub::reset();
+        stub::define_type("percent", &[("value", DataSourceType::Gauge)]);
+        stub::define_type(
+            "if_octets",
+            &[("rx", DataSourceType::Derive), ("tx", DataSourceType::Derive)],
+        );
+
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let mut builder = ValueListBuilder::new("cpu", "percent")
+            .plugin_instance("0")
+            .type_instance("idle")
+            .host("web-1")
+            .interval(Duration::from_secs(10));
+        builder.push_value(Value::Gauge(90.0)).set_time(at(10));
+        builder.submit().unwrap();
+        builder.clear_values().push_value(Value::Gauge(80.0)).set_time(at(20));
+        builder.submit().unwrap();
+        ValueListBuilder::new("interface", "if_octets")
+            .plugin_instance("eth0")
+            .host("web-1")
+            .values(&[Value::Derive(5), Value::Derive(7)])
+            .time(at(15))
+            .interval(Duration::from_secs(10))
+            .submit()
+            .unwrap();
+
+        let mut entries = cache_snapshot().unwrap();
+        entries.sort_by_key(|x| x.identifier.to_string());
+        assert_eq!(
+            entries,
+            vec![
+                CacheEntry {
+                    identifier: "web-1/cpu-0/percent-idle".parse().unwrap(),
+                    time: at(20),
+                    interval: Duration::from_secs(10),
+                    values: vec![Value::Gauge(80.0)],
+                },
+                CacheEntry {
+                    identifier: "web-1/interface-eth0/if_octets".parse().unwrap(),
+                    time: at(15),
+                    interval: Duration::from_secs(10),
+                    values: vec![Value::Derive(5), Value::Derive(7)],
+                },
+            ]
+        );
+
+        // Entries of types that collectd doesn't know about can't be decoded
+        ValueListBuilder::new("myplugin", "mystery")
+            .host("web-1")
+            .values(&[Value::Gauge(1.0)])
+            .submit()
+            .unwrap();
+        let errors: Vec<String> = CacheIter::new()
+            .unwrap()
+            .filter_map(|x| x.err())
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(errors, vec![String::from("unknown type: mystery")]);
+        stub::reset();
+    }
+}
@@ -0,0 +1,164 @@
+//! Collectd's constants as typed Rust values. Bindgen exposes `#define`s as `c_uint`, while
+//! collectd's API passes most of them around as `int`, so these are converted once here instead of
+//! casting magic numbers throughout plugin code.
+
+use bindings;
+use std::fmt;
+use std::os::raw::c_int;
+
+/// Maximum length of text fields (host, plugin, type, etc), including the null terminator. This is
+/// 64 before collectd 5.7 and 128 afterwards.
+pub const DATA_MAX_NAME_LEN: usize = bindings::DATA_MAX_NAME_LEN as usize;
+
+/// Maximum length of a notification's message, including the null terminator
+pub const NOTIF_MAX_MSG_LEN: usize = bindings::NOTIF_MAX_MSG_LEN as usize;
+
+pub const DS_TYPE_COUNTER: c_int = bindings::DS_TYPE_COUNTER as c_int;
+pub const DS_TYPE_GAUGE: c_int = bindings::DS_TYPE_GAUGE as c_int;
+pub const DS_TYPE_DERIVE: c_int = bindings::DS_TYPE_DERIVE as c_int;
+pub const DS_TYPE_ABSOLUTE: c_int = bindings::DS_TYPE_ABSOLUTE as c_int;
+
+pub const LOG_ERR: c_int = bindings::LOG_ERR as c_int;
+pub const LOG_WARNING: c_int = bindings::LOG_WARNING as c_int;
+pub const LOG_NOTICE: c_int = bindings::LOG_NOTICE as c_int;
+pub const LOG_INFO: c_int = bindings::LOG_INFO as c_int;
+pub const LOG_DEBUG: c_int = bindings::LOG_DEBUG as c_int;
+
+pub const NOTIF_FAILURE: c_int = bindings::NOTIF_FAILURE as c_int;
+pub const NOTIF_WARNING: c_int = bindings::NOTIF_WARNING as c_int;
+pub const NOTIF_OKAY: c_int = bindings::NOTIF_OKAY as c_int;
+
+pub const MD_TYPE_STRING: c_int = bindings::MD_TYPE_STRING as c_int;
+pub const MD_TYPE_SIGNED_INT: c_int = bindings::MD_TYPE_SIGNED_INT as c_int;
+pub const MD_TYPE_UNSIGNED_INT: c_int = bindings::MD_TYPE_UNSIGNED_INT as c_int;
+pub const MD_TYPE_DOUBLE: c_int = bindings::MD_TYPE_DOUBLE as c_int;
+pub const MD_TYPE_BOOLEAN: c_int = bindings::MD_TYPE_BOOLEAN as c_int;
+
+pub const NM_TYPE_STRING: c_int = bindings::notification_meta_type_e_NM_TYPE_STRING as c_int;
+pub const NM_TYPE_SIGNED_INT: c_int =
+    bindings::notification_meta_type_e_NM_TYPE_SIGNED_INT as c_int;
+pub const NM_TYPE_UNSIGNED_INT: c_int =
+    bindings::notification_meta_type_e_NM_TYPE_UNSIGNED_INT as c_int;
+pub const NM_TYPE_DOUBLE: c_int = bindings::notification_meta_type_e_NM_TYPE_DOUBLE as c_int;
+pub const NM_TYPE_BOOLEAN: c_int = bindings::notification_meta_type_e_NM_TYPE_BOOLEAN as c_int;
+
+pub const OCONFIG_TYPE_STRING: c_int = bindings::OCONFIG_TYPE_STRING as c_int;
+pub const OCONFIG_TYPE_NUMBER: c_int = bindings::OCONFIG_TYPE_NUMBER as c_int;
+pub const OCONFIG_TYPE_BOOLEAN: c_int = bindings::OCONFIG_TYPE_BOOLEAN as c_int;
+
+/// The kind of a data source as declared in types.db, which determines how a value is interpreted
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum DataSourceType {
+    Counter,
+    Gauge,
+    Derive,
+    Absolute,
+}
+
+impl DataSourceType {
+    /// Converts collectd's `DS_TYPE_*`, returning `None` for unrecognized values
+    pub fn from_raw(raw: c_int) -> Option<DataSourceType> {
+        match raw {
+            DS_TYPE_COUNTER => Some(DataSourceType::Counter),
+            DS_TYPE_GAUGE => Some(DataSourceType::Gauge),
+            DS_TYPE_DERIVE => Some(DataSourceType::Derive),
+            DS_TYPE_ABSOLUTE => Some(DataSourceType::Absolute),
+            _ => None,
+        }
+    }
+
+    pub fn as_raw(&self) -> c_int {
+        match *self {
+            DataSourceType::Counter => DS_TYPE_COUNTER,
+            DataSourceType::Gauge => DS_TYPE_GAUGE,
+            DataSourceType::Derive => DS_TYPE_DERIVE,
+            DataSourceType::Absolute => DS_TYPE_ABSOLUTE,
+        }
+    }
+}
+
+impl fmt::Display for DataSourceType {
+    /// Formats the type the same way as types.db (eg: `GAUGE`)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            DataSourceType::Counter => "COUNTER",
+            DataSourceType::Gauge => "GAUGE",
+            DataSourceType::Derive => "DERIVE",
+            DataSourceType::Absolute => "ABSOLUTE",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The severity of a notification
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Severity {
+    Failure,
+    Warning,
+    Okay,
+}
+
+impl Severity {
+    /// Converts collectd's `NOTIF_*`, returning `None` for unrecognized values
+    pub fn from_raw(raw: c_int) -> Option<Severity> {
+        match raw {
+            NOTIF_FAILURE => Some(Severity::Failure),
+            NOTIF_WARNING => Some(Severity::Warning),
+            NOTIF_OKAY => Some(Severity::Okay),
+            _ => None,
+        }
+    }
+
+    pub fn as_raw(&self) -> c_int {
+        match *self {
+            Severity::Failure => NOTIF_FAILURE,
+            Severity::Warning => NOTIF_WARNING,
+            Severity::Okay => NOTIF_OKAY,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    /// Formats the severity the same way as collectd (eg: `FAILURE`)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            Severity::Failure => "FAILURE",
+            Severity::Warning => "WARNING",
+            Severity::Okay => "OKAY",
+        };
+        f.write_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_source_type_round_trip() {
+        let types = [
+            DataSourceType::Counter,
+            DataSourceType::Gauge,
+            DataSourceType::Derive,
+            DataSourceType::Absolute,
+        ];
+
+        for &t in types.iter() {
+            assert_eq!(DataSourceType::from_raw(t.as_raw()), Some(t));
+        }
+
+        assert_eq!(DataSourceType::from_raw(42), None);
+        assert_eq!(DataSourceType::Gauge.to_string(), "GAUGE");
+    }
+
+    #[test]
+    fn test_severity_round_trip() {
+        let severities = [Severity::Failure, Severity::Warning, Severity::Okay];
+        for &s in severities.iter() {
+            assert_eq!(Severity::from_raw(s.as_raw()), Some(s));
+        }
+
+        assert_eq!(Severity::from_raw(3), None);
+        assert_eq!(Severity::Okay.to_string(), "OKAY");
+    }
+}
@@ -0,0 +1,218 @@
+//! Operators can poke a running plugin by sending it a notification, for instance with the
+//! unixsock plugin:
+//!
+//! ```text
+//! PUTNOTIF severity=okay time=1500000000 plugin=myplugin type=control type_instance=pause
+//! ```
+//!
+//! A `ControlChannel` treats notifications addressed to a plugin and type as commands, where the
+//! type instance names the command and the message carries its argument, and invokes the handler
+//! that the plugin defined for the command. This gives plugins a standard way to pause, resume,
+//! flush, or reload their targets without restarting collectd.
+
+use api::{collectd_log, LogLevel, RecvNotification};
+use bindings::{notification_t, plugin_register_notification, user_data_t};
+use errors::{Error, ErrorChain};
+use panics::catch_panic;
+use plugins::null_argument;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::raw::{c_int, c_void};
+use std::sync::Mutex;
+
+/// Stop collecting until resumed
+pub const PAUSE: &str = "pause";
+
+/// Resume collecting after a pause
+pub const RESUME: &str = "resume";
+
+/// Write out buffered values
+pub const FLUSH: &str = "flush";
+
+/// Re-read the list of targets (hosts, devices, etc) to collect from
+pub const RELOAD: &str = "reload";
+
+/// Handles a command, which receives the notification's message as the argument
+pub type ControlHandler = Box<dyn FnMut(&str) -> Result<(), Error> + Send>;
+
+/// Dispatches notifications addressed to `plugin` and `type_` to command handlers
+pub struct ControlChannel {
+    plugin: String,
+    type_: String,
+    handlers: HashMap<String, ControlHandler>,
+}
+
+impl ControlChannel {
+    /// Listens for notifications from the given plugin and type. The plugin is typically the
+    /// name of the plugin being controlled, and the type something like `control`.
+    pub fn new(plugin: &str, type_: &str) -> Self {
+        ControlChannel {
+            plugin: String::from(plugin),
+            type_: String::from(type_),
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Invokes the handler when the command is received. See `PAUSE`, `RESUME`, `FLUSH`, and
+    /// `RELOAD` for the standard commands, though any name can be used.
+    pub fn on<F>(mut self, command: &str, handler: F) -> Self
+    where
+        F: FnMut(&str) -> Result<(), Error> + Send + 'static,
+    {
+        self.handlers.insert(String::from(command), Box::new(handler));
+        self
+    }
+
+    /// Returns if the notification is addressed to this channel
+    pub fn accepts(&self, n: &RecvNotification) -> bool {
+        n.plugin == self.plugin && n.type_ == Some(self.type_.as_str())
+    }
+
+    /// Invokes the handler of the command carried by the notification. Returns `None` if the
+    /// notification isn't addressed to this channel, and an error if the command is unknown or
+    /// its handler failed.
+    pub fn handle(&mut self, n: &RecvNotification) -> Option<Result<(), Error>> {
+        if !self.accepts(n) {
+            return None;
+        }
+
+        let command = n.type_instance.unwrap_or("");
+        let res = match self.handlers.get_mut(command) {
+            Some(handler) => handler(n.message),
+            None => Err(Error::from(format!("unknown control command: {}", command))),
+        };
+        Some(res)
+    }
+
+    /// Registers the channel with collectd under the given name to receive every notification.
+    /// Call from `PluginManager::plugins` or `initialize`.
+    pub fn register(self, name: &str) -> Result<(), Error> {
+        let s = CString::new(name)?;
+        let channel = Box::new(Mutex::new(self));
+
+        #[allow(unused_mut)]
+        let mut data = user_data_t {
+            data: Box::into_raw(channel) as *mut c_void,
+            free_func: Some(free_channel),
+        };
+
+        #[cfg(collectd_57)]
+        let data_ptr = &data;
+
+        #[cfg(not(collectd_57))]
+        let data_ptr = &mut data;
+
+        match unsafe { plugin_register_notification(s.as_ptr(), Some(notification), data_ptr) } {
+            0 => Ok(()),
+            code => {
+                unsafe { free_channel(data.data) };
+                Err(Error::from(format!(
+                    "plugin_register_notification returned an error: {}",
+                    code
+                )))
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn notification(n: *const notification_t, dt: *mut user_data_t) -> c_int {
+    catch_panic("notification", || {
+        let channel = match dt.as_ref()
+            .and_then(|x| (x.data as *const Mutex<ControlChannel>).as_ref())
+        {
+            Some(x) => x,
+            None => return null_argument("notification", "user data"),
+        };
+
+        let n = match n.as_ref() {
+            Some(x) => x,
+            None => return null_argument("notification", "notification"),
+        };
+
+        // Notifications that can't be decoded can't be commands either
+        let n = match RecvNotification::from(n) {
+            Ok(x) => x,
+            Err(_) => return 0,
+        };
+
+        let mut channel = match channel.lock() {
+            Ok(x) => x,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        match channel.handle(&n) {
+            Some(Err(ref e)) => {
+                collectd_log(
+                    LogLevel::Error,
+                    &format!("{}: control error: {}", channel.plugin, ErrorChain(&**e)),
+                );
+                -1
+            }
+            _ => 0,
+        }
+    })
+}
+
+unsafe extern "C" fn free_channel(raw: *mut c_void) {
+    if !raw.is_null() {
+        drop(Box::from_raw(raw as *mut Mutex<ControlChannel>));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::NotificationBuilder;
+    use constants::Severity;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use stub;
+    use testing;
+
+    #[test]
+    fn test_control_commands() {
+        stub::reset();
+        let paused = Arc::new(AtomicBool::new(false));
+        let (pause, resume) = (paused.clone(), paused.clone());
+        ControlChannel::new("myplugin", "control")
+            .on(PAUSE, move |_| {
+                pause.store(true, Ordering::SeqCst);
+                Ok(())
+            })
+            .on(RESUME, move |_| {
+                resume.store(false, Ordering::SeqCst);
+                Ok(())
+            })
+            .on(RELOAD, |target| Err(Error::from(format!("no such target: {}", target))))
+            .register("myplugin")
+            .unwrap();
+
+        let command = |type_: &str, command: &str, message: &str| {
+            NotificationBuilder::new("myplugin", Severity::Okay, message)
+                .type_(type_)
+                .type_instance(command)
+                .dispatch()
+                .unwrap()
+        };
+
+        command("control", PAUSE, "");
+        assert!(paused.load(Ordering::SeqCst));
+
+        // Notifications of other types aren't commands
+        command("alert", RESUME, "");
+        assert!(paused.load(Ordering::SeqCst));
+
+        command("control", RESUME, "");
+        assert!(!paused.load(Ordering::SeqCst));
+
+        command("control", RELOAD, "db-3");
+        testing::assert_logged(
+            LogLevel::Error,
+            "myplugin: control error: no such target: db-3",
+        );
+
+        command("control", "dance", "");
+        testing::assert_logged(LogLevel::Error, "unknown control command: dance");
+        stub::reset();
+    }
+}
@@ -0,0 +1,172 @@
+//! Durations in the config can be written like [humantime](https://docs.rs/humantime) formats
+//! them (eg: `Timeout "1m 30s"`), or as a number of seconds as collectd's own options are (eg:
+//! `Timeout 90`).
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use std::fmt;
+use std::ops::Deref;
+use std::time::Duration;
+
+/// A duration from the config, which dereferences to the `Duration`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
+pub struct HumanDuration(pub Duration);
+
+impl Deref for HumanDuration {
+    type Target = Duration;
+
+    fn deref(&self) -> &Duration {
+        &self.0
+    }
+}
+
+impl From<HumanDuration> for Duration {
+    fn from(d: HumanDuration) -> Duration {
+        d.0
+    }
+}
+
+/// The nanoseconds in each unit, where months and years are humantime's average lengths
+fn unit_nanos(unit: &str) -> Option<u64> {
+    let second = 1_000_000_000;
+    let nanos = match unit {
+        "nsec" | "ns" => 1,
+        "usec" | "us" | "µs" => 1_000,
+        "msec" | "ms" => 1_000_000,
+        "seconds" | "second" | "sec" | "secs" | "s" => second,
+        "minutes" | "minute" | "min" | "mins" | "m" => 60 * second,
+        "hours" | "hour" | "hr" | "hrs" | "h" => 3_600 * second,
+        "days" | "day" | "d" => 86_400 * second,
+        "weeks" | "week" | "w" => 604_800 * second,
+        "months" | "month" | "M" => 2_630_016 * second,
+        "years" | "year" | "y" => 31_557_600 * second,
+        _ => return None,
+    };
+    Some(nanos)
+}
+
+/// Parses a duration written as a sequence of integers and units (eg: `1h 30m` or `250ms`)
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = |reason: &str| format!("invalid duration `{}`: {}", s, reason);
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(invalid("empty"));
+    }
+
+    let mut nanos: u64 = 0;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(invalid("expected a number"));
+        }
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid("number is too large"))?;
+        rest = rest[digits..].trim_start();
+
+        let letters = rest.find(|c: char| !c.is_alphabetic()).unwrap_or(rest.len());
+        let unit = &rest[..letters];
+        if unit.is_empty() {
+            return Err(invalid("expected a unit (eg: `s`) after each number"));
+        }
+        let per = unit_nanos(unit).ok_or_else(|| invalid(&format!("unknown unit `{}`", unit)))?;
+        rest = rest[letters..].trim_start();
+
+        nanos = value
+            .checked_mul(per)
+            .and_then(|x| nanos.checked_add(x))
+            .ok_or_else(|| invalid("too large"))?;
+    }
+
+    let second = 1_000_000_000;
+    Ok(Duration::new(nanos / second, (nanos % second) as u32))
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(DurationVisitor)
+    }
+}
+
+struct DurationVisitor;
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = HumanDuration;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a duration (eg: \"1m 30s\") or a number of seconds")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<HumanDuration, E> {
+        parse_duration(v).map(HumanDuration).map_err(E::custom)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<HumanDuration, E> {
+        if v.is_finite() && v >= 0.0 && v <= u64::MAX as f64 {
+            let secs = v.trunc();
+            let nanos = ((v - secs) * 1e9).round() as u32;
+            Ok(HumanDuration(Duration::new(secs as u64, nanos.min(999_999_999))))
+        } else {
+            Err(E::custom(format!("invalid duration: {} seconds", v)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{ConfigItem, ConfigValue};
+    use de::from_collectd;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("15s"), Ok(Duration::from_secs(15)));
+        assert_eq!(parse_duration("1h 30m"), Ok(Duration::from_secs(5_400)));
+        assert_eq!(parse_duration("2 days 3hrs"), Ok(Duration::from_secs(183_600)));
+        assert_eq!(parse_duration("1s500ms"), Ok(Duration::from_millis(1_500)));
+        assert_eq!(parse_duration("250us"), Ok(Duration::from_micros(250)));
+        assert_eq!(
+            parse_duration("15"),
+            Err(String::from(
+                "invalid duration `15`: expected a unit (eg: `s`) after each number"
+            ))
+        );
+        assert_eq!(
+            parse_duration("3 fortnights"),
+            Err(String::from("invalid duration `3 fortnights`: unknown unit `fortnights`"))
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("99999999999y").is_err());
+    }
+
+    #[test]
+    fn test_serde_duration() {
+        #[derive(Deserialize, PartialEq, Eq, Debug)]
+        struct MyStruct {
+            timeout: HumanDuration,
+            retry: HumanDuration,
+        };
+
+        let items = vec![
+            ConfigItem {
+                key: "timeout",
+                values: vec![ConfigValue::Number(2.5)],
+                children: vec![],
+            },
+            ConfigItem {
+                key: "retry",
+                values: vec![ConfigValue::String("1m 30s")],
+                children: vec![],
+            },
+        ];
+
+        let actual = from_collectd(&items).unwrap();
+        assert_eq!(
+            MyStruct {
+                timeout: HumanDuration(Duration::from_millis(2_500)),
+                retry: HumanDuration(Duration::from_secs(90)),
+            },
+            actual
+        );
+    }
+}
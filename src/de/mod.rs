@@ -1,20 +1,77 @@
+use std::convert::TryFrom;
 use std::fmt::{self, Display};
+use std::ptr;
 use serde::de::{self, Deserialize, DeserializeSeed, MapAccess, SeqAccess, Visitor};
-use api::{ConfigItem, ConfigValue};
+use api::{collectd_log, ConfigItem, ConfigValue, LogLevel};
+
+mod duration;
+mod path;
+#[cfg(feature = "regex")]
+mod pattern;
+
+pub use self::duration::{parse_duration, HumanDuration};
+pub use self::path::{ExistingPath, ReadablePath};
+#[cfg(feature = "regex")]
+pub use self::pattern::Pattern;
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
-#[derive(Fail, Debug)]
+/// The largest integer that a double represents exactly, 2^53
+const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+#[derive(Debug)]
 pub enum DeError {
-    #[fail(display = "No more values left, this should never happen")] NoMoreValuesLeft,
-    #[fail(display = "Error from deserialization: {}", _0)] SerdeError(String),
-    #[fail(display = "Expecting values to contain a single entry")] ExpectSingleValue,
-    #[fail(display = "Expecting string")] ExpectString,
-    #[fail(display = "Expecting string of length one, received `{}`", _0)] ExpectChar(String),
-    #[fail(display = "Expecting boolean")] ExpectBoolean,
-    #[fail(display = "Expecting number")] ExpectNumber,
-    #[fail(display = "Expecting struct")] ExpectStruct,
-    #[fail(display = "Could not deserialize as datatype not supported")] DataTypeNotSupported,
+    NoMoreValuesLeft,
+    SerdeError(String),
+    InvalidOption(String, String),
+    ExpectSingleValue,
+    ExpectString,
+    ExpectChar(String),
+    ExpectBoolean,
+    ExpectNumber,
+    ExpectInteger(String),
+    ImpreciseInteger(f64),
+    IntegerOutOfRange(String, &'static str),
+    ExpectStruct,
+    ExpectEmpty,
+    UnknownOption(String),
+    DataTypeNotSupported,
+}
+
+impl Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeError::NoMoreValuesLeft => {
+                write!(f, "No more values left, this should never happen")
+            }
+            DeError::SerdeError(ref msg) => write!(f, "Error from deserialization: {}", msg),
+            DeError::InvalidOption(ref key, ref msg) => {
+                write!(f, "Invalid option `{}`: {}", key, msg)
+            }
+            DeError::ExpectSingleValue => write!(f, "Expecting values to contain a single entry"),
+            DeError::ExpectString => write!(f, "Expecting string"),
+            DeError::ExpectChar(ref s) => {
+                write!(f, "Expecting string of length one, received `{}`", s)
+            }
+            DeError::ExpectBoolean => write!(f, "Expecting boolean"),
+            DeError::ExpectNumber => write!(f, "Expecting number"),
+            DeError::ExpectInteger(ref s) => write!(f, "Expecting integer, received `{}`", s),
+            DeError::ImpreciseInteger(x) => write!(
+                f,
+                "{} is too large to be read precisely from a number, quote it as a string",
+                x
+            ),
+            DeError::IntegerOutOfRange(ref s, type_) => {
+                write!(f, "`{}` is out of range for {}", s, type_)
+            }
+            DeError::ExpectStruct => write!(f, "Expecting struct"),
+            DeError::ExpectEmpty => write!(f, "Expecting an option without values or children"),
+            DeError::UnknownOption(ref key) => write!(f, "Unknown option `{}`", key),
+            DeError::DataTypeNotSupported => {
+                write!(f, "Could not deserialize as datatype not supported")
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -38,24 +95,122 @@ impl Display for Error {
     }
 }
 
+/// What to do with an option that doesn't correspond to a field
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UnknownKeys {
+    /// Leave the option to serde, which ignores it unless the struct has
+    /// `#[serde(deny_unknown_fields)]`
+    Ignore,
+
+    /// Log a warning to collectd and ignore the option
+    Warn,
+
+    /// Fail deserialization
+    Reject,
+}
+
+/// How forgiving the deserializer is towards a config. By default quoted integers are accepted,
+/// fractional ones are not, unknown options are ignored, options must match their field's name
+/// exactly, and only sequences can be given more than once.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DeserializerOptions {
+    strict_numbers: bool,
+    allow_truncation: bool,
+    unknown_keys: UnknownKeys,
+    case_insensitive: bool,
+    last_wins: bool,
+}
+
+impl Default for DeserializerOptions {
+    fn default() -> Self {
+        DeserializerOptions {
+            strict_numbers: false,
+            allow_truncation: false,
+            unknown_keys: UnknownKeys::Ignore,
+            case_insensitive: false,
+            last_wins: false,
+        }
+    }
+}
+
+impl DeserializerOptions {
+    pub fn new() -> Self {
+        DeserializerOptions::default()
+    }
+
+    /// Only accept numbers written as numbers, instead of also parsing quoted ones (eg: `"80"`)
+    pub fn strict_numbers(mut self, strict: bool) -> Self {
+        self.strict_numbers = strict;
+        self
+    }
+
+    /// Truncate numbers towards zero when read into an integer (eg: `1.5` becomes `1`), instead
+    /// of rejecting those that aren't whole or are too large to be exact
+    pub fn allow_truncation(mut self, allow: bool) -> Self {
+        self.allow_truncation = allow;
+        self
+    }
+
+    pub fn unknown_keys(mut self, policy: UnknownKeys) -> Self {
+        self.unknown_keys = policy;
+        self
+    }
+
+    /// Match options to fields regardless of case (eg: `Port` and `port`), like collectd's own
+    /// plugins do
+    pub fn case_insensitive(mut self, insensitive: bool) -> Self {
+        self.case_insensitive = insensitive;
+        self
+    }
+
+    /// Have a field that isn't a sequence take the last occurrence of a repeated option, instead
+    /// of rejecting the option as a duplicate
+    pub fn last_wins(mut self, wins: bool) -> Self {
+        self.last_wins = wins;
+        self
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 enum DeType<'a> {
     Struct(&'a ConfigItem<'a>),
     Seq(&'a ConfigValue<'a>),
 }
 
+/// Deserializes collectd's config. Many collectd plugins accept an option several times (eg:
+/// `Port 80 443` followed by `Port 8080`), so when a key is repeated within a block, sequences
+/// receive the values of every occurrence in order. Other fields reject the repetition as a
+/// duplicate, unless `DeserializerOptions::last_wins` is set.
 pub struct Deserializer<'a> {
     input: &'a [ConfigItem<'a>],
     depth: Vec<DeType<'a>>,
     root: bool,
+
+    /// Every occurrence of the key that is being deserialized
+    occurrences: Vec<&'a ConfigItem<'a>>,
+
+    /// The last occurrences of repeated keys that were deserialized as a single sequence
+    accumulated: Vec<&'a ConfigItem<'a>>,
+
+    /// The name of the field that the key is deserialized into
+    field: Option<&'a str>,
+    options: DeserializerOptions,
 }
 
 impl<'a> Deserializer<'a> {
     pub fn from_collectd(input: &'a [ConfigItem]) -> Self {
+        Deserializer::with_options(input, DeserializerOptions::default())
+    }
+
+    pub fn with_options(input: &'a [ConfigItem], options: DeserializerOptions) -> Self {
         Deserializer {
             input: input,
             depth: vec![],
             root: true,
+            occurrences: vec![],
+            accumulated: vec![],
+            field: None,
+            options: options,
         }
     }
 
@@ -67,6 +222,10 @@ impl<'a> Deserializer<'a> {
         Ok(self.depth[self.depth.len() - 1])
     }
 
+    fn pop(&mut self) -> Result<DeType<'a>> {
+        self.depth.pop().ok_or(Error(DeError::NoMoreValuesLeft))
+    }
+
     fn grab_val(&self) -> Result<&ConfigValue<'a>> {
         match self.current()? {
             DeType::Struct(item) => {
@@ -88,7 +247,20 @@ impl<'a> Deserializer<'a> {
         }
     }
 
+    /// Returns if the current item is a bare key or an empty block, which carries no data
+    fn is_empty(&self) -> Result<bool> {
+        match self.current()? {
+            DeType::Struct(item) => Ok(item.values.is_empty() && item.children.is_empty()),
+            DeType::Seq(_) => Ok(false),
+        }
+    }
+
     fn grab_bool(&self) -> Result<bool> {
+        // A bare key (eg: `EnableFeatureX`) is a flag that is turned on
+        if self.is_empty()? {
+            return Ok(true);
+        }
+
         if let ConfigValue::Boolean(x) = *self.grab_val()? {
             Ok(x)
         } else {
@@ -96,20 +268,81 @@ impl<'a> Deserializer<'a> {
         }
     }
 
-    fn grab_number(&self) -> Result<f64> {
-        if let ConfigValue::Number(x) = *self.grab_val()? {
-            Ok(x)
+    /// collectd parses numbers as doubles, which only represent integers up to 2^53 exactly, so
+    /// larger integers (eg: byte counts) must be quoted to be read without losing precision
+    fn grab_integer<T: TryFrom<i128>>(&self, type_: &'static str) -> Result<T> {
+        let x = match *self.grab_val()? {
+            ConfigValue::Number(x) => self.integer_from(x)?,
+            ConfigValue::String(s) if !self.options.strict_numbers => {
+                // collectd's simple config interface formats numbers as decimals (eg: `80.000000`)
+                let s = s.trim();
+                match (s.parse::<i128>(), s.parse::<f64>()) {
+                    (Ok(x), _) => x,
+                    (Err(_), Ok(x)) => self.integer_from(x)?,
+                    _ => return Err(Error(DeError::ExpectInteger(String::from(s)))),
+                }
+            }
+            ConfigValue::String(_) | ConfigValue::Boolean(_) => {
+                return Err(Error(DeError::ExpectNumber))
+            }
+        };
+
+        T::try_from(x).map_err(|_| Error(DeError::IntegerOutOfRange(x.to_string(), type_)))
+    }
+
+    fn integer_from(&self, x: f64) -> Result<i128> {
+        let truncate = self.options.allow_truncation;
+        if !x.is_finite() || (x.fract() != 0.0 && !truncate) {
+            Err(Error(DeError::ExpectInteger(x.to_string())))
+        } else if x.abs() > MAX_EXACT_INTEGER && !truncate {
+            Err(Error(DeError::ImpreciseInteger(x)))
         } else {
-            Err(Error(DeError::ExpectNumber))
+            Ok(x.trunc() as i128)
         }
     }
+
+    fn grab_number(&self) -> Result<f64> {
+        match *self.grab_val()? {
+            ConfigValue::Number(x) => Ok(x),
+            ConfigValue::String(s) if !self.options.strict_numbers => {
+                s.trim().parse().map_err(|_| Error(DeError::ExpectNumber))
+            }
+            _ => Err(Error(DeError::ExpectNumber)),
+        }
+    }
+
+    /// The field that the item deserializes into, out of the struct's fields
+    fn resolve_field(
+        &self,
+        item: &'a ConfigItem<'a>,
+        fields: &'static [&'static str],
+    ) -> Option<&'a str> {
+        let name = field_name(item)?;
+        if self.options.case_insensitive {
+            if let Some(field) = fields.iter().find(|x| x.eq_ignore_ascii_case(name)) {
+                return Some(field);
+            }
+        }
+        Some(name)
+    }
 }
 
 pub fn from_collectd<'a, T>(s: &'a [ConfigItem<'a>]) -> Result<T>
 where
     T: Deserialize<'a>,
 {
-    let mut deserializer = Deserializer::from_collectd(s);
+    from_collectd_with(s, DeserializerOptions::default())
+}
+
+/// Deserializes the config with options that make the deserializer stricter or more lenient
+pub fn from_collectd_with<'a, T>(
+    s: &'a [ConfigItem<'a>],
+    options: DeserializerOptions,
+) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    let mut deserializer = Deserializer::with_options(s, options);
     T::deserialize(&mut deserializer)
 }
 
@@ -143,56 +376,56 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        self.grab_number().and_then(|x| visitor.visit_i8(x as i8))
+        self.grab_integer("i8").and_then(|x| visitor.visit_i8(x))
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.grab_number().and_then(|x| visitor.visit_i16(x as i16))
+        self.grab_integer("i16").and_then(|x| visitor.visit_i16(x))
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.grab_number().and_then(|x| visitor.visit_i32(x as i32))
+        self.grab_integer("i32").and_then(|x| visitor.visit_i32(x))
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.grab_number().and_then(|x| visitor.visit_i64(x as i64))
+        self.grab_integer("i64").and_then(|x| visitor.visit_i64(x))
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.grab_number().and_then(|x| visitor.visit_u8(x as u8))
+        self.grab_integer("u8").and_then(|x| visitor.visit_u8(x))
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.grab_number().and_then(|x| visitor.visit_u16(x as u16))
+        self.grab_integer("u16").and_then(|x| visitor.visit_u16(x))
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.grab_number().and_then(|x| visitor.visit_u32(x as u32))
+        self.grab_integer("u32").and_then(|x| visitor.visit_u32(x))
     }
 
     fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.grab_number().and_then(|x| visitor.visit_u64(x as u64))
+        self.grab_integer("u64").and_then(|x| visitor.visit_u64(x))
     }
 
     fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
@@ -233,14 +466,10 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        let v = &self.depth[self.depth.len() - 1];
-        if let DeType::Struct(item) = *v {
-            if item.children.is_empty() || item.values.is_empty() {
-                visitor.visit_borrowed_str(item.key)
-            } else if let ConfigValue::String(x) = item.values[0] {
-                visitor.visit_borrowed_str(x)
-            } else {
-                Err(Error(DeError::ExpectString))
+        if let DeType::Struct(_) = self.current()? {
+            match self.field {
+                Some(x) => visitor.visit_borrowed_str(x),
+                None => Err(Error(DeError::ExpectString)),
             }
         } else {
             Err(Error(DeError::ExpectStruct))
@@ -252,7 +481,18 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         V: Visitor<'de>,
     {
         match self.current()? {
-            DeType::Struct(item) => visitor.visit_seq(SeqSeparated::new(&mut self, &item.values)),
+            DeType::Struct(item) => {
+                let occurrences = ::std::mem::replace(&mut self.occurrences, Vec::new());
+                let values = if occurrences.iter().any(|x| ptr::eq(*x, item)) {
+                    if occurrences.len() > 1 {
+                        self.accumulated.push(item);
+                    }
+                    occurrences.iter().flat_map(|x| x.values.iter()).collect()
+                } else {
+                    item.values.iter().collect()
+                };
+                visitor.visit_seq(SeqSeparated::new(&mut self, values))
+            }
             DeType::Seq(_item) => Err(Error(DeError::ExpectStruct)),
         }
     }
@@ -260,7 +500,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     fn deserialize_struct<V>(
         mut self,
         _name: &'static str,
-        _fields: &'static [&'static str],
+        fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
@@ -268,14 +508,33 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     {
         if self.root {
             self.root = false;
-            visitor.visit_map(FieldSeparated::new(&mut self, self.input))
+            let input = self.input;
+            visitor.visit_map(FieldSeparated::new(&mut self, input, fields))
         } else if let DeType::Struct(item) = self.current()? {
-            visitor.visit_map(FieldSeparated::new(&mut self, &item.children[..]))
+            visitor.visit_map(FieldSeparated::new(&mut self, &item.children[..], fields))
         } else {
             Err(Error(DeError::ExpectStruct))
         }
     }
 
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if self.is_empty()? {
+            visitor.visit_unit()
+        } else {
+            Err(Error(DeError::ExpectEmpty))
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
     fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
@@ -283,32 +542,90 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         visitor.visit_none()
     }
 
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    /// Types that accept several kinds of values (eg: a duration that is either a number of
+    /// seconds or a string) receive single values as they are
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        Err(Error(DeError::DataTypeNotSupported))
+        match *self.grab_val().map_err(|_| Error(DeError::DataTypeNotSupported))? {
+            ConfigValue::String(x) => visitor.visit_borrowed_str(x),
+            ConfigValue::Number(x) => visitor.visit_f64(x),
+            ConfigValue::Boolean(x) => visitor.visit_bool(x),
+        }
     }
 
     forward_to_deserialize_any! {
         bytes
-        byte_buf unit unit_struct newtype_struct tuple
+        byte_buf newtype_struct tuple
         tuple_struct map enum
     }
 }
 
+/// The name of the field that the item deserializes into, which for a block with values (eg:
+/// `<Node "example">`) is its first value
+fn field_name<'de>(item: &ConfigItem<'de>) -> Option<&'de str> {
+    if item.children.is_empty() || item.values.is_empty() {
+        Some(item.key)
+    } else if let ConfigValue::String(x) = item.values[0] {
+        Some(x)
+    } else {
+        None
+    }
+}
+
 struct FieldSeparated<'a, 'de: 'a> {
     de: &'a mut Deserializer<'de>,
-    items: &'de [ConfigItem<'de>],
+
+    /// The occurrences of each field, in the order that the fields first appear
+    fields: ::std::vec::IntoIter<(Option<&'de str>, Vec<&'de ConfigItem<'de>>)>,
+    known: &'static [&'static str],
     first: bool,
+
+    /// The field of the current key, if it's known and repeated
+    repeated: Option<&'de str>,
 }
 
 impl<'a, 'de> FieldSeparated<'a, 'de> {
-    fn new(de: &'a mut Deserializer<'de>, items: &'de [ConfigItem<'de>]) -> Self {
+    fn new(
+        de: &'a mut Deserializer<'de>,
+        items: &'de [ConfigItem<'de>],
+        known: &'static [&'static str],
+    ) -> Self {
+        let mut fields: Vec<(Option<&'de str>, Vec<&'de ConfigItem<'de>>)> = Vec::new();
+        for item in items {
+            let name = de.resolve_field(item, known);
+            let existing = fields
+                .iter_mut()
+                .find(|x| name.is_some() && x.0 == name);
+            match existing {
+                Some(&mut (_, ref mut occurrences)) => occurrences.push(item),
+                None => fields.push((name, vec![item])),
+            }
+        }
+
         FieldSeparated {
             de: de,
             first: true,
-            items: items,
+            fields: fields.into_iter(),
+            known: known,
+            repeated: None,
+        }
+    }
+
+    fn check_known(&self, name: Option<&str>, item: &ConfigItem) -> Result<()> {
+        if name.map(|x| self.known.contains(&x)).unwrap_or(false) {
+            return Ok(());
+        }
+
+        match self.de.options.unknown_keys {
+            UnknownKeys::Ignore => Ok(()),
+            UnknownKeys::Warn => {
+                let msg = format!("ignoring unknown config option `{}`", item.key);
+                collectd_log(LogLevel::Warning, &msg);
+                Ok(())
+            }
+            UnknownKeys::Reject => Err(Error(DeError::UnknownOption(String::from(item.key)))),
         }
     }
 }
@@ -321,22 +638,33 @@ impl<'de, 'a> MapAccess<'de> for FieldSeparated<'a, 'de> {
         K: DeserializeSeed<'de>,
     {
         // Check if there are no more entries.
-        if self.items.is_empty() {
-            if !self.first {
-                self.de.depth.pop().unwrap();
+        let (name, occurrences) = match self.fields.next() {
+            Some(x) => x,
+            None => {
+                if !self.first {
+                    self.de.pop()?;
+                }
+                return Ok(None);
             }
-            return Ok(None);
-        }
+        };
 
+        // A sequence receives every occurrence, other fields the last one (if that's allowed)
+        let item = occurrences[occurrences.len() - 1];
+        self.check_known(name, item)?;
+        self.repeated = match name {
+            Some(x) if occurrences.len() > 1 && self.known.contains(&x) => Some(x),
+            _ => None,
+        };
         if self.first || self.de.depth.is_empty() {
-            self.de.depth.push(DeType::Struct(&self.items[0]));
+            self.de.depth.push(DeType::Struct(item));
             self.first = false;
         } else {
             let ind = self.de.depth.len() - 1;
-            self.de.depth[ind] = DeType::Struct(&self.items[0]);
+            self.de.depth[ind] = DeType::Struct(item);
         }
 
-        self.items = &self.items[1..];
+        self.de.occurrences = occurrences;
+        self.de.field = name;
         seed.deserialize(&mut *self.de).map(Some)
     }
 
@@ -344,21 +672,39 @@ impl<'de, 'a> MapAccess<'de> for FieldSeparated<'a, 'de> {
     where
         V: DeserializeSeed<'de>,
     {
-        seed.deserialize(&mut *self.de)
+        // Errors raised by a field's type (eg: a path that doesn't exist) don't know where the
+        // value came from, so name the option that failed
+        let item = match self.de.current()? {
+            DeType::Struct(item) => item,
+            DeType::Seq(_) => return seed.deserialize(&mut *self.de),
+        };
+
+        let value = seed.deserialize(&mut *self.de).map_err(|e| match e.0 {
+            DeError::SerdeError(msg) => Error(DeError::InvalidOption(String::from(item.key), msg)),
+            other => Error(other),
+        })?;
+
+        if let Some(field) = self.repeated {
+            let accumulated = self.de.accumulated.iter().any(|x| ptr::eq(*x, item));
+            if !accumulated && !self.de.options.last_wins {
+                return Err(de::Error::custom(format_args!("duplicate field `{}`", field)));
+            }
+        }
+        Ok(value)
     }
 }
 
 struct SeqSeparated<'a, 'de: 'a> {
     de: &'a mut Deserializer<'de>,
-    values: &'de [ConfigValue<'de>],
+    values: ::std::vec::IntoIter<&'de ConfigValue<'de>>,
     first: bool,
 }
 
 impl<'a, 'de> SeqSeparated<'a, 'de> {
-    fn new(de: &'a mut Deserializer<'de>, v: &'de [ConfigValue<'de>]) -> Self {
+    fn new(de: &'a mut Deserializer<'de>, v: Vec<&'de ConfigValue<'de>>) -> Self {
         SeqSeparated {
             de: de,
-            values: v,
+            values: v.into_iter(),
             first: true,
         }
     }
@@ -371,22 +717,24 @@ impl<'de, 'a> SeqAccess<'de> for SeqSeparated<'a, 'de> {
     where
         T: DeserializeSeed<'de>,
     {
-        if self.values.is_empty() {
-            if !self.first {
-                self.de.depth.pop().unwrap();
+        let value = match self.values.next() {
+            Some(x) => x,
+            None => {
+                if !self.first {
+                    self.de.pop()?;
+                }
+                return Ok(None);
             }
-            return Ok(None);
-        }
+        };
 
         if self.first {
-            self.de.depth.push(DeType::Seq(&self.values[0]));
+            self.de.depth.push(DeType::Seq(value));
             self.first = false;
         } else {
             let ind = self.de.depth.len() - 1;
-            self.de.depth[ind] = DeType::Seq(&self.values[0]);
+            self.de.depth[ind] = DeType::Seq(value);
         }
 
-        self.values = &self.values[1..];
         seed.deserialize(&mut *self.de).map(Some)
     }
 }
@@ -614,4 +962,236 @@ mod tests {
             actual
         );
     }
+
+    #[test]
+    fn test_serde_repeated_keys() {
+        #[derive(Deserialize, PartialEq, Eq, Debug)]
+        struct MyStruct {
+            ports: Vec<u16>,
+            host: String,
+        };
+
+        let items = vec![
+            ConfigItem {
+                key: "ports",
+                values: vec![ConfigValue::Number(80.0), ConfigValue::Number(443.0)],
+                children: vec![],
+            },
+            ConfigItem {
+                key: "host",
+                values: vec![ConfigValue::String("web-1")],
+                children: vec![],
+            },
+            ConfigItem {
+                key: "ports",
+                values: vec![ConfigValue::Number(8080.0)],
+                children: vec![],
+            },
+            ConfigItem {
+                key: "host",
+                values: vec![ConfigValue::String("web-2")],
+                children: vec![],
+            },
+        ];
+
+        let last_wins = DeserializerOptions::new().last_wins(true);
+        let actual = from_collectd_with(&items, last_wins).unwrap();
+        assert_eq!(
+            MyStruct {
+                ports: vec![80, 443, 8080],
+                host: String::from("web-2"),
+            },
+            actual
+        );
+
+        // Only sequences accept repetition by default
+        let err = from_collectd::<MyStruct>(&items).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error from deserialization: duplicate field `host`"
+        );
+        let actual: MyStruct = from_collectd(&items[..3]).unwrap();
+        assert_eq!(actual.ports, vec![80, 443, 8080]);
+        assert_eq!(actual.host, "web-1");
+    }
+
+    #[test]
+    fn test_serde_empty_options() {
+        #[derive(Deserialize, PartialEq, Eq, Debug)]
+        struct Marker;
+
+        #[derive(Deserialize, PartialEq, Eq, Debug)]
+        struct MyStruct {
+            enable_x: Option<()>,
+            enable_y: Option<Marker>,
+            enable_z: Option<()>,
+            verbose: bool,
+        };
+
+        let empty = |key| ConfigItem {
+            key: key,
+            values: vec![],
+            children: vec![],
+        };
+        let items = vec![empty("enable_x"), empty("enable_y"), empty("verbose")];
+
+        let actual = from_collectd(&items).unwrap();
+        assert_eq!(
+            MyStruct {
+                enable_x: Some(()),
+                enable_y: Some(Marker),
+                enable_z: None,
+                verbose: true,
+            },
+            actual
+        );
+
+        let items = vec![
+            ConfigItem {
+                key: "enable_x",
+                values: vec![ConfigValue::Boolean(true)],
+                children: vec![],
+            },
+            empty("verbose"),
+        ];
+        let err = from_collectd::<MyStruct>(&items).unwrap_err();
+        assert_eq!(err.to_string(), "Expecting an option without values or children");
+    }
+
+    #[test]
+    fn test_serde_integer_precision() {
+        #[derive(Deserialize, PartialEq, Eq, Debug)]
+        struct MyStruct {
+            max_bytes: u64,
+        };
+
+        let item = |value| {
+            vec![
+                ConfigItem {
+                    key: "max_bytes",
+                    values: vec![value],
+                    children: vec![],
+                },
+            ]
+        };
+        let de = |items: &[ConfigItem]| from_collectd::<MyStruct>(items).map(|x| x.max_bytes);
+
+        assert_eq!(de(&item(ConfigValue::Number(1024.0))).unwrap(), 1024);
+        assert_eq!(
+            de(&item(ConfigValue::String("18446744073709551615"))).unwrap(),
+            u64::MAX
+        );
+        assert_eq!(
+            de(&item(ConfigValue::Number(1e17))).unwrap_err().to_string(),
+            "100000000000000000 is too large to be read precisely from a number, quote it as a \
+             string"
+        );
+        assert_eq!(
+            de(&item(ConfigValue::Number(1.5))).unwrap_err().to_string(),
+            "Expecting integer, received `1.5`"
+        );
+        assert_eq!(
+            de(&item(ConfigValue::Number(-1.0))).unwrap_err().to_string(),
+            "`-1` is out of range for u64"
+        );
+        assert_eq!(
+            de(&item(ConfigValue::String("lots"))).unwrap_err().to_string(),
+            "Expecting integer, received `lots`"
+        );
+
+        // As formatted by collectd's simple config interface
+        assert_eq!(de(&item(ConfigValue::String("80.000000"))).unwrap(), 80);
+        assert_eq!(
+            de(&item(ConfigValue::String("80.5"))).unwrap_err().to_string(),
+            "Expecting integer, received `80.5`"
+        );
+    }
+
+    #[test]
+    fn test_serde_with_options() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct MyStruct {
+            port: u16,
+            ratio: Option<f64>,
+        };
+
+        let items = vec![
+            ConfigItem {
+                key: "Port",
+                values: vec![ConfigValue::Number(80.7)],
+                children: vec![],
+            },
+            ConfigItem {
+                key: "RATIO",
+                values: vec![ConfigValue::String("0.5")],
+                children: vec![],
+            },
+            ConfigItem {
+                key: "Color",
+                values: vec![ConfigValue::String("blue")],
+                children: vec![],
+            },
+        ];
+
+        let lenient = DeserializerOptions::new()
+            .case_insensitive(true)
+            .allow_truncation(true);
+        let actual: MyStruct = from_collectd_with(&items, lenient).unwrap();
+        assert_eq!(
+            actual,
+            MyStruct {
+                port: 80,
+                ratio: Some(0.5),
+            }
+        );
+
+        let strict = lenient.strict_numbers(true);
+        let err = from_collectd_with::<MyStruct>(&items, strict).unwrap_err();
+        assert_eq!(err.to_string(), "Expecting number");
+
+        let reject = lenient.unknown_keys(UnknownKeys::Reject);
+        let err = from_collectd_with::<MyStruct>(&items, reject).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown option `Color`");
+
+        // By default option names must match exactly and fractions aren't truncated
+        let err = from_collectd::<MyStruct>(&items).unwrap_err();
+        assert_eq!(err.to_string(), "Error from deserialization: missing field `port`");
+        let insensitive = DeserializerOptions::new().case_insensitive(true);
+        let err = from_collectd_with::<MyStruct>(&items, insensitive).unwrap_err();
+        assert_eq!(err.to_string(), "Expecting integer, received `80.7`");
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_serde_chrono() {
+        use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+
+        #[derive(Deserialize, PartialEq, Eq, Debug)]
+        struct MyStruct {
+            full_scan_at: NaiveTime,
+            not_before: DateTime<Utc>,
+        };
+
+        let items = vec![
+            ConfigItem {
+                key: "full_scan_at",
+                values: vec![ConfigValue::String("03:00")],
+                children: vec![],
+            },
+            ConfigItem {
+                key: "not_before",
+                values: vec![ConfigValue::String("2018-01-02T03:04:05Z")],
+                children: vec![],
+            },
+        ];
+
+        let actual = from_collectd(&items).unwrap();
+        assert_eq!(
+            MyStruct {
+                full_scan_at: NaiveTime::from_hms_opt(3, 0, 0).unwrap(),
+                not_before: Utc.with_ymd_and_hms(2018, 1, 2, 3, 4, 5).unwrap(),
+            },
+            actual
+        );
+    }
 }
@@ -0,0 +1,138 @@
+//! Almost every plugin reads a file or socket whose path is in its config. A `PathBuf` field takes
+//! the path as written, while `ExistingPath` and `ReadablePath` check the path while the config is
+//! deserialized, so that a typo is reported against its option when collectd starts rather than
+//! at the first read.
+
+use serde::de::{self, Deserialize, Deserializer};
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A path from the config that must exist
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct ExistingPath(pub PathBuf);
+
+/// A path from the config that must exist and be readable by collectd: a file that can be opened
+/// or a directory that can be listed
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct ReadablePath(pub PathBuf);
+
+impl Deref for ExistingPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Deref for ReadablePath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl From<ExistingPath> for PathBuf {
+    fn from(p: ExistingPath) -> PathBuf {
+        p.0
+    }
+}
+
+impl From<ReadablePath> for PathBuf {
+    fn from(p: ReadablePath) -> PathBuf {
+        p.0
+    }
+}
+
+fn check_exists(path: &Path) -> Result<fs::Metadata, String> {
+    fs::metadata(path).map_err(|e| format!("`{}` does not exist: {}", path.display(), e))
+}
+
+fn check_readable(path: &Path) -> Result<(), String> {
+    let readable = if check_exists(path)?.is_dir() {
+        fs::read_dir(path).map(|_| ())
+    } else {
+        fs::File::open(path).map(|_| ())
+    };
+
+    readable.map_err(|e| format!("`{}` is not readable: {}", path.display(), e))
+}
+
+impl<'de> Deserialize<'de> for ExistingPath {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let path = PathBuf::deserialize(deserializer)?;
+        check_exists(&path).map_err(de::Error::custom)?;
+        Ok(ExistingPath(path))
+    }
+}
+
+impl<'de> Deserialize<'de> for ReadablePath {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let path = PathBuf::deserialize(deserializer)?;
+        check_readable(&path).map_err(de::Error::custom)?;
+        Ok(ReadablePath(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{ConfigItem, ConfigValue};
+    use de::from_collectd;
+
+    #[test]
+    fn test_serde_paths() {
+        #[derive(Deserialize, PartialEq, Eq, Debug)]
+        #[serde(rename_all = "PascalCase")]
+        struct MyStruct {
+            socket: PathBuf,
+            manifest: ReadablePath,
+            source: ExistingPath,
+        };
+
+        let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let source = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+        let items = |manifest| {
+            vec![
+                ConfigItem {
+                    key: "Socket",
+                    values: vec![ConfigValue::String("/var/run/missing.sock")],
+                    children: vec![],
+                },
+                ConfigItem {
+                    key: "Manifest",
+                    values: vec![ConfigValue::String(manifest)],
+                    children: vec![],
+                },
+                ConfigItem {
+                    key: "Source",
+                    values: vec![ConfigValue::String(source)],
+                    children: vec![],
+                },
+            ]
+        };
+
+        let actual: MyStruct = from_collectd(&items(manifest)).unwrap();
+        assert_eq!(
+            actual,
+            MyStruct {
+                socket: PathBuf::from("/var/run/missing.sock"),
+                manifest: ReadablePath(PathBuf::from(manifest)),
+                source: ExistingPath(PathBuf::from(source)),
+            }
+        );
+
+        let err = from_collectd::<MyStruct>(&items("/nonexistent/Cargo.toml")).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Invalid option `Manifest`: `/nonexistent/Cargo.toml` does not exist")
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Match and filter style plugins almost always take patterns from their config, so with the
+//! `regex` feature a `Pattern` field is compiled while the config is deserialized, and an invalid
+//! pattern is reported like any other config error.
+
+use regex::Regex;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use std::fmt;
+use std::ops::Deref;
+
+/// A regular expression from the config (eg: `Interface "^eth[0-9]+$"`), which dereferences to
+/// the compiled `Regex`
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl Deref for Pattern {
+    type Target = Regex;
+
+    fn deref(&self) -> &Regex {
+        &self.0
+    }
+}
+
+/// Patterns are equal when they were compiled from the same expression
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PatternVisitor)
+    }
+}
+
+struct PatternVisitor;
+
+impl<'de> Visitor<'de> for PatternVisitor {
+    type Value = Pattern;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a regular expression")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Pattern, E> {
+        Regex::new(v)
+            .map(Pattern)
+            .map_err(|e| E::custom(format!("invalid pattern `{}`: {}", v, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{ConfigItem, ConfigValue};
+    use de::from_collectd;
+
+    #[test]
+    fn test_serde_pattern() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct MyStruct {
+            interface: Vec<Pattern>,
+        };
+
+        let items = |pattern| {
+            vec![
+                ConfigItem {
+                    key: "interface",
+                    values: vec![ConfigValue::String(pattern)],
+                    children: vec![],
+                },
+            ]
+        };
+
+        let actual: MyStruct = from_collectd(&items("^eth[0-9]+$")).unwrap();
+        assert!(actual.interface[0].is_match("eth0"));
+        assert!(!actual.interface[0].is_match("lo"));
+
+        let err = from_collectd::<MyStruct>(&items("eth(")).unwrap_err();
+        assert!(err.to_string().contains("invalid pattern `eth(`"));
+    }
+}
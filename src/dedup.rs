@@ -0,0 +1,131 @@
+//! A metric that is reported both by a local plugin and by a network peer (or by several peers
+//! forwarding the same host) reaches write plugins once per path. `Deduplicator` remembers the
+//! identifier and time of the most recently seen value lists, so a write plugin can drop the
+//! repeats instead of writing the same point to its backend twice.
+
+use api::RecvValueList;
+use identifier::Identifier;
+use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
+
+type Key = (Identifier, SystemTime);
+
+/// Recognizes value lists with the same identifier and time as one seen before, remembering a
+/// bounded number of lists and forgetting the least recently seen first
+#[derive(Debug, Clone)]
+pub struct Deduplicator {
+    capacity: usize,
+    tick: u64,
+    seen: HashMap<Key, u64>,
+    order: BTreeMap<u64, Key>,
+    duplicates: u64,
+}
+
+impl Deduplicator {
+    /// Remembers up to `capacity` value lists, which should cover the lists received in the time
+    /// it takes for a duplicate to arrive (eg: the number of identifiers written per interval)
+    pub fn new(capacity: usize) -> Self {
+        Deduplicator {
+            capacity: capacity.max(1),
+            tick: 0,
+            seen: HashMap::new(),
+            order: BTreeMap::new(),
+            duplicates: 0,
+        }
+    }
+
+    /// Returns if a list with the same identifier and time has been seen, remembering the list
+    /// if it hasn't
+    pub fn is_duplicate(&mut self, list: &RecvValueList) -> bool {
+        self.check((Identifier::from(list), list.time))
+    }
+
+    fn check(&mut self, key: Key) -> bool {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(last) = self.seen.get_mut(&key) {
+            let key = self.order.remove(last).expect("seen lists to be ordered");
+            *last = tick;
+            self.order.insert(tick, key);
+            self.duplicates += 1;
+            return true;
+        }
+
+        if self.seen.len() >= self.capacity {
+            let oldest = self.order.keys().next().cloned();
+            if let Some(key) = oldest.and_then(|x| self.order.remove(&x)) {
+                self.seen.remove(&key);
+            }
+        }
+
+        self.seen.insert(key.clone(), tick);
+        self.order.insert(tick, key);
+        false
+    }
+
+    /// The number of duplicates recognized so far
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// The number of lists remembered
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Forgets every list seen
+    pub fn clear(&mut self) {
+        self.seen.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{Value, ValueReport};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn list<'a>(plugin_instance: &'a str, secs: u64) -> RecvValueList<'a> {
+        RecvValueList {
+            values: smallvec![ValueReport {
+                name: "value",
+                value: Value::Gauge(1.0),
+                min: 0.0,
+                max: 100.0,
+            }],
+            plugin_instance: Some(plugin_instance),
+            plugin: "cpu",
+            type_: "percent",
+            type_instance: Some("idle"),
+            host: "localhost",
+            time: UNIX_EPOCH + Duration::from_secs(secs),
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn test_duplicates_dropped() {
+        let mut dedup = Deduplicator::new(2);
+        assert!(!dedup.is_duplicate(&list("0", 10)));
+        assert!(dedup.is_duplicate(&list("0", 10)));
+
+        // A new time or identifier isn't a duplicate
+        assert!(!dedup.is_duplicate(&list("0", 20)));
+        assert!(!dedup.is_duplicate(&list("1", 20)));
+        assert_eq!(dedup.len(), 2);
+
+        // The first list was the least recently seen, so it made room for the last
+        assert!(!dedup.is_duplicate(&list("0", 10)));
+        assert!(dedup.is_duplicate(&list("1", 20)));
+        assert!(!dedup.is_duplicate(&list("0", 20)));
+        assert_eq!(dedup.duplicates(), 2);
+
+        dedup.clear();
+        assert!(dedup.is_empty());
+    }
+}
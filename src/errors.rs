@@ -1,10 +1,35 @@
+use std::error;
 use std::ffi::NulError;
+use std::fmt;
+use std::str::Utf8Error;
 
-#[derive(Fail, Debug)]
+/// The error type that plugins and plugin managers return. Any error that implements
+/// `std::error::Error` (as well as strings and `anyhow::Error`) converts into it with `?`, so
+/// plugins are free to define errors however they see fit.
+pub type Error = Box<dyn error::Error + Send + Sync + 'static>;
+
+#[derive(Debug)]
 pub enum ArrayError {
-    #[fail(display = "Null encountered in string")] NullPresent(#[cause] NulError),
+    NullPresent(NulError),
+    TooLong(usize),
+}
+
+impl fmt::Display for ArrayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArrayError::NullPresent(_) => write!(f, "Null encountered in string"),
+            ArrayError::TooLong(len) => write!(f, "Length of {} is too long", len),
+        }
+    }
+}
 
-    #[fail(display = "Length of {} is too long", _0)] TooLong(usize),
+impl error::Error for ArrayError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ArrayError::NullPresent(ref e) => Some(e),
+            ArrayError::TooLong(_) => None,
+        }
+    }
 }
 
 impl From<NulError> for ArrayError {
@@ -13,11 +38,252 @@ impl From<NulError> for ArrayError {
     }
 }
 
-#[derive(Fail, Debug)]
+#[derive(Debug)]
 pub enum SubmitError {
-    #[fail(display = "plugin_dispatch_values returned an error: {}", _0)] DispatchError(i32),
+    DispatchError(i32),
+
+    /// `plugin_dispatch_notification` returned an error
+    NotificationError(i32),
+
+    /// `plugin_write` returned an error for the named write plugin (eg: it isn't loaded)
+    WriteError(String, i32),
+
+    /// The named field couldn't be converted into one of collectd's text fields
+    Field(&'static str, ArrayError),
+
+    /// The named string contains a null character, so collectd would only see part of it
+    InvalidString(&'static str, NulError),
+}
+
+impl SubmitError {
+    /// The error for a string that couldn't be converted into the named text field
+    pub fn field(name: &'static str, err: ArrayError) -> SubmitError {
+        match err {
+            ArrayError::NullPresent(e) => SubmitError::InvalidString(name, e),
+            e => SubmitError::Field(name, e),
+        }
+    }
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SubmitError::DispatchError(code) => {
+                write!(f, "plugin_dispatch_values returned an error: {}", code)
+            }
+            SubmitError::NotificationError(code) => {
+                write!(f, "plugin_dispatch_notification returned an error: {}", code)
+            }
+            SubmitError::WriteError(ref plugin, code) => {
+                write!(f, "plugin_write to {} returned an error: {}", plugin, code)
+            }
+            SubmitError::Field(name, ref e) => write!(f, "{}: {}", name, e),
+            SubmitError::InvalidString(name, ref e) => {
+                write!(f, "{} contains a null character: {}", name, e)
+            }
+        }
+    }
+}
+
+impl error::Error for SubmitError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            SubmitError::DispatchError(_)
+            | SubmitError::NotificationError(_)
+            | SubmitError::WriteError(_, _) => None,
+            SubmitError::Field(_, ref e) => Some(e),
+            SubmitError::InvalidString(_, ref e) => Some(e),
+        }
+    }
+}
+
+/// Errors from converting a value list (or notification) received from collectd
+#[derive(Debug)]
+pub enum ReceiveError {
+    /// The described text field is not valid UTF-8
+    Utf8(String, Utf8Error),
+
+    /// A data source of the plugin has a type this crate doesn't know about
+    UnknownDataSourceType(String, i32),
+
+    /// A notification's severity isn't one of collectd's `NOTIF_*`
+    UnknownSeverity(i32),
+
+    /// A notification's metadata couldn't be read
+    Meta(MetaDataError),
+
+    /// collectd's cache has no rates for the described value list
+    RatesUnavailable(String),
+
+    /// The plugin's value list left the named timestamp unset
+    MissingTime(String, &'static str),
+}
+
+impl fmt::Display for ReceiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReceiveError::Utf8(ref field, _) => write!(f, "{} could not be decoded", field),
+            ReceiveError::UnknownDataSourceType(ref plugin, t) => write!(
+                f,
+                "For plugin: {}, unrecognized data source type: {}",
+                plugin, t
+            ),
+            ReceiveError::UnknownSeverity(s) => {
+                write!(f, "unrecognized notification severity: {}", s)
+            }
+            ReceiveError::Meta(ref e) => write!(f, "notification metadata: {}", e),
+            ReceiveError::RatesUnavailable(ref list) => {
+                write!(f, "collectd's cache has no rates for {}", list)
+            }
+            ReceiveError::MissingTime(ref plugin, what) => {
+                write!(f, "For plugin: {}, value list has no {}", plugin, what)
+            }
+        }
+    }
+}
+
+impl error::Error for ReceiveError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ReceiveError::Utf8(_, ref e) => Some(e),
+            ReceiveError::Meta(ref e) => Some(e),
+            ReceiveError::UnknownDataSourceType(_, _)
+            | ReceiveError::UnknownSeverity(_)
+            | ReceiveError::RatesUnavailable(_)
+            | ReceiveError::MissingTime(_, _) => None,
+        }
+    }
+}
+
+impl From<MetaDataError> for ReceiveError {
+    fn from(e: MetaDataError) -> Self {
+        ReceiveError::Meta(e)
+    }
+}
+
+/// Errors from converting collectd's configuration tree
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The named part of the configuration is not valid UTF-8
+    Utf8(&'static str, Utf8Error),
+
+    /// A value has a type this crate doesn't know about
+    UnknownType(i32),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Utf8(what, _) => write!(f, "failed to decode {}", what),
+            ConfigError::UnknownType(t) => write!(f, "Unrecognized value: {}", t),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ConfigError::Utf8(_, ref e) => Some(e),
+            ConfigError::UnknownType(_) => None,
+        }
+    }
 }
 
-#[derive(Fail, Debug)]
-#[fail(display = "Function is not implemented")]
+/// Displays an error followed by each of its causes (eg: `read error: unable to open config:
+/// No such file or directory`), as the outermost error alone often doesn't say what failed
+pub struct ErrorChain<'a>(pub &'a (dyn error::Error + 'static));
+
+impl<'a> fmt::Display for ErrorChain<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(cause) = source {
+            write!(f, ": {}", cause)?;
+            source = cause.source();
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
 pub struct NotImplemented;
+
+impl fmt::Display for NotImplemented {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Function is not implemented")
+    }
+}
+
+impl error::Error for NotImplemented {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownLogLevel(pub String);
+
+impl fmt::Display for UnknownLogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unrecognized log level: {}", self.0)
+    }
+}
+
+impl error::Error for UnknownLogLevel {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AbiMismatch {
+    pub compiled: &'static str,
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl fmt::Display for AbiMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "plugin was compiled against collectd {} bindings where text fields are {} bytes, \
+             but the running collectd has text fields of {} bytes. Rebuild the plugin with the \
+             collectd feature matching the daemon (collectd-54, collectd-55, collectd-57)",
+            self.compiled, self.expected, self.actual
+        )
+    }
+}
+
+impl error::Error for AbiMismatch {}
+
+#[derive(Debug)]
+pub enum MetaDataError {
+    NullPresent(NulError),
+    Allocation,
+    Call(&'static str, String, i32),
+    UnknownType(String, i32),
+}
+
+impl fmt::Display for MetaDataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MetaDataError::NullPresent(_) => write!(f, "Null encountered in string"),
+            MetaDataError::Allocation => write!(f, "meta_data_create failed to allocate"),
+            MetaDataError::Call(func, ref key, code) => write!(
+                f,
+                "meta_data_{} for key {} returned an error: {}",
+                func, key, code
+            ),
+            MetaDataError::UnknownType(ref key, t) => {
+                write!(f, "Unrecognized type for key {}: {}", key, t)
+            }
+        }
+    }
+}
+
+impl error::Error for MetaDataError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            MetaDataError::NullPresent(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<NulError> for MetaDataError {
+    fn from(err: NulError) -> MetaDataError {
+        MetaDataError::NullPresent(err)
+    }
+}
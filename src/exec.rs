@@ -0,0 +1,252 @@
+//! Helpers for writing binaries run by collectd's `exec` plugin, which reads `PUTVAL` and
+//! `PUTNOTIF` commands from the binary's stdout. The daemon passes its hostname and interval in
+//! the environment.
+//!
+//! ```no_run
+//! use collectd_plugin::Value;
+//! use collectd_plugin::exec::Exec;
+//!
+//! # fn main() -> Result<(), collectd_plugin::Error> {
+//! let exec = Exec::from_env()?;
+//! exec.run(|out| {
+//!     let list = exec.value_list("magic", "gauge", vec![Value::Gauge(42.0)]);
+//!     out.putval(&list)?;
+//!     Ok(())
+//! })
+//! # }
+//! ```
+
+use api::{CdTime, Value};
+use errors::Error;
+use network::{Notification, ValueList};
+use plaintext::{putnotif_command, putval_command};
+use std::env;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The hostname used when `COLLECTD_HOSTNAME` isn't set
+const DEFAULT_HOSTNAME: &str = "localhost";
+
+/// The interval used when `COLLECTD_INTERVAL` isn't set, which is collectd's default
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// `COLLECTD_INTERVAL` isn't a positive number of seconds
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InvalidInterval(pub String);
+
+impl fmt::Display for InvalidInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid COLLECTD_INTERVAL: {}", self.0)
+    }
+}
+
+impl error::Error for InvalidInterval {}
+
+/// Writes commands to the `exec` plugin. Each command is flushed immediately, as stdout is
+/// buffered when piped to the daemon.
+#[derive(Debug)]
+pub struct ExecWriter<W: Write> {
+    inner: W,
+}
+
+impl ExecWriter<io::Stdout> {
+    pub fn stdout() -> Self {
+        ExecWriter::new(io::stdout())
+    }
+}
+
+impl<W: Write> ExecWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ExecWriter { inner: inner }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    pub fn putval(&mut self, list: &ValueList) -> io::Result<()> {
+        self.command(&putval_command(list))
+    }
+
+    pub fn putnotif(&mut self, notif: &Notification) -> io::Result<()> {
+        self.command(&putnotif_command(notif))
+    }
+
+    fn command(&mut self, cmd: &str) -> io::Result<()> {
+        writeln!(self.inner, "{}", cmd)?;
+        self.inner.flush()
+    }
+}
+
+/// The hostname and interval that the `exec` plugin runs the binary with
+#[derive(Debug, PartialEq, Clone)]
+pub struct Exec {
+    hostname: String,
+    interval: Duration,
+}
+
+impl Exec {
+    pub fn new(hostname: &str, interval: Duration) -> Self {
+        Exec {
+            hostname: String::from(hostname),
+            interval: interval,
+        }
+    }
+
+    /// Reads `COLLECTD_HOSTNAME` and `COLLECTD_INTERVAL`, falling back to `localhost` and ten
+    /// seconds when the binary isn't run by collectd.
+    pub fn from_env() -> Result<Self, InvalidInterval> {
+        let hostname = env::var("COLLECTD_HOSTNAME").ok();
+        let interval = env::var("COLLECTD_INTERVAL").ok();
+        Exec::from_vars(
+            hostname.as_ref().map(|x| x.as_str()),
+            interval.as_ref().map(|x| x.as_str()),
+        )
+    }
+
+    fn from_vars(hostname: Option<&str>, interval: Option<&str>) -> Result<Self, InvalidInterval> {
+        let interval = match interval {
+            None => DEFAULT_INTERVAL,
+            Some(s) => match s.trim().parse::<f64>() {
+                Ok(secs) if secs > 0.0 && secs.is_finite() => {
+                    Duration::from(CdTime((secs * 1e9) as u64))
+                }
+                _ => return Err(InvalidInterval(String::from(s))),
+            },
+        };
+
+        let hostname = hostname.filter(|x| !x.is_empty()).unwrap_or(DEFAULT_HOSTNAME);
+        Ok(Exec::new(hostname, interval))
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// A value list for this host and interval, timestamped by the daemon when it's received
+    pub fn value_list(&self, plugin: &str, type_: &str, values: Vec<Value>) -> ValueList {
+        ValueList {
+            host: self.hostname.clone(),
+            plugin: String::from(plugin),
+            plugin_instance: None,
+            type_: String::from(type_),
+            type_instance: None,
+            time: CdTime(0),
+            interval: CdTime::from(self.interval),
+            values: values,
+        }
+    }
+
+    /// Calls `tick` once per interval, writing to stdout. Returns once the daemon closes stdout
+    /// (as it does when shutting down) or `tick` fails.
+    pub fn run<F>(&self, tick: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut ExecWriter<io::Stdout>) -> Result<(), Error>,
+    {
+        self.run_with(&mut ExecWriter::stdout(), tick)
+    }
+
+    fn run_with<W, F>(&self, out: &mut ExecWriter<W>, mut tick: F) -> Result<(), Error>
+    where
+        W: Write,
+        F: FnMut(&mut ExecWriter<W>) -> Result<(), Error>,
+    {
+        let mut next = Instant::now();
+        loop {
+            if let Err(e) = tick(out) {
+                let closed = e.downcast_ref::<io::Error>()
+                    .map_or(false, |e| e.kind() == io::ErrorKind::BrokenPipe);
+                return if closed { Ok(()) } else { Err(e) };
+            }
+
+            // Ticks that overrun the interval push back the schedule instead of firing in a burst
+            next += self.interval;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            } else {
+                next = now;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use constants::Severity;
+
+    #[test]
+    fn test_from_vars() {
+        let exec = Exec::from_vars(Some("web-1"), Some("2.500")).unwrap();
+        assert_eq!(exec.hostname(), "web-1");
+        assert_eq!(exec.interval(), Duration::from_millis(2500));
+
+        let exec = Exec::from_vars(None, None).unwrap();
+        assert_eq!(exec, Exec::new("localhost", Duration::from_secs(10)));
+
+        assert_eq!(
+            Exec::from_vars(None, Some("soon")),
+            Err(InvalidInterval(String::from("soon")))
+        );
+        assert!(Exec::from_vars(None, Some("0")).is_err());
+    }
+
+    #[test]
+    fn test_writer() {
+        let exec = Exec::new("my host", Duration::from_secs(10));
+        let mut out = ExecWriter::new(Vec::new());
+        let mut list = exec.value_list("magic", "gauge", vec![Value::Gauge(1.5)]);
+        list.type_instance = Some(String::from("answer"));
+        out.putval(&list).unwrap();
+
+        let notif = Notification {
+            host: String::from("my host"),
+            plugin: String::from("magic"),
+            plugin_instance: None,
+            type_: String::from("gauge"),
+            type_instance: None,
+            time: CdTime(0),
+            severity: Severity::Okay,
+            message: String::from("all good"),
+        };
+        out.putnotif(&notif).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out.into_inner()).unwrap(),
+            "PUTVAL \"my host/magic/gauge-answer\" interval=10.000000000 N:1.5\n\
+             PUTNOTIF severity=okay time=N host=\"my host\" plugin=magic type=gauge \
+             message=\"all good\"\n"
+        );
+    }
+
+    #[test]
+    fn test_run_until_closed() {
+        let exec = Exec::new("localhost", Duration::from_millis(1));
+        let mut out = ExecWriter::new(Vec::new());
+        let mut ticks = 0;
+        let res = exec.run_with(&mut out, |out| {
+            ticks += 1;
+            if ticks == 3 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed").into());
+            }
+
+            out.putval(&exec.value_list("magic", "gauge", vec![Value::Gauge(1.0)]))?;
+            Ok(())
+        });
+
+        assert!(res.is_ok());
+        assert_eq!(ticks, 3);
+        assert_eq!(out.into_inner().iter().filter(|&&x| x == b'\n').count(), 2);
+
+        let res = exec.run_with(&mut ExecWriter::new(Vec::new()), |_| Err("oops".into()));
+        assert_eq!(res.unwrap_err().to_string(), "oops");
+    }
+}
@@ -0,0 +1,456 @@
+//! Support for implementing `<Match>` rules and `<Target>` actions of collectd's filter chains.
+//! Unlike plugins, collectd creates a new match or target for every block that references it, so
+//! they are registered as constructors that receive the block's configuration.
+
+use api::{collectd_log, to_array_res, ConfigItem, ConfigValue, LogLevel, MetaData, MetaValue,
+          RecvValueList};
+use bindings::{data_set_t, fc_register_match, fc_register_target, match_proc_t,
+               notification_meta_t, oconfig_item_t, target_proc_t, value_list_t,
+               FC_MATCH_MATCHES, FC_MATCH_NO_MATCH, FC_TARGET_CONTINUE, FC_TARGET_RETURN,
+               FC_TARGET_STOP};
+use errors::{ArrayError, Error, MetaDataError};
+use panics::catch_panic;
+use std::ffi::CString;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+/// A rule that decides whether a value list should be handled by the targets of a filter chain
+/// rule. Collectd evaluates matches from every thread that dispatches values, hence `Sync`.
+pub trait Match: Send + Sync {
+    /// Whether the value list satisfies the rule
+    fn matches(&self, list: &RecvValueList) -> Result<bool, Error>;
+}
+
+/// An action taken on value lists that satisfied a filter chain rule. Like matches, targets are
+/// invoked from every thread that dispatches values.
+pub trait Target: Send + Sync {
+    /// Acts on (and possibly rewrites) the value list, and decides how the chain proceeds
+    fn invoke(&self, list: &mut TargetValueList) -> Result<TargetResult, Error>;
+}
+
+/// How collectd should proceed after a target is invoked
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TargetResult {
+    /// Continue with the next target or rule
+    Continue,
+
+    /// Stop processing the value list entirely, which drops it
+    Stop,
+
+    /// Stop processing the current chain and return to the calling chain
+    Return,
+}
+
+/// Creates a match from the `<Match>` block, which holds the match's name as its sole value and
+/// the match's options as children
+pub type MatchConstructor = fn(config: &ConfigItem) -> Result<Box<dyn Match>, Error>;
+
+/// Creates a target from the `<Target>` block, which holds the target's name as its sole value
+/// and the target's options as children
+pub type TargetConstructor = fn(config: &ConfigItem) -> Result<Box<dyn Target>, Error>;
+
+/// A value list handed to a target, whose identity and metadata can be rewritten before it
+/// continues through the chain
+pub struct TargetValueList<'a> {
+    ds: &'a data_set_t,
+    list: &'a mut value_list_t,
+}
+
+impl<'a> TargetValueList<'a> {
+    /// The value list as received
+    pub fn values(&self) -> Result<RecvValueList, Error> {
+        Ok(RecvValueList::from(self.ds, self.list)?)
+    }
+
+    pub fn set_host(&mut self, host: &str) -> Result<(), ArrayError> {
+        self.list.host = to_array_res(host)?;
+        Ok(())
+    }
+
+    pub fn set_plugin(&mut self, plugin: &str) -> Result<(), ArrayError> {
+        self.list.plugin = to_array_res(plugin)?;
+        Ok(())
+    }
+
+    pub fn set_plugin_instance(&mut self, instance: &str) -> Result<(), ArrayError> {
+        self.list.plugin_instance = to_array_res(instance)?;
+        Ok(())
+    }
+
+    pub fn set_type_instance(&mut self, instance: &str) -> Result<(), ArrayError> {
+        self.list.type_instance = to_array_res(instance)?;
+        Ok(())
+    }
+
+    /// Adds (or replaces) a metadata entry, creating the value list's metadata if absent
+    pub fn add_meta(&mut self, key: &str, value: &MetaValue) -> Result<(), MetaDataError> {
+        // The metadata belongs to the value list, so it is only borrowed here and never destroyed
+        let mut meta = match unsafe { MetaData::from_raw(self.list.meta) } {
+            Some(meta) => meta,
+            None => MetaData::new()?,
+        };
+
+        let res = meta.add(key, value);
+        self.list.meta = meta.into_raw();
+        res
+    }
+}
+
+// Collectd's create callbacks don't carry user data, so constructors are looked up by the name
+// given in the block
+static MATCHES: Mutex<Vec<(String, MatchConstructor)>> = Mutex::new(Vec::new());
+static TARGETS: Mutex<Vec<(String, TargetConstructor)>> = Mutex::new(Vec::new());
+
+/// Registers a match with collectd's filter chain under the given name
+pub fn register_match(name: &str, constructor: MatchConstructor) {
+    let s = match CString::new(name) {
+        Ok(x) => x,
+        Err(e) => {
+            collectd_log(
+                LogLevel::Error,
+                &format!("unable to register match {}: {}", name, e),
+            );
+            return;
+        }
+    };
+    let procs = match_proc_t {
+        create: Some(match_create),
+        destroy: Some(match_destroy),
+        match_: Some(match_invoke),
+    };
+
+    lock(&MATCHES).push((String::from(name), constructor));
+    unsafe { fc_register_match(s.as_ptr(), procs) };
+}
+
+/// Registers a target with collectd's filter chain under the given name
+pub fn register_target(name: &str, constructor: TargetConstructor) {
+    let s = match CString::new(name) {
+        Ok(x) => x,
+        Err(e) => {
+            collectd_log(
+                LogLevel::Error,
+                &format!("unable to register target {}: {}", name, e),
+            );
+            return;
+        }
+    };
+    let procs = target_proc_t {
+        create: Some(target_create),
+        destroy: Some(target_destroy),
+        invoke: Some(target_invoke),
+    };
+
+    lock(&TARGETS).push((String::from(name), constructor));
+    unsafe { fc_register_target(s.as_ptr(), procs) };
+}
+
+/// Logs the error at the error level and reports failure to collectd
+fn report(context: &str, res: Result<c_int, Error>) -> c_int {
+    match res {
+        Ok(x) => x,
+        Err(ref e) => {
+            collectd_log(LogLevel::Error, &format!("{} error: {}", context, e));
+            -1
+        }
+    }
+}
+
+fn lock<T>(registry: &Mutex<T>) -> MutexGuard<T> {
+    // Constructors are only ever appended, so a panic can't leave the registry inconsistent
+    registry.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Finds the constructor for a `<Match "name">` or `<Target "name">` block
+fn constructor<T: Copy>(
+    registry: &Mutex<Vec<(String, T)>>,
+    kind: &str,
+    config: &ConfigItem,
+) -> Result<T, Error> {
+    let name = match config.values.first() {
+        Some(&ConfigValue::String(x)) => x,
+        _ => return Err(format!("<{}> block is missing the {} name", kind, kind).into()),
+    };
+
+    lock(registry)
+        .iter()
+        .find(|&&(ref n, _)| n == name)
+        .map(|&(_, c)| c)
+        .ok_or_else(|| format!("no {} registered as {}", kind, name).into())
+}
+
+unsafe extern "C" fn match_create(
+    ci: *const oconfig_item_t,
+    user_data: *mut *mut c_void,
+) -> c_int {
+    catch_panic("match create", || {
+        let res = ConfigItem::from(&*ci).map_err(Error::from).and_then(|config| {
+            let m = constructor(&MATCHES, "Match", &config)?(&config)?;
+            *user_data = Box::into_raw(Box::new(m)) as *mut c_void;
+            Ok(0)
+        });
+        report("match create", res)
+    })
+}
+
+unsafe extern "C" fn match_destroy(user_data: *mut *mut c_void) -> c_int {
+    if !user_data.is_null() && !(*user_data).is_null() {
+        drop(Box::from_raw(*user_data as *mut Box<dyn Match>));
+        *user_data = ptr::null_mut();
+    }
+    0
+}
+
+unsafe extern "C" fn match_invoke(
+    ds: *const data_set_t,
+    vl: *const value_list_t,
+    _meta: *mut *mut notification_meta_t,
+    user_data: *mut *mut c_void,
+) -> c_int {
+    catch_panic("match", || {
+        let m = &*(*user_data as *mut Box<dyn Match>);
+        let res = RecvValueList::from(&*ds, &*vl)
+            .map_err(Error::from)
+            .and_then(|list| m.matches(&list));
+        let res = res.map(|matched| {
+            if matched {
+                FC_MATCH_MATCHES
+            } else {
+                FC_MATCH_NO_MATCH
+            }
+        });
+        report("match", res)
+    })
+}
+
+unsafe extern "C" fn target_create(
+    ci: *const oconfig_item_t,
+    user_data: *mut *mut c_void,
+) -> c_int {
+    catch_panic("target create", || {
+        let res = ConfigItem::from(&*ci).map_err(Error::from).and_then(|config| {
+            let t = constructor(&TARGETS, "Target", &config)?(&config)?;
+            *user_data = Box::into_raw(Box::new(t)) as *mut c_void;
+            Ok(0)
+        });
+        report("target create", res)
+    })
+}
+
+unsafe extern "C" fn target_destroy(user_data: *mut *mut c_void) -> c_int {
+    if !user_data.is_null() && !(*user_data).is_null() {
+        drop(Box::from_raw(*user_data as *mut Box<dyn Target>));
+        *user_data = ptr::null_mut();
+    }
+    0
+}
+
+unsafe extern "C" fn target_invoke(
+    ds: *const data_set_t,
+    vl: *mut value_list_t,
+    _meta: *mut *mut notification_meta_t,
+    user_data: *mut *mut c_void,
+) -> c_int {
+    catch_panic("target", || {
+        let t = &*(*user_data as *mut Box<dyn Target>);
+        let mut list = TargetValueList {
+            ds: &*ds,
+            list: &mut *vl,
+        };
+
+        let res = t.invoke(&mut list).map(|x| match x {
+            TargetResult::Continue => FC_TARGET_CONTINUE,
+            TargetResult::Stop => FC_TARGET_STOP,
+            TargetResult::Return => FC_TARGET_RETURN,
+        });
+        report("target", res)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bindings::{data_source_t, oconfig_value_s__bindgen_ty_1, oconfig_value_t, value_t,
+                   ARR_LENGTH};
+    use constants::{DS_TYPE_GAUGE, OCONFIG_TYPE_STRING};
+    use std::os::raw::c_char;
+    use stub;
+
+    struct HostIs(String);
+
+    impl Match for HostIs {
+        fn matches(&self, list: &RecvValueList) -> Result<bool, Error> {
+            Ok(list.host == self.0)
+        }
+    }
+
+    fn host_is(config: &ConfigItem) -> Result<Box<dyn Match>, Error> {
+        match config.children.first().and_then(|x| x.values.first()) {
+            Some(&ConfigValue::String(host)) => Ok(Box::new(HostIs(String::from(host)))),
+            _ => Err("Option is required".into()),
+        }
+    }
+
+    struct Rename(String);
+
+    impl Target for Rename {
+        fn invoke(&self, list: &mut TargetValueList) -> Result<TargetResult, Error> {
+            if list.values()?.plugin_instance.is_some() {
+                return Ok(TargetResult::Stop);
+            }
+
+            list.set_plugin_instance(&self.0)?;
+            list.add_meta("renamed", &MetaValue::Boolean(true))?;
+            Ok(TargetResult::Continue)
+        }
+    }
+
+    fn rename(config: &ConfigItem) -> Result<Box<dyn Target>, Error> {
+        match config.children.first().and_then(|x| x.values.first()) {
+            Some(&ConfigValue::String(x)) => Ok(Box::new(Rename(String::from(x)))),
+            _ => Err("Option is required".into()),
+        }
+    }
+
+    fn arr(s: &str) -> [c_char; ARR_LENGTH] {
+        let mut res = [0; ARR_LENGTH];
+        for (i, b) in s.bytes().enumerate() {
+            res[i] = b as c_char;
+        }
+        res
+    }
+
+    fn string_value(s: &CString) -> oconfig_value_t {
+        oconfig_value_t {
+            value: oconfig_value_s__bindgen_ty_1 {
+                string: s.as_ptr() as *mut c_char,
+            },
+            type_: OCONFIG_TYPE_STRING,
+        }
+    }
+
+    /// Builds a `<$key "$name">` block with an optional single child option, and a single gauge
+    /// value list from the given host and plugin instance, and passes them to the function
+    fn with_fixtures<F, R>(key: &str, name: &str, option: Option<&str>, host: &str, f: F) -> R
+    where
+        F: FnOnce(&oconfig_item_t, &data_set_t, &mut value_list_t) -> R,
+    {
+        let block_key = CString::new(key).unwrap();
+        let option_key = CString::new("Option").unwrap();
+        let name = CString::new(name).unwrap();
+        let option = option.map(|x| CString::new(x).unwrap());
+
+        let mut option_values: Vec<oconfig_value_t> = option.iter().map(string_value).collect();
+        let mut none: Vec<oconfig_item_t> = Vec::new();
+        let mut children: Vec<oconfig_item_t> = option
+            .iter()
+            .map(|_| oconfig_item_t {
+                key: option_key.as_ptr() as *mut c_char,
+                values: option_values.as_mut_ptr(),
+                values_num: option_values.len() as c_int,
+                parent: ptr::null_mut(),
+                children: none.as_mut_ptr(),
+                children_num: 0,
+            })
+            .collect();
+
+        let mut values = vec![string_value(&name)];
+        let block = oconfig_item_t {
+            key: block_key.as_ptr() as *mut c_char,
+            values: values.as_mut_ptr(),
+            values_num: 1,
+            parent: ptr::null_mut(),
+            children: children.as_mut_ptr(),
+            children_num: children.len() as c_int,
+        };
+
+        let mut sources = vec![
+            data_source_t {
+                name: arr("value"),
+                type_: DS_TYPE_GAUGE,
+                min: 0.0,
+                max: 1.0,
+            },
+        ];
+
+        let ds = data_set_t {
+            type_: arr("gauge"),
+            ds_num: 1,
+            ds: sources.as_mut_ptr(),
+        };
+
+        let mut vals = vec![value_t { gauge: 0.5 }];
+        let mut vl = value_list_t {
+            values: vals.as_mut_ptr(),
+            values_len: 1,
+            // One second in cdtime_t
+            time: 1 << 30,
+            interval: 1 << 30,
+            host: arr(host),
+            plugin: arr("myplugin"),
+            plugin_instance: arr(""),
+            type_: arr("gauge"),
+            type_instance: arr(""),
+            meta: ptr::null_mut(),
+        };
+
+        let res = f(&block, &ds, &mut vl);
+        unsafe { MetaData::from_raw(vl.meta) };
+        res
+    }
+
+    fn run_match(host: Option<&str>, list_host: &str) -> Option<c_int> {
+        with_fixtures("Match", "host_is", host, list_host, |block, ds, vl| {
+            unsafe { stub::filter_match("host_is", block, ds, vl) }
+        })
+    }
+
+    #[test]
+    fn test_register_match() {
+        stub::reset();
+        register_match("host_is", host_is);
+        assert_eq!(
+            stub::registrations(),
+            vec![(String::from("host_is"), stub::CallbackKind::Match)]
+        );
+
+        assert_eq!(run_match(Some("localhost"), "localhost"), Some(1));
+        assert_eq!(run_match(Some("localhost"), "otherhost"), Some(0));
+
+        // Constructor errors are reported as a failure to create
+        assert_eq!(run_match(None, "localhost"), Some(-1));
+        stub::reset();
+    }
+
+    #[test]
+    fn test_register_target() {
+        stub::reset();
+        register_target("rename", rename);
+        assert_eq!(
+            stub::registrations(),
+            vec![(String::from("rename"), stub::CallbackKind::Target)]
+        );
+
+        with_fixtures("Target", "rename", Some("renamed"), "localhost", |block, ds, vl| {
+            let res = unsafe { stub::filter_target("rename", block, ds, vl) };
+            assert_eq!(res, Some(0));
+            let list = RecvValueList::from(ds, vl).unwrap();
+            assert_eq!(list.plugin_instance, Some("renamed"));
+
+            let meta = unsafe { MetaData::from_raw(vl.meta) }.unwrap();
+            assert_eq!(meta.get("renamed").unwrap(), Some(MetaValue::Boolean(true)));
+            vl.meta = meta.into_raw();
+
+            // Renamed lists are dropped the second time around
+            let res = unsafe { stub::filter_target("rename", block, ds, vl) };
+            assert_eq!(res, Some(1));
+        });
+
+        with_fixtures("Target", "rename", None, "localhost", |block, ds, vl| {
+            let res = unsafe { stub::filter_target("rename", block, ds, vl) };
+            assert_eq!(res, Some(-1));
+        });
+        stub::reset();
+    }
+}
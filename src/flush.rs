@@ -0,0 +1,196 @@
+//! collectd asks write plugins to flush values older than a timeout, optionally only those of a
+//! single identifier (eg: `collectdctl flush identifier=host/cpu-0/percent-idle`). Honoring both
+//! means keeping buffered entries by identifier and time, which `FlushTracker` does so that a
+//! write plugin's flush callback can hand its arguments straight to `flush`.
+
+use api::RecvValueList;
+use identifier::Identifier;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// Buffered entries of a write plugin, keyed by the identifier of their value list
+#[derive(Debug, Clone)]
+pub struct FlushTracker<T> {
+    entries: HashMap<Identifier, Vec<(SystemTime, T)>>,
+    len: usize,
+}
+
+impl<T> Default for FlushTracker<T> {
+    fn default() -> Self {
+        FlushTracker {
+            entries: HashMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> FlushTracker<T> {
+    pub fn new() -> Self {
+        FlushTracker::default()
+    }
+
+    /// Buffers an entry (eg: the formatted line) for the received value list
+    pub fn push(&mut self, list: &RecvValueList, entry: T) {
+        self.push_at(Identifier::from(list), list.time, entry);
+    }
+
+    /// Buffers an entry for the identifier, as observed at the given time
+    pub fn push_at(&mut self, identifier: Identifier, time: SystemTime, entry: T) {
+        self.entries
+            .entry(identifier)
+            .or_insert_with(Vec::new)
+            .push((time, entry));
+        self.len += 1;
+    }
+
+    /// Takes the entries that collectd asked to be flushed, as given to `Plugin::flush`: entries
+    /// older than the timeout (or all of them without a timeout) of the matching identifiers (or
+    /// all of them without an identifier). Any field of the identifier may be `*` to match every
+    /// value of that field, and a `*` plugin or type without an instance matches every instance.
+    /// Entries are returned in the order they were pushed per identifier.
+    pub fn flush(
+        &mut self,
+        timeout: Option<Duration>,
+        identifier: Option<&Identifier>,
+    ) -> Vec<(Identifier, Vec<T>)> {
+        let cutoff = timeout.and_then(|x| SystemTime::now().checked_sub(x));
+        self.flush_before(cutoff, identifier)
+    }
+
+    /// Takes the entries observed at or before the cutoff (or all of them without one) of the
+    /// matching identifiers
+    pub fn flush_before(
+        &mut self,
+        cutoff: Option<SystemTime>,
+        identifier: Option<&Identifier>,
+    ) -> Vec<(Identifier, Vec<T>)> {
+        let mut flushed = Vec::new();
+        for (id, entries) in &mut self.entries {
+            if !identifier.map(|x| matches(x, id)).unwrap_or(true) {
+                continue;
+            }
+
+            let taken: Vec<T> = match cutoff {
+                None => entries.drain(..).map(|x| x.1).collect(),
+                Some(cutoff) => {
+                    let (old, new) = entries.drain(..).partition(|x| x.0 <= cutoff);
+                    *entries = new;
+                    old.into_iter().map(|x: (SystemTime, T)| x.1).collect()
+                }
+            };
+
+            if !taken.is_empty() {
+                self.len -= taken.len();
+                flushed.push((id.clone(), taken));
+            }
+        }
+
+        self.entries.retain(|_, entries| !entries.is_empty());
+        flushed
+    }
+
+    /// Number of buffered entries
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+fn field_matches(pattern: &str, field: &str) -> bool {
+    pattern == "*" || pattern == field
+}
+
+/// A name and instance pair (eg: `cpu-0`), where a `*` name without an instance matches any
+/// instance too
+fn pair_matches(pattern: (&str, &Option<String>), field: (&str, &Option<String>)) -> bool {
+    match (pattern, field) {
+        (("*", &None), _) => true,
+        ((name, &Some(ref instance)), (field, _)) if instance == "*" => {
+            field_matches(name, field)
+        }
+        ((name, instance), (field, field_instance)) => {
+            field_matches(name, field) && instance == field_instance
+        }
+    }
+}
+
+/// Returns if the identifier matches the pattern, where `*` fields match anything
+fn matches(pattern: &Identifier, id: &Identifier) -> bool {
+    field_matches(&pattern.host, &id.host)
+        && pair_matches(
+            (&pattern.plugin, &pattern.plugin_instance),
+            (&id.plugin, &id.plugin_instance),
+        )
+        && pair_matches(
+            (&pattern.type_, &pattern.type_instance),
+            (&id.type_, &id.type_instance),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn id(s: &str) -> Identifier {
+        s.parse().unwrap()
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_flush_everything() {
+        let mut tracker = FlushTracker::new();
+        tracker.push_at(id("web-1/cpu-0/percent-idle"), at(10), "a");
+        tracker.push_at(id("web-1/cpu-0/percent-idle"), at(20), "b");
+        tracker.push_at(id("web-1/memory/memory-used"), at(20), "c");
+        assert_eq!(tracker.len(), 3);
+
+        let mut flushed = tracker.flush(None, None);
+        flushed.sort_by(|a, b| a.0.to_string().cmp(&b.0.to_string()));
+        assert_eq!(
+            flushed,
+            vec![
+                (id("web-1/cpu-0/percent-idle"), vec!["a", "b"]),
+                (id("web-1/memory/memory-used"), vec!["c"]),
+            ]
+        );
+        assert!(tracker.is_empty());
+    }
+
+    #[test]
+    fn test_flush_by_age_and_identifier() {
+        let mut tracker = FlushTracker::new();
+        tracker.push_at(id("web-1/cpu-0/percent-idle"), at(10), 1);
+        tracker.push_at(id("web-1/cpu-0/percent-idle"), at(30), 2);
+        tracker.push_at(id("web-1/cpu-1/percent-idle"), at(10), 3);
+        tracker.push_at(id("web-1/memory/memory-used"), at(10), 4);
+
+        let flushed = tracker.flush_before(Some(at(20)), Some(&id("web-1/cpu-0/percent-idle")));
+        assert_eq!(flushed, vec![(id("web-1/cpu-0/percent-idle"), vec![1])]);
+        assert_eq!(tracker.len(), 3);
+
+        let mut flushed = tracker.flush_before(None, Some(&id("*/cpu-*/percent-idle")));
+        flushed.sort_by_key(|x| x.1.clone());
+        assert_eq!(
+            flushed,
+            vec![
+                (id("web-1/cpu-0/percent-idle"), vec![2]),
+                (id("web-1/cpu-1/percent-idle"), vec![3]),
+            ]
+        );
+
+        // An identifier without an instance only matches lists without an instance
+        assert!(tracker.flush(None, Some(&id("web-1/memory/memory"))).is_empty());
+        assert_eq!(tracker.flush(None, Some(&id("web-1/*/memory-used"))).len(), 1);
+        assert!(tracker.is_empty());
+
+        tracker.push_at(id("web-1/cpu-0/percent-idle"), at(10), 5);
+        assert_eq!(tracker.flush(None, Some(&id("*/*/*"))).len(), 1);
+    }
+}
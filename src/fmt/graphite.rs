@@ -0,0 +1,283 @@
+//! Graphite's plaintext protocol, formatted the same way as collectd's `write_graphite` plugin:
+//! `<prefix><host><postfix>.<plugin>-<instance>.<type>-<instance>[.<dsname>] <value> <time>`.
+//! Options mirror the plugin's options of the same name.
+
+use super::json_gauge;
+use api::{RecvValueList, Value, ValueReport};
+use identifier::escape_graphite;
+use std::io::{self, Write};
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GraphiteFormatter {
+    prefix: String,
+    postfix: String,
+    escape_character: char,
+    separate_instances: bool,
+    always_append_ds: bool,
+    drop_duplicate_fields: bool,
+    preserve_separator: bool,
+}
+
+impl Default for GraphiteFormatter {
+    fn default() -> Self {
+        GraphiteFormatter::new()
+    }
+}
+
+impl GraphiteFormatter {
+    /// Formats metrics with `write_graphite`'s defaults
+    pub fn new() -> Self {
+        GraphiteFormatter {
+            prefix: String::new(),
+            postfix: String::new(),
+            escape_character: '_',
+            separate_instances: false,
+            always_append_ds: false,
+            drop_duplicate_fields: false,
+            preserve_separator: false,
+        }
+    }
+
+    /// Prepended to the host, eg: `collectd.`
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = String::from(prefix);
+        self
+    }
+
+    /// Appended to the host
+    pub fn postfix(mut self, postfix: &str) -> Self {
+        self.postfix = String::from(postfix);
+        self
+    }
+
+    /// Replaces dots, whitespace, and control characters within each part of the name
+    pub fn escape_character(mut self, c: char) -> Self {
+        self.escape_character = c;
+        self
+    }
+
+    /// Separates instances from the plugin and type with a dot instead of a dash, so each
+    /// instance is a level of Graphite's tree
+    pub fn separate_instances(mut self, separate: bool) -> Self {
+        self.separate_instances = separate;
+        self
+    }
+
+    /// Appends the data source name even when there is only one data source
+    pub fn always_append_ds(mut self, append: bool) -> Self {
+        self.always_append_ds = append;
+        self
+    }
+
+    /// Omits the type when it is the same as the plugin: `memory.memory-used` becomes
+    /// `memory.used`, and `load.load.shortterm` becomes `load.shortterm`
+    pub fn drop_duplicate_fields(mut self, drop: bool) -> Self {
+        self.drop_duplicate_fields = drop;
+        self
+    }
+
+    /// Keeps dots within each part of the name instead of escaping them
+    pub fn preserve_separator(mut self, preserve: bool) -> Self {
+        self.preserve_separator = preserve;
+        self
+    }
+
+    /// The metric path for the value
+    pub fn metric_name(&self, list: &RecvValueList, report: &ValueReport) -> String {
+        let separator = if self.separate_instances { "." } else { "-" };
+        let plugin = self.join(list.plugin, list.plugin_instance, separator);
+
+        // Like write_graphite, a type that duplicates the plugin is first dropped in favor of the
+        // type instance, and the whole type part only when the data source name follows it
+        let type_ = match list.type_instance.filter(|x| !x.is_empty()) {
+            Some(instance)
+                if self.drop_duplicate_fields
+                    && self.escape(list.plugin) == self.escape(list.type_) =>
+            {
+                self.escape(instance)
+            }
+            _ => self.join(list.type_, list.type_instance, separator),
+        };
+        let append_ds = list.values.len() > 1 || self.always_append_ds;
+
+        let mut name = format!("{}{}{}", self.prefix, self.escape(list.host), self.postfix);
+        name.push('.');
+        name.push_str(&plugin);
+        if !self.drop_duplicate_fields || !append_ds || plugin != type_ {
+            name.push('.');
+            name.push_str(&type_);
+        }
+
+        if append_ds {
+            name.push('.');
+            name.push_str(&self.escape(report.name));
+        }
+
+        name
+    }
+
+    /// Writes a line for each value in the list. Counters and derives are written as is, as
+    /// rates need state from previous values.
+    pub fn write<W: Write>(&self, list: &RecvValueList, w: &mut W) -> io::Result<()> {
+        let time = list.time
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+
+        for report in &list.values {
+            let name = self.metric_name(list, report);
+            match report.value {
+                Value::Gauge(x) => writeln!(w, "{} {} {}", name, format_gauge(x), time)?,
+                Value::Counter(x) | Value::Absolute(x) => writeln!(w, "{} {} {}", name, x, time)?,
+                Value::Derive(x) => writeln!(w, "{} {} {}", name, x, time)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn join(&self, name: &str, instance: Option<&str>, separator: &str) -> String {
+        match instance.filter(|x| !x.is_empty()) {
+            Some(instance) => {
+                format!("{}{}{}", self.escape(name), separator, self.escape(instance))
+            }
+            None => self.escape(name),
+        }
+    }
+
+    fn escape(&self, part: &str) -> String {
+        escape_graphite(part, self.escape_character, self.preserve_separator)
+    }
+}
+
+/// Formats a gauge like C's `%.15g`, which is what write_graphite uses, including for values that
+/// aren't finite
+fn format_gauge(x: f64) -> String {
+    if x.is_nan() {
+        String::from("nan")
+    } else if x.is_infinite() {
+        String::from(if x > 0.0 { "inf" } else { "-inf" })
+    } else {
+        json_gauge(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn list<'a>(values: Vec<ValueReport<'a>>) -> RecvValueList<'a> {
+        RecvValueList {
+            values: values.into(),
+            plugin_instance: Some("0"),
+            plugin: "cpu",
+            type_: "percent",
+            type_instance: Some("idle"),
+            host: "web-1.example.com",
+            time: UNIX_EPOCH + Duration::from_millis(1_500_000_000_500),
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    fn report(name: &str, value: Value) -> ValueReport {
+        ValueReport {
+            name: name,
+            value: value,
+            min: 0.0,
+            max: 0.0,
+        }
+    }
+
+    fn lines(formatter: &GraphiteFormatter, list: &RecvValueList) -> String {
+        let mut out = Vec::new();
+        formatter.write(list, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_defaults() {
+        let list = list(vec![report("value", Value::Gauge(12.5))]);
+        assert_eq!(
+            lines(&GraphiteFormatter::new(), &list),
+            "web-1_example_com.cpu-0.percent-idle 12.5 1500000000\n"
+        );
+        assert_eq!(format_gauge(0.1 + 0.2), "0.3");
+        assert_eq!(format_gauge(1e20), "1e+20");
+        assert_eq!(format_gauge(f64::NEG_INFINITY), "-inf");
+
+        let list = RecvValueList {
+            values: smallvec![report("rx", Value::Derive(10)), report("tx", Value::Counter(20))],
+            plugin: "interface",
+            plugin_instance: Some("eth 0"),
+            type_: "if_octets",
+            type_instance: None,
+            ..list
+        };
+        assert_eq!(
+            lines(&GraphiteFormatter::new(), &list),
+            "web-1_example_com.interface-eth_0.if_octets.rx 10 1500000000\n\
+             web-1_example_com.interface-eth_0.if_octets.tx 20 1500000000\n"
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let list = list(vec![report("value", Value::Gauge(f64::NAN))]);
+        let formatter = GraphiteFormatter::new()
+            .prefix("collectd.")
+            .postfix(".dc1")
+            .escape_character('-')
+            .separate_instances(true)
+            .always_append_ds(true)
+            .preserve_separator(true);
+        assert_eq!(
+            lines(&formatter, &list),
+            "collectd.web-1.example.com.dc1.cpu.0.percent.idle.value nan 1500000000\n"
+        );
+
+    }
+
+    #[test]
+    fn test_drop_duplicate_fields() {
+        let formatter = GraphiteFormatter::new().drop_duplicate_fields(true);
+        let memory = RecvValueList {
+            plugin: "memory",
+            plugin_instance: None,
+            type_: "memory",
+            type_instance: Some("used"),
+            ..list(vec![report("value", Value::Gauge(1.0))])
+        };
+        assert_eq!(
+            formatter.metric_name(&memory, &memory.values[0]),
+            "web-1_example_com.memory.used"
+        );
+
+        // The type is only dropped entirely when the data source name takes its place
+        let load = RecvValueList {
+            values: smallvec![
+                report("shortterm", Value::Gauge(0.5)),
+                report("midterm", Value::Gauge(0.4)),
+            ],
+            plugin: "load",
+            plugin_instance: None,
+            type_: "load",
+            type_instance: None,
+            ..memory
+        };
+        assert_eq!(
+            formatter.metric_name(&load, &load.values[0]),
+            "web-1_example_com.load.shortterm"
+        );
+
+        let single = RecvValueList {
+            values: smallvec![report("value", Value::Gauge(0.5))],
+            ..load
+        };
+        assert_eq!(
+            formatter.metric_name(&single, &single.values[0]),
+            "web-1_example_com.load.load"
+        );
+    }
+}
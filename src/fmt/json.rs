@@ -0,0 +1,135 @@
+//! The JSON format of collectd's `write_http` plugin (`Format JSON`), byte for byte, for
+//! endpoints that ingest it such as Logstash's collectd codec or Splunk's HEC:
+//!
+//! ```text
+//! [{"values":[12.5],"dstypes":["gauge"],"dsnames":["value"],"time":1500000000.500,
+//! "interval":10.000,"host":"web-1","plugin":"cpu","plugin_instance":"0","type":"percent",
+//! "type_instance":"idle"}]
+//! ```
+//!
+//! (without the line breaks). Counters and derives are written as is, since `StoreRates`
+//! requires state from previous values.
+
+use super::{json_gauge, json_string};
+use api::{RecvValueList, Value};
+use std::io::{self, Write};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Writes the value lists as a JSON array, as `write_http` does for each batch it sends
+pub fn write<W: Write>(lists: &[RecvValueList], w: &mut W) -> io::Result<()> {
+    write!(w, "[")?;
+    for (i, list) in lists.iter().enumerate() {
+        if i != 0 {
+            write!(w, ",")?;
+        }
+        write_value_list(list, w)?;
+    }
+    write!(w, "]")
+}
+
+/// Writes the value list as a single JSON object
+pub fn write_value_list<W: Write>(list: &RecvValueList, w: &mut W) -> io::Result<()> {
+    let reports = list.values.iter();
+    let values: Vec<String> = reports.clone().map(|x| format_value(&x.value)).collect();
+    let dstypes: Vec<String> = reports.clone().map(|x| json_string(ds_type(&x.value))).collect();
+    let dsnames: Vec<String> = reports.map(|x| json_string(x.name)).collect();
+    let time = list.time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::new(0, 0));
+
+    write!(w, "{{\"values\":[{}],", values.join(","))?;
+    write!(w, "\"dstypes\":[{}],", dstypes.join(","))?;
+    write!(w, "\"dsnames\":[{}],", dsnames.join(","))?;
+    write!(w, "\"time\":{},", format_seconds(time))?;
+    write!(w, "\"interval\":{},", format_seconds(list.interval))?;
+    write!(w, "\"host\":{},", json_string(list.host))?;
+    write!(w, "\"plugin\":{},", json_string(list.plugin))?;
+    write!(
+        w,
+        "\"plugin_instance\":{},",
+        json_string(list.plugin_instance.unwrap_or(""))
+    )?;
+    write!(w, "\"type\":{},", json_string(list.type_))?;
+    write!(
+        w,
+        "\"type_instance\":{}}}",
+        json_string(list.type_instance.unwrap_or(""))
+    )
+}
+
+fn format_value(value: &Value) -> String {
+    match *value {
+        Value::Gauge(x) => json_gauge(x),
+        Value::Counter(x) | Value::Absolute(x) => x.to_string(),
+        Value::Derive(x) => x.to_string(),
+    }
+}
+
+fn ds_type(value: &Value) -> &'static str {
+    match *value {
+        Value::Gauge(_) => "gauge",
+        Value::Counter(_) => "counter",
+        Value::Derive(_) => "derive",
+        Value::Absolute(_) => "absolute",
+    }
+}
+
+/// Seconds with millisecond precision, like collectd's `%.3f`
+fn format_seconds(d: Duration) -> String {
+    let secs = d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9;
+    format!("{:.3}", secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::ValueReport;
+
+    fn list<'a>(values: Vec<ValueReport<'a>>) -> RecvValueList<'a> {
+        RecvValueList {
+            values: values.into(),
+            plugin_instance: Some("0"),
+            plugin: "cpu",
+            type_: "percent",
+            type_instance: Some("idle"),
+            host: "web-1",
+            time: UNIX_EPOCH + Duration::from_millis(1_500_000_000_500),
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    fn report(name: &str, value: Value) -> ValueReport {
+        ValueReport {
+            name: name,
+            value: value,
+            min: 0.0,
+            max: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let cpu = list(vec![report("value", Value::Gauge(12.5))]);
+        let interface = RecvValueList {
+            values: smallvec![report("rx", Value::Derive(-1)), report("tx", Value::Counter(2))],
+            plugin: "interface",
+            plugin_instance: None,
+            type_: "if_octets",
+            type_instance: None,
+            ..list(vec![])
+        };
+
+        let mut out = Vec::new();
+        write(&[cpu, interface], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[{\"values\":[12.5],\"dstypes\":[\"gauge\"],\"dsnames\":[\"value\"],\
+             \"time\":1500000000.500,\"interval\":10.000,\"host\":\"web-1\",\"plugin\":\"cpu\",\
+             \"plugin_instance\":\"0\",\"type\":\"percent\",\"type_instance\":\"idle\"},\
+             {\"values\":[-1,2],\"dstypes\":[\"derive\",\"counter\"],\"dsnames\":[\"rx\",\"tx\"],\
+             \"time\":1500000000.500,\"interval\":10.000,\"host\":\"web-1\",\
+             \"plugin\":\"interface\",\"plugin_instance\":\"\",\"type\":\"if_octets\",\
+             \"type_instance\":\"\"}]"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! The KairosDB JSON format of collectd's `write_http` plugin (`Format KAIROSDB`). Each value is
+//! a metric named after the plugin, with the rest of the identifier as tags:
+//!
+//! ```text
+//! [{"name":"collectd.cpu", "datapoints": [[1500000000500,12.5]], "tags":{"host": "web-1",
+//! "plugin_instance": "0", "type": "percent", "type_instance": "idle"}}]
+//! ```
+//!
+//! (without the line break). Gauges that aren't finite are skipped, as KairosDB can't store them.
+
+use super::{json_gauge, json_string};
+use api::{RecvValueList, Value, ValueReport};
+use std::io::{self, Write};
+use std::time::{Duration, UNIX_EPOCH};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct KairosdbFormatter {
+    prefix: Option<String>,
+    ttl: Option<u32>,
+    attributes: Vec<(String, String)>,
+}
+
+impl Default for KairosdbFormatter {
+    fn default() -> Self {
+        KairosdbFormatter::new()
+    }
+}
+
+impl KairosdbFormatter {
+    /// Names metrics with `write_http`'s default prefix of `collectd`
+    pub fn new() -> Self {
+        KairosdbFormatter {
+            prefix: Some(String::from("collectd")),
+            ttl: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Prepended to metric names with a dot, like `KairosDBMetricPrefix`. `None` leaves the
+    /// plugin as the name.
+    pub fn prefix(mut self, prefix: Option<&str>) -> Self {
+        self.prefix = prefix.map(String::from);
+        self
+    }
+
+    /// How long KairosDB keeps the data points, like `TTL`
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl.as_secs() as u32);
+        self
+    }
+
+    /// An extra tag added to every metric, like `Attribute`
+    pub fn attribute(mut self, key: &str, value: &str) -> Self {
+        self.attributes.push((String::from(key), String::from(value)));
+        self
+    }
+
+    /// Writes the value lists as a JSON array with a metric per value
+    pub fn write<W: Write>(&self, lists: &[RecvValueList], w: &mut W) -> io::Result<()> {
+        write!(w, "[")?;
+        let mut first = true;
+        for list in lists {
+            for report in &list.values {
+                if let Value::Gauge(x) = report.value {
+                    if !x.is_finite() {
+                        continue;
+                    }
+                }
+
+                if !first {
+                    write!(w, ",")?;
+                }
+                first = false;
+                self.write_metric(list, report, w)?;
+            }
+        }
+        write!(w, "]")
+    }
+
+    fn write_metric<W: Write>(
+        &self,
+        list: &RecvValueList,
+        report: &ValueReport,
+        w: &mut W,
+    ) -> io::Result<()> {
+        let name = match self.prefix {
+            Some(ref prefix) => format!("{}.{}", prefix, list.plugin),
+            None => String::from(list.plugin),
+        };
+
+        let time = list.time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::new(0, 0));
+        let ms = time.as_secs() * 1000 + u64::from(time.subsec_nanos() / 1_000_000);
+        let value = match report.value {
+            Value::Gauge(x) => json_gauge(x),
+            Value::Counter(x) | Value::Absolute(x) => x.to_string(),
+            Value::Derive(x) => x.to_string(),
+        };
+
+        write!(w, "{{\"name\":{}, \"datapoints\": [[{},{}]]", json_string(&name), ms, value)?;
+        if let Some(ttl) = self.ttl {
+            write!(w, ", \"ttl\": {}", ttl)?;
+        }
+
+        write!(w, ", \"tags\":{{\"host\": {}", json_string(list.host))?;
+        for &(ref key, ref value) in &self.attributes {
+            write!(w, ", {}: {}", json_string(key), json_string(value))?;
+        }
+
+        if let Some(instance) = list.plugin_instance.filter(|x| !x.is_empty()) {
+            write!(w, ", \"plugin_instance\": {}", json_string(instance))?;
+        }
+
+        write!(w, ", \"type\": {}", json_string(list.type_))?;
+        if let Some(instance) = list.type_instance.filter(|x| !x.is_empty()) {
+            write!(w, ", \"type_instance\": {}", json_string(instance))?;
+        }
+
+        if list.values.len() != 1 {
+            write!(w, ", \"ds\": {}", json_string(report.name))?;
+        }
+
+        write!(w, "}}}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list<'a>(values: Vec<ValueReport<'a>>) -> RecvValueList<'a> {
+        RecvValueList {
+            values: values.into(),
+            plugin_instance: Some("0"),
+            plugin: "cpu",
+            type_: "percent",
+            type_instance: Some("idle"),
+            host: "web-1",
+            time: UNIX_EPOCH + Duration::from_millis(1_500_000_000_500),
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    fn report(name: &str, value: Value) -> ValueReport {
+        ValueReport {
+            name: name,
+            value: value,
+            min: 0.0,
+            max: 0.0,
+        }
+    }
+
+    fn format(formatter: &KairosdbFormatter, lists: &[RecvValueList]) -> String {
+        let mut out = Vec::new();
+        formatter.write(lists, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write() {
+        let cpu = list(vec![report("value", Value::Gauge(12.5))]);
+        assert_eq!(
+            format(&KairosdbFormatter::new(), &[cpu]),
+            "[{\"name\":\"collectd.cpu\", \"datapoints\": [[1500000000500,12.5]], \
+             \"tags\":{\"host\": \"web-1\", \"plugin_instance\": \"0\", \"type\": \"percent\", \
+             \"type_instance\": \"idle\"}}]"
+        );
+    }
+
+    #[test]
+    fn test_write_options() {
+        let interface = RecvValueList {
+            values: smallvec![
+                report("rx", Value::Derive(10)),
+                report("tx", Value::Gauge(f64::NAN)),
+            ],
+            plugin: "interface",
+            plugin_instance: None,
+            type_: "if_octets",
+            type_instance: None,
+            ..list(vec![])
+        };
+
+        let formatter = KairosdbFormatter::new()
+            .prefix(None)
+            .ttl(Duration::from_secs(3600))
+            .attribute("dc", "east");
+        assert_eq!(
+            format(&formatter, &[interface]),
+            "[{\"name\":\"interface\", \"datapoints\": [[1500000000500,10]], \"ttl\": 3600, \
+             \"tags\":{\"host\": \"web-1\", \"dc\": \"east\", \"type\": \"if_octets\", \
+             \"ds\": \"rx\"}}]"
+        );
+        assert_eq!(format(&formatter, &[]), "[]");
+    }
+}
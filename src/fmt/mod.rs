@@ -0,0 +1,77 @@
+//! Formatters that turn received value lists into the wire formats of other monitoring systems,
+//! for write plugins that forward collectd's values elsewhere.
+
+pub mod graphite;
+pub mod json;
+pub mod kairosdb;
+pub mod prometheus;
+
+/// Formats a gauge like C's `%.15g`, which collectd uses for gauges in JSON. Values that aren't
+/// finite can't be represented in JSON, so they are `null`.
+fn json_gauge(x: f64) -> String {
+    if !x.is_finite() {
+        return String::from("null");
+    }
+
+    let sci = format!("{:.14e}", x);
+    let exp: i32 = sci[sci.find('e').unwrap() + 1..].parse().unwrap();
+    if exp < -4 || exp >= 15 {
+        let mantissa = &sci[..sci.find('e').unwrap()];
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_zeros(mantissa), sign, exp.abs())
+    } else {
+        let fixed = format!("{:.*}", (14 - exp) as usize, x);
+        String::from(trim_zeros(&fixed))
+    }
+}
+
+/// Strips trailing zeros after the decimal point (and the point itself if nothing is left)
+fn trim_zeros(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+/// Quotes a string for JSON the way collectd does, which replaces control characters with `?`
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                res.push('\\');
+                res.push(c);
+            }
+            '\u{0}'..='\u{1f}' => res.push('?'),
+            _ => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_gauge() {
+        assert_eq!(json_gauge(12.5), "12.5");
+        assert_eq!(json_gauge(0.0), "0");
+        assert_eq!(json_gauge(-3.0), "-3");
+        assert_eq!(json_gauge(0.1), "0.1");
+        assert_eq!(json_gauge(1.0 / 3.0), "0.333333333333333");
+        assert_eq!(json_gauge(0.00001), "1e-05");
+        assert_eq!(json_gauge(1e20), "1e+20");
+        assert_eq!(json_gauge(123456789012345.0), "123456789012345");
+        assert_eq!(json_gauge(f64::NAN), "null");
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("eth0"), "\"eth0\"");
+        assert_eq!(json_string("a \"b\"\\\n"), "\"a \\\"b\\\"\\\\?\"");
+    }
+}
@@ -0,0 +1,255 @@
+//! Prometheus' text exposition format, naming metrics the same way as
+//! [collectd_exporter](https://github.com/prometheus/collectd_exporter) so that dashboards built
+//! against the exporter keep working.
+//!
+//! A value list of `cpu-0/percent-idle` from `web-1` becomes (along with a `HELP` line):
+//!
+//! ```text
+//! # TYPE collectd_cpu_percent gauge
+//! collectd_cpu_percent{cpu="0",instance="web-1",type="idle"} 12.5
+//! ```
+//!
+//! Like the exporter, samples aren't timestamped, as Prometheus expects the latest value of each
+//! series.
+
+use api::{RecvValueList, Value, ValueReport};
+use std::io::{self, Write};
+
+/// The metric name for a value: `collectd_<plugin>_<type>`, then `_<dsname>` unless the data
+/// source is named `value`, then `_total` for counters and derives. When the plugin and type are
+/// the same the plugin is omitted.
+pub fn metric_name(list: &RecvValueList, report: &ValueReport) -> String {
+    let mut name = if list.plugin == list.type_ {
+        format!("collectd_{}", list.type_)
+    } else {
+        format!("collectd_{}_{}", list.plugin, list.type_)
+    };
+
+    if report.name != "value" {
+        name.push('_');
+        name.push_str(report.name);
+    }
+
+    if is_counter(&report.value) {
+        name.push_str("_total");
+    }
+
+    sanitize(&name)
+}
+
+/// The labels of a value list, sorted by name. The plugin instance is labeled by the plugin's
+/// name, as is the type instance if there's no plugin instance (otherwise it is `type`). The host
+/// is the `instance` label.
+pub fn labels(list: &RecvValueList) -> Vec<(String, String)> {
+    let mut labels = Vec::new();
+    let plugin_instance = list.plugin_instance.filter(|x| !x.is_empty());
+    let type_instance = list.type_instance.filter(|x| !x.is_empty());
+    if let Some(instance) = plugin_instance {
+        labels.push((sanitize(list.plugin), String::from(instance)));
+    }
+
+    if let Some(instance) = type_instance {
+        let name = if plugin_instance.is_none() {
+            sanitize(list.plugin)
+        } else {
+            String::from("type")
+        };
+        labels.push((name, String::from(instance)));
+    }
+
+    labels.push((String::from("instance"), String::from(list.host)));
+    labels.sort();
+    labels
+}
+
+/// Writes a metric family (`HELP`, `TYPE`, and a sample) for each value in the list. When
+/// exposing several lists that share metric names (eg: each cpu), group them with `write_samples`
+/// under a single `HELP` and `TYPE` instead, as Prometheus rejects repeated families.
+pub fn write<W: Write>(list: &RecvValueList, w: &mut W) -> io::Result<()> {
+    for report in &list.values {
+        write_header(list, report, w)?;
+        write_sample(list, report, w)?;
+    }
+
+    Ok(())
+}
+
+/// Writes a sample line for each value in the list
+pub fn write_samples<W: Write>(list: &RecvValueList, w: &mut W) -> io::Result<()> {
+    for report in &list.values {
+        write_sample(list, report, w)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the `HELP` and `TYPE` lines for the value's metric
+pub fn write_header<W: Write>(
+    list: &RecvValueList,
+    report: &ValueReport,
+    w: &mut W,
+) -> io::Result<()> {
+    let name = metric_name(list, report);
+    let (metric_type, ds_type) = match report.value {
+        Value::Gauge(_) => ("gauge", "gauge"),
+        Value::Absolute(_) => ("gauge", "absolute"),
+        Value::Counter(_) => ("counter", "counter"),
+        Value::Derive(_) => ("counter", "derive"),
+    };
+
+    writeln!(
+        w,
+        "# HELP {} Collectd exporter: '{}' Type: '{}' Dstype: '{}' Dsname: '{}'",
+        name,
+        escape_help(list.plugin),
+        escape_help(list.type_),
+        ds_type,
+        escape_help(report.name)
+    )?;
+    writeln!(w, "# TYPE {} {}", name, metric_type)
+}
+
+fn write_sample<W: Write>(
+    list: &RecvValueList,
+    report: &ValueReport,
+    w: &mut W,
+) -> io::Result<()> {
+    write!(w, "{}{{", metric_name(list, report))?;
+    for (i, (name, value)) in labels(list).iter().enumerate() {
+        if i != 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "{}=\"{}\"", name, escape_label(value))?;
+    }
+
+    match report.value {
+        Value::Gauge(x) => writeln!(w, "}} {}", format_float(x)),
+        Value::Counter(x) | Value::Absolute(x) => writeln!(w, "}} {}", x),
+        Value::Derive(x) => writeln!(w, "}} {}", x),
+    }
+}
+
+fn is_counter(value: &Value) -> bool {
+    match *value {
+        Value::Counter(_) | Value::Derive(_) => true,
+        Value::Gauge(_) | Value::Absolute(_) => false,
+    }
+}
+
+/// Replaces characters that aren't allowed in metric and label names with underscores
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if c.is_ascii_alphabetic() || c == '_' || (i != 0 && c.is_ascii_digit()) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_help(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn format_float(x: f64) -> String {
+    if x.is_nan() {
+        String::from("NaN")
+    } else if x.is_infinite() {
+        String::from(if x > 0.0 { "+Inf" } else { "-Inf" })
+    } else {
+        x.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn list<'a>(values: Vec<ValueReport<'a>>) -> RecvValueList<'a> {
+        RecvValueList {
+            values: values.into(),
+            plugin_instance: Some("0"),
+            plugin: "cpu",
+            type_: "percent",
+            type_instance: Some("idle"),
+            host: "web-1",
+            time: SystemTime::now(),
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    fn report(name: &str, value: Value) -> ValueReport {
+        ValueReport {
+            name: name,
+            value: value,
+            min: 0.0,
+            max: 0.0,
+        }
+    }
+
+    fn exposition(list: &RecvValueList) -> String {
+        let mut out = Vec::new();
+        write(list, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_gauge() {
+        let list = list(vec![report("value", Value::Gauge(12.5))]);
+        assert_eq!(
+            exposition(&list),
+            "# HELP collectd_cpu_percent Collectd exporter: 'cpu' Type: 'percent' Dstype: 'gauge' \
+             Dsname: 'value'\n\
+             # TYPE collectd_cpu_percent gauge\n\
+             collectd_cpu_percent{cpu=\"0\",instance=\"web-1\",type=\"idle\"} 12.5\n"
+        );
+    }
+
+    #[test]
+    fn test_names_and_labels() {
+        let mut list = list(vec![
+            report("rx", Value::Derive(10)),
+            report("tx", Value::Gauge(f64::NAN)),
+        ]);
+        list.plugin = "interface";
+        list.plugin_instance = None;
+        list.type_ = "if-octets";
+        list.type_instance = Some("eth\"0\"");
+
+        assert_eq!(
+            metric_name(&list, &list.values[0]),
+            "collectd_interface_if_octets_rx_total"
+        );
+        assert_eq!(
+            labels(&list),
+            vec![
+                (String::from("instance"), String::from("web-1")),
+                (String::from("interface"), String::from("eth\"0\"")),
+            ]
+        );
+
+        let mut out = Vec::new();
+        write_samples(&list, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "collectd_interface_if_octets_rx_total{instance=\"web-1\",interface=\"eth\\\"0\\\"\"} \
+             10\n\
+             collectd_interface_if_octets_tx{instance=\"web-1\",interface=\"eth\\\"0\\\"\"} NaN\n"
+        );
+
+        list.plugin = "load";
+        list.type_ = "load";
+        assert_eq!(metric_name(&list, &list.values[1]), "collectd_load_tx");
+    }
+}
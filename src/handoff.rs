@@ -0,0 +1,318 @@
+//! Write plugins that do their work on their own threads hand value lists over from collectd's
+//! write threads through a queue, which must be bounded so that a stalled backend doesn't
+//! exhaust memory. What to do once the queue is full depends on the plugin: wait for room
+//! (backpressure onto collectd's own write queue), or drop the oldest or the newest values and
+//! count them. `handoff` creates such a queue, and `HandoffSender::submit_stats` dispatches its
+//! length and drops like collectd's own write queue statistics.
+
+use api::{Value, ValueListBuilder};
+use errors::Error;
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// What `HandoffSender::send` does when the queue is full
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Overflow {
+    /// Wait until the receiver makes room, which holds up the write thread
+    Block,
+
+    /// Drop the item that has been queued the longest to make room for the new one
+    DropOldest,
+
+    /// Drop the new item
+    DropNewest,
+}
+
+/// The receiver was dropped, so the item (returned inside) can't be sent
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Disconnected<T>(pub T);
+
+impl<T> fmt::Display for Disconnected<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "receiver of the handoff queue has stopped")
+    }
+}
+
+impl<T: fmt::Debug> error::Error for Disconnected<T> {}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiving: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    overflow: Overflow,
+    dropped: AtomicU64,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<State<T>> {
+        // A panic while holding the lock can't leave the queue in an inconsistent state
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A queue of at most `capacity` items from any number of senders (eg: collectd's write
+/// threads) to one receiver (eg: the plugin's worker)
+pub fn handoff<T>(capacity: usize, overflow: Overflow) -> (HandoffSender<T>, HandoffReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            senders: 1,
+            receiving: true,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        capacity: capacity.max(1),
+        overflow: overflow,
+        dropped: AtomicU64::new(0),
+    });
+
+    let sender = HandoffSender {
+        shared: shared.clone(),
+    };
+    (sender, HandoffReceiver { shared: shared })
+}
+
+/// The sending half of a handoff queue, which can be cloned for each write thread
+pub struct HandoffSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> HandoffSender<T> {
+    /// Queues the item, applying the queue's overflow policy if it's full
+    pub fn send(&self, item: T) -> Result<(), Disconnected<T>> {
+        let shared = &*self.shared;
+        let mut state = shared.lock();
+        while state.receiving && state.queue.len() >= shared.capacity {
+            match shared.overflow {
+                Overflow::Block => {
+                    state = shared
+                        .not_full
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+                Overflow::DropOldest => {
+                    state.queue.pop_front();
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Overflow::DropNewest => {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+            }
+        }
+
+        if !state.receiving {
+            return Err(Disconnected(item));
+        }
+
+        state.queue.push_back(item);
+        shared.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// The number of items waiting for the receiver
+    pub fn len(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of items dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Dispatches the queue's length (as `queue_length`) and drops (as `derive-dropped`) under
+    /// the plugin and instance, so that a saturated queue can be graphed and alerted on
+    pub fn submit_stats(&self, plugin: &str, plugin_instance: &str) -> Result<(), Error> {
+        let length = [Value::Gauge(self.len() as f64)];
+        ValueListBuilder::new(plugin, "queue_length")
+            .plugin_instance(plugin_instance)
+            .values(&length)
+            .submit()?;
+
+        let dropped = [Value::Derive(self.dropped() as i64)];
+        ValueListBuilder::new(plugin, "derive")
+            .plugin_instance(plugin_instance)
+            .type_instance("dropped")
+            .values(&dropped)
+            .submit()
+    }
+}
+
+impl<T> Clone for HandoffSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        HandoffSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for HandoffSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+/// The receiving half of a handoff queue. Dropping it fails further sends and wakes blocked
+/// senders.
+pub struct HandoffReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> HandoffReceiver<T> {
+    /// Waits for the next item, or returns `None` once every sender has been dropped and the
+    /// queue is empty
+    pub fn recv(&self) -> Option<T> {
+        let shared = &*self.shared;
+        let mut state = shared.lock();
+        loop {
+            if let Some(item) = state.queue.pop_front() {
+                shared.not_full.notify_one();
+                return Some(item);
+            }
+
+            if state.senders == 0 {
+                return None;
+            }
+
+            state = shared
+                .not_empty
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Waits up to the timeout for the next item
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let shared = &*self.shared;
+        let deadline = Instant::now() + timeout;
+        let mut state = shared.lock();
+        loop {
+            if let Some(item) = state.queue.pop_front() {
+                shared.not_full.notify_one();
+                return Ok(item);
+            }
+
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            state = shared
+                .not_empty
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Takes up to `max` queued items without waiting, for writing in a batch
+    pub fn drain(&self, max: usize) -> Vec<T> {
+        let mut state = self.shared.lock();
+        let count = max.min(state.queue.len());
+        let items: Vec<T> = state.queue.drain(..count).collect();
+        if !items.is_empty() {
+            self.shared.not_full.notify_all();
+        }
+        items
+    }
+
+    /// The number of items dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for HandoffReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiving = false;
+        self.shared.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use stub;
+
+    #[test]
+    fn test_overflow_policies() {
+        let (sender, receiver) = handoff(2, Overflow::DropOldest);
+        for i in 0..5 {
+            sender.send(i).unwrap();
+        }
+        assert_eq!(receiver.drain(10), vec![3, 4]);
+        assert_eq!(sender.dropped(), 3);
+
+        let (sender, receiver) = handoff(2, Overflow::DropNewest);
+        for i in 0..5 {
+            sender.send(i).unwrap();
+        }
+        assert_eq!(receiver.drain(1), vec![0]);
+        assert_eq!(receiver.drain(10), vec![1]);
+        assert_eq!(receiver.dropped(), 3);
+
+        drop(receiver);
+        assert_eq!(sender.send(5), Err(Disconnected(5)));
+    }
+
+    #[test]
+    fn test_blocked_sender_waits_for_room() {
+        let (sender, receiver) = handoff(1, Overflow::Block);
+        sender.send(1).unwrap();
+
+        let writer = sender.clone();
+        let blocked = thread::spawn(move || writer.send(2));
+        assert_eq!(receiver.recv(), Some(1));
+        blocked.join().unwrap().unwrap();
+        assert_eq!(receiver.recv(), Some(2));
+        assert_eq!(sender.dropped(), 0);
+
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        drop(sender);
+        assert_eq!(receiver.recv(), None);
+    }
+
+    #[test]
+    fn test_submit_stats() {
+        stub::reset();
+        let (sender, _receiver) = handoff(1, Overflow::DropNewest);
+        sender.send("used").unwrap();
+        sender.send("free").unwrap();
+        sender.submit_stats("myplugin", "handoff").unwrap();
+
+        let dispatched = stub::dispatched();
+        assert_eq!(dispatched.len(), 2);
+        assert_eq!(dispatched[0].type_, "queue_length");
+        assert_eq!(dispatched[1].type_instance, Some(String::from("dropped")));
+        stub::reset();
+    }
+}
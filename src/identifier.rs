@@ -0,0 +1,228 @@
+use api::RecvValueList;
+use network::ValueList;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Names a value list the way collectd does: `host/plugin[-plugin_instance]/type[-type_instance]`
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Identifier {
+    pub host: String,
+    pub plugin: String,
+    pub plugin_instance: Option<String>,
+    pub type_: String,
+    pub type_instance: Option<String>,
+}
+
+impl Identifier {
+    /// The identifier of a value list. Empty instances are treated as absent.
+    pub fn of(list: &ValueList) -> Identifier {
+        Identifier {
+            host: list.host.clone(),
+            plugin: list.plugin.clone(),
+            plugin_instance: list.plugin_instance.clone().filter(|x| !x.is_empty()),
+            type_: list.type_.clone(),
+            type_instance: list.type_instance.clone().filter(|x| !x.is_empty()),
+        }
+    }
+
+    /// The identifier with each part made safe with `sanitize_part`, so that it's displayed (and
+    /// parsed back) with the same parts
+    pub fn sanitized(&self) -> Identifier {
+        Identifier {
+            host: sanitize_part(&self.host),
+            plugin: sanitize_part(&self.plugin),
+            plugin_instance: self.plugin_instance.as_ref().map(|x| sanitize_part(x)),
+            type_: sanitize_part(&self.type_),
+            type_instance: self.type_instance.as_ref().map(|x| sanitize_part(x)),
+        }
+    }
+
+    /// Returns if the received value list has this identifier. Flush callbacks use this to find
+    /// the buffered values that collectd asked to be flushed.
+    pub fn matches(&self, list: &RecvValueList) -> bool {
+        self.host == list.host && self.plugin == list.plugin
+            && self.plugin_instance.as_ref().map(|x| x.as_str()) == list.plugin_instance
+            && self.type_ == list.type_
+            && self.type_instance.as_ref().map(|x| x.as_str()) == list.type_instance
+    }
+}
+
+impl<'a, 'b> From<&'b RecvValueList<'a>> for Identifier {
+    fn from(list: &'b RecvValueList<'a>) -> Identifier {
+        Identifier {
+            host: String::from(list.host),
+            plugin: String::from(list.plugin),
+            plugin_instance: list.plugin_instance.filter(|x| !x.is_empty()).map(String::from),
+            type_: String::from(list.type_),
+            type_instance: list.type_instance.filter(|x| !x.is_empty()).map(String::from),
+        }
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.host, self.plugin)?;
+        if let Some(ref instance) = self.plugin_instance {
+            write!(f, "-{}", instance)?;
+        }
+
+        write!(f, "/{}", self.type_)?;
+        if let Some(ref instance) = self.type_instance {
+            write!(f, "-{}", instance)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Makes text usable as a part of an identifier like collectd's `escape_slashes`: leading slashes
+/// are removed and the rest are replaced with underscores, as they would separate the parts. A
+/// lone `/` becomes `root` (eg: the root file system in the `df` plugin).
+pub fn escape_slashes(part: &str) -> String {
+    if part == "/" {
+        return String::from("root");
+    }
+
+    part.trim_start_matches('/').replace('/', "_")
+}
+
+/// Like `escape_slashes`, but also replaces whitespace and control characters with underscores,
+/// which collectd's text protocols would otherwise split on or choke on
+pub fn sanitize_part(part: &str) -> String {
+    escape_slashes(part)
+        .chars()
+        .map(|c| if c.is_whitespace() || c.is_control() { '_' } else { c })
+        .collect()
+}
+
+/// Whether the part would be left as is by `sanitize_part`, for rejecting names instead of
+/// rewriting them
+pub fn is_valid_part(part: &str) -> bool {
+    !part.is_empty() && !part.contains(|c: char| c == '/' || c.is_whitespace() || c.is_control())
+}
+
+/// Escapes a part of a Graphite metric path like collectd's `write_graphite`: whitespace, control
+/// characters, and (unless `preserve_dots`) the dots that separate Graphite's levels are replaced
+/// with the escape character
+pub fn escape_graphite(part: &str, escape: char, preserve_dots: bool) -> String {
+    part.chars()
+        .map(|c| {
+            if (c == '.' && !preserve_dots) || c.is_whitespace() || c.is_control() {
+                escape
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// The text isn't of the form `host/plugin[-plugin_instance]/type[-type_instance]`
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseIdentifierError(pub String);
+
+impl fmt::Display for ParseIdentifierError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid identifier: {}", self.0)
+    }
+}
+
+impl error::Error for ParseIdentifierError {}
+
+/// Splits `name-instance` at the first dash, as collectd does
+fn split_instance(s: &str) -> (String, Option<String>) {
+    match s.find('-') {
+        Some(idx) => (String::from(&s[..idx]), Some(String::from(&s[idx + 1..]))),
+        None => (String::from(s), None),
+    }
+}
+
+impl FromStr for Identifier {
+    type Err = ParseIdentifierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, '/');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(host), Some(plugin), Some(type_))
+                if !host.is_empty() && !plugin.is_empty() && !type_.is_empty() =>
+            {
+                let (plugin, plugin_instance) = split_instance(plugin);
+                let (type_, type_instance) = split_instance(type_);
+                Ok(Identifier {
+                    host: String::from(host),
+                    plugin: plugin,
+                    plugin_instance: plugin_instance,
+                    type_: type_,
+                    type_instance: type_instance,
+                })
+            }
+            _ => Err(ParseIdentifierError(String::from(s))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{Value, ValueReport};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_matches() {
+        let list = RecvValueList {
+            values: smallvec![ValueReport {
+                name: "value",
+                value: Value::Gauge(1.0),
+                min: 0.0,
+                max: 100.0,
+            }],
+            plugin_instance: Some("0"),
+            plugin: "cpu",
+            type_: "percent",
+            type_instance: Some("idle"),
+            host: "localhost",
+            time: UNIX_EPOCH,
+            interval: Duration::from_secs(10),
+        };
+
+        let id: Identifier = "localhost/cpu-0/percent-idle".parse().unwrap();
+        assert!(id.matches(&list));
+        assert_eq!(Identifier::from(&list), id);
+
+        let id: Identifier = "localhost/cpu-1/percent-idle".parse().unwrap();
+        assert!(!id.matches(&list));
+
+        let id: Identifier = "localhost/cpu-0/percent".parse().unwrap();
+        assert!(!id.matches(&list));
+
+        assert_eq!(
+            "localhost/cpu".parse::<Identifier>(),
+            Err(ParseIdentifierError(String::from("localhost/cpu")))
+        );
+    }
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(escape_slashes("/"), "root");
+        assert_eq!(escape_slashes("/var/lib"), "var_lib");
+        assert_eq!(sanitize_part("/mnt/my disk\t"), "mnt_my_disk_");
+        assert_eq!(sanitize_part("eth0\n"), "eth0_");
+        assert!(is_valid_part("eth0"));
+        assert!(!is_valid_part("my disk"));
+        assert!(!is_valid_part("var/lib"));
+        assert!(!is_valid_part(""));
+        assert_eq!(escape_graphite("web-1.example com", '_', false), "web-1_example_com");
+        assert_eq!(escape_graphite("web-1.example", '_', true), "web-1.example");
+
+        let id = Identifier {
+            host: String::from("web 1"),
+            plugin: String::from("df"),
+            plugin_instance: Some(String::from("/")),
+            type_: String::from("df_complex"),
+            type_instance: Some(String::from("free")),
+        };
+        let sanitized = id.sanitized();
+        assert_eq!(sanitized.to_string(), "web_1/df-root/df_complex-free");
+        assert_eq!(sanitized.to_string().parse(), Ok(sanitized));
+    }
+}
@@ -0,0 +1,131 @@
+//! Plugins often report a fixed set of type instances (eg: memory that is `used`, `free`, or
+//! `cached`), which are best modelled as an enum. `TypeInstance` (usually implemented with
+//! `#[derive(TypeInstance)]` from the `derive` feature) maps each variant to the lowercase string
+//! collectd conventionally uses, so the enum can be handed to `ValueListBuilder::type_instance`
+//! and parsed back out of the value lists that write plugins and formatters receive.
+
+use std::error;
+use std::fmt;
+
+/// An enum whose variants are the type instances of a value list
+pub trait TypeInstance: Sized + 'static {
+    /// The type instance as submitted to collectd
+    fn as_str(&self) -> &'static str;
+
+    /// Every variant, in declaration order
+    fn instances() -> &'static [Self];
+
+    /// The variant submitted as the given type instance
+    fn from_instance(s: &str) -> Option<Self>
+    where
+        Self: Clone,
+    {
+        Self::instances().iter().find(|x| x.as_str() == s).cloned()
+    }
+}
+
+/// The type instance isn't one of the enum's variants
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseInstanceError {
+    /// The name of the enum
+    pub name: &'static str,
+
+    pub instance: String,
+}
+
+impl ParseInstanceError {
+    pub fn new(name: &'static str, instance: &str) -> Self {
+        ParseInstanceError {
+            name: name,
+            instance: String::from(instance),
+        }
+    }
+}
+
+impl fmt::Display for ParseInstanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown {} type instance: {}", self.name, self.instance)
+    }
+}
+
+impl error::Error for ParseInstanceError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    enum State {
+        Used,
+        Free,
+    }
+
+    impl TypeInstance for State {
+        fn as_str(&self) -> &'static str {
+            match *self {
+                State::Used => "used",
+                State::Free => "free",
+            }
+        }
+
+        fn instances() -> &'static [Self] {
+            &[State::Used, State::Free]
+        }
+    }
+
+    #[test]
+    fn test_from_instance() {
+        assert_eq!(State::from_instance("free"), Some(State::Free));
+        assert_eq!(State::from_instance("Free"), None);
+        assert_eq!(
+            ParseInstanceError::new("State", "cached").to_string(),
+            "unknown State type instance: cached"
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_type_instance() {
+        use api::{Value, ValueListBuilder};
+        use stub;
+
+        #[derive(TypeInstance, Debug, PartialEq, Clone, Copy)]
+        enum Cpu {
+            User,
+            SoftIrq,
+            #[collectd(rename = "wait")]
+            IoWait,
+        }
+
+        #[derive(TypeInstance, Debug, PartialEq, Clone, Copy)]
+        #[collectd(rename_all = "snake_case")]
+        enum Memory {
+            SlabRecl,
+            Used,
+        }
+
+        assert_eq!(Cpu::SoftIrq.to_string(), "softirq");
+        assert_eq!(Cpu::IoWait.as_str(), "wait");
+        assert_eq!(Memory::SlabRecl.as_str(), "slab_recl");
+        assert_eq!(Cpu::instances(), &[Cpu::User, Cpu::SoftIrq, Cpu::IoWait]);
+        assert_eq!("user".parse::<Cpu>(), Ok(Cpu::User));
+        assert_eq!(
+            "steal".parse::<Cpu>(),
+            Err(ParseInstanceError::new("Cpu", "steal"))
+        );
+
+        // The variants can be submitted directly and parsed back out of the dispatched lists
+        stub::reset();
+        let values = [Value::Gauge(10.0)];
+        ValueListBuilder::new("cpu", "percent")
+            .type_instance(Cpu::IoWait)
+            .values(&values)
+            .submit()
+            .unwrap();
+
+        let dispatched = stub::dispatched();
+        let instance = dispatched[0].type_instance.as_ref().unwrap();
+        assert_eq!(instance.parse::<Cpu>(), Ok(Cpu::IoWait));
+        stub::reset();
+    }
+}
@@ -0,0 +1,204 @@
+//! A plugin that monitors several databases or devices is usually configured with a block per
+//! target, as collectd's own plugins are:
+//!
+//! ```text
+//! <Plugin myplugin>
+//!   <Instance "db1">
+//!     Host "10.0.0.1"
+//!   </Instance>
+//!   <Instance "db2">
+//!     Host "10.0.0.2"
+//!   </Instance>
+//! </Plugin>
+//! ```
+//!
+//! `instances_from` constructs a plugin from each block, which are registered as separate
+//! plugins (`myplugin/db1` and `myplugin/db2`). Every instance is read by its own callback with
+//! its own state, so a slow or failing target doesn't hold up or reset the others.
+
+use api::{ConfigItem, ConfigValue};
+use errors::Error;
+use plugins::{DynPlugin, Plugin, PluginRegistration};
+use std::error;
+use std::fmt;
+
+/// The instance blocks of the config don't describe a set of plugins
+#[derive(Debug)]
+pub enum InstanceError {
+    /// A block (named by its key) doesn't have a name as its only value
+    Unnamed(String),
+
+    /// Several blocks have the same name
+    Duplicate(String),
+
+    /// The plugin couldn't be constructed from the named block
+    Failed(String, Error),
+}
+
+impl fmt::Display for InstanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InstanceError::Unnamed(ref key) => {
+                write!(f, "<{}> blocks take the instance's name as their only value", key)
+            }
+            InstanceError::Duplicate(ref name) => {
+                write!(f, "instance {} is configured more than once", name)
+            }
+            InstanceError::Failed(ref name, ref e) => write!(f, "instance {}: {}", name, e),
+        }
+    }
+}
+
+impl error::Error for InstanceError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            InstanceError::Failed(_, ref e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
+
+/// Constructs a plugin from the name and children of each block whose key is `key` (matched
+/// regardless of case, like collectd's options), for returning from `PluginManager::plugins`.
+/// Options outside of the blocks are left for the caller to handle.
+///
+/// ```ignore
+/// fn plugins(config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
+///     instances_from(config, "Instance", |name, children| {
+///         let config: DbConfig = collectd_plugin::de::from_collectd(children)?;
+///         Ok(DbPlugin::new(name, config))
+///     })
+/// }
+/// ```
+pub fn instances_from<P, F>(
+    config: Option<&[ConfigItem]>,
+    key: &str,
+    mut plugin: F,
+) -> Result<PluginRegistration, Error>
+where
+    P: Plugin + 'static,
+    F: FnMut(&str, &[ConfigItem]) -> Result<P, Error>,
+{
+    let blocks = config
+        .unwrap_or(&[])
+        .iter()
+        .filter(|item| item.key.eq_ignore_ascii_case(key));
+
+    let mut plugins: Vec<(String, Box<dyn DynPlugin>)> = Vec::new();
+    for block in blocks {
+        let name = match block.values.as_slice() {
+            [ConfigValue::String(name)] if !name.is_empty() => *name,
+            _ => return Err(InstanceError::Unnamed(String::from(block.key)).into()),
+        };
+
+        if plugins.iter().any(|&(ref x, _)| x == name) {
+            return Err(InstanceError::Duplicate(String::from(name)).into());
+        }
+
+        match plugin(name, &block.children) {
+            Ok(x) => plugins.push((String::from(name), Box::new(x))),
+            Err(e) => return Err(InstanceError::Failed(String::from(name), e).into()),
+        }
+    }
+
+    Ok(PluginRegistration::Multiple(plugins))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugins::PluginCapabilities;
+
+    struct Db {
+        host: String,
+    }
+
+    impl Plugin for Db {
+        type Error = Error;
+
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities::READ
+        }
+    }
+
+    fn block<'a>(key: &'a str, values: Vec<ConfigValue<'a>>, host: &'a str) -> ConfigItem<'a> {
+        ConfigItem {
+            key: key,
+            values: values,
+            children: vec![ConfigItem {
+                key: "Host",
+                values: vec![ConfigValue::String(host)],
+                children: vec![],
+            }],
+        }
+    }
+
+    fn db(_name: &str, children: &[ConfigItem]) -> Result<Db, Error> {
+        match children[0].values[0] {
+            ConfigValue::String("") => Err(Error::from("host is empty")),
+            ConfigValue::String(host) => Ok(Db {
+                host: String::from(host),
+            }),
+            _ => Err(Error::from("host is not a string")),
+        }
+    }
+
+    #[test]
+    fn test_instances_from() {
+        let items = vec![
+            block("Instance", vec![ConfigValue::String("db1")], "10.0.0.1"),
+            ConfigItem {
+                key: "Timeout",
+                values: vec![ConfigValue::Number(5.0)],
+                children: vec![],
+            },
+            block("instance", vec![ConfigValue::String("db2")], "10.0.0.2"),
+        ];
+
+        let mut hosts = Vec::new();
+        let registration = instances_from(Some(&items), "Instance", |name, children| {
+            let plugin = db(name, children)?;
+            hosts.push(format!("{}={}", name, plugin.host));
+            Ok(plugin)
+        });
+        match registration.unwrap() {
+            PluginRegistration::Multiple(plugins) => {
+                let names: Vec<&str> = plugins.iter().map(|&(ref x, _)| x.as_str()).collect();
+                assert_eq!(names, vec!["db1", "db2"]);
+            }
+            PluginRegistration::Single(_) => panic!("expected a plugin per instance"),
+        }
+        assert_eq!(hosts, vec!["db1=10.0.0.1", "db2=10.0.0.2"]);
+
+        match instances_from(None, "Instance", db).unwrap() {
+            PluginRegistration::Multiple(plugins) => assert!(plugins.is_empty()),
+            PluginRegistration::Single(_) => panic!("expected no plugins"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_instances() {
+        let err = |items: Vec<ConfigItem>| {
+            instances_from(Some(&items), "Instance", db)
+                .err()
+                .expect("instances to be rejected")
+                .to_string()
+        };
+
+        assert_eq!(
+            err(vec![block("Instance", vec![], "10.0.0.1")]),
+            "<Instance> blocks take the instance's name as their only value"
+        );
+        assert_eq!(
+            err(vec![
+                block("Instance", vec![ConfigValue::String("db1")], "10.0.0.1"),
+                block("Instance", vec![ConfigValue::String("db1")], "10.0.0.2"),
+            ]),
+            "instance db1 is configured more than once"
+        );
+        assert_eq!(
+            err(vec![block("Instance", vec![ConfigValue::String("db1")], "")]),
+            "instance db1: host is empty"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Write plugins that buffer values per identifier see the same few thousand host, plugin, and
+//! type names hundreds of thousands of times a minute. Keying buffers by those strings means
+//! cloning and rehashing them for every value list; an `Interner` stores each string once and
+//! hands out a `Symbol`, which is a small integer that is cheap to copy, hash, and compare.
+
+use api::RecvValueList;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A string stored in an `Interner`. Symbols are only meaningful to the interner that created
+/// them.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct Symbol(u32);
+
+/// The interned fields of a value list's identifier, for use as a map key
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct InternedIdentifier {
+    pub host: Symbol,
+    pub plugin: Symbol,
+    pub plugin_instance: Option<Symbol>,
+    pub type_: Symbol,
+    pub type_instance: Option<Symbol>,
+}
+
+/// Stores each distinct string once. Strings are never removed, so an interner should be used
+/// for sets of names that stay bounded (like identifiers), and not for arbitrary text.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    symbols: HashMap<Arc<str>, Symbol>,
+    strings: Vec<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner::default()
+    }
+
+    /// The symbol for the string, storing the string if it hasn't been seen before
+    pub fn intern(&mut self, s: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(s) {
+            return symbol;
+        }
+
+        let symbol = Symbol(self.strings.len() as u32);
+        let stored: Arc<str> = Arc::from(s);
+        self.strings.push(stored.clone());
+        self.symbols.insert(stored, symbol);
+        symbol
+    }
+
+    /// The symbol for the string, if it has been interned
+    pub fn get(&self, s: &str) -> Option<Symbol> {
+        self.symbols.get(s).cloned()
+    }
+
+    /// The string of a symbol from this interner
+    pub fn resolve(&self, symbol: Symbol) -> Option<&str> {
+        self.strings.get(symbol.0 as usize).map(|x| &**x)
+    }
+
+    /// A shared handle to the string of a symbol, for keeping the string beyond the interner
+    pub fn resolve_arc(&self, symbol: Symbol) -> Option<Arc<str>> {
+        self.strings.get(symbol.0 as usize).cloned()
+    }
+
+    /// Interns the identifier of a value list
+    pub fn identifier(&mut self, list: &RecvValueList) -> InternedIdentifier {
+        InternedIdentifier {
+            host: self.intern(list.host),
+            plugin: self.intern(list.plugin),
+            plugin_instance: list.plugin_instance.map(|x| self.intern(x)),
+            type_: self.intern(list.type_),
+            type_instance: list.type_instance.map(|x| self.intern(x)),
+        }
+    }
+
+    /// Number of distinct strings that have been interned
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_intern() {
+        let mut interner = Interner::new();
+        let a = interner.intern("web-1.example.com");
+        let b = interner.intern("cpu");
+        assert_ne!(a, b);
+        assert_eq!(interner.intern("web-1.example.com"), a);
+        assert_eq!(interner.get("cpu"), Some(b));
+        assert_eq!(interner.get("memory"), None);
+        assert_eq!(interner.resolve(a), Some("web-1.example.com"));
+        assert_eq!(&*interner.resolve_arc(b).unwrap(), "cpu");
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn test_identifier() {
+        let mut interner = Interner::new();
+        let list = RecvValueList {
+            values: smallvec![],
+            plugin_instance: Some("0"),
+            plugin: "cpu",
+            type_: "cpu",
+            type_instance: None,
+            host: "web-1",
+            time: UNIX_EPOCH,
+            interval: Duration::from_secs(10),
+        };
+
+        let id = interner.identifier(&list);
+        assert_eq!(id.plugin, id.type_);
+        assert_eq!(id.type_instance, None);
+        assert_eq!(interner.identifier(&list), id);
+        assert_eq!(interner.len(), 3);
+
+        let other = interner.identifier(&RecvValueList {
+            plugin_instance: Some("1"),
+            ..list
+        });
+        assert_ne!(other, id);
+        assert_eq!(other.host, id.host);
+    }
+}
@@ -0,0 +1,459 @@
+//! Write plugins for backends that are occasionally unreachable lose whatever they buffered in
+//! memory when collectd restarts during an outage. A `Journal` keeps value lists on disk instead:
+//! lists are appended to segment files in a directory, each record with a checksum, and the
+//! segments are deleted once a flush has written their lists to the backend. A record that was
+//! only partly written when collectd was killed fails its checksum and is skipped on replay.
+
+use api::{OwnedRecvValueList, OwnedValueReport, Value};
+use errors::Error;
+use smallvec::SmallVec;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Written at the start of every segment, so that unrelated files are never replayed
+const MAGIC: &[u8; 8] = b"cdjrnl01";
+
+/// Segment files are named by their sequence number, which orders them
+const EXTENSION: &str = "seg";
+
+/// An append-only queue of value lists in a directory, which survives restarts. Writes aren't
+/// synchronized, so share a journal between write threads behind a `Mutex`.
+pub struct Journal {
+    dir: PathBuf,
+    segment_size: u64,
+    sync: bool,
+
+    /// Sequence numbers of the segments on disk, oldest first, which includes the active segment
+    segments: Vec<u64>,
+    active: Option<(BufWriter<File>, u64)>,
+}
+
+impl Journal {
+    /// Opens the journal in the directory, creating the directory if needed. Segments left by a
+    /// previous run are kept for the next `drain`, and new lists go into a new segment.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Journal> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |x| x == EXTENSION) {
+                if let Some(seq) = path.file_stem().and_then(|x| x.to_str()?.parse().ok()) {
+                    segments.push(seq);
+                }
+            }
+        }
+        segments.sort();
+
+        Ok(Journal {
+            dir: dir,
+            segment_size: 4 * 1024 * 1024,
+            sync: false,
+            segments: segments,
+            active: None,
+        })
+    }
+
+    /// Starts a new segment once the current one has grown past this many bytes, so a flush can
+    /// release part of the journal without having written all of it. Defaults to 4 MiB.
+    pub fn segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = bytes;
+        self
+    }
+
+    /// Syncs every list to disk before `push` returns, which survives a crash of the machine (and
+    /// not only of collectd) at the cost of a disk write per list
+    pub fn sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// The number of segments on disk, including the one being written
+    pub fn segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Appends the list to the journal
+    pub fn push(&mut self, list: &OwnedRecvValueList) -> io::Result<()> {
+        let payload = encode(list)?;
+        let mut record = Vec::with_capacity(payload.len() + 8);
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+
+        let full = match self.active {
+            Some((_, written)) => written >= self.segment_size,
+            None => true,
+        };
+        if full {
+            self.roll()?;
+        }
+
+        let sync = self.sync;
+        if let Some((ref mut file, ref mut written)) = self.active {
+            file.write_all(&record)?;
+            *written += record.len() as u64;
+            if sync {
+                file.flush()?;
+                file.get_ref().sync_data()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands the lists of each segment, oldest first, to `write` and deletes the segment once
+    /// they have been written. Stops at the first error, keeping that segment and any newer ones
+    /// for the next drain. Returns the number of lists written.
+    pub fn drain<F>(&mut self, mut write: F) -> Result<usize, Error>
+    where
+        F: FnMut(Vec<OwnedRecvValueList>) -> Result<(), Error>,
+    {
+        // Lists pushed from now on go into a new segment
+        if let Some((mut file, _)) = self.active.take() {
+            file.flush()?;
+        }
+
+        let mut written = 0;
+        while let Some(&seq) = self.segments.first() {
+            let path = self.path(seq);
+            let lists = read_segment(&path)?;
+            let len = lists.len();
+            if len > 0 {
+                write(lists)?;
+            }
+
+            fs::remove_file(&path)?;
+            self.segments.remove(0);
+            written += len;
+        }
+        Ok(written)
+    }
+
+    fn path(&self, seq: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.{}", seq, EXTENSION))
+    }
+
+    /// Finishes the active segment and starts the next
+    fn roll(&mut self) -> io::Result<()> {
+        if let Some((mut file, _)) = self.active.take() {
+            file.flush()?;
+        }
+
+        let seq = self.segments.last().map_or(0, |x| x + 1);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.path(seq))?;
+        let mut file = BufWriter::new(file);
+        file.write_all(MAGIC)?;
+        self.segments.push(seq);
+        self.active = Some((file, MAGIC.len() as u64));
+        Ok(())
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        if let Some((ref mut file, _)) = self.active {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Reads every intact record of a segment. Reading stops at the first record that is truncated
+/// or fails its checksum, as segments are only appended to.
+fn read_segment(path: &Path) -> io::Result<Vec<OwnedRecvValueList>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    if !data.starts_with(MAGIC) {
+        return Ok(Vec::new());
+    }
+
+    let mut lists = Vec::new();
+    let mut rest = &data[MAGIC.len()..];
+    while rest.len() >= 8 {
+        let len = u32_at(rest, 0) as usize;
+        let checksum = u32_at(rest, 4);
+        let payload = match rest.get(8..8 + len) {
+            Some(x) if crc32(x) == checksum => x,
+            _ => break,
+        };
+
+        match decode(payload) {
+            Some(list) => lists.push(list),
+            None => break,
+        }
+        rest = &rest[8 + len..];
+    }
+    Ok(lists)
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[at..at + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// The CRC-32 (IEEE) of the data
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn nanos(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000_000 + u64::from(d.subsec_nanos())
+}
+
+fn from_nanos(ns: u64) -> Duration {
+    Duration::new(ns / 1_000_000_000, (ns % 1_000_000_000) as u32)
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) -> io::Result<()> {
+    if s.len() > usize::from(u16::MAX) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "field is too long"));
+    }
+
+    buf.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn put_opt(buf: &mut Vec<u8>, s: &Option<String>) -> io::Result<()> {
+    match *s {
+        Some(ref x) => {
+            buf.push(1);
+            put_str(buf, x)
+        }
+        None => {
+            buf.push(0);
+            Ok(())
+        }
+    }
+}
+
+fn encode(list: &OwnedRecvValueList) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(64 + list.values.len() * 32);
+    put_str(&mut buf, &list.host)?;
+    put_str(&mut buf, &list.plugin)?;
+    put_opt(&mut buf, &list.plugin_instance)?;
+    put_str(&mut buf, &list.type_)?;
+    put_opt(&mut buf, &list.type_instance)?;
+
+    let time = list.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    buf.extend_from_slice(&nanos(time).to_le_bytes());
+    buf.extend_from_slice(&nanos(list.interval).to_le_bytes());
+
+    buf.extend_from_slice(&(list.values.len() as u16).to_le_bytes());
+    for report in &list.values {
+        put_str(&mut buf, &report.name)?;
+        let (kind, bits) = match report.value {
+            Value::Counter(x) => (0, x),
+            Value::Gauge(x) => (1, x.to_bits()),
+            Value::Derive(x) => (2, x as u64),
+            Value::Absolute(x) => (3, x),
+        };
+        buf.push(kind);
+        buf.extend_from_slice(&bits.to_le_bytes());
+        buf.extend_from_slice(&report.min.to_bits().to_le_bytes());
+        buf.extend_from_slice(&report.max.to_bits().to_le_bytes());
+    }
+    Ok(buf)
+}
+
+/// Reads the fields of a record in the order they were encoded
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|x| x[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Some(u16::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u16()?;
+        let bytes = self.take(usize::from(len))?;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+
+    fn opt(&mut self) -> Option<Option<String>> {
+        match self.u8()? {
+            0 => Some(None),
+            _ => self.string().map(Some),
+        }
+    }
+}
+
+fn decode(payload: &[u8]) -> Option<OwnedRecvValueList> {
+    let mut r = Reader(payload);
+    let host = r.string()?;
+    let plugin = r.string()?;
+    let plugin_instance = r.opt()?;
+    let type_ = r.string()?;
+    let type_instance = r.opt()?;
+    let time = UNIX_EPOCH + from_nanos(r.u64()?);
+    let interval = from_nanos(r.u64()?);
+
+    let len = r.u16()?;
+    let mut values = Vec::with_capacity(usize::from(len));
+    for _ in 0..len {
+        let name = r.string()?;
+        let kind = r.u8()?;
+        let bits = r.u64()?;
+        let value = match kind {
+            0 => Value::Counter(bits),
+            1 => Value::Gauge(f64::from_bits(bits)),
+            2 => Value::Derive(bits as i64),
+            3 => Value::Absolute(bits),
+            _ => return None,
+        };
+        values.push(OwnedValueReport {
+            name: name,
+            value: value,
+            min: f64::from_bits(r.u64()?),
+            max: f64::from_bits(r.u64()?),
+        });
+    }
+
+    Some(OwnedRecvValueList {
+        values: SmallVec::from_vec(values),
+        plugin_instance: plugin_instance,
+        plugin: plugin,
+        type_: type_,
+        type_instance: type_instance,
+        host: host,
+        time: time,
+        interval: interval,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn list(secs: u64, value: Value) -> OwnedRecvValueList {
+        OwnedRecvValueList {
+            values: smallvec![OwnedValueReport {
+                name: String::from("value"),
+                value: value,
+                min: 0.0,
+                max: 100.0,
+            }],
+            plugin_instance: Some(String::from("0")),
+            plugin: String::from("cpu"),
+            type_: String::from("percent"),
+            type_instance: None,
+            host: String::from("localhost"),
+            time: UNIX_EPOCH + Duration::from_millis(secs * 1_000 + 250),
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!("journal-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_replayed_after_reopening() {
+        let dir = temp_dir("replay");
+        let lists = vec![
+            list(1, Value::Gauge(1.5)),
+            list(2, Value::Derive(-3)),
+            list(3, Value::Counter(7)),
+        ];
+
+        {
+            let mut journal = Journal::open(&dir).unwrap().segment_size(64);
+            for x in &lists {
+                journal.push(x).unwrap();
+            }
+            assert_eq!(journal.segments(), 3);
+        }
+
+        // A write that failed keeps the journal for the next drain
+        let mut journal = Journal::open(&dir).unwrap();
+        let err = journal.drain(|_| Err(Error::from("connection refused")));
+        assert_eq!(err.unwrap_err().to_string(), "connection refused");
+        assert_eq!(journal.segments(), 3);
+
+        let mut drained = Vec::new();
+        let count = journal.drain(|x| {
+            drained.extend(x);
+            Ok(())
+        });
+        assert_eq!(count.unwrap(), 3);
+        assert_eq!(drained.len(), 3);
+        assert_eq!(drained[0].values[0].value, Value::Gauge(1.5));
+        assert_eq!(drained[1].values[0].value, Value::Derive(-3));
+        assert_eq!(drained[2].values[0].value, Value::Counter(7));
+        assert_eq!(drained[2].time, lists[2].time);
+        assert_eq!(journal.segments(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_torn_record_skipped() {
+        let dir = temp_dir("torn");
+        {
+            let mut journal = Journal::open(&dir).unwrap();
+            journal.push(&list(1, Value::Gauge(1.0))).unwrap();
+            journal.push(&list(2, Value::Gauge(2.0))).unwrap();
+        }
+
+        // Cut the last record short, as if collectd was killed while writing it
+        let path = dir.join(format!("{:020}.{}", 0, EXTENSION));
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() - 3]).unwrap();
+
+        let mut journal = Journal::open(&dir).unwrap();
+        let mut drained = Vec::new();
+        journal
+            .drain(|x| {
+                drained.extend(x);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(drained, vec![list(1, Value::Gauge(1.0))]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
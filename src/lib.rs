@@ -1,8 +1,21 @@
 #[macro_use]
 extern crate bitflags;
+#[cfg_attr(test, macro_use)]
+extern crate smallvec;
+#[cfg(feature = "chrono")]
 extern crate chrono;
-#[macro_use]
-extern crate failure;
+#[cfg(feature = "network-security")]
+extern crate aes;
+#[cfg(feature = "network-security")]
+extern crate getrandom;
+#[cfg(feature = "network-security")]
+extern crate hmac;
+#[cfg(feature = "network-security")]
+extern crate ofb;
+#[cfg(feature = "network-security")]
+extern crate sha1;
+#[cfg(feature = "network-security")]
+extern crate sha2;
 
 #[cfg(feature = "serde")]
 #[macro_use]
@@ -13,37 +26,400 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
+#[cfg(feature = "derive")]
+#[allow(unused_imports)]
+#[macro_use]
+extern crate collectd_plugin_derive;
+
+// Lets the derive macros' `::collectd_plugin` paths resolve within this crate's tests
+#[cfg(all(test, feature = "derive"))]
+extern crate self as collectd_plugin;
+
+#[cfg(feature = "log")]
+extern crate log;
+
+#[cfg(feature = "metrics")]
+extern crate metrics;
+
+#[cfg(feature = "regex")]
+extern crate regex;
+
+#[cfg(feature = "async")]
+extern crate tokio;
+
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "tracing")]
+extern crate tracing_subscriber;
+
 #[cfg(feature = "serde")]
 pub mod de;
 
+pub mod aggregation;
 pub mod bindings;
+mod buffer;
+mod abi;
+#[cfg(collectd_57)]
+mod cache;
+#[macro_use]
+mod macros;
 mod api;
+#[cfg(feature = "async")]
+mod async_write;
+pub mod constants;
+pub mod control;
+mod dedup;
 mod errors;
+pub mod exec;
+mod filters;
+mod flush;
+pub mod fmt;
+mod handoff;
+mod identifier;
+mod interner;
+mod instance;
+mod instances;
+mod journal;
+mod panics;
+mod parallel;
 #[macro_use]
 mod plugins;
+mod plaintext;
+mod queue;
+mod rates;
+mod retry;
+mod runtime;
+mod sample;
+#[cfg(feature = "log")]
+mod logger;
+pub mod network;
+#[cfg(feature = "metrics")]
+mod recorder;
+pub mod statsd;
+#[cfg(any(test, feature = "stub"))]
+pub mod stub;
+#[cfg(feature = "tracing")]
+mod subscriber;
+#[cfg(any(test, feature = "stub"))]
+#[macro_use]
+pub mod testing;
+pub mod threshold;
+mod throttle;
+pub mod unixsock;
 
-pub use api::{collectd_log, empty_to_none, from_array, get_default_interval, CdTime, ConfigItem,
-              ConfigValue, LogLevel, RecvValueList, Value, ValueListBuilder};
-pub use errors::{ArrayError, SubmitError};
-pub use plugins::{Plugin, PluginCapabilities, PluginManager, PluginManagerCapabilities,
-                  PluginRegistration};
+pub use abi::verify_abi;
+pub use api::{collectd_log, collectd_log_args, empty_to_none, from_array, get_default_interval,
+              plugin_context, plugin_name, register_default_interval, register_plugin_name,
+              submit_derive, submit_gauge, AlertTracker, CdTime, ConfigAccumulator, ConfigItem,
+              ConfigValue, GlobalConfig, LogFilter, LogLevel, MetaData, MetaValue,
+              NotificationBuilder, OwnedConfigItem, OwnedConfigValue, OwnedRecvValueList,
+              OwnedValueReport, PluginContext, RecvNotification, RecvValueList, ThreadContext,
+              Value, ValueListBuilder, ValueReport, WriteFilter, INLINE_VALUES};
+#[cfg(feature = "async")]
+pub use async_write::{AsyncWriteBuilder, AsyncWritePlugin, BatchFuture, BatchWriter};
+pub use buffer::WriteBuffer;
+#[cfg(collectd_57)]
+pub use cache::{cache_snapshot, CacheEntry, CacheIter};
+pub use constants::{DataSourceType, Severity};
+pub use dedup::Deduplicator;
+pub use errors::{AbiMismatch, ArrayError, ConfigError, Error, ErrorChain, MetaDataError,
+                 ReceiveError, SubmitError, UnknownLogLevel};
+pub use filters::{register_match, register_target, Match, MatchConstructor, Target,
+                  TargetConstructor, TargetResult, TargetValueList};
+pub use flush::FlushTracker;
+pub use handoff::{handoff, Disconnected, HandoffReceiver, HandoffSender, Overflow};
+pub use identifier::{escape_graphite, escape_slashes, is_valid_part, sanitize_part, Identifier,
+                     ParseIdentifierError};
+pub use interner::{InternedIdentifier, Interner, Symbol};
+pub use instance::{ParseInstanceError, TypeInstance};
+pub use instances::{instances_from, InstanceError};
+pub use journal::Journal;
+#[cfg(feature = "log")]
+pub use logger::{install_logger, CollectdLoggerBackend};
+pub use panics::{catch_panic, install_panic_hook};
+pub use queue::WriteQueueStats;
+pub use retry::{Attempt, GaveUp, Retrier, RetryPolicy};
+pub use parallel::{ParallelReadError, ParallelReader, TargetError};
+pub use plugins::{initialize_manager, null_argument, plugin_from_user_data,
+                  reconfigure_interval, record_config_block, record_config_option,
+                  register_plugin, register_read, register_simple_config, registration_from,
+                  take_config_blocks, take_reconfigured_interval, DynPlugin, Plugin,
+                  PluginCallbacks, PluginCapabilities, PluginManager, PluginManagerCapabilities,
+                  PluginRegistration, RegisteredPlugin, SimpleConfigCallback};
+pub use rates::{counter_diff, RateTracker};
+pub use sample::{render_sample, CollectdConfig, ConfigField};
+#[cfg(feature = "derive")]
+pub use collectd_plugin_derive::{CollectdConfig, TypeInstance};
+#[cfg(feature = "metrics")]
+pub use recorder::{install_recorder, CollectdRecorder, RecorderHandle};
+#[cfg(feature = "tracing")]
+pub use subscriber::{install_tracing, CollectdLayer};
+pub use smallvec::SmallVec;
+pub use throttle::ThrottledLogger;
 
 #[cfg(test)]
 #[allow(private_no_mangle_fns)]
 #[allow(dead_code)]
 mod tests {
     use super::*;
-    use failure::Error;
 
     struct MyPlugin;
 
+    thread_local! {
+        static FAIL_INIT: ::std::cell::Cell<bool> = ::std::cell::Cell::new(false);
+        static FAIL_PLUGINS: ::std::cell::Cell<bool> = ::std::cell::Cell::new(false);
+        static INITIALIZED: ::std::cell::Cell<bool> = ::std::cell::Cell::new(false);
+        static SHUT_DOWN: ::std::cell::Cell<bool> = ::std::cell::Cell::new(false);
+        static DROPS: ::std::cell::Cell<u32> = ::std::cell::Cell::new(0);
+        static QUIET_RECEIVED: ::std::cell::RefCell<Vec<LogLevel>> =
+            ::std::cell::RefCell::new(Vec::new());
+        static CHATTY_RECEIVED: ::std::cell::RefCell<Vec<String>> =
+            ::std::cell::RefCell::new(Vec::new());
+        static SIMPLE_CONFIG: ::std::cell::Cell<bool> = ::std::cell::Cell::new(false);
+        static CONFIGURED: ::std::cell::RefCell<Vec<OwnedConfigItem>> =
+            ::std::cell::RefCell::new(Vec::new());
+    }
+
     impl PluginManager for MyPlugin {
         fn name() -> &'static str {
             "myplugin"
         }
 
-        fn plugins(_config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
-            Ok(PluginRegistration::Multiple(vec![]))
+        fn capabilities() -> PluginManagerCapabilities {
+            let capabilities =
+                PluginManagerCapabilities::INIT | PluginManagerCapabilities::SHUTDOWN;
+            if SIMPLE_CONFIG.with(|x| x.get()) {
+                capabilities | PluginManagerCapabilities::SIMPLE_CONFIG
+            } else {
+                capabilities
+            }
+        }
+
+        fn config_keys() -> &'static [&'static str] {
+            &["Port"]
+        }
+
+        fn shutdown() -> Result<(), Error> {
+            SHUT_DOWN.with(|x| x.set(true));
+            Ok(())
+        }
+
+        fn initialize() -> Result<(), Error> {
+            INITIALIZED.with(|x| x.set(true));
+            if FAIL_INIT.with(|x| x.get()) {
+                let cause = ::std::io::Error::new(::std::io::ErrorKind::AddrInUse, "in use");
+                Err(Error::from(BindError(cause)))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn plugins(config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
+            let items = config.unwrap_or_default().iter().map(|x| x.to_owned_item());
+            CONFIGURED.with(|x| *x.borrow_mut() = items.collect());
+            if FAIL_PLUGINS.with(|x| x.get()) {
+                return Err(Error::from("unable to connect to db-1"));
+            }
+
+            let flusher: Box<dyn DynPlugin> = Box::new(Flusher);
+            let forgetful: Box<dyn DynPlugin> = Box::new(Forgetful);
+            let quiet: Box<dyn DynPlugin> = Box::new(Quiet);
+            let backoff: Box<dyn DynPlugin> = Box::new(Backoff(0));
+            let cpu_writer: Box<dyn DynPlugin> = Box::new(CpuWriter);
+            let everything: Box<dyn DynPlugin> = Box::new(Everything);
+            let rate_writer: Box<dyn DynPlugin> = Box::new(RateWriter);
+            let chatty: Box<dyn DynPlugin> = Box::new(Chatty);
+            Ok(PluginRegistration::Multiple(vec![
+                (String::from("flusher"), flusher),
+                (String::from("forgetful"), forgetful),
+                (String::from("quiet"), quiet),
+                (String::from("backoff"), backoff),
+                (String::from("cpu_writer"), cpu_writer),
+                (String::from("everything"), everything),
+                (String::from("rate_writer"), rate_writer),
+                (String::from("chatty"), chatty),
+            ]))
+        }
+    }
+
+    #[derive(Debug)]
+    struct BindError(::std::io::Error);
+
+    impl ::std::fmt::Display for BindError {
+        fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+            write!(f, "unable to bind to 0.0.0.0:8125")
+        }
+    }
+
+    impl ::std::error::Error for BindError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    // Advertises reading without implementing it
+    struct Forgetful;
+
+    impl Plugin for Forgetful {
+        type Error = Error;
+
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities::READ
+        }
+    }
+
+    // Only wants warnings and errors
+    struct Quiet;
+
+    impl Plugin for Quiet {
+        type Error = Error;
+
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities::LOG
+        }
+
+        fn log_filter(&self) -> Option<LogFilter> {
+            Some(LogFilter::new(LogLevel::Warning))
+        }
+
+        fn log(&mut self, lvl: LogLevel, _msg: &str) -> Result<(), Error> {
+            QUIET_RECEIVED.with(|x| x.borrow_mut().push(lvl));
+            Ok(())
+        }
+    }
+
+    // Logs about every message it logs, which collectd hands back to its log callback
+    struct Chatty;
+
+    impl Plugin for Chatty {
+        type Error = Error;
+
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities::LOG
+        }
+
+        fn log(&mut self, lvl: LogLevel, msg: &str) -> Result<(), Error> {
+            CHATTY_RECEIVED.with(|x| x.borrow_mut().push(String::from(msg)));
+            let nested = ::std::ffi::CString::new(format!("logged {}", msg)).unwrap();
+            stub::log("myplugin/chatty", lvl.as_raw() as i32, &nested);
+            Ok(())
+        }
+    }
+
+    // Slows down after its first read
+    struct Backoff(u32);
+
+    impl Plugin for Backoff {
+        type Error = Error;
+
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities::READ
+        }
+
+        fn read_values(&mut self) -> Result<(), Error> {
+            self.0 += 1;
+            if self.0 == 1 {
+                reconfigure_interval(::std::time::Duration::from_secs(60));
+            }
+            collectd_log(LogLevel::Info, &format!("read {}", self.0));
+            Ok(())
+        }
+    }
+
+    // Only stores cpu values
+    struct CpuWriter;
+
+    impl Plugin for CpuWriter {
+        type Error = Error;
+
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities::WRITE
+        }
+
+        fn write_filter(&self) -> Option<WriteFilter> {
+            Some(WriteFilter::new().plugin("cpu"))
+        }
+
+        fn write_values<'a>(&mut self, list: RecvValueList<'a>) -> Result<(), Error> {
+            collectd_log(LogLevel::Info, &format!("wrote {}", list.plugin));
+            Ok(())
+        }
+    }
+
+    // Stores rates instead of raw counters
+    struct RateWriter;
+
+    impl Plugin for RateWriter {
+        type Error = Error;
+
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities::WRITE | PluginCapabilities::STORE_RATES
+        }
+
+        fn write_values<'a>(&mut self, list: RecvValueList<'a>) -> Result<(), Error> {
+            let values: Vec<Value> = list.values.iter().map(|x| x.value).collect();
+            collectd_log(LogLevel::Info, &format!("wrote {:?}", values));
+            Ok(())
+        }
+    }
+
+    // Registers every callback, reschedules its reads, and counts how often it's dropped
+    struct Everything;
+
+    impl Plugin for Everything {
+        type Error = Error;
+
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities::READ
+                | PluginCapabilities::WRITE
+                | PluginCapabilities::LOG
+                | PluginCapabilities::FLUSH
+        }
+
+        fn read_values(&mut self) -> Result<(), Error> {
+            reconfigure_interval(::std::time::Duration::from_secs(30));
+            Ok(())
+        }
+
+        fn write_values<'a>(&mut self, _list: RecvValueList<'a>) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn flush(
+            &mut self,
+            _timeout: Option<::std::time::Duration>,
+            _identifier: Option<Identifier>,
+        ) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl Drop for Everything {
+        fn drop(&mut self) {
+            DROPS.with(|x| x.set(x.get() + 1));
+        }
+    }
+
+    struct Flusher;
+
+    impl Plugin for Flusher {
+        type Error = Error;
+
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities::FLUSH
+        }
+
+        fn flush(
+            &mut self,
+            _timeout: Option<::std::time::Duration>,
+            identifier: Option<Identifier>,
+        ) -> Result<(), Error> {
+            let msg = match identifier {
+                Some(id) => format!("flushing {}", id),
+                None => String::from("flushing everything"),
+            };
+            collectd_log(LogLevel::Info, &msg);
+            Ok(())
         }
     }
 
@@ -53,4 +429,333 @@ mod tests {
     fn can_generate_blank_plugin() {
         assert!(true);
     }
+
+    #[test]
+    fn test_module_register_with_stub() {
+        stub::reset();
+        module_register();
+        assert_eq!(
+            stub::registrations(),
+            vec![
+                (String::from("myplugin"), stub::CallbackKind::Config),
+                (String::from("myplugin"), stub::CallbackKind::Init),
+                (String::from("myplugin"), stub::CallbackKind::Shutdown),
+            ]
+        );
+        assert_eq!(stub::init(), vec![0]);
+        assert_eq!(
+            stub::registrations()[3],
+            (String::from("myplugin/flusher"), stub::CallbackKind::Flush)
+        );
+
+        let context = plugin_context().unwrap();
+        assert_eq!(context.name(), "myplugin");
+        assert_eq!(context.c_name().to_str(), Ok("myplugin"));
+        assert_eq!(context.interval(), Some(::std::time::Duration::from_secs(10)));
+        stub::reset();
+    }
+
+    #[test]
+    fn test_simple_config() {
+        stub::reset();
+        SIMPLE_CONFIG.with(|x| x.set(true));
+        module_register();
+        SIMPLE_CONFIG.with(|x| x.set(false));
+        assert_eq!(
+            stub::registrations()[0],
+            (String::from("myplugin"), stub::CallbackKind::SimpleConfig)
+        );
+
+        assert_eq!(stub::simple_config("myplugin", "port", "80.000000"), Some(0));
+        assert_eq!(stub::simple_config("myplugin", "Color", "blue"), Some(-1));
+        assert_eq!(stub::init(), vec![0]);
+
+        let configured = CONFIGURED.with(|x| x.borrow_mut().split_off(0));
+        let expected = OwnedConfigItem {
+            key: String::from("port"),
+            values: vec![OwnedConfigValue::String(String::from("80.000000"))],
+            children: vec![],
+        };
+        assert_eq!(configured, vec![expected]);
+        stub::reset();
+    }
+
+    #[test]
+    fn test_init_error_is_logged() {
+        stub::reset();
+        module_register();
+        FAIL_INIT.with(|x| x.set(true));
+        assert_eq!(stub::init(), vec![-1]);
+        FAIL_INIT.with(|x| x.set(false));
+
+        testing::assert_logged(
+            LogLevel::Error,
+            "init error: unable to bind to 0.0.0.0:8125: in use",
+        );
+        stub::reset();
+    }
+
+    #[test]
+    fn test_plugin_construction_error_aborts_registration() {
+        stub::reset();
+        module_register();
+        FAIL_PLUGINS.with(|x| x.set(true));
+        INITIALIZED.with(|x| x.set(false));
+        assert_eq!(stub::init(), vec![-1]);
+        FAIL_PLUGINS.with(|x| x.set(false));
+
+        testing::assert_logged(LogLevel::Error, "config error: unable to connect to db-1");
+        assert_eq!(stub::registrations().len(), 3);
+        assert!(!INITIALIZED.with(|x| x.get()));
+        stub::reset();
+    }
+
+    #[test]
+    fn test_unimplemented_capability_warns_once() {
+        stub::reset();
+        module_register();
+        stub::init();
+
+        assert_eq!(stub::read("myplugin/forgetful"), Some(0));
+        assert_eq!(stub::read("myplugin/forgetful"), Some(0));
+        assert_eq!(stub::read("myplugin/forgetful"), Some(0));
+
+        testing::assert_logged(
+            LogLevel::Warning,
+            "myplugin/forgetful: READ capability is advertised but `read_values` is not \
+             implemented",
+        );
+        assert_eq!(stub::logged().len(), 1);
+        stub::reset();
+    }
+
+    #[test]
+    fn test_callbacks_reject_null_pointers() {
+        use std::ptr;
+
+        stub::reset();
+        unsafe {
+            assert_eq!(collectd_plugin_read(ptr::null_mut()), -1);
+            assert_eq!(
+                collectd_plugin_write(ptr::null(), ptr::null(), ptr::null_mut()),
+                -1
+            );
+            assert_eq!(collectd_plugin_flush(0, ptr::null(), ptr::null_mut()), -1);
+            assert_eq!(collectd_plugin_complex_config(ptr::null_mut()), -1);
+            collectd_plugin_log(LogLevel::Info.as_raw() as i32, ptr::null(), ptr::null_mut());
+            runtime::release::<RegisteredPlugin>(ptr::null_mut());
+        }
+
+        testing::assert_logged(LogLevel::Error, "read callback received a null user data");
+        testing::assert_logged(LogLevel::Error, "write callback received a null user data");
+        testing::assert_logged(LogLevel::Error, "config callback received a null config block");
+        assert_eq!(stub::logged().len(), 4);
+        stub::reset();
+    }
+
+    #[test]
+    fn test_flush_identifier() {
+        use std::ffi::CString;
+
+        stub::reset();
+        module_register();
+        stub::init();
+
+        let id = CString::new("localhost/cpu-0/percent-idle").unwrap();
+        let empty = CString::new("").unwrap();
+        let invalid = CString::new("localhost").unwrap();
+        assert_eq!(stub::flush("myplugin/flusher", 0, None), Some(0));
+        assert_eq!(stub::flush("myplugin/flusher", 0, Some(&id)), Some(0));
+        assert_eq!(stub::flush("myplugin/flusher", 0, Some(&empty)), Some(0));
+        assert_eq!(stub::flush("myplugin/flusher", 0, Some(&invalid)), Some(-1));
+
+        testing::assert_logged(LogLevel::Info, "flushing everything");
+        testing::assert_logged(LogLevel::Info, "flushing localhost/cpu-0/percent-idle");
+        testing::assert_logged(LogLevel::Error, "flush error: invalid identifier: localhost");
+        stub::reset();
+    }
+
+    #[test]
+    fn test_plugin_dropped_once_after_shutdown() {
+        stub::reset();
+        module_register();
+        stub::init();
+        DROPS.with(|x| x.set(0));
+        SHUT_DOWN.with(|x| x.set(false));
+
+        assert_eq!(stub::shutdown(), vec![0]);
+        assert!(SHUT_DOWN.with(|x| x.get()));
+        assert_eq!(DROPS.with(|x| x.get()), 0);
+
+        // Each of the four registrations is freed, but the plugin is only dropped by the last
+        stub::reset();
+        assert_eq!(DROPS.with(|x| x.get()), 1);
+    }
+
+    #[test]
+    fn test_invalid_plugin_name_not_registered() {
+        stub::reset();
+        DROPS.with(|x| x.set(0));
+
+        let callbacks = PluginCallbacks {
+            read: Some(collectd_plugin_read),
+            write: Some(collectd_plugin_write),
+            log: Some(collectd_plugin_log),
+            flush: Some(collectd_plugin_flush),
+        };
+        register_plugin("myplugin/every\0thing", Box::new(Everything), &callbacks);
+
+        // The error is logged (without the null) and the plugin is dropped without registering
+        assert!(stub::registrations().is_empty());
+        assert_eq!(DROPS.with(|x| x.get()), 1);
+        testing::assert_logged(
+            LogLevel::Error,
+            "myplugin/everything: unable to register plugin: nul byte found in provided data at \
+             position: 14",
+        );
+        stub::reset();
+    }
+
+    #[test]
+    fn test_plugin_dropped_once_after_rescheduling() {
+        stub::reset();
+        module_register();
+        stub::init();
+        DROPS.with(|x| x.set(0));
+
+        // Every read hands the plugin to a new read registration, while the write, log, and
+        // flush registrations keep using the same plugin
+        let values = [Value::Gauge(1.0)];
+        let fixture = testing::ValueListFixture::new("cpu", "percent", &values);
+        for _ in 0..3 {
+            assert_eq!(stub::read("myplugin/everything"), Some(0));
+            let res = stub::write("myplugin/everything", fixture.data_set(), fixture.value_list());
+            assert_eq!(res, Some(0));
+            assert_eq!(stub::flush("myplugin/everything", 0, None), Some(0));
+        }
+        assert_eq!(DROPS.with(|x| x.get()), 0);
+
+        // The unregistered reads no longer own the plugin, so it's freed once with the rest
+        stub::reset();
+        assert_eq!(DROPS.with(|x| x.get()), 1);
+    }
+
+    #[test]
+    fn test_reconfigure_interval() {
+        stub::reset();
+        module_register();
+        stub::init();
+
+        assert_eq!(stub::read_interval("myplugin/backoff"), Some(0));
+        assert_eq!(stub::read("myplugin/backoff"), Some(0));
+        let interval: ::std::time::Duration =
+            CdTime::from(stub::read_interval("myplugin/backoff").unwrap()).into();
+        assert_eq!(interval, ::std::time::Duration::from_secs(60));
+
+        // The plugin survives being re-registered
+        assert_eq!(stub::read("myplugin/backoff"), Some(0));
+        testing::assert_logged(LogLevel::Info, "read 2");
+        stub::reset();
+    }
+
+    #[test]
+    fn test_write_to_single_plugin() {
+        stub::reset();
+        module_register();
+        stub::init();
+        stub::define_type("percent", &[("value", DataSourceType::Gauge)]);
+
+        let values = [Value::Gauge(1.0)];
+        let builder = ValueListBuilder::new("cpu", "percent").values(&values);
+        assert!(builder.write_to("myplugin/cpu_writer").is_ok());
+        testing::assert_logged(LogLevel::Info, "wrote cpu");
+        assert!(stub::dispatched().is_empty());
+
+        let err = builder.write_to("write_tsdb").unwrap_err();
+        assert_eq!(err.to_string(), "plugin_write to write_tsdb returned an error: 2");
+        stub::reset();
+    }
+
+    #[test]
+    fn test_write_filter_drops_lists() {
+        stub::reset();
+        module_register();
+        stub::init();
+
+        let values = [Value::Gauge(1.0)];
+        for plugin in &["memory", "cpu"] {
+            let fixture = testing::ValueListFixture::new(plugin, "percent", &values);
+            let res = stub::write(
+                "myplugin/cpu_writer",
+                fixture.data_set(),
+                fixture.value_list(),
+            );
+            assert_eq!(res, Some(0));
+        }
+
+        testing::assert_logged(LogLevel::Info, "wrote cpu");
+        assert_eq!(stub::logged().len(), 1);
+        stub::reset();
+    }
+
+    #[test]
+    fn test_write_store_rates() {
+        stub::reset();
+        module_register();
+        stub::init();
+
+        let values = [Value::Gauge(1.5), Value::Derive(500)];
+        let fixture = testing::ValueListFixture::new("interface", "if_octets", &values);
+        let write = || {
+            stub::write("myplugin/rate_writer", fixture.data_set(), fixture.value_list())
+        };
+
+        assert_eq!(write(), Some(-1));
+        testing::assert_logged(
+            LogLevel::Error,
+            "Unable to decode collectd data: collectd's cache has no rates for \
+             localhost/interface/if_octets",
+        );
+
+        stub::set_rates("localhost/interface/if_octets", &[1.5, 25.0]);
+        assert_eq!(write(), Some(0));
+        testing::assert_logged(LogLevel::Info, "wrote [Gauge(1.5), Gauge(25.0)]");
+        stub::reset();
+    }
+
+    #[test]
+    fn test_log_filter_drops_messages() {
+        use std::ffi::CString;
+
+        stub::reset();
+        module_register();
+        stub::init();
+
+        let msg = CString::new("hello").unwrap();
+        for lvl in &[LogLevel::Debug, LogLevel::Warning, LogLevel::Info, LogLevel::Error] {
+            assert!(stub::log("myplugin/quiet", lvl.as_raw() as i32, &msg));
+        }
+
+        let received = QUIET_RECEIVED.with(|x| x.borrow_mut().split_off(0));
+        assert_eq!(received, vec![LogLevel::Warning, LogLevel::Error]);
+        stub::reset();
+    }
+
+    #[test]
+    fn test_nested_log_messages_not_reentered() {
+        use std::ffi::CString;
+
+        stub::reset();
+        module_register();
+        stub::init();
+
+        let msg = CString::new("hello").unwrap();
+        assert!(stub::log("myplugin/chatty", LogLevel::Info.as_raw() as i32, &msg));
+        assert!(stub::log("myplugin/chatty", LogLevel::Info.as_raw() as i32, &msg));
+
+        // The plugin's own messages aren't handed back to it, but it keeps receiving the rest
+        let received = CHATTY_RECEIVED.with(|x| x.borrow_mut().split_off(0));
+        assert_eq!(received, vec![String::from("hello"), String::from("hello")]);
+        stub::reset();
+    }
 }
@@ -0,0 +1,113 @@
+//! A backend for the [log](https://crates.io/crates/log) crate that forwards records to collectd's
+//! log, so that libraries used within a plugin have their output show up alongside collectd's.
+
+use log::{self, Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use api::{collectd_log, LogLevel};
+
+/// Implements `log::Log` by prefixing each message with the plugin's name and sending it to
+/// `collectd_log` at the equivalent level.
+#[derive(Debug)]
+pub struct CollectdLoggerBackend {
+    plugin: &'static str,
+    filter: LevelFilter,
+}
+
+impl CollectdLoggerBackend {
+    pub fn new(plugin: &'static str, filter: LevelFilter) -> Self {
+        CollectdLoggerBackend {
+            plugin: plugin,
+            filter: filter,
+        }
+    }
+
+    /// Installs this backend as the global logger. As the `log` crate allows only one global
+    /// logger per process, this can fail if another logger has already been installed
+    pub fn install(self) -> Result<(), SetLoggerError> {
+        let filter = self.filter;
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(filter);
+        Ok(())
+    }
+}
+
+/// Installs a collectd logger backend for the given plugin, which will forward records at or
+/// above the given filter
+///
+/// ```ignore
+/// collectd_plugin::install_logger("myplugin", log::LevelFilter::Info)?;
+/// ```
+pub fn install_logger(plugin: &'static str, filter: LevelFilter) -> Result<(), SetLoggerError> {
+    CollectdLoggerBackend::new(plugin, filter).install()
+}
+
+/// Maps a `log::Level` to the closest collectd log level. Collectd has no concept of trace, so
+/// those are logged as debug.
+pub fn log_level(lvl: Level) -> LogLevel {
+    match lvl {
+        Level::Error => LogLevel::Error,
+        Level::Warn => LogLevel::Warning,
+        Level::Info => LogLevel::Info,
+        Level::Debug | Level::Trace => LogLevel::Debug,
+    }
+}
+
+impl Log for CollectdLoggerBackend {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Collectd messages can't contain nulls, so strip them instead of panicking
+        let msg = format!("{}: {}", self.plugin, record.args()).replace('\0', "");
+        collectd_log(log_level(record.level()), &msg);
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_level_mapping() {
+        assert_eq!(log_level(Level::Error), LogLevel::Error);
+        assert_eq!(log_level(Level::Warn), LogLevel::Warning);
+        assert_eq!(log_level(Level::Info), LogLevel::Info);
+        assert_eq!(log_level(Level::Debug), LogLevel::Debug);
+        assert_eq!(log_level(Level::Trace), LogLevel::Debug);
+    }
+
+    #[test]
+    fn test_enabled_respects_filter() {
+        let backend = CollectdLoggerBackend::new("myplugin", LevelFilter::Warn);
+        let warn = Metadata::builder().level(Level::Warn).build();
+        let info = Metadata::builder().level(Level::Info).build();
+        assert!(backend.enabled(&warn));
+        assert!(!backend.enabled(&info));
+    }
+
+    #[test]
+    fn test_records_are_logged() {
+        use stub;
+
+        stub::reset();
+        install_logger("myplugin", LevelFilter::Info).unwrap();
+        log::info!("connected to {}", "db1");
+        log::debug!("handshake complete");
+
+        let logged = stub::logged();
+        assert_eq!(logged.len(), 1);
+        assert_eq!(logged[0].severity, LogLevel::Info.as_raw() as i32);
+        assert_eq!(logged[0].message, "myplugin: connected to db1");
+
+        // Records are filtered by the backend too, not just by the `log` crate's max level
+        let backend = CollectdLoggerBackend::new("myplugin", LevelFilter::Warn);
+        backend.log(&Record::builder().level(Level::Info).args(format_args!("hi")).build());
+        assert_eq!(stub::logged().len(), 1);
+    }
+}
@@ -0,0 +1,43 @@
+/// Logs a formatted message at the error level, prefixed with the plugin's name
+///
+/// ```ignore
+/// collectd_error!("unable to connect to {}: {}", host, e);
+/// ```
+#[macro_export]
+macro_rules! collectd_error {
+    ($($arg:tt)+) => {
+        $crate::collectd_log_args($crate::LogLevel::Error, format_args!($($arg)+))
+    };
+}
+
+/// Logs a formatted message at the warning level, prefixed with the plugin's name
+#[macro_export]
+macro_rules! collectd_warning {
+    ($($arg:tt)+) => {
+        $crate::collectd_log_args($crate::LogLevel::Warning, format_args!($($arg)+))
+    };
+}
+
+/// Logs a formatted message at the notice level, prefixed with the plugin's name
+#[macro_export]
+macro_rules! collectd_notice {
+    ($($arg:tt)+) => {
+        $crate::collectd_log_args($crate::LogLevel::Notice, format_args!($($arg)+))
+    };
+}
+
+/// Logs a formatted message at the info level, prefixed with the plugin's name
+#[macro_export]
+macro_rules! collectd_info {
+    ($($arg:tt)+) => {
+        $crate::collectd_log_args($crate::LogLevel::Info, format_args!($($arg)+))
+    };
+}
+
+/// Logs a formatted message at the debug level, prefixed with the plugin's name
+#[macro_export]
+macro_rules! collectd_debug {
+    ($($arg:tt)+) => {
+        $crate::collectd_log_args($crate::LogLevel::Debug, format_args!($($arg)+))
+    };
+}
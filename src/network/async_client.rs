@@ -0,0 +1,184 @@
+use super::client::{bind, is_multicast_v4};
+use super::{ClientError, EncodeError, Encoder, ValueList};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::net::UdpSocket;
+
+/// The async counterpart to `NetworkClient`, for services running on tokio. It must be created
+/// within a tokio runtime. Unlike `NetworkClient`, the pending packet isn't sent on drop, so
+/// `flush` should be awaited before the client goes away.
+///
+/// ```ignore
+/// let mut client = AsyncNetworkClient::new("127.0.0.1:25826".parse()?)?;
+/// client.send(&list).await?;
+/// client.flush().await?;
+/// ```
+#[derive(Debug)]
+pub struct AsyncNetworkClient {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    encoder: Encoder,
+}
+
+impl AsyncNetworkClient {
+    /// Sends unsigned packets of the default size to the address. The address is already resolved
+    /// as resolving a hostname would block.
+    pub fn new(addr: SocketAddr) -> io::Result<Self> {
+        AsyncNetworkClient::with_encoder(addr, Encoder::new())
+    }
+
+    /// Sends packets as configured by the encoder, which determines their maximum size and
+    /// whether they're signed or encrypted
+    pub fn with_encoder(addr: SocketAddr, encoder: Encoder) -> io::Result<Self> {
+        let socket = bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(AsyncNetworkClient {
+            socket: UdpSocket::from_std(socket)?,
+            addr: addr,
+            encoder: encoder,
+        })
+    }
+
+    /// Sets how many hops packets may take, like the network plugin's `TimeToLive` option. For
+    /// IPv4 multicast groups this is the multicast TTL.
+    pub fn ttl(&self, ttl: u32) -> io::Result<()> {
+        if is_multicast_v4(self.addr) {
+            self.socket.set_multicast_ttl_v4(ttl)
+        } else {
+            self.socket.set_ttl(ttl)
+        }
+    }
+
+    /// Server (or multicast group) that packets are sent to
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Queues the value list. If it doesn't fit into the pending packet, a new packet is started
+    /// and the returned future sends the pending one.
+    pub fn send(&mut self, list: &ValueList) -> SendPacket {
+        let (packet, error) = match self.encoder.add(list) {
+            Err(EncodeError::PacketFull) => match self.encoder.finish() {
+                Ok(packet) => (Some(packet), self.encoder.add(list).err()),
+                Err(e) => (None, Some(e)),
+            },
+            x => (None, x.err()),
+        };
+
+        SendPacket {
+            socket: &self.socket,
+            addr: self.addr,
+            packet: packet,
+            error: error,
+        }
+    }
+
+    /// Sends the pending packet, if there is one
+    pub fn flush(&mut self) -> SendPacket {
+        let (packet, error) = if self.encoder.is_empty() {
+            (None, None)
+        } else {
+            match self.encoder.finish() {
+                Ok(packet) => (Some(packet), None),
+                Err(e) => (None, Some(e)),
+            }
+        };
+
+        SendPacket {
+            socket: &self.socket,
+            addr: self.addr,
+            packet: packet,
+            error: error,
+        }
+    }
+}
+
+/// Future that sends a finished packet (if any) and then reports whether the value list was
+/// queued and the packet could be finished
+#[derive(Debug)]
+pub struct SendPacket<'a> {
+    socket: &'a UdpSocket,
+    addr: SocketAddr,
+    packet: Option<Vec<u8>>,
+    error: Option<EncodeError>,
+}
+
+impl<'a> Future for SendPacket<'a> {
+    type Output = Result<(), ClientError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let me = self.get_mut();
+        if let Some(ref packet) = me.packet {
+            match me.socket.poll_send_to(cx, packet, me.addr) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(ClientError::Io(e))),
+                Poll::Ready(Ok(_)) => {}
+            }
+        }
+
+        me.packet = None;
+        Poll::Ready(me.error.take().map_or(Ok(()), |e| Err(ClientError::Encode(e))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{CdTime, Value};
+    use network::{decode, Record};
+    use std::time::Duration;
+    use tokio::runtime;
+
+    fn list(plugin_instance: &str) -> ValueList {
+        ValueList {
+            host: String::from("localhost"),
+            plugin: String::from("cpu"),
+            plugin_instance: Some(String::from(plugin_instance)),
+            type_: String::from("percent"),
+            type_instance: None,
+            time: CdTime::from(Duration::from_secs(1_500_000_000)),
+            interval: CdTime::from(Duration::from_secs(10)),
+            values: vec![Value::Gauge(1.0)],
+        }
+    }
+
+    #[test]
+    fn test_async_client_batches() {
+        let rt = runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        let _guard = rt.enter();
+
+        let server = ::std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let addr = server.local_addr().unwrap();
+        let mut client = AsyncNetworkClient::with_encoder(addr, Encoder::with_max_size(90))
+            .unwrap();
+
+        // Both lists don't fit into one packet, so sending the second sends the first
+        rt.block_on(client.send(&list("0"))).unwrap();
+        rt.block_on(client.send(&list("1"))).unwrap();
+        rt.block_on(client.flush()).unwrap();
+
+        let mut buf = [0u8; 1452];
+        for id in 0..2 {
+            let len = server.recv(&mut buf).unwrap();
+            let expected = vec![Record::Values(list(&id.to_string()))];
+            assert_eq!(decode(&buf[..len]), Ok(expected));
+        }
+
+        let mut client = AsyncNetworkClient::with_encoder(addr, Encoder::with_max_size(10))
+            .unwrap();
+        match rt.block_on(client.send(&list("0"))) {
+            Err(ClientError::Encode(EncodeError::TooLong(_))) => {}
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use super::{EncodeError, Encoder, ValueList};
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The value list couldn't be encoded (even into an empty packet), or the packet couldn't be
+    /// encrypted
+    Encode(EncodeError),
+
+    /// A finished packet couldn't be sent
+    Io(io::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::Encode(_) => write!(f, "unable to encode value list"),
+            ClientError::Io(_) => write!(f, "unable to send packet"),
+        }
+    }
+}
+
+impl error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ClientError::Encode(ref e) => Some(e),
+            ClientError::Io(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<EncodeError> for ClientError {
+    fn from(err: EncodeError) -> ClientError {
+        ClientError::Encode(err)
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> ClientError {
+        ClientError::Io(err)
+    }
+}
+
+/// Binds an ephemeral port of the same address family as the server
+pub fn bind(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let local = match addr.ip() {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+
+    UdpSocket::bind(SocketAddr::new(local, 0))
+}
+
+/// Whether the TTL of packets to the address is set as a multicast TTL
+pub fn is_multicast_v4(addr: SocketAddr) -> bool {
+    match addr.ip() {
+        IpAddr::V4(ip) => ip.is_multicast(),
+        IpAddr::V6(_) => false,
+    }
+}
+
+/// Pushes value lists to a collectd server (or multicast group) over UDP. Value lists are batched
+/// into packets, which are sent once full, when `flush` is called, or when the client is dropped.
+///
+/// ```no_run
+/// use collectd_plugin::network::{Encoder, NetworkClient};
+///
+/// # fn lists() -> Vec<collectd_plugin::network::ValueList> { vec![] }
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let encoder = Encoder::with_max_size(8192);
+/// let mut client = NetworkClient::with_encoder("239.192.74.66:25826", encoder)?;
+/// for list in lists() {
+///     client.send(&list)?;
+/// }
+/// client.flush()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct NetworkClient {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    encoder: Encoder,
+}
+
+impl NetworkClient {
+    /// Sends unsigned packets of the default size to the first address that `addr` resolves to
+    pub fn new<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        NetworkClient::with_encoder(addr, Encoder::new())
+    }
+
+    /// Sends packets as configured by the encoder, which determines their maximum size and
+    /// whether they're signed or encrypted
+    pub fn with_encoder<A: ToSocketAddrs>(addr: A, encoder: Encoder) -> io::Result<Self> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+        })?;
+
+        Ok(NetworkClient {
+            socket: bind(addr)?,
+            addr: addr,
+            encoder: encoder,
+        })
+    }
+
+    /// Sets how many hops packets may take, like the network plugin's `TimeToLive` option. For
+    /// IPv4 multicast groups this is the multicast TTL.
+    pub fn ttl(&self, ttl: u32) -> io::Result<()> {
+        if is_multicast_v4(self.addr) {
+            self.socket.set_multicast_ttl_v4(ttl)
+        } else {
+            self.socket.set_ttl(ttl)
+        }
+    }
+
+    /// Server (or multicast group) that packets are sent to
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Queues the value list, first sending the pending packet if the list doesn't fit into it
+    pub fn send(&mut self, list: &ValueList) -> Result<(), ClientError> {
+        match self.encoder.add(list) {
+            Err(EncodeError::PacketFull) => {
+                self.flush()?;
+                self.encoder.add(list)?;
+                Ok(())
+            }
+            x => x.map_err(ClientError::from),
+        }
+    }
+
+    /// Sends the pending packet, if there is one
+    pub fn flush(&mut self) -> Result<(), ClientError> {
+        if self.encoder.is_empty() {
+            return Ok(());
+        }
+
+        let packet = self.encoder.finish()?;
+        self.socket.send_to(&packet, self.addr)?;
+        Ok(())
+    }
+}
+
+impl Drop for NetworkClient {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{CdTime, Value};
+    #[cfg(feature = "network-security")]
+    use network::{Decoder, Record};
+    use std::time::Duration;
+
+    fn list(plugin_instance: &str) -> ValueList {
+        ValueList {
+            host: String::from("localhost"),
+            plugin: String::from("cpu"),
+            plugin_instance: Some(String::from(plugin_instance)),
+            type_: String::from("percent"),
+            type_instance: None,
+            time: CdTime::from(Duration::from_secs(1_500_000_000)),
+            interval: CdTime::from(Duration::from_secs(10)),
+            values: vec![Value::Gauge(1.0)],
+        }
+    }
+
+    #[cfg(feature = "network-security")]
+    fn receive(server: &UdpSocket, decoder: &Decoder) -> Vec<Record> {
+        let mut buf = [0u8; 1452];
+        let len = server.recv(&mut buf).unwrap();
+        decoder.decode(&buf[..len]).unwrap()
+    }
+
+    #[cfg(feature = "network-security")]
+    #[test]
+    fn test_client_batches() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let encoder = Encoder::with_max_size(130).encrypt("admin", "secret");
+        let addr = server.local_addr().unwrap();
+        let mut client = NetworkClient::with_encoder(addr, encoder).unwrap();
+        let decoder = Decoder::new().user("admin", "secret");
+
+        // The first list takes up most of the packet, so the second is sent in its own
+        for id in 0..2 {
+            client.send(&list(&id.to_string())).unwrap();
+        }
+        assert_eq!(receive(&server, &decoder), vec![Record::Values(list("0"))]);
+
+        drop(client);
+        assert_eq!(receive(&server, &decoder), vec![Record::Values(list("1"))]);
+    }
+
+    #[test]
+    fn test_client_too_long() {
+        let encoder = Encoder::with_max_size(10);
+        let mut client = NetworkClient::with_encoder("127.0.0.1:25826", encoder).unwrap();
+        match client.send(&list("0")) {
+            Err(ClientError::Encode(EncodeError::TooLong(_))) => {}
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+}
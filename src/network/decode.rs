@@ -0,0 +1,502 @@
+#[cfg(feature = "network-security")]
+use super::security::{decrypt, verify};
+use super::security::{encrypted_user, signature, SecurityLevel};
+use super::{Notification, Record, ValueList, HEADER_LEN, TYPE_ENCR_AES256, TYPE_HOST,
+            TYPE_INTERVAL, TYPE_INTERVAL_HR, TYPE_MESSAGE, TYPE_PLUGIN, TYPE_PLUGIN_INSTANCE,
+            TYPE_SEVERITY, TYPE_SIGN_SHA256, TYPE_TIME, TYPE_TIME_HR, TYPE_TYPE,
+            TYPE_TYPE_INSTANCE, TYPE_VALUES};
+use api::{empty_to_none, CdTime, Value};
+use constants::{DataSourceType, Severity};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::os::raw::c_int;
+use std::str::{self, Utf8Error};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DecodeError {
+    /// The packet ended in the middle of a part
+    Truncated,
+
+    /// The part of the given type has a length that doesn't fit its contents
+    InvalidLength(u16),
+
+    /// A string part isn't null terminated
+    NotTerminated(u16),
+
+    /// A string part isn't valid UTF-8
+    Utf8(u16, Utf8Error),
+
+    UnknownDataSourceType(u8),
+    UnknownSeverity(u64),
+
+    /// A signature part is from a user without a password, and the security level requires it
+    UnknownUser(String),
+
+    /// A signature part doesn't match the data it signs
+    SignatureMismatch(String),
+
+    /// An encryption part couldn't be decrypted with the user's password
+    DecryptionFailed(String),
+
+    /// Data was received that isn't protected as the security level requires
+    InsufficientSecurity,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Truncated => write!(f, "packet ended in the middle of a part"),
+            DecodeError::InvalidLength(kind) => {
+                write!(f, "part 0x{:04x} has an invalid length", kind)
+            }
+            DecodeError::NotTerminated(kind) => {
+                write!(f, "string part 0x{:04x} is not null terminated", kind)
+            }
+            DecodeError::Utf8(kind, _) => write!(f, "string part 0x{:04x} is not UTF-8", kind),
+            DecodeError::UnknownDataSourceType(x) => write!(f, "unknown data source type: {}", x),
+            DecodeError::UnknownSeverity(x) => write!(f, "unknown notification severity: {}", x),
+            DecodeError::UnknownUser(ref user) => write!(f, "no password for user: {}", user),
+            DecodeError::SignatureMismatch(ref user) => {
+                write!(f, "signature from user {} does not match", user)
+            }
+            DecodeError::DecryptionFailed(ref user) => {
+                write!(f, "unable to decrypt data from user {}", user)
+            }
+            DecodeError::InsufficientSecurity => {
+                write!(f, "data does not meet the required security level")
+            }
+        }
+    }
+}
+
+impl error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            DecodeError::Utf8(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The fields set by the parts seen so far in the packet
+#[derive(Debug, Default)]
+struct State<'a> {
+    host: &'a str,
+    plugin: &'a str,
+    plugin_instance: &'a str,
+    type_: &'a str,
+    type_instance: &'a str,
+    time: u64,
+    interval: u64,
+    severity: u64,
+}
+
+impl<'a> State<'a> {
+    fn value_list(&self, values: Vec<Value>) -> ValueList {
+        ValueList {
+            host: String::from(self.host),
+            plugin: String::from(self.plugin),
+            plugin_instance: empty_to_none(self.plugin_instance).map(String::from),
+            type_: String::from(self.type_),
+            type_instance: empty_to_none(self.type_instance).map(String::from),
+            time: CdTime::from(self.time),
+            interval: CdTime::from(self.interval),
+            values: values,
+        }
+    }
+
+    fn notification(&self, message: &str) -> Result<Notification, DecodeError> {
+        let severity = c_int::try_from(self.severity)
+            .ok()
+            .and_then(Severity::from_raw)
+            .ok_or(DecodeError::UnknownSeverity(self.severity))?;
+
+        Ok(Notification {
+            host: String::from(self.host),
+            plugin: String::from(self.plugin),
+            plugin_instance: empty_to_none(self.plugin_instance).map(String::from),
+            type_: String::from(self.type_),
+            type_instance: empty_to_none(self.type_instance).map(String::from),
+            time: CdTime::from(self.time),
+            severity: severity,
+            message: String::from(message),
+        })
+    }
+}
+
+/// Splits a packet into its parts' types, contents, and the remainder of the packet after the part
+fn parts(mut packet: &[u8]) -> Result<Vec<(u16, &[u8], &[u8])>, DecodeError> {
+    let mut parts = Vec::new();
+    while !packet.is_empty() {
+        if packet.len() < HEADER_LEN {
+            return Err(DecodeError::Truncated);
+        }
+
+        let kind = u16::from_be_bytes([packet[0], packet[1]]);
+        let len = usize::from(u16::from_be_bytes([packet[2], packet[3]]));
+        if len < HEADER_LEN {
+            return Err(DecodeError::InvalidLength(kind));
+        }
+
+        if len > packet.len() {
+            return Err(DecodeError::Truncated);
+        }
+
+        parts.push((kind, &packet[HEADER_LEN..len], &packet[len..]));
+        packet = &packet[len..];
+    }
+
+    Ok(parts)
+}
+
+fn string(kind: u16, body: &[u8]) -> Result<&str, DecodeError> {
+    match body.split_last() {
+        Some((&0, s)) => str::from_utf8(s).map_err(|e| DecodeError::Utf8(kind, e)),
+        _ => Err(DecodeError::NotTerminated(kind)),
+    }
+}
+
+fn number(kind: u16, body: &[u8]) -> Result<u64, DecodeError> {
+    if body.len() != 8 {
+        return Err(DecodeError::InvalidLength(kind));
+    }
+
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(body);
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn values(body: &[u8]) -> Result<Vec<Value>, DecodeError> {
+    if body.len() < 2 {
+        return Err(DecodeError::InvalidLength(TYPE_VALUES));
+    }
+
+    let count = usize::from(u16::from_be_bytes([body[0], body[1]]));
+    let body = &body[2..];
+    if body.len() != count * 9 {
+        return Err(DecodeError::InvalidLength(TYPE_VALUES));
+    }
+
+    let (types, data) = body.split_at(count);
+    types
+        .iter()
+        .zip(data.chunks(8))
+        .map(|(&kind, raw)| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(raw);
+            match DataSourceType::from_raw(c_int::from(kind)) {
+                Some(DataSourceType::Counter) => Ok(Value::Counter(u64::from_be_bytes(bytes))),
+                Some(DataSourceType::Gauge) => {
+                    Ok(Value::Gauge(f64::from_bits(u64::from_le_bytes(bytes))))
+                }
+                Some(DataSourceType::Derive) => Ok(Value::Derive(i64::from_be_bytes(bytes))),
+                Some(DataSourceType::Absolute) => Ok(Value::Absolute(u64::from_be_bytes(bytes))),
+                None => Err(DecodeError::UnknownDataSourceType(kind)),
+            }
+        })
+        .collect()
+}
+
+/// Parses the value lists and notifications out of a packet without requiring any security.
+/// Signatures can't be verified, so they are skipped.
+pub fn decode(packet: &[u8]) -> Result<Vec<Record>, DecodeError> {
+    Decoder::new().decode(packet)
+}
+
+/// Parses packets, verifying signatures from known users
+#[derive(Debug, Default, Clone)]
+pub struct Decoder {
+    users: HashMap<String, String>,
+    security_level: SecurityLevel,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+
+    /// Adds a user that packets may be signed by, like an entry in the network plugin's
+    /// `AuthFile`
+    #[cfg(feature = "network-security")]
+    pub fn user(mut self, username: &str, password: &str) -> Self {
+        self.users
+            .insert(String::from(username), String::from(password));
+        self
+    }
+
+    pub fn security_level(mut self, level: SecurityLevel) -> Self {
+        self.security_level = level;
+        self
+    }
+
+    /// Parses the value lists and notifications out of a packet. Like collectd, parts of unknown
+    /// types are skipped.
+    pub fn decode(&self, packet: &[u8]) -> Result<Vec<Record>, DecodeError> {
+        let mut records = Vec::new();
+        self.decode_into(packet, false, &mut records)?;
+        Ok(records)
+    }
+
+    /// Decodes a packet, or the payload of an encryption part when `encrypted` is true
+    fn decode_into(
+        &self,
+        packet: &[u8],
+        encrypted: bool,
+        records: &mut Vec<Record>,
+    ) -> Result<(), DecodeError> {
+        let mut state = State::default();
+
+        // A signature covers the rest of the packet, so once one is verified everything after it
+        // is trusted
+        let mut signed = false;
+        for (kind, body, rest) in parts(packet)? {
+            if kind == TYPE_SIGN_SHA256 {
+                signed = signed || self.verify(body, rest)?;
+                continue;
+            }
+
+            if kind == TYPE_ENCR_AES256 {
+                if let Some(payload) = self.decrypt(body)? {
+                    self.decode_into(&payload, true, records)?;
+                }
+                continue;
+            }
+
+            let secure = match self.security_level {
+                SecurityLevel::None => true,
+                SecurityLevel::Sign => signed || encrypted,
+                SecurityLevel::Encrypt => encrypted,
+            };
+
+            if !secure {
+                return Err(DecodeError::InsufficientSecurity);
+            }
+
+            match kind {
+                TYPE_HOST => state.host = string(kind, body)?,
+                TYPE_PLUGIN => state.plugin = string(kind, body)?,
+                TYPE_PLUGIN_INSTANCE => state.plugin_instance = string(kind, body)?,
+                TYPE_TYPE => state.type_ = string(kind, body)?,
+                TYPE_TYPE_INSTANCE => state.type_instance = string(kind, body)?,
+                TYPE_TIME_HR => state.time = number(kind, body)?,
+                TYPE_INTERVAL_HR => state.interval = number(kind, body)?,
+
+                // Older versions of collectd send whole seconds
+                TYPE_TIME => state.time = number(kind, body)? << 30,
+                TYPE_INTERVAL => state.interval = number(kind, body)? << 30,
+                TYPE_SEVERITY => state.severity = number(kind, body)?,
+                TYPE_VALUES => records.push(Record::Values(state.value_list(values(body)?))),
+                TYPE_MESSAGE => {
+                    let message = string(kind, body)?;
+                    records.push(Record::Notification(state.notification(message)?));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the decrypted payload of an encryption part. Like signatures, encrypted data from
+    /// unknown users is skipped when security isn't required. Without the `network-security`
+    /// feature there are no users, so encrypted data is always from an unknown user.
+    #[cfg_attr(not(feature = "network-security"), allow(unused_variables))]
+    fn decrypt(&self, body: &[u8]) -> Result<Option<Vec<u8>>, DecodeError> {
+        let (username, rest) =
+            encrypted_user(body).ok_or(DecodeError::InvalidLength(TYPE_ENCR_AES256))?;
+        let username = str::from_utf8(username)
+            .map_err(|e| DecodeError::Utf8(TYPE_ENCR_AES256, e))?;
+
+        match self.users.get(username) {
+            #[cfg(feature = "network-security")]
+            Some(password) => decrypt(rest, password)
+                .map(Some)
+                .ok_or_else(|| DecodeError::DecryptionFailed(String::from(username))),
+            None if self.security_level == SecurityLevel::None => Ok(None),
+            _ => Err(DecodeError::UnknownUser(String::from(username))),
+        }
+    }
+
+    /// Returns whether the signature part was verified. Signatures from unknown users are
+    /// skipped when security isn't required, like collectd does.
+    #[cfg_attr(not(feature = "network-security"), allow(unused_variables))]
+    fn verify(&self, body: &[u8], rest: &[u8]) -> Result<bool, DecodeError> {
+        let (hash, username) =
+            signature(body).ok_or(DecodeError::InvalidLength(TYPE_SIGN_SHA256))?;
+        let username = str::from_utf8(username)
+            .map_err(|e| DecodeError::Utf8(TYPE_SIGN_SHA256, e))?;
+
+        match self.users.get(username) {
+            #[cfg(feature = "network-security")]
+            Some(password) if verify(hash, username.as_bytes(), password, rest) => Ok(true),
+            #[cfg(feature = "network-security")]
+            Some(_) => Err(DecodeError::SignatureMismatch(String::from(username))),
+            None if self.security_level == SecurityLevel::None => Ok(false),
+            _ => Err(DecodeError::UnknownUser(String::from(username))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use network::Encoder;
+
+    fn list(type_instance: &str, values: Vec<Value>) -> ValueList {
+        ValueList {
+            host: String::from("localhost"),
+            plugin: String::from("cpu"),
+            plugin_instance: Some(String::from("0")),
+            type_: String::from("percent"),
+            type_instance: Some(String::from(type_instance)),
+            time: CdTime(1_500_000_000_000_000_000),
+            interval: CdTime(10_000_000_000),
+            values: values,
+        }
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        let lists = vec![
+            list("idle", vec![Value::Gauge(99.5)]),
+            list("user", vec![Value::Counter(10), Value::Derive(-10), Value::Absolute(5)]),
+        ];
+
+        let mut encoder = Encoder::new();
+        for list in &lists {
+            encoder.add(list).unwrap();
+        }
+
+        let records = decode(&encoder.finish().unwrap()).unwrap();
+        let expected: Vec<Record> = lists.into_iter().map(Record::Values).collect();
+        assert_eq!(records, expected);
+    }
+
+    #[test]
+    fn test_decode_notification() {
+        let mut packet = vec![0x00, 0x00, 0x00, 0x06, b'h', 0];
+        packet.extend_from_slice(&[0x00, 0x01, 0x00, 0x0c]);
+        packet.extend_from_slice(&1_500_000_000u64.to_be_bytes());
+        packet.extend_from_slice(&[0x00, 0x02, 0x00, 0x06, b'p', 0]);
+        packet.extend_from_slice(&[0x01, 0x01, 0x00, 0x0c]);
+        packet.extend_from_slice(&2u64.to_be_bytes());
+        packet.extend_from_slice(&[0x01, 0x00, 0x00, 0x08, b'o', b'o', b'f', 0]);
+
+        // Unknown parts are skipped
+        packet.extend_from_slice(&[0x7f, 0x7f, 0x00, 0x05, 0xff]);
+
+        let records = decode(&packet).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                Record::Notification(Notification {
+                    host: String::from("h"),
+                    plugin: String::from("p"),
+                    plugin_instance: None,
+                    type_: String::from(""),
+                    type_instance: None,
+                    time: CdTime(1_500_000_000_000_000_000),
+                    severity: Severity::Warning,
+                    message: String::from("oof"),
+                }),
+            ]
+        );
+    }
+
+    #[cfg(feature = "network-security")]
+    #[test]
+    fn test_decode_signed() {
+        let mut encoder = Encoder::new().sign("admin", "secret");
+        encoder.add(&list("idle", vec![Value::Gauge(1.0)])).unwrap();
+        let packet = encoder.finish().unwrap();
+        assert_eq!(&packet[..2], &[0x02, 0x00]);
+
+        let expected = vec![Record::Values(list("idle", vec![Value::Gauge(1.0)]))];
+        let decoder = Decoder::new()
+            .user("admin", "secret")
+            .security_level(SecurityLevel::Sign);
+        assert_eq!(decoder.decode(&packet), Ok(expected.clone()));
+
+        // Without a password the signature is skipped unless it is required
+        assert_eq!(decode(&packet), Ok(expected));
+        assert_eq!(
+            Decoder::new()
+                .security_level(SecurityLevel::Sign)
+                .decode(&packet),
+            Err(DecodeError::UnknownUser(String::from("admin")))
+        );
+
+        let wrong = Decoder::new().user("admin", "guess");
+        assert_eq!(
+            wrong.decode(&packet),
+            Err(DecodeError::SignatureMismatch(String::from("admin")))
+        );
+
+        let mut tampered = packet.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            decoder.decode(&tampered),
+            Err(DecodeError::SignatureMismatch(String::from("admin")))
+        );
+
+        // Unsigned packets don't meet the security level
+        let mut encoder = Encoder::new();
+        encoder.add(&list("idle", vec![Value::Gauge(1.0)])).unwrap();
+        assert_eq!(
+            decoder.decode(&encoder.finish().unwrap()),
+            Err(DecodeError::InsufficientSecurity)
+        );
+    }
+
+    #[cfg(feature = "network-security")]
+    #[test]
+    fn test_decode_encrypted() {
+        let mut encoder = Encoder::new().encrypt("admin", "secret");
+        encoder.add(&list("idle", vec![Value::Gauge(1.0)])).unwrap();
+        let packet = encoder.finish().unwrap();
+        assert_eq!(&packet[..2], &[0x02, 0x10]);
+
+        let expected = vec![Record::Values(list("idle", vec![Value::Gauge(1.0)]))];
+        let decoder = Decoder::new()
+            .user("admin", "secret")
+            .security_level(SecurityLevel::Encrypt);
+        assert_eq!(decoder.decode(&packet), Ok(expected.clone()));
+        assert_eq!(
+            decoder.clone().security_level(SecurityLevel::Sign).decode(&packet),
+            Ok(expected)
+        );
+
+        // Encrypted data from unknown users is skipped unless it is required
+        assert_eq!(decode(&packet), Ok(vec![]));
+        assert_eq!(
+            Decoder::new().user("admin", "guess").decode(&packet),
+            Err(DecodeError::DecryptionFailed(String::from("admin")))
+        );
+
+        // Signed data isn't enough
+        let mut encoder = Encoder::new().sign("admin", "secret");
+        encoder.add(&list("idle", vec![Value::Gauge(1.0)])).unwrap();
+        assert_eq!(
+            decoder.decode(&encoder.finish().unwrap()),
+            Err(DecodeError::InsufficientSecurity)
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(decode(&[0x00, 0x00, 0x00]), Err(DecodeError::Truncated));
+        assert_eq!(decode(&[0x00, 0x00, 0x00, 0x08, b'h']), Err(DecodeError::Truncated));
+        assert_eq!(decode(&[0x00, 0x02, 0x00, 0x02]), Err(DecodeError::InvalidLength(2)));
+        assert_eq!(decode(&[0x00, 0x00, 0x00, 0x05, b'h']), Err(DecodeError::NotTerminated(0)));
+        assert_eq!(
+            decode(&[0x00, 0x06, 0x00, 0x0f, 0x00, 0x01, 0x09, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(DecodeError::UnknownDataSourceType(9))
+        );
+        assert_eq!(
+            decode(&[0x00, 0x06, 0x00, 0x0e, 0x00, 0x01, 0x01, 0, 0, 0, 0, 0, 0, 0]),
+            Err(DecodeError::InvalidLength(TYPE_VALUES))
+        );
+    }
+}
@@ -0,0 +1,380 @@
+#[cfg(feature = "network-security")]
+use super::security::{encrypt, encryption_overhead, sign, signature_overhead};
+use super::{ValueList, DEFAULT_PACKET_SIZE, HEADER_LEN, TYPE_HOST, TYPE_INTERVAL_HR,
+            TYPE_PLUGIN, TYPE_PLUGIN_INSTANCE, TYPE_TIME_HR, TYPE_TYPE, TYPE_TYPE_INSTANCE,
+            TYPE_VALUES};
+use api::Value;
+use bindings::cdtime_t;
+use constants::{DS_TYPE_ABSOLUTE, DS_TYPE_COUNTER, DS_TYPE_DERIVE, DS_TYPE_GAUGE};
+use std::error;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EncodeError {
+    /// The named field contains a null character, which can't be represented in a string part
+    NullPresent(&'static str),
+
+    /// The named field is too long to fit into a single part (or packet)
+    TooLong(&'static str),
+
+    /// The value list doesn't fit into the remainder of the packet, which should be finished and
+    /// sent before trying again
+    PacketFull,
+
+    /// The operating system's random number generator couldn't provide an IV for encryption
+    #[cfg(feature = "network-security")]
+    Random(getrandom::Error),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncodeError::NullPresent(field) => write!(f, "{} contains a null character", field),
+            EncodeError::TooLong(field) => write!(f, "{} is too long to encode", field),
+            EncodeError::PacketFull => write!(f, "value list does not fit into the packet"),
+            #[cfg(feature = "network-security")]
+            EncodeError::Random(_) => write!(f, "unable to generate an IV for encryption"),
+        }
+    }
+}
+
+impl error::Error for EncodeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            #[cfg(feature = "network-security")]
+            EncodeError::Random(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The fields most recently written to the packet, which subsequent value lists inherit
+#[derive(Debug, Default, Clone)]
+struct State {
+    host: String,
+    plugin: String,
+    plugin_instance: String,
+    type_: String,
+    type_instance: String,
+    time: u64,
+    interval: u64,
+}
+
+/// Encodes value lists into packets of at most a given size. Like the network plugin, fields that
+/// are the same as the previous value list in the packet are omitted.
+///
+/// ```
+/// use collectd_plugin::network::Encoder;
+///
+/// # fn lists() -> Vec<collectd_plugin::network::ValueList> { vec![] }
+/// let mut encoder = Encoder::new();
+/// let mut packets = Vec::new();
+/// for list in lists() {
+///     if encoder.add(&list).is_err() {
+///         packets.push(encoder.finish().unwrap());
+///         encoder.add(&list).unwrap();
+///     }
+/// }
+/// packets.push(encoder.finish().unwrap());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    buf: Vec<u8>,
+    max_size: usize,
+    state: State,
+    security: Security,
+}
+
+/// How finished packets are protected, along with the username and password
+#[derive(Debug, Clone)]
+enum Security {
+    None,
+    #[cfg(feature = "network-security")]
+    Sign(String, String),
+    #[cfg(feature = "network-security")]
+    Encrypt(String, String),
+}
+
+impl Encoder {
+    /// Encodes packets no larger than the network plugin's default buffer size
+    pub fn new() -> Self {
+        Encoder::with_max_size(DEFAULT_PACKET_SIZE)
+    }
+
+    pub fn with_max_size(max_size: usize) -> Self {
+        Encoder {
+            buf: Vec::with_capacity(max_size),
+            max_size: max_size,
+            state: State::default(),
+            security: Security::None,
+        }
+    }
+
+    /// Signs finished packets with HMAC-SHA256, for servers with `SecurityLevel Sign`. Room for
+    /// the signature is reserved within the maximum size.
+    #[cfg(feature = "network-security")]
+    pub fn sign(mut self, username: &str, password: &str) -> Self {
+        self.security = Security::Sign(String::from(username), String::from(password));
+        self
+    }
+
+    /// Encrypts finished packets with AES-256, for servers with `SecurityLevel Encrypt`. Room for
+    /// the encryption's header is reserved within the maximum size.
+    #[cfg(feature = "network-security")]
+    pub fn encrypt(mut self, username: &str, password: &str) -> Self {
+        self.security = Security::Encrypt(String::from(username), String::from(password));
+        self
+    }
+
+    /// Size of the value lists encoded into the packet so far, which excludes any signature or
+    /// encryption
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Room left in the packet for value lists
+    fn limit(&self) -> usize {
+        let overhead = match self.security {
+            Security::None => 0,
+            #[cfg(feature = "network-security")]
+            Security::Sign(ref username, _) => signature_overhead(username),
+            #[cfg(feature = "network-security")]
+            Security::Encrypt(ref username, _) => encryption_overhead(username),
+        };
+
+        self.max_size.saturating_sub(overhead)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Appends the value list to the packet. If it would make the packet too large, the packet is
+    /// left untouched and `EncodeError::PacketFull` is returned.
+    pub fn add(&mut self, list: &ValueList) -> Result<(), EncodeError> {
+        let start = self.buf.len();
+        let mut state = self.state.clone();
+        let res = self.write(&mut state, list);
+
+        let res = match res {
+            Ok(()) if self.buf.len() > self.limit() => {
+                if start == 0 {
+                    Err(EncodeError::TooLong("value list"))
+                } else {
+                    Err(EncodeError::PacketFull)
+                }
+            }
+            x => x,
+        };
+
+        match res {
+            Ok(()) => self.state = state,
+            Err(_) => self.buf.truncate(start),
+        }
+
+        res
+    }
+
+    /// Returns the encoded (and signed or encrypted, if configured) packet and starts a new one.
+    /// If the packet can't be encrypted, its value lists are discarded.
+    pub fn finish(&mut self) -> Result<Vec<u8>, EncodeError> {
+        self.state = State::default();
+        let packet = match self.security {
+            Security::None => Ok(self.buf.clone()),
+            #[cfg(feature = "network-security")]
+            Security::Sign(ref username, ref password) => Ok(sign(&self.buf, username, password)),
+            #[cfg(feature = "network-security")]
+            Security::Encrypt(ref username, ref password) => {
+                encrypt(&self.buf, username, password).map_err(EncodeError::Random)
+            }
+        };
+        self.buf.clear();
+        packet
+    }
+
+    fn write(&mut self, state: &mut State, list: &ValueList) -> Result<(), EncodeError> {
+        let plugin_instance = list.plugin_instance.as_ref().map(|x| x.as_str());
+        let type_instance = list.type_instance.as_ref().map(|x| x.as_str());
+        let time: cdtime_t = list.time.into();
+        let interval: cdtime_t = list.interval.into();
+
+        write_string_if(&mut self.buf, &mut state.host, TYPE_HOST, "host", &list.host)?;
+        write_number_if(&mut self.buf, &mut state.time, TYPE_TIME_HR, time);
+        write_number_if(&mut self.buf, &mut state.interval, TYPE_INTERVAL_HR, interval);
+        write_string_if(&mut self.buf, &mut state.plugin, TYPE_PLUGIN, "plugin", &list.plugin)?;
+        write_string_if(
+            &mut self.buf,
+            &mut state.plugin_instance,
+            TYPE_PLUGIN_INSTANCE,
+            "plugin instance",
+            plugin_instance.unwrap_or(""),
+        )?;
+        write_string_if(&mut self.buf, &mut state.type_, TYPE_TYPE, "type", &list.type_)?;
+        write_string_if(
+            &mut self.buf,
+            &mut state.type_instance,
+            TYPE_TYPE_INSTANCE,
+            "type instance",
+            type_instance.unwrap_or(""),
+        )?;
+        write_values(&mut self.buf, &list.values)
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Encoder::new()
+    }
+}
+
+fn write_header(buf: &mut Vec<u8>, kind: u16, len: usize) {
+    buf.extend_from_slice(&kind.to_be_bytes());
+    buf.extend_from_slice(&(len as u16).to_be_bytes());
+}
+
+/// Writes a string part unless the packet already has the same value for the field
+fn write_string_if(
+    buf: &mut Vec<u8>,
+    last: &mut String,
+    kind: u16,
+    field: &'static str,
+    value: &str,
+) -> Result<(), EncodeError> {
+    if last == value {
+        return Ok(());
+    }
+
+    if value.contains('\0') {
+        return Err(EncodeError::NullPresent(field));
+    }
+
+    let len = HEADER_LEN + value.len() + 1;
+    if len > usize::from(u16::MAX) {
+        return Err(EncodeError::TooLong(field));
+    }
+
+    write_header(buf, kind, len);
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(0);
+    *last = String::from(value);
+    Ok(())
+}
+
+/// Writes a numeric part unless the packet already has the same value for the field
+fn write_number_if(buf: &mut Vec<u8>, last: &mut u64, kind: u16, value: u64) {
+    if *last != value {
+        write_header(buf, kind, HEADER_LEN + 8);
+        buf.extend_from_slice(&value.to_be_bytes());
+        *last = value;
+    }
+}
+
+/// The values part is the number of values, followed by the data source type of every value, and
+/// then the values themselves. Gauges are little endian while all other types are big endian.
+fn write_values(buf: &mut Vec<u8>, values: &[Value]) -> Result<(), EncodeError> {
+    let len = HEADER_LEN + 2 + values.len() * 9;
+    if len > usize::from(u16::MAX) {
+        return Err(EncodeError::TooLong("values"));
+    }
+
+    write_header(buf, TYPE_VALUES, len);
+    buf.extend_from_slice(&(values.len() as u16).to_be_bytes());
+    for value in values {
+        let kind = match *value {
+            Value::Counter(_) => DS_TYPE_COUNTER,
+            Value::Gauge(_) => DS_TYPE_GAUGE,
+            Value::Derive(_) => DS_TYPE_DERIVE,
+            Value::Absolute(_) => DS_TYPE_ABSOLUTE,
+        };
+        buf.push(kind as u8);
+    }
+
+    for value in values {
+        let bytes = match *value {
+            Value::Counter(x) | Value::Absolute(x) => x.to_be_bytes(),
+            Value::Gauge(x) => x.to_bits().to_le_bytes(),
+            Value::Derive(x) => x.to_be_bytes(),
+        };
+        buf.extend_from_slice(&bytes);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::CdTime;
+
+    fn list(plugin_instance: Option<&str>, values: Vec<Value>) -> ValueList {
+        ValueList {
+            host: String::from("localhost"),
+            plugin: String::from("cpu"),
+            plugin_instance: plugin_instance.map(String::from),
+            type_: String::from("percent"),
+            type_instance: None,
+            time: CdTime(1_500_000_000_000_000_000),
+            interval: CdTime(10_000_000_000),
+            values: values,
+        }
+    }
+
+    #[test]
+    fn test_encode_value_list() {
+        let mut encoder = Encoder::new();
+        encoder.add(&list(None, vec![Value::Gauge(1.5)])).unwrap();
+        let packet = encoder.finish().unwrap();
+
+        let mut expected = vec![0x00, 0x00, 0x00, 0x0e];
+        expected.extend_from_slice(b"localhost\0");
+        expected.extend_from_slice(&[0x00, 0x08, 0x00, 0x0c]);
+        expected.extend_from_slice(&(1_500_000_000u64 << 30).to_be_bytes());
+        expected.extend_from_slice(&[0x00, 0x09, 0x00, 0x0c]);
+        expected.extend_from_slice(&(10u64 << 30).to_be_bytes());
+        expected.extend_from_slice(&[0x00, 0x02, 0x00, 0x08]);
+        expected.extend_from_slice(b"cpu\0");
+        expected.extend_from_slice(&[0x00, 0x04, 0x00, 0x0c]);
+        expected.extend_from_slice(b"percent\0");
+        expected.extend_from_slice(&[0x00, 0x06, 0x00, 0x0f, 0x00, 0x01, 0x01]);
+        expected.extend_from_slice(&1.5f64.to_bits().to_le_bytes());
+        assert_eq!(packet, expected);
+        assert!(encoder.is_empty());
+    }
+
+    #[test]
+    fn test_encode_only_changed_fields() {
+        let mut encoder = Encoder::new();
+        encoder.add(&list(Some("0"), vec![Value::Derive(-1)])).unwrap();
+        let first = encoder.len();
+        encoder.add(&list(Some("1"), vec![Value::Derive(-1)])).unwrap();
+
+        // Only the plugin instance and values are repeated
+        let packet = encoder.finish().unwrap();
+        assert_eq!(packet.len() - first, 6 + 15);
+        assert_eq!(&packet[first..first + 6], &[0x00, 0x03, 0x00, 0x06, b'1', 0]);
+        assert_eq!(&packet[packet.len() - 8..], &(-1i64).to_be_bytes());
+    }
+
+    #[test]
+    fn test_encode_packet_full() {
+        let mut encoder = Encoder::with_max_size(100);
+        encoder.add(&list(Some("0"), vec![Value::Gauge(1.0)])).unwrap();
+        let len = encoder.len();
+        let values = vec![Value::Gauge(1.0); 5];
+        assert_eq!(encoder.add(&list(Some("1"), values)), Err(EncodeError::PacketFull));
+        assert_eq!(encoder.len(), len);
+
+        let values = vec![Value::Gauge(1.0); 20];
+        let mut encoder = Encoder::with_max_size(100);
+        assert_eq!(encoder.add(&list(None, values)), Err(EncodeError::TooLong("value list")));
+    }
+
+    #[test]
+    fn test_encode_null() {
+        let mut encoder = Encoder::new();
+        let mut list = list(None, vec![]);
+        list.host = String::from("local\0host");
+        assert_eq!(encoder.add(&list), Err(EncodeError::NullPresent("host")));
+        assert!(encoder.is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+//! Collectd's binary network protocol, as spoken by the `network` plugin. Packets are a series of
+//! parts, each with a 16 bit type and a 16 bit length (which includes the four byte header).
+//! Parts set fields (host, plugin, time, etc) that apply to all subsequent values parts within the
+//! same packet, so consecutive value lists only repeat the fields that changed.
+//!
+//! This module is pure Rust and doesn't call into collectd, so it can be used from standalone
+//! tools that send metrics to (or receive metrics from) a collectd instance.
+
+use api::{CdTime, Value};
+use constants::Severity;
+
+#[cfg(feature = "async")]
+mod async_client;
+mod client;
+mod decode;
+mod encode;
+mod security;
+
+#[cfg(feature = "async")]
+pub use self::async_client::{AsyncNetworkClient, SendPacket};
+pub use self::client::{ClientError, NetworkClient};
+pub use self::decode::{decode, DecodeError, Decoder};
+pub use self::encode::{EncodeError, Encoder};
+pub use self::security::SecurityLevel;
+
+/// Packet size that the network plugin uses by default, which fits into an ethernet frame after
+/// IPv6 and UDP headers. Collectd discards packets larger than its buffer.
+pub const DEFAULT_PACKET_SIZE: usize = 1452;
+
+pub const TYPE_HOST: u16 = 0x0000;
+pub const TYPE_TIME: u16 = 0x0001;
+pub const TYPE_TIME_HR: u16 = 0x0008;
+pub const TYPE_PLUGIN: u16 = 0x0002;
+pub const TYPE_PLUGIN_INSTANCE: u16 = 0x0003;
+pub const TYPE_TYPE: u16 = 0x0004;
+pub const TYPE_TYPE_INSTANCE: u16 = 0x0005;
+pub const TYPE_VALUES: u16 = 0x0006;
+pub const TYPE_INTERVAL: u16 = 0x0007;
+pub const TYPE_INTERVAL_HR: u16 = 0x0009;
+pub const TYPE_MESSAGE: u16 = 0x0100;
+pub const TYPE_SEVERITY: u16 = 0x0101;
+pub const TYPE_SIGN_SHA256: u16 = 0x0200;
+pub const TYPE_ENCR_AES256: u16 = 0x0210;
+
+/// Size of a part's type and length
+const HEADER_LEN: usize = 4;
+
+/// An owned value list as sent over the network. Unlike `ValueListBuilder`, every field is
+/// explicit as there is no daemon to fill in the host, time, or interval.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ValueList {
+    pub host: String,
+    pub plugin: String,
+    pub plugin_instance: Option<String>,
+    pub type_: String,
+    pub type_instance: Option<String>,
+    pub time: CdTime,
+    pub interval: CdTime,
+    pub values: Vec<Value>,
+}
+
+/// An owned notification as sent over the network
+#[derive(Debug, PartialEq, Clone)]
+pub struct Notification {
+    pub host: String,
+    pub plugin: String,
+    pub plugin_instance: Option<String>,
+    pub type_: String,
+    pub type_instance: Option<String>,
+    pub time: CdTime,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// What a packet carries, in the order it was sent
+#[derive(Debug, PartialEq, Clone)]
+pub enum Record {
+    Values(ValueList),
+    Notification(Notification),
+}
@@ -0,0 +1,216 @@
+//! The parts that implement the network plugin's `SecurityLevel` option
+
+use super::HEADER_LEN;
+#[cfg(feature = "network-security")]
+use super::{TYPE_ENCR_AES256, TYPE_SIGN_SHA256};
+#[cfg(feature = "network-security")]
+use aes::cipher::generic_array::GenericArray;
+#[cfg(feature = "network-security")]
+use aes::cipher::{KeyIvInit, StreamCipher};
+#[cfg(feature = "network-security")]
+use aes::Aes256;
+#[cfg(feature = "network-security")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "network-security")]
+use ofb::Ofb;
+#[cfg(feature = "network-security")]
+use sha1::Sha1;
+#[cfg(feature = "network-security")]
+use sha2::{Digest, Sha256};
+
+/// Size of the signature part before the username
+const SIGNATURE_LEN: usize = HEADER_LEN + 32;
+
+/// Size of the encryption part without the username: the username's length, IV, and SHA-1 of the
+/// payload
+const ENCRYPTION_LEN: usize = HEADER_LEN + 2 + 16 + 20;
+
+/// How strictly received packets are checked, mirroring the network plugin's `SecurityLevel`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum SecurityLevel {
+    /// Signatures from known users are verified, but unsigned data is accepted
+    None,
+
+    /// All data must be signed or encrypted
+    Sign,
+
+    /// All data must be encrypted
+    Encrypt,
+}
+
+impl Default for SecurityLevel {
+    fn default() -> Self {
+        SecurityLevel::None
+    }
+}
+
+/// How much signing a packet adds to its size
+#[cfg(feature = "network-security")]
+pub fn signature_overhead(username: &str) -> usize {
+    SIGNATURE_LEN + username.len()
+}
+
+/// The HMAC-SHA256 keyed by the password over the username followed by the signed data
+#[cfg(feature = "network-security")]
+fn hmac(password: &str, username: &[u8], data: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(password.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(username);
+    mac.update(data);
+    mac
+}
+
+/// Prefixes the payload with a signature part. The HMAC covers the username followed by the
+/// payload.
+#[cfg(feature = "network-security")]
+pub fn sign(payload: &[u8], username: &str, password: &str) -> Vec<u8> {
+    let hash = hmac(password, username.as_bytes(), payload).finalize().into_bytes();
+
+    let len = signature_overhead(username);
+    let mut packet = Vec::with_capacity(len + payload.len());
+    packet.extend_from_slice(&TYPE_SIGN_SHA256.to_be_bytes());
+    packet.extend_from_slice(&(len as u16).to_be_bytes());
+    packet.extend_from_slice(&hash);
+    packet.extend_from_slice(username.as_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Splits the body of a signature part into its hash and username, or `None` if it is too short
+pub fn signature(body: &[u8]) -> Option<(&[u8], &[u8])> {
+    if body.len() < SIGNATURE_LEN - HEADER_LEN {
+        None
+    } else {
+        Some(body.split_at(SIGNATURE_LEN - HEADER_LEN))
+    }
+}
+
+/// Checks the hash of a signature part against the data that follows the part, in constant time
+#[cfg(feature = "network-security")]
+pub fn verify(hash: &[u8], username: &[u8], password: &str, rest: &[u8]) -> bool {
+    hmac(password, username, rest).verify_slice(hash).is_ok()
+}
+
+/// How much encrypting a packet adds to its size
+#[cfg(feature = "network-security")]
+pub fn encryption_overhead(username: &str) -> usize {
+    ENCRYPTION_LEN + username.len()
+}
+
+/// Wraps the payload in an encryption part with an IV from the operating system's random number
+/// generator. There is no fallback if it is unavailable, as a predictable IV would weaken the
+/// encryption.
+#[cfg(feature = "network-security")]
+pub fn encrypt(
+    payload: &[u8],
+    username: &str,
+    password: &str,
+) -> Result<Vec<u8>, getrandom::Error> {
+    let mut iv = [0u8; 16];
+    getrandom::getrandom(&mut iv)?;
+    Ok(encrypt_with_iv(payload, username, password, &iv))
+}
+
+/// Output feedback mode, which is its own inverse: the IV is repeatedly encrypted to produce a
+/// keystream that is xor'd with the data
+#[cfg(feature = "network-security")]
+fn aes256_ofb(key: &[u8], iv: &[u8], data: &mut [u8]) {
+    Ofb::<Aes256>::new(GenericArray::from_slice(key), GenericArray::from_slice(iv))
+        .apply_keystream(data);
+}
+
+/// The part is the username and IV in the clear, followed by the SHA-1 of the payload and the
+/// payload, both encrypted with AES-256 in OFB mode keyed by the SHA-256 of the password
+#[cfg(feature = "network-security")]
+fn encrypt_with_iv(payload: &[u8], username: &str, password: &str, iv: &[u8; 16]) -> Vec<u8> {
+    let len = encryption_overhead(username) + payload.len();
+    let mut packet = Vec::with_capacity(len);
+    packet.extend_from_slice(&TYPE_ENCR_AES256.to_be_bytes());
+    packet.extend_from_slice(&(len as u16).to_be_bytes());
+    packet.extend_from_slice(&(username.len() as u16).to_be_bytes());
+    packet.extend_from_slice(username.as_bytes());
+    packet.extend_from_slice(iv);
+
+    let start = packet.len();
+    packet.extend_from_slice(&Sha1::digest(payload));
+    packet.extend_from_slice(payload);
+    aes256_ofb(&Sha256::digest(password.as_bytes()), iv, &mut packet[start..]);
+    packet
+}
+
+/// Splits the body of an encryption part into the username and the rest of the body, or `None`
+/// if it is too short
+pub fn encrypted_user(body: &[u8]) -> Option<(&[u8], &[u8])> {
+    if body.len() < 2 {
+        return None;
+    }
+
+    let len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+    let body = &body[2..];
+    if body.len() < len + ENCRYPTION_LEN - HEADER_LEN - 2 {
+        None
+    } else {
+        Some(body.split_at(len))
+    }
+}
+
+/// Decrypts what follows the username in an encryption part, returning the payload if its
+/// checksum matches (which it won't if the password is wrong)
+#[cfg(feature = "network-security")]
+pub fn decrypt(rest: &[u8], password: &str) -> Option<Vec<u8>> {
+    let (iv, encrypted) = rest.split_at(16);
+    let mut data = encrypted.to_vec();
+    aes256_ofb(&Sha256::digest(password.as_bytes()), iv, &mut data);
+    let payload = data.split_off(20);
+    if data[..] == Sha1::digest(&payload)[..] {
+        Some(payload)
+    } else {
+        None
+    }
+}
+
+#[cfg(all(test, feature = "network-security"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let payload = [0x00, 0x00, 0x00, 0x06, b'h', 0];
+        let packet = encrypt_with_iv(&payload, "admin", "secret", &[7; 16]);
+        assert_eq!(packet.len(), encryption_overhead("admin") + payload.len());
+        assert_eq!(&packet[..6], &[0x02, 0x10, 0x00, 0x35, 0x00, 0x05]);
+        assert_eq!(&packet[6..11], b"admin");
+        assert_eq!(&packet[11..27], &[7; 16]);
+
+        let (username, rest) = encrypted_user(&packet[HEADER_LEN..]).unwrap();
+        assert_eq!(username, b"admin");
+        assert_eq!(decrypt(rest, "secret"), Some(payload.to_vec()));
+        assert_eq!(decrypt(rest, "guess"), None);
+        assert_eq!(encrypted_user(&packet[HEADER_LEN..20]), None);
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_aes256_ofb() {
+        // NIST SP 800-38A F.4.5
+        let key = unhex("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4");
+        let iv = unhex("000102030405060708090a0b0c0d0e0f");
+        let plain = unhex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+
+        let mut data = plain.clone();
+        aes256_ofb(&key, &iv, &mut data);
+        assert_eq!(
+            data,
+            unhex("dc7e84bfda79164b7ecd8486985d38604febdc6740d20b3ac88f6ad82a4fb08d")
+        );
+
+        aes256_ofb(&key, &iv, &mut data);
+        assert_eq!(data, plain);
+    }
+}
@@ -0,0 +1,65 @@
+//! Panics must not unwind into collectd, as unwinding across an FFI boundary is undefined
+//! behavior. Every generated callback runs within `catch_panic`, and plugin managers can opt into a
+//! panic hook that records the panic message and backtrace in collectd's log before the callback
+//! reports failure.
+
+use std::backtrace::Backtrace;
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use api::{collectd_log, plugin_name, LogLevel};
+
+/// Runs a callback, converting a panic into a return value of -1 (error) for collectd
+#[doc(hidden)]
+pub fn catch_panic<F>(callback: &str, f: F) -> c_int
+where
+    F: FnOnce() -> c_int,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(_) => {
+            collectd_log(
+                LogLevel::Error,
+                &format!("{} callback panicked", callback),
+            );
+            -1
+        }
+    }
+}
+
+/// Installs a process wide panic hook that logs the panic's message, location, and backtrace to
+/// collectd at the error level. The previous hook is replaced.
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        collectd_log(LogLevel::Error, &panic_message(info));
+
+        // Log the backtrace a frame per line, as syslog doesn't take kindly to multi-line messages
+        let trace = Backtrace::force_capture().to_string();
+        for line in trace.lines() {
+            collectd_log(LogLevel::Error, &line.replace('\0', ""));
+        }
+    }));
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    let msg = if let Some(s) = payload.downcast_ref::<&str>() {
+        *s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "Box<Any>"
+    };
+
+    let location = info.location()
+        .map(|l| format!("{}:{}", l.file(), l.line()))
+        .unwrap_or_else(|| String::from("<unknown>"));
+
+    let line = format!(
+        "{} panicked at '{}', {}",
+        plugin_name().unwrap_or("plugin"),
+        msg,
+        location
+    );
+
+    line.replace('\0', "")
+}
@@ -0,0 +1,165 @@
+//! A read callback that polls many targets (hosts, devices, etc) one after another takes as long
+//! as all of them combined, and a few slow targets can push the read past its interval.
+//! `ParallelReader` spreads the targets of a single read across worker threads and collects the
+//! failures, so that one unreachable target neither delays nor hides the others.
+
+use errors::Error;
+use std::error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// A target that failed to be read
+#[derive(Debug)]
+pub struct TargetError {
+    /// Position of the target in the list passed to `read`
+    pub index: usize,
+    pub error: Error,
+}
+
+/// Returned when one or more targets failed to be read. The remaining targets were still read.
+#[derive(Debug)]
+pub struct ParallelReadError {
+    pub targets: usize,
+
+    /// Failures ordered by target position
+    pub failures: Vec<TargetError>,
+}
+
+impl fmt::Display for ParallelReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} of {} targets failed", self.failures.len(), self.targets)?;
+        for failure in &self.failures {
+            write!(f, "; target {}: {}", failure.index, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ParallelReadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.failures.first().map(|x| &*x.error as &(dyn error::Error + 'static))
+    }
+}
+
+/// Reads a list of targets with a fixed number of worker threads
+#[derive(Debug, Clone, Copy)]
+pub struct ParallelReader {
+    workers: usize,
+}
+
+impl ParallelReader {
+    /// Reads up to `workers` targets at once
+    pub fn new(workers: usize) -> Self {
+        ParallelReader {
+            workers: workers.max(1),
+        }
+    }
+
+    /// Calls `f` for every target and waits for all of them to finish. Workers take the next
+    /// unread target as soon as they finish with one, so slow targets only hold up a single
+    /// worker. A panic while reading a target is reported as that target's failure.
+    pub fn read<T, F>(&self, targets: &[T], f: F) -> Result<(), ParallelReadError>
+    where
+        T: Sync,
+        F: Fn(&T) -> Result<(), Error> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let failures = Mutex::new(Vec::new());
+        let work = || loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let target = match targets.get(index) {
+                Some(x) => x,
+                None => break,
+            };
+
+            let res = panic::catch_unwind(AssertUnwindSafe(|| f(target)))
+                .unwrap_or_else(|_| Err(Error::from("panicked while reading target")));
+            if let Err(e) = res {
+                failures.lock().unwrap().push(TargetError {
+                    index: index,
+                    error: e,
+                });
+            }
+        };
+
+        let workers = self.workers.min(targets.len());
+        if workers <= 1 {
+            work();
+        } else {
+            thread::scope(|s| {
+                for _ in 0..workers {
+                    s.spawn(&work);
+                }
+            });
+        }
+
+        let mut failures = failures.into_inner().unwrap();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            failures.sort_by_key(|x| x.index);
+            Err(ParallelReadError {
+                targets: targets.len(),
+                failures: failures,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn test_read_all_targets() {
+        let reader = ParallelReader::new(4);
+        let targets: Vec<u32> = (0..100).collect();
+        let sum = AtomicUsize::new(0);
+        reader
+            .read(&targets, |x| {
+                sum.fetch_add(*x as usize, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(sum.load(Ordering::SeqCst), 4950);
+
+        reader.read(&Vec::<u32>::new(), |_| Ok(())).unwrap();
+    }
+
+    #[test]
+    fn test_targets_read_concurrently() {
+        // Every worker must be reading at the same time to get past the barrier
+        let barrier = Barrier::new(3);
+        ParallelReader::new(3)
+            .read(&["a", "b", "c"], |_| {
+                barrier.wait();
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_failures_are_collected() {
+        let targets = ["web-1", "web-2", "web-3", "web-4"];
+        let err = ParallelReader::new(2)
+            .read(&targets, |x| match *x {
+                "web-2" => Err(Error::from("connection refused")),
+                "web-4" => panic!("bad response"),
+                _ => Ok(()),
+            })
+            .unwrap_err();
+
+        assert_eq!(err.failures.len(), 2);
+        assert_eq!(err.failures[0].index, 1);
+        assert_eq!(err.failures[1].index, 3);
+        assert_eq!(
+            err.to_string(),
+            "2 of 4 targets failed; target 1: connection refused; \
+             target 3: panicked while reading target"
+        );
+    }
+}
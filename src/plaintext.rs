@@ -0,0 +1,102 @@
+//! The plain-text commands understood by both the `unixsock` and `exec` plugins
+
+use api::{CdTime, Value};
+use constants::Severity;
+use network::{Notification, ValueList};
+use std::time::Duration;
+use unixsock::Identifier;
+
+/// Quotes a command's argument if it contains characters that the daemon would split on
+pub fn quote(s: &str) -> String {
+    if !s.is_empty() && !s.contains(|c: char| c == ' ' || c == '"' || c == '\\') {
+        return String::from(s);
+    }
+
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res.push('"');
+    res
+}
+
+/// Formats a time as epoch seconds, or `N` (now) if it isn't set
+fn format_time(time: CdTime) -> String {
+    let CdTime(ns) = time;
+    if ns == 0 {
+        String::from("N")
+    } else {
+        format!("{}.{:09}", ns / 1_000_000_000, ns % 1_000_000_000)
+    }
+}
+
+/// The value as written in a `PUTVAL` command, where unknown gauges are `U`
+fn format_value(value: &Value) -> String {
+    match *value {
+        Value::Gauge(x) if x.is_nan() => String::from("U"),
+        ref x => x.to_string(),
+    }
+}
+
+/// `PUTVAL` for the value list. A time of zero is sent as `N` so that the daemon uses the current
+/// time, and an interval of zero uses the daemon's interval.
+pub fn putval_command(list: &ValueList) -> String {
+    let mut cmd = format!("PUTVAL {}", quote(&Identifier::of(list).to_string()));
+    let CdTime(interval) = list.interval;
+    if interval != 0 {
+        let interval = Duration::from(list.interval);
+        let (secs, nanos) = (interval.as_secs(), interval.subsec_nanos());
+        cmd.push_str(&format!(" interval={}.{:09}", secs, nanos));
+    }
+
+    cmd.push_str(&format!(" {}", format_time(list.time)));
+    for value in &list.values {
+        cmd.push_str(&format!(":{}", format_value(value)));
+    }
+
+    cmd
+}
+
+/// `PUTNOTIF` for the notification
+pub fn putnotif_command(notif: &Notification) -> String {
+    let severity = match notif.severity {
+        Severity::Failure => "failure",
+        Severity::Warning => "warning",
+        Severity::Okay => "okay",
+    };
+
+    let mut cmd = format!("PUTNOTIF severity={} time={}", severity, format_time(notif.time));
+    let fields = [
+        ("host", Some(&notif.host)),
+        ("plugin", Some(&notif.plugin)),
+        ("plugin_instance", notif.plugin_instance.as_ref()),
+        ("type", Some(&notif.type_)),
+        ("type_instance", notif.type_instance.as_ref()),
+    ];
+
+    for &(key, value) in fields.iter() {
+        if let Some(value) = value.filter(|x| !x.is_empty()) {
+            cmd.push_str(&format!(" {}={}", key, quote(value)));
+        }
+    }
+
+    // The message must come last, as everything after it is taken as the message
+    cmd.push_str(&format!(" message={}", quote(&notif.message)));
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("load"), "load");
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(quote("disk \"sda\""), "\"disk \\\"sda\\\"\"");
+    }
+}
@@ -1,7 +1,35 @@
-use failure::Error;
-use errors::NotImplemented;
-use api::{ConfigItem, LogLevel, RecvValueList};
-use chrono::Duration;
+use errors::{Error, ErrorChain, NotImplemented};
+use filters::{MatchConstructor, TargetConstructor};
+use identifier::Identifier;
+use panics::catch_panic;
+use runtime::{self, Registrar};
+use api::{collectd_log, plugin_context, ConfigAccumulator, ConfigItem, LogFilter, LogLevel,
+          RecvValueList, Value, WriteFilter};
+#[cfg(collectd_57)]
+use api::CdTime;
+use bindings::{data_set_t, plugin_flush_cb, plugin_log_cb, plugin_read_cb,
+               plugin_register_complex_read, plugin_register_config, plugin_register_flush,
+               plugin_register_log,
+               plugin_register_write, plugin_unregister_read, plugin_write_cb, uc_get_rate,
+               user_data_t, value_list_t};
+use constants::DataSourceType;
+use errors::ReceiveError;
+#[cfg(not(collectd_57))]
+use bindings::timespec;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+extern "C" {
+    fn free(ptr: *mut c_void);
+}
 
 bitflags! {
     /// Bitflags of capabilities that a plugin advertises to collectd.
@@ -11,6 +39,17 @@ bitflags! {
         const LOG =    0b0000_0010;
         const WRITE =  0b0000_0100;
         const FLUSH =  0b0000_1000;
+
+        /// Have `write_values` receive counters, derives, and absolutes as gauges of their per
+        /// second rate, as computed by collectd's cache (like `StoreRates true` in collectd's own
+        /// write plugins). Values that collectd has only seen once have a NaN rate.
+        const STORE_RATES = 0b0001_0000;
+
+        /// Log only the first of consecutive write errors and then a summary every
+        /// `Plugin::write_errors_summarized` errors, instead of an error per value list, which
+        /// floods the log when the backend is down. Once a write succeeds, the recovery is
+        /// logged and the next error is logged immediately.
+        const SUMMARIZE_WRITE_ERRORS = 0b0010_0000;
     }
 }
 
@@ -19,16 +58,35 @@ bitflags! {
     #[derive(Default)]
     pub struct PluginManagerCapabilities: u32 {
         const INIT = 0b0000_0001;
+
+        /// Install a panic hook on registration that logs the panic message and backtrace to
+        /// collectd, so that panics are diagnosable from collectd's log
+        const LOG_PANICS = 0b0000_0010;
+
+        /// Invoke `PluginManager::shutdown` when collectd shuts down
+        const SHUTDOWN = 0b0000_0100;
+
+        /// Receive the options listed by `PluginManager::config_keys` through collectd's legacy
+        /// simple config interface instead of as blocks. Each option's values are joined into a
+        /// single string, so fields should be strings or numbers (which are parsed leniently).
+        const SIMPLE_CONFIG = 0b0000_1000;
     }
 }
 
 /// How many instances of the plugin will be registered
 pub enum PluginRegistration {
     /// Our module will only register a single plugin
-    Single(Box<Plugin>),
+    Single(Box<dyn DynPlugin>),
 
     /// Our module registers several modules. The String in the tuple must be unique identifier
-    Multiple(Vec<(String, Box<Plugin>)>),
+    Multiple(Vec<(String, Box<dyn DynPlugin>)>),
+}
+
+/// A single plugin, so that the plugins given to `collectd_plugin!` can be a plugin itself
+impl<T: Plugin + 'static> From<T> for PluginRegistration {
+    fn from(plugin: T) -> Self {
+        PluginRegistration::Single(Box::new(plugin))
+    }
 }
 
 impl PluginCapabilities {
@@ -47,6 +105,14 @@ impl PluginCapabilities {
     pub fn has_flush(&self) -> bool {
         self.intersects(PluginCapabilities::FLUSH)
     }
+
+    pub fn has_store_rates(&self) -> bool {
+        self.intersects(PluginCapabilities::STORE_RATES)
+    }
+
+    pub fn has_summarize_write_errors(&self) -> bool {
+        self.intersects(PluginCapabilities::SUMMARIZE_WRITE_ERRORS)
+    }
 }
 
 pub trait PluginManager {
@@ -58,26 +124,88 @@ pub trait PluginManager {
         PluginManagerCapabilities::default()
     }
 
+    /// Returns the plugins that will be registered with collectd. Invoked once at initialization,
+    /// after the config has been read, so construction may fail: the error is logged, none of
+    /// the plugins are registered, and `initialize` is skipped. If the collectd config contains
+    /// several `<Plugin>` blocks for this plugin, the children of all blocks are concatenated (in
+    /// order) as if written in a single block.
     fn plugins(_config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error>;
 
+    /// The options that the plugin manager accepts through collectd's simple config interface,
+    /// if it has the `SIMPLE_CONFIG` capability. collectd rejects other options, and matches
+    /// options to these keys regardless of case.
+    fn config_keys() -> &'static [&'static str] {
+        &[]
+    }
+
     /// Initialize any socket, files, or expensive resources that may have been parsed from the
     /// configuration. If an error is reported, all hooks registered will be unregistered. This is
     /// really only useful for `PluginRegistration::Single` modules who want global data.
     fn initialize() -> Result<(), Error> {
         Err(Error::from(NotImplemented))
     }
+
+    /// Invoked once when collectd shuts down, if the plugin manager has the `SHUTDOWN` capability.
+    /// Read callbacks have stopped by then, but collectd may still invoke write, flush, and log
+    /// callbacks afterwards. The plugins themselves are dropped once collectd has freed the last
+    /// of their callbacks, so per plugin cleanup belongs in `Drop`, which runs exactly once.
+    fn shutdown() -> Result<(), Error> {
+        Err(Error::from(NotImplemented))
+    }
+
+    /// Filter chain matches (see `Match`) provided by the module, keyed by the name used in
+    /// `<Match "name">` blocks. Registered alongside the plugin manager.
+    fn matches() -> Vec<(&'static str, MatchConstructor)> {
+        Vec::new()
+    }
+
+    /// Filter chain targets (see `Target`) provided by the module, keyed by the name used in
+    /// `<Target "name">` blocks. Registered alongside the plugin manager.
+    fn targets() -> Vec<(&'static str, TargetConstructor)> {
+        Vec::new()
+    }
 }
 
+/// A plugin is handed to collectd once per callback that it registers (read, write, log, and
+/// flush), but it's only dropped once: after collectd has freed the last of those registrations,
+/// whether at shutdown or when a read callback is re-registered at another interval. `Drop` is
+/// therefore the place to release what the plugin holds, and it won't run while one of the
+/// plugin's callbacks is still executing. Callbacks that are left to their defaults return
+/// `NotImplemented`, so advertising their capability only logs a warning.
 pub trait Plugin {
+    /// The error returned by the plugin's callbacks, which is logged with its `Display`
+    /// implementation. Plugins without an error type of their own use `Error`, whose causes are
+    /// logged as well. `NotImplemented` is only recognized (rather than logged on every call)
+    /// when it is returned as an `Error` or as the plugin's error type itself.
+    type Error: fmt::Display + Send + From<NotImplemented>;
+
     /// A plugin's capabilities. By default a plugin does nothing, but can advertise that it can
     /// configure itself and / or report values.
     fn capabilities(&self) -> PluginCapabilities {
         PluginCapabilities::default()
     }
 
-    /// Customizes how a message of a given level is logged
-    fn log(&mut self, _lvl: LogLevel, _msg: String) -> Result<(), Error> {
-        Err(Error::from(NotImplemented))
+    /// Customizes how a message of a given level is logged. The message is borrowed from collectd
+    /// for the duration of the call, so no allocation occurs unless the plugin needs to hold onto
+    /// it. Whatever the plugin logs from here is written to stderr rather than handed back to it.
+    #[allow(deprecated)]
+    fn log(&mut self, lvl: LogLevel, msg: &str) -> Result<(), Self::Error> {
+        self.log_owned(lvl, String::from(msg))
+    }
+
+    /// The least severe messages that `log` should receive, which typically comes from a
+    /// `LogLevel` option in the plugin's config. Queried once at registration, and messages that
+    /// don't pass the filter are dropped before they are decoded. By default every message is
+    /// received.
+    fn log_filter(&self) -> Option<LogFilter> {
+        None
+    }
+
+    /// The previous signature of `log`, which received an owned copy of every message. Plugins
+    /// that have not migrated can rename their implementation to `log_owned`.
+    #[deprecated(note = "implement `log`, which borrows the message, instead")]
+    fn log_owned(&mut self, _lvl: LogLevel, _msg: String) -> Result<(), Self::Error> {
+        Err(NotImplemented.into())
     }
 
     /// This function is called when collectd expects the plugin to report values, which will occur
@@ -90,8 +218,15 @@ pub trait Plugin {
     ///
     /// It is up to you to make sure that this function is thread safe, so make sure anything that
     /// is being worked with implements `Sync`
-    fn read_values(&mut self) -> Result<(), Error> {
-        Err(Error::from(NotImplemented))
+    fn read_values(&mut self) -> Result<(), Self::Error> {
+        Err(NotImplemented.into())
+    }
+
+    /// The value lists that `write_values` should receive. Queried once at registration, and
+    /// value lists that don't pass the filter are dropped before they are decoded. By default
+    /// every value list is received.
+    fn write_filter(&self) -> Option<WriteFilter> {
+        None
     }
 
     /// Collectd is giving you reported values, do with them as you please. If writing values is
@@ -101,12 +236,19 @@ pub trait Plugin {
     ///
     /// It is up to you to make sure that this function is thread safe, so make sure anything that
     /// is being worked with implements `Sync`
-    fn write_values<'a>(&mut self, _list: RecvValueList<'a>) -> Result<(), Error> {
-        Err(Error::from(NotImplemented))
+    fn write_values<'a>(&mut self, _list: RecvValueList<'a>) -> Result<(), Self::Error> {
+        Err(NotImplemented.into())
+    }
+
+    /// With the `SUMMARIZE_WRITE_ERRORS` capability, the number of consecutive write errors
+    /// between summaries in the log. Queried once at registration. Defaults to 100.
+    fn write_errors_summarized(&self) -> u64 {
+        100
     }
 
     /// Flush values to be written that are older than given duration. If an identifier is given,
-    /// then only those buffered values should be flushed.
+    /// then only those buffered values should be flushed (see `Identifier::matches`), otherwise
+    /// everything should be.
     ///
     /// ## Warning
     ///
@@ -115,62 +257,740 @@ pub trait Plugin {
     fn flush(
         &mut self,
         _timeout: Option<Duration>,
-        _identifier: Option<&str>,
+        _identifier: Option<Identifier>,
+    ) -> Result<(), Self::Error> {
+        Err(NotImplemented.into())
+    }
+}
+
+/// Invokes a callback of the plugin, boxing its error so that it can be logged
+fn dispatch<E, F>(callback: F) -> Result<(), Error>
+where
+    E: fmt::Display + Send + 'static,
+    F: FnOnce() -> Result<(), E>,
+{
+    callback().map_err(|e| {
+        // An `Error` is kept as is, so that its causes are logged too
+        let mut e = Some(e);
+        let any = &mut e as &mut dyn Any;
+        if any.is::<Option<NotImplemented>>() {
+            return Error::from(NotImplemented);
+        }
+
+        match any.downcast_mut::<Option<Error>>() {
+            Some(x) => x.take().unwrap(),
+            None => Error::from(e.unwrap().to_string()),
+        }
+    })
+}
+
+/// A `Plugin` whatever its error type, which is how `PluginRegistration` holds plugins. Every
+/// `Plugin` is a `DynPlugin`, so `Box::new(plugin)` is all it takes to register one.
+pub trait DynPlugin {
+    fn capabilities(&self) -> PluginCapabilities;
+    fn log(&mut self, lvl: LogLevel, msg: &str) -> Result<(), Error>;
+    fn log_filter(&self) -> Option<LogFilter>;
+    fn read_values(&mut self) -> Result<(), Error>;
+    fn write_filter(&self) -> Option<WriteFilter>;
+    fn write_values<'a>(&mut self, list: RecvValueList<'a>) -> Result<(), Error>;
+    fn write_errors_summarized(&self) -> u64;
+    fn flush(
+        &mut self,
+        timeout: Option<Duration>,
+        identifier: Option<Identifier>,
+    ) -> Result<(), Error>;
+}
+
+impl<P: Plugin + 'static> DynPlugin for P {
+    fn capabilities(&self) -> PluginCapabilities {
+        Plugin::capabilities(self)
+    }
+
+    fn log(&mut self, lvl: LogLevel, msg: &str) -> Result<(), Error> {
+        dispatch(|| Plugin::log(self, lvl, msg))
+    }
+
+    fn log_filter(&self) -> Option<LogFilter> {
+        Plugin::log_filter(self)
+    }
+
+    fn read_values(&mut self) -> Result<(), Error> {
+        dispatch(|| Plugin::read_values(self))
+    }
+
+    fn write_filter(&self) -> Option<WriteFilter> {
+        Plugin::write_filter(self)
+    }
+
+    fn write_values<'a>(&mut self, list: RecvValueList<'a>) -> Result<(), Error> {
+        dispatch(|| Plugin::write_values(self, list))
+    }
+
+    fn write_errors_summarized(&self) -> u64 {
+        Plugin::write_errors_summarized(self)
+    }
+
+    fn flush(
+        &mut self,
+        timeout: Option<Duration>,
+        identifier: Option<Identifier>,
     ) -> Result<(), Error> {
-        Err(Error::from(NotImplemented))
+        dispatch(|| Plugin::flush(self, timeout, identifier))
     }
 }
 
+/// The callbacks that `collectd_plugin!` generates, which are registered according to a plugin's
+/// capabilities
+#[doc(hidden)]
+pub struct PluginCallbacks {
+    pub read: plugin_read_cb,
+    pub write: plugin_write_cb,
+    pub log: plugin_log_cb,
+    pub flush: plugin_flush_cb,
+}
+
+/// The user data that `register_plugin` gives collectd for each plugin
+#[doc(hidden)]
+pub struct RegisteredPlugin {
+    pub plugin: Box<dyn DynPlugin>,
+    name: String,
+
+    /// Capabilities whose callbacks turned out to be unimplemented
+    disabled: AtomicU32,
+    log_filter: Option<LogFilter>,
+    write_filter: Option<WriteFilter>,
+    store_rates: bool,
+    read: plugin_read_cb,
+
+    /// Write errors between summaries, if they are summarized
+    summarize_write_errors: Option<u64>,
+    write_failures: AtomicU64,
+}
+
+impl RegisteredPlugin {
+    fn new(name: &str, plugin: Box<dyn DynPlugin>, read: plugin_read_cb) -> Self {
+        let capabilities = plugin.capabilities();
+        let summarize_write_errors = if capabilities.has_summarize_write_errors() {
+            Some(plugin.write_errors_summarized().max(1))
+        } else {
+            None
+        };
+
+        RegisteredPlugin {
+            log_filter: plugin.log_filter(),
+            write_filter: plugin.write_filter(),
+            plugin: plugin,
+            name: String::from(name),
+            disabled: AtomicU32::new(0),
+            store_rates: capabilities.has_store_rates(),
+            read: read,
+            summarize_write_errors: summarize_write_errors,
+            write_failures: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns if the callback of the capability should still be invoked
+    pub fn enabled(&self, capability: PluginCapabilities) -> bool {
+        self.disabled.load(Ordering::Relaxed) & capability.bits() == 0
+    }
+
+    /// Returns if a message of the level passes the plugin's log filter
+    pub fn logs(&self, lvl: LogLevel) -> bool {
+        self.log_filter.map(|x| x.allows(lvl)).unwrap_or(true)
+    }
+
+    /// Returns if the value list passes the plugin's write filter
+    pub fn writes(&self, list: &value_list_t) -> bool {
+        self.write_filter
+            .as_ref()
+            .map(|x| x.allows_raw(list))
+            .unwrap_or(true)
+    }
+
+    /// Decodes the value list for `write_values`, replacing non-gauge values with their rates if
+    /// the plugin has the `STORE_RATES` capability
+    pub unsafe fn receive<'b>(
+        &self,
+        ds: &'b data_set_t,
+        vl: &'b value_list_t,
+    ) -> Result<RecvValueList<'b>, ReceiveError> {
+        let mut list = RecvValueList::from(ds, vl)?;
+        if !self.store_rates || list.values
+            .iter()
+            .all(|x| x.data_source_type() == DataSourceType::Gauge) {
+            return Ok(list);
+        }
+
+        let rates = uc_get_rate(ds, vl);
+        if rates.is_null() {
+            return Err(ReceiveError::RatesUnavailable(Identifier::from(&list).to_string()));
+        }
+
+        for (i, report) in list.values.iter_mut().enumerate() {
+            if report.data_source_type() != DataSourceType::Gauge {
+                report.value = Value::Gauge(*rates.add(i));
+            }
+        }
+
+        free(rates as *mut c_void);
+        Ok(list)
+    }
+
+    /// Re-registers the read callback at the new interval. collectd passes callbacks a pointer to
+    /// its copy of the user data, and frees the user data of an unregistered read callback once
+    /// the callback returns, so the free function is cleared before unregistering to hand
+    /// ownership of the plugin to the new registration.
+    pub unsafe fn reschedule(&self, dt: *mut user_data_t, interval: Duration) {
+        let name = match CString::new(self.name.as_str()) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+
+        let data = user_data_t {
+            data: (*dt).data,
+            free_func: (*dt).free_func,
+        };
+
+        (*dt).free_func = None;
+        plugin_unregister_read(name.as_ptr());
+        match register_complex_read(ptr::null(), &name, self.read, Some(interval), data) {
+            0 => {}
+            code => collectd_log(
+                LogLevel::Error,
+                &format!(
+                    "{}: unable to re-register read callback at {:?}: {}",
+                    self.name, interval, code
+                ),
+            ),
+        }
+    }
+
+    /// Hands the message to the plugin's log callback. collectd hands whatever is logged to every
+    /// log callback, so a plugin that logs while it's logging (directly or through a library)
+    /// would be re-entered until the stack overflows. Instead these nested messages are written
+    /// to stderr, and the plugin only sees the messages that it didn't log itself (including the
+    /// error logged when the callback fails).
+    pub fn log(&mut self, lvl: LogLevel, msg: &str) -> c_int {
+        let _guard = match LogGuard::enter(self) {
+            Some(x) => x,
+            None => {
+                let _ = writeln!(io::stderr(), "{}: [{}] {}", self.name, lvl, msg);
+                return 0;
+            }
+        };
+
+        let res = self.plugin.log(lvl, msg);
+        self.finish(PluginCapabilities::LOG, "log", "logging error", res)
+    }
+
+    /// Converts the result of a callback into collectd's status code, logging errors prefixed by
+    /// `context`. A plugin that advertises a capability without implementing its method would
+    /// otherwise log an error on every call (and collectd would consider it failing), so instead
+    /// a single warning is logged and the callback is ignored from then on. The callback isn't
+    /// unregistered, as collectd would free this user data from under the running callback.
+    pub fn finish(
+        &self,
+        capability: PluginCapabilities,
+        method: &str,
+        context: &str,
+        res: Result<(), Error>,
+    ) -> c_int {
+        match res {
+            Ok(()) => {
+                if capability == PluginCapabilities::WRITE {
+                    self.write_recovered();
+                }
+                0
+            }
+            Err(ref e) if e.is::<NotImplemented>() => {
+                let bit = capability.bits();
+                if self.disabled.fetch_or(bit, Ordering::Relaxed) & bit == 0 {
+                    collectd_log(
+                        LogLevel::Warning,
+                        &format!(
+                            "{}: {:?} capability is advertised but `{}` is not implemented, so \
+                             it will be ignored",
+                            self.name, capability, method
+                        ),
+                    );
+                }
+                0
+            }
+            Err(ref e) => {
+                match self.consecutive_failures(capability) {
+                    Some(1) => collectd_log(
+                        LogLevel::Error,
+                        &format!("{}: {}", context, ErrorChain(&**e)),
+                    ),
+                    Some(n) => collectd_log(
+                        LogLevel::Error,
+                        &format!("{}: {} ({} consecutive errors)", context, ErrorChain(&**e), n),
+                    ),
+                    None => {}
+                }
+                -1
+            }
+        }
+    }
+
+    /// Counts a failure of the capability's callback, returning the consecutive failures to
+    /// report or `None` if the failure is left for the next summary
+    fn consecutive_failures(&self, capability: PluginCapabilities) -> Option<u64> {
+        let every = match self.summarize_write_errors {
+            Some(every) if capability == PluginCapabilities::WRITE => every,
+            _ => return Some(1),
+        };
+
+        let failures = self.write_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures == 1 || failures % every == 0 {
+            Some(failures)
+        } else {
+            None
+        }
+    }
+
+    fn write_recovered(&self) {
+        if self.summarize_write_errors.is_none() {
+            return;
+        }
+
+        let failures = self.write_failures.swap(0, Ordering::Relaxed);
+        if failures > 1 {
+            collectd_log(
+                LogLevel::Info,
+                &format!(
+                    "{}: writing recovered after {} consecutive errors",
+                    self.name, failures
+                ),
+            );
+        }
+    }
+}
+
+/// Borrows the plugin that `register_plugin` stored in the user data, or `None` if collectd
+/// passed a null pointer
+#[doc(hidden)]
+pub unsafe fn plugin_from_user_data<'a>(dt: *mut user_data_t) -> Option<&'a mut RegisteredPlugin> {
+    runtime::borrow(dt.as_ref()?.data)
+}
+
+/// Logs that a callback was passed a null pointer for the named argument and returns the
+/// callback's error code
+#[doc(hidden)]
+pub fn null_argument(callback: &str, argument: &str) -> c_int {
+    collectd_log(
+        LogLevel::Error,
+        &format!("{} callback received a null {}", callback, argument),
+    );
+    -1
+}
+
+/// Registers the plugin's callbacks with collectd. This lives in the crate instead of the macro so
+/// that the collectd version is decided by how this crate was compiled and not the plugin's crate.
+#[doc(hidden)]
+pub fn register_plugin(name: &str, plugin: Box<dyn DynPlugin>, callbacks: &PluginCallbacks) {
+    let capabilities = plugin.capabilities();
+    let pl = RegisteredPlugin::new(name, plugin, callbacks.read);
+
+    let s = match CString::new(name) {
+        Ok(x) => x,
+        Err(e) => {
+            collectd_log(
+                LogLevel::Error,
+                &format!("{}: unable to register plugin: {}", name, e),
+            );
+            return;
+        }
+    };
+
+    // Each registration owns the plugin along with the registrar, so that the plugin is dropped
+    // once collectd has freed every registration (see `runtime`)
+    let registrar = Registrar::new(pl);
+    unsafe {
+        if capabilities.has_read() {
+            let data = registrar.user_data();
+            register_complex_read(ptr::null(), &s, callbacks.read, None, data);
+        }
+
+        // collectd 5.7 takes the user data as a const pointer, which `&mut` coerces to
+        if capabilities.has_write() {
+            let mut data = registrar.user_data();
+            plugin_register_write(s.as_ptr(), callbacks.write, &mut data);
+        }
+
+        if capabilities.has_log() {
+            let mut data = registrar.user_data();
+            plugin_register_log(s.as_ptr(), callbacks.log, &mut data);
+        }
+
+        if capabilities.has_flush() {
+            let mut data = registrar.user_data();
+            plugin_register_flush(s.as_ptr(), callbacks.flush, &mut data);
+        }
+    }
+}
+
+thread_local! {
+    // The interval that the running read callback asked to be invoked at
+    static RECONFIGURED_INTERVAL: Cell<Option<Duration>> = Cell::new(None);
+
+    // The plugins whose log callback is running on this thread, innermost last
+    static LOGGING: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+}
+
+/// Marks a plugin as logging on this thread until dropped, including when the plugin panics
+struct LogGuard(usize);
+
+impl LogGuard {
+    fn enter(plugin: &RegisteredPlugin) -> Option<LogGuard> {
+        let id = plugin as *const RegisteredPlugin as usize;
+        LOGGING.with(|x| {
+            let mut logging = x.borrow_mut();
+            if logging.contains(&id) {
+                None
+            } else {
+                logging.push(id);
+                Some(LogGuard(id))
+            }
+        })
+    }
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        let id = self.0;
+        LOGGING.with(|x| x.borrow_mut().retain(|&y| y != id));
+    }
+}
+
+/// Asks for the plugin's read callback to be invoked at the given interval from now on. Call from
+/// `Plugin::read_values`, for instance to back off while the monitored system is under load.
+/// The read callback is re-registered with collectd once `read_values` returns. Requests made
+/// outside of `read_values` are ignored.
+pub fn reconfigure_interval(interval: Duration) {
+    RECONFIGURED_INTERVAL.with(|x| x.set(Some(interval)));
+}
+
+/// Takes the interval requested with `reconfigure_interval`, if any
+#[doc(hidden)]
+pub fn take_reconfigured_interval() -> Option<Duration> {
+    RECONFIGURED_INTERVAL.with(|x| x.take())
+}
+
+/// Registers a complex read callback, which is invoked at collectd's `Interval` if the interval
+/// is `None`
+unsafe fn register_complex_read(
+    group: *const c_char,
+    name: &CStr,
+    callback: plugin_read_cb,
+    interval: Option<Duration>,
+    data: user_data_t,
+) -> c_int {
+    #[cfg(collectd_57)]
+    {
+        let interval = interval.map(|x| CdTime::from(x).into()).unwrap_or(0);
+        plugin_register_complex_read(group, name.as_ptr(), callback, interval, &data)
+    }
+
+    #[cfg(not(collectd_57))]
+    {
+        let mut data = data;
+        let spec = interval.map(|x| timespec {
+            tv_sec: x.as_secs() as _,
+            tv_nsec: x.subsec_nanos() as _,
+        });
+        let spec_ptr = spec.as_ref().map(|x| x as *const timespec).unwrap_or_else(ptr::null);
+        plugin_register_complex_read(group, name.as_ptr(), callback, spec_ptr, &mut data)
+    }
+}
+
+/// A callback of collectd's simple config interface, which receives an option's key and values
+pub type SimpleConfigCallback = unsafe extern "C" fn(*const c_char, *const c_char) -> c_int;
+
+/// Registers the plugin for the given options of collectd's simple config interface. collectd
+/// keeps the keys for as long as it runs, so they are leaked. Keys that contain a null character
+/// are logged and left out.
+#[doc(hidden)]
+pub fn register_simple_config(
+    name: &CStr,
+    keys: &[&str],
+    callback: SimpleConfigCallback,
+) -> c_int {
+    let keys: Vec<*const c_char> = keys.iter()
+        .filter_map(|x| match CString::new(*x) {
+            Ok(key) => Some(key.into_raw() as *const c_char),
+            Err(e) => {
+                collectd_log(
+                    LogLevel::Error,
+                    &format!("{}: invalid config key {}: {}", name.to_string_lossy(), x, e),
+                );
+                None
+            }
+        })
+        .collect();
+    let keys = Box::leak(keys.into_boxed_slice());
+    let len = keys.len() as c_int;
+
+    unsafe { plugin_register_config(name.as_ptr(), Some(callback), keys.as_mut_ptr(), len) }
+}
+
+/// A read callback registered with `register_read`
+type ReadFn = Box<dyn FnMut() -> Result<(), Error> + Send>;
+
+/// Registers a read callback under its own name (eg: `myplugin/slow`), so that one plugin can
+/// poll different subsystems at different cadences. The callback is invoked at the given interval,
+/// or collectd's `Interval` if `None`, and is freed when collectd shuts down. Call from
+/// `PluginManager::plugins` or `initialize`, as collectd only accepts registrations while loading
+/// plugins.
+pub fn register_read<F>(name: &str, interval: Option<Duration>, read: F) -> Result<(), Error>
+where
+    F: FnMut() -> Result<(), Error> + Send + 'static,
+{
+    let s = CString::new(name)?;
+    let read: Box<ReadFn> = Box::new(Box::new(read));
+
+    // Grouping the reads under the plugin's name lets collectd unregister them all at once
+    let group = plugin_context()
+        .map(|x| x.c_name().as_ptr())
+        .unwrap_or_else(ptr::null);
+
+    let data = user_data_t {
+        data: Box::into_raw(read) as *mut c_void,
+        free_func: Some(free_read),
+    };
+
+    match unsafe { register_complex_read(group, &s, Some(read_callback), interval, data) } {
+        0 => Ok(()),
+        code => {
+            unsafe { free_read(data.data) };
+            Err(Error::from(format!(
+                "plugin_register_complex_read returned an error: {}",
+                code
+            )))
+        }
+    }
+}
+
+unsafe extern "C" fn read_callback(dt: *mut user_data_t) -> c_int {
+    catch_panic("read", || {
+        let read = match dt.as_ref().and_then(|x| (x.data as *mut ReadFn).as_mut()) {
+            Some(x) => x,
+            None => return null_argument("read", "user data"),
+        };
+
+        match read() {
+            Ok(()) => 0,
+            Err(ref e) => {
+                collectd_log(
+                    LogLevel::Error,
+                    &format!("read error: {}", ErrorChain(&**e)),
+                );
+                -1
+            }
+        }
+    })
+}
+
+unsafe extern "C" fn free_read(raw: *mut c_void) {
+    if !raw.is_null() {
+        drop(Box::from_raw(raw as *mut ReadFn));
+    }
+}
+
+/// Constructs the plugins given to `collectd_plugin!("name", plugins)`, which is a function or
+/// closure from the config to a plugin or a `PluginRegistration`
+#[doc(hidden)]
+pub fn registration_from<F, R>(
+    config: Option<&[ConfigItem]>,
+    plugins: F,
+) -> Result<PluginRegistration, Error>
+where
+    F: FnOnce(Option<&[ConfigItem]>) -> Result<R, Error>,
+    R: Into<PluginRegistration>,
+{
+    plugins(config).map(Into::into)
+}
+
+/// Invokes `initialize` if the plugin manager has the `INIT` capability. collectd only reports
+/// that the init callback failed, so the error is logged along with its causes.
+#[doc(hidden)]
+pub fn initialize_manager<T: PluginManager>() -> c_int {
+    if !T::capabilities().intersects(PluginManagerCapabilities::INIT) {
+        return 0;
+    }
+
+    match T::initialize() {
+        Ok(()) => 0,
+        Err(ref e) => {
+            collectd_log(
+                LogLevel::Error,
+                &format!("init error: {}", ErrorChain(&**e)),
+            );
+            -1
+        }
+    }
+}
+
+// Collectd invokes the config callback once for every matching `<Plugin>` block, so each block is
+// stashed here and handed to the plugin manager all at once during init
+static CONFIG_BLOCKS: Mutex<Option<ConfigAccumulator>> = Mutex::new(None);
+
+fn config_blocks() -> MutexGuard<'static, Option<ConfigAccumulator>> {
+    // Blocks are only ever appended, so a panic can't leave them inconsistent
+    CONFIG_BLOCKS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Stashes a `<Plugin>` block that collectd passed to the config callback
+#[doc(hidden)]
+pub fn record_config_block(block: &ConfigItem) {
+    config_blocks()
+        .get_or_insert_with(ConfigAccumulator::new)
+        .add(block);
+}
+
+/// Stashes an option that collectd passed to the simple config callback
+#[doc(hidden)]
+pub fn record_config_option(key: &str, value: &str) {
+    config_blocks()
+        .get_or_insert_with(ConfigAccumulator::new)
+        .add_option(key, value);
+}
+
+/// Takes the config stashed since the last init, or `None` if collectd passed none
+#[doc(hidden)]
+pub fn take_config_blocks() -> Option<ConfigAccumulator> {
+    config_blocks().take()
+}
+
+/// Exports the plugin manager to collectd (as `module_register`). Either give the type that
+/// implements `PluginManager`, or the plugin's name and a function or closure that constructs
+/// the plugins from the config, for plugins that are parameterized by the environment or at
+/// build time and have no use for the rest of `PluginManager`:
+///
+/// ```ignore
+/// collectd_plugin!("myplugin", |_config| {
+///     let socket = env::var("MYPLUGIN_SOCKET").unwrap_or_else(|_| String::from(DEFAULT_SOCKET));
+///     Ok(MyPlugin::new(socket))
+/// });
+/// ```
 #[macro_export]
 macro_rules! collectd_plugin {
-    ($type: ty) => {
+    ($name: literal, $plugins: expr) => {
+        #[doc(hidden)]
+        pub mod collectd_plugin_manager {
+            use super::*;
+            use $crate::PluginManager;
 
-        // Let's us know if we've seen our config section before
-        static mut CONFIG_SEEN: bool = false;
+            pub struct Manager;
+
+            impl PluginManager for Manager {
+                fn name() -> &'static str {
+                    $name
+                }
+
+                fn plugins(
+                    config: Option<&[$crate::ConfigItem]>
+                ) -> Result<$crate::PluginRegistration, $crate::Error> {
+                    $crate::registration_from(config, $plugins)
+                }
+            }
+
+            collectd_plugin!(Manager);
+        }
+    };
+
+    ($type: ty) => {
 
         // This is the main entry point that collectd looks for. Our plugin manager will register
         // callbacks for configuration related to our name. It also registers a callback for
-        // initialization for when configuratio is absent or a single plugin wants to hold global
-        // data
+        // initialization, which is when the plugins are actually created (as all config blocks
+        // have been seen by then) and when a single plugin may want to hold global data
         #[no_mangle]
         pub extern "C" fn module_register() {
-            use std::ffi::CString;
             use $crate::bindings::{plugin_register_init, plugin_register_complex_config};
 
-            let s = CString::new(<$type as PluginManager>::name())
-                .expect("Plugin name to not contain nulls");
-
-            unsafe {
-                plugin_register_complex_config(
-                    s.as_ptr(),
-                    Some(collectd_plugin_complex_config)
+            if let Err(ref e) = $crate::register_plugin_name(<$type as PluginManager>::name()) {
+                $crate::collectd_log(
+                    $crate::LogLevel::Error,
+                    &format!("{}: invalid plugin name: {}", <$type as PluginManager>::name(), e)
                 );
-
-                plugin_register_init(s.as_ptr(), Some(collectd_plugin_init));
+                return;
             }
 
-        }
-
-        unsafe extern "C" fn collectd_plugin_read(dt: *mut $crate::bindings::user_data_t) -> std::os::raw::c_int {
-            let ptr: *mut Box<$crate::Plugin>  = std::mem::transmute((*dt).data);
-            let mut plugin = Box::from_raw(ptr);
-            let result = if let Err(ref e) = plugin.read_values() {
+            // Registering anything with a daemon whose structs differ from our bindings will end
+            // in a crash, so bail with an explanation instead
+            if let Err(ref e) = $crate::verify_abi() {
                 $crate::collectd_log(
                     $crate::LogLevel::Error,
-                    &format!("read error: {}", e)
+                    &format!("{}: {}", <$type as PluginManager>::name(), e)
                 );
-                -1
-            } else {
-                0
-            };
+                return;
+            }
+            let capabilities = <$type as PluginManager>::capabilities();
+            if capabilities.intersects($crate::PluginManagerCapabilities::LOG_PANICS) {
+                $crate::install_panic_hook();
+            }
+
+            let context = $crate::plugin_context().expect("plugin context to be registered");
+            unsafe {
+                // collectd only passes options to simple config callbacks of plugins without a
+                // complex config callback
+                if capabilities.intersects($crate::PluginManagerCapabilities::SIMPLE_CONFIG) {
+                    $crate::register_simple_config(
+                        context.c_name(),
+                        <$type as PluginManager>::config_keys(),
+                        collectd_plugin_simple_config
+                    );
+                } else {
+                    plugin_register_complex_config(
+                        context.c_name().as_ptr(),
+                        Some(collectd_plugin_complex_config)
+                    );
+                }
+
+                plugin_register_init(context.c_name().as_ptr(), Some(collectd_plugin_init));
+
+                if capabilities.intersects($crate::PluginManagerCapabilities::SHUTDOWN) {
+                    $crate::bindings::plugin_register_shutdown(
+                        context.c_name().as_ptr(),
+                        Some(collectd_plugin_shutdown)
+                    );
+                }
+            }
+
+            for (name, constructor) in <$type as PluginManager>::matches() {
+                $crate::register_match(name, constructor);
+            }
+
+            for (name, constructor) in <$type as PluginManager>::targets() {
+                $crate::register_target(name, constructor);
+            }
 
-            std::mem::forget(plugin);
-            result
         }
 
-        unsafe extern "C" fn collectd_plugin_free_user_data(raw: *mut ::std::os::raw::c_void) {
-            let ptr: *mut Box<$crate::Plugin> = std::mem::transmute(raw);
-            Box::from_raw(ptr);
+        // Callbacks borrow the plugin from the user data instead of reconstructing the box so
+        // that a panic unwinding through a callback can't drop the plugin out from under
+        // collectd. Panics are caught before they cross back into C.
+        unsafe extern "C" fn collectd_plugin_read(dt: *mut $crate::bindings::user_data_t) -> std::os::raw::c_int {
+            $crate::catch_panic("read", || {
+                let plugin = match $crate::plugin_from_user_data(dt) {
+                    Some(x) => x,
+                    None => return $crate::null_argument("read", "user data"),
+                };
+                let capability = $crate::PluginCapabilities::READ;
+                if !plugin.enabled(capability) {
+                    return 0;
+                }
+
+                // Discard requests that were made outside of a read
+                $crate::take_reconfigured_interval();
+                let res = plugin.plugin.read_values();
+                let status = plugin.finish(capability, "read_values", "read error", res);
+                if let Some(interval) = $crate::take_reconfigured_interval() {
+                    plugin.reschedule(dt, interval);
+                }
+                status
+            })
         }
 
         unsafe extern "C" fn collectd_plugin_log(
@@ -179,18 +999,20 @@ macro_rules! collectd_plugin {
             dt: *mut $crate::bindings::user_data_t
         ) {
             use std::ffi::CStr;
-            let ptr: *mut Box<$crate::Plugin> = std::mem::transmute((*dt).data);
-            let mut plugin = Box::from_raw(ptr);
-            if let Ok(msg) = CStr::from_ptr(message).to_owned().into_string() {
-                let lvl: $crate::LogLevel = std::mem::transmute(severity as u32);
-                if let Err(ref e) = plugin.log(lvl, msg) {
-                    $crate::collectd_log(
-                        $crate::LogLevel::Error,
-                        &format!("logging error: {}", e)
-                    );
-                }
-            }
-            std::mem::forget(plugin);
+            $crate::catch_panic("log", || {
+                // Logging about the null would only invoke this callback again
+                let capability = $crate::PluginCapabilities::LOG;
+                let lvl = <$crate::LogLevel as ::std::convert::TryFrom<u32>>::try_from(severity as u32)
+                    .unwrap_or($crate::LogLevel::Unknown(severity as u32));
+                let plugin = match $crate::plugin_from_user_data(dt) {
+                    Some(x) if !message.is_null() && x.enabled(capability) && x.logs(lvl) => x,
+                    _ => return 0,
+                };
+                // Only allocates when the message isn't valid UTF-8
+                let msg = CStr::from_ptr(message).to_string_lossy();
+                plugin.log(lvl, &msg);
+                0
+            });
         }
 
         unsafe extern "C" fn collectd_plugin_write(
@@ -198,51 +1020,67 @@ macro_rules! collectd_plugin {
            vl: *const $crate::bindings::value_list_t,
            dt: *mut $crate::bindings::user_data_t
         ) -> std::os::raw::c_int {
-            let ptr: *mut Box<$crate::Plugin> = std::mem::transmute((*dt).data);
-            let mut plugin = Box::from_raw(ptr);
-            let list = $crate::RecvValueList::from(&*ds, &*vl);
-            if let Err(ref e) = list {
-                $crate::collectd_log(
-                    $crate::LogLevel::Error,
-                    &format!("Unable to decode collectd data: {}", e)
-                );
-                std::mem::forget(plugin);
-                return -1;
-            }
+            $crate::catch_panic("write", || {
+                let plugin = match $crate::plugin_from_user_data(dt) {
+                    Some(x) => x,
+                    None => return $crate::null_argument("write", "user data"),
+                };
+                if ds.is_null() || vl.is_null() {
+                    return $crate::null_argument("write", "value list");
+                }
 
-            let result =
-                if let Err(ref e) = plugin.write_values(list.unwrap()) {
-                    $crate::collectd_log(
-                        $crate::LogLevel::Error,
-                        &format!("writing error: {}", e)
-                    );
-                    -1
-                } else {
-                    0
+                let capability = $crate::PluginCapabilities::WRITE;
+                if !plugin.enabled(capability) || !plugin.writes(&*vl) {
+                    return 0;
+                }
+
+                let list = match plugin.receive(&*ds, &*vl) {
+                    Ok(list) => list,
+                    Err(ref e) => {
+                        $crate::collectd_log(
+                            $crate::LogLevel::Error,
+                            &format!("Unable to decode collectd data: {}", $crate::ErrorChain(e))
+                        );
+                        return -1;
+                    }
                 };
-            std::mem::forget(plugin);
-            result
+
+                let res = plugin.plugin.write_values(list);
+                plugin.finish(capability, "write_values", "writing error", res)
+            })
         }
 
         unsafe extern "C" fn collectd_plugin_init() -> std::os::raw::c_int {
-            let mut result = if !CONFIG_SEEN {
-                collectd_register_all_plugins(None)
-            } else {
-                0
-            };
+            $crate::catch_panic("init", || {
+                $crate::register_default_interval();
+                let result = match $crate::take_config_blocks() {
+                    Some(blocks) => collectd_register_all_plugins(Some(&blocks.merged())),
+                    None => collectd_register_all_plugins(None),
+                };
 
-            let capabilities = <$type as PluginManager>::capabilities();
-            if capabilities.intersects($crate::PluginManagerCapabilities::INIT) {
-                if let Err(ref e) = <$type as PluginManager>::initialize() {
-                    result = -1;
-                    $crate::collectd_log(
-                        $crate::LogLevel::Error,
-                        &format!("init error: {}", e)
-                    );
+                // The plugins failed to be constructed (and nothing was registered), so there is
+                // nothing to initialize
+                if result != 0 {
+                    return result;
                 }
-            }
 
-            result
+                $crate::initialize_manager::<$type>()
+            })
+        }
+
+        unsafe extern "C" fn collectd_plugin_shutdown() -> std::os::raw::c_int {
+            $crate::catch_panic("shutdown", || {
+                match <$type as PluginManager>::shutdown() {
+                    Ok(()) => 0,
+                    Err(ref e) => {
+                        $crate::collectd_log(
+                            $crate::LogLevel::Error,
+                            &format!("shutdown error: {}", $crate::ErrorChain(&**e))
+                        );
+                        -1
+                    }
+                }
+            })
         }
 
         unsafe extern "C" fn collectd_plugin_flush(
@@ -252,53 +1090,99 @@ macro_rules! collectd_plugin {
         ) -> std::os::raw::c_int {
             use std::ffi::CStr;
 
-            let ptr: *mut Box<$crate::Plugin> = std::mem::transmute((*dt).data);
-            let mut plugin = Box::from_raw(ptr);
+            $crate::catch_panic("flush", || {
+                let plugin = match $crate::plugin_from_user_data(dt) {
+                    Some(x) => x,
+                    None => return $crate::null_argument("flush", "user data"),
+                };
 
-            let dur = if timeout == 0 { None } else { Some($crate::CdTime::from(timeout).into()) };
-            let result =
-                if let Ok(ident) = CStr::from_ptr(identifier).to_str() {
-                    if let Err(ref e) = plugin.flush(dur, $crate::empty_to_none(ident)) {
-                        $crate::collectd_log(
-                            $crate::LogLevel::Error,
-                            &format!("flush error: {}", e)
-                        );
-                        -1
-                    } else {
-                        0
-                    }
+                let capability = $crate::PluginCapabilities::FLUSH;
+                if !plugin.enabled(capability) {
+                    return 0;
+                }
+
+                let dur = if timeout == 0 { None } else { Some($crate::CdTime::from(timeout).into()) };
+
+                // collectd passes a null identifier when everything should be flushed
+                let ident = if identifier.is_null() {
+                    None
                 } else {
-                    -1
+                    let parsed = CStr::from_ptr(identifier)
+                        .to_str()
+                        .map_err($crate::Error::from)
+                        .and_then(|x| match $crate::empty_to_none(x) {
+                            Some(x) => x.parse::<$crate::Identifier>()
+                                .map(Some)
+                                .map_err($crate::Error::from),
+                            None => Ok(None),
+                        });
+
+                    match parsed {
+                        Ok(x) => x,
+                        Err(ref e) => {
+                            $crate::collectd_log(
+                                $crate::LogLevel::Error,
+                                &format!("flush error: {}", $crate::ErrorChain(&**e))
+                            );
+                            return -1;
+                        }
+                    }
                 };
 
-            std::mem::forget(plugin);
-            result
+                let res = plugin.plugin.flush(dur, ident);
+                plugin.finish(capability, "flush", "flush error", res)
+            })
         }
 
         unsafe extern "C" fn collectd_plugin_complex_config(
             config: *mut $crate::bindings::oconfig_item_t
         ) -> std::os::raw::c_int {
-            // If we've already seen the config, let's error out as one shouldn't use multiple
-            // sections of configuration (group them under nodes like write_graphite)
-            if CONFIG_SEEN {
-                $crate::collectd_log(
-                    $crate::LogLevel::Error,
-                    &format!("Already seen a config section for {}", <$type as PluginManager>::name())
-                );
-                return -1;
-            }
+            $crate::catch_panic("config", || {
+                if config.is_null() {
+                    return $crate::null_argument("config", "config block");
+                }
 
-            CONFIG_SEEN = true;
-            match $crate::ConfigItem::from(&*config) {
-                Ok(config) => collectd_register_all_plugins(Some(&config.children)),
-                Err(ref e) => {
-                    $crate::collectd_log(
-                        $crate::LogLevel::Error,
-                        &format!("Can't convert from collectd config: {}", e)
-                    );
-                    -1
+                match $crate::ConfigItem::from(&*config) {
+                    Ok(config) => {
+                        $crate::record_config_block(&config);
+                        0
+                    }
+                    Err(ref e) => {
+                        $crate::collectd_log(
+                            $crate::LogLevel::Error,
+                            &format!("Can't convert from collectd config: {}", $crate::ErrorChain(e))
+                        );
+                        -1
+                    }
                 }
-            }
+            })
+        }
+
+        unsafe extern "C" fn collectd_plugin_simple_config(
+            key: *const std::os::raw::c_char,
+            value: *const std::os::raw::c_char
+        ) -> std::os::raw::c_int {
+            use std::ffi::CStr;
+
+            $crate::catch_panic("config", || {
+                if key.is_null() || value.is_null() {
+                    return $crate::null_argument("config", "option");
+                }
+
+                match (CStr::from_ptr(key).to_str(), CStr::from_ptr(value).to_str()) {
+                    (Ok(key), Ok(value)) => {
+                        $crate::record_config_option(key, value);
+                        0
+                    }
+                    (Err(ref e), _) | (_, Err(ref e)) => {
+                        $crate::collectd_log(
+                            $crate::LogLevel::Error,
+                            &format!("Can't convert from collectd config: {}", e)
+                        );
+                        -1
+                    }
+                }
+            })
         }
 
         fn collectd_register_all_plugins(
@@ -329,109 +1213,22 @@ macro_rules! collectd_plugin {
                 Err(ref e) => {
                     $crate::collectd_log(
                         $crate::LogLevel::Error,
-                        &format!("config error: {}", e)
+                        &format!("config error: {}", $crate::ErrorChain(&**e))
                     );
                     -1
                 }
             }
         }
 
-        fn collectd_plugin_registration(name: &str, plugin: Box<$crate::Plugin>) {
-            use std::os::raw::c_void;
-            use std::ptr;
-            use std::ffi::CString;
-            use $crate::bindings::{plugin_register_write, plugin_register_complex_read, plugin_register_log, plugin_register_flush};
-
-            let pl: Box<Box<$crate::Plugin>> = Box::new(plugin);
-
-            // Grab all the properties we need until `into_raw` away
-            let should_read = pl.capabilities().has_read();
-            let should_log = pl.capabilities().has_log();
-            let should_write = pl.capabilities().has_write();
-            let should_flush = pl.capabilities().has_flush();
-
-            let s = CString::new(name).expect("Plugin name to not contain nulls");
-            unsafe {
-                let plugin_ptr: *mut c_void = std::mem::transmute(Box::into_raw(pl));
-
-                // Plugin registration differs only a tiny bit between collectd-57 and older
-                // versions. The one difference is that user_data_t went from mutable to not
-                // mutable. The code duplication is annoying, but it's better to have it
-                // encapsulated in a single crate instead of many others.
-                #[cfg(not(feature = "collectd-57"))]
-                {
-                    // The user data that is passed to read, writes, logs, etc. It is not passed to
-                    // config or init. Since user_data_t implements copy, we don't need to forget about
-                    // it. See clippy suggestion (forget_copy)
-                    let mut data = $crate::bindings::user_data_t {
-                        data: plugin_ptr,
-                        free_func: Some(collectd_plugin_free_user_data),
-                    };
-
-                    if should_read {
-                        plugin_register_complex_read(
-                            ptr::null(),
-                            s.as_ptr(),
-                            Some(collectd_plugin_read),
-                            $crate::get_default_interval(),
-                            &mut data
-                        );
-                    }
-
-                    if should_write {
-                        plugin_register_write(
-                            s.as_ptr(),
-                            Some(collectd_plugin_write),
-                            &mut data
-                        );
-                    }
-
-                    if should_log {
-                        plugin_register_log(s.as_ptr(), Some(collectd_plugin_log), &mut data);
-                    }
-
-                    if should_flush {
-                        plugin_register_flush(s.as_ptr(), Some(collectd_plugin_flush), &mut data);
-                    }
-                }
-
-                #[cfg(feature = "collectd-57")]
-                {
-                    // The user data that is passed to read, writes, logs, etc. It is not passed to
-                    // config or init. Since user_data_t implements copy, we don't need to forget about
-                    // it. See clippy suggestion (forget_copy)
-                    let data = $crate::bindings::user_data_t {
-                        data: plugin_ptr,
-                        free_func: Some(collectd_plugin_free_user_data),
-                    };
-
-                    if should_read {
-                        plugin_register_complex_read(
-                            ptr::null(),
-                            s.as_ptr(),
-                            Some(collectd_plugin_read),
-                            $crate::get_default_interval(),
-                            &data
-                        );
-                    }
-
-                    if should_write {
-                        plugin_register_write(
-                            s.as_ptr(),
-                            Some(collectd_plugin_write),
-                            &data
-                        );
-                    }
-
-                    if should_log {
-                        plugin_register_log(s.as_ptr(), Some(collectd_plugin_log), &data);
-                    }
+        fn collectd_plugin_registration(name: &str, plugin: Box<dyn $crate::DynPlugin>) {
+            let callbacks = $crate::PluginCallbacks {
+                read: Some(collectd_plugin_read),
+                write: Some(collectd_plugin_write),
+                log: Some(collectd_plugin_log),
+                flush: Some(collectd_plugin_flush),
+            };
 
-                    if should_flush {
-                        plugin_register_flush(s.as_ptr(), Some(collectd_plugin_flush), &data);
-                    }
-                }
-            }
+            $crate::register_plugin(name, plugin, &callbacks);
         }
     };
 }
@@ -450,4 +1247,205 @@ mod tests {
         assert_eq!(capabilities.has_read(), true);
         assert_eq!(capabilities.has_write(), false);
     }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_log_falls_back_to_owned_signature() {
+        struct OldPlugin(Vec<String>);
+
+        impl Plugin for OldPlugin {
+            type Error = Error;
+
+            fn log_owned(&mut self, _lvl: LogLevel, msg: String) -> Result<(), Error> {
+                self.0.push(msg);
+                Ok(())
+            }
+        }
+
+        let mut plugin = OldPlugin(vec![]);
+        Plugin::log(&mut plugin, LogLevel::Info, "hello").unwrap();
+        assert_eq!(plugin.0, vec![String::from("hello")]);
+    }
+
+    #[test]
+    fn test_register_read() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::Arc;
+        use stub;
+        use testing;
+
+        stub::reset();
+        let reads = Arc::new(AtomicUsize::new(0));
+        let counter = reads.clone();
+        register_read("test/fast", Some(Duration::from_secs(1)), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }).unwrap();
+        register_read("test/slow", None, || Err(Error::from("device busy"))).unwrap();
+
+        assert_eq!(stub::read("test/fast"), Some(0));
+        assert_eq!(stub::read("test/fast"), Some(0));
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+        assert_eq!(stub::read("test/slow"), Some(-1));
+        testing::assert_logged(LogLevel::Error, "read error: device busy");
+
+        // The closures are freed along with the registrations
+        stub::reset();
+        assert_eq!(Arc::strong_count(&reads), 1);
+    }
+
+    #[test]
+    fn test_plugin_error_type() {
+        #[derive(Debug)]
+        enum ReadError {
+            Timeout(u32),
+            Unsupported,
+        }
+
+        impl fmt::Display for ReadError {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match *self {
+                    ReadError::Timeout(secs) => write!(f, "timed out after {}s", secs),
+                    ReadError::Unsupported => write!(f, "unsupported"),
+                }
+            }
+        }
+
+        impl From<NotImplemented> for ReadError {
+            fn from(_: NotImplemented) -> Self {
+                ReadError::Unsupported
+            }
+        }
+
+        struct Poller;
+
+        impl Plugin for Poller {
+            type Error = ReadError;
+
+            fn capabilities(&self) -> PluginCapabilities {
+                PluginCapabilities::READ
+            }
+
+            fn read_values(&mut self) -> Result<(), ReadError> {
+                Err(ReadError::Timeout(5))
+            }
+        }
+
+        let mut plugin: Box<dyn DynPlugin> = Box::new(Poller);
+        assert!(plugin.capabilities().has_read());
+        let err = plugin.read_values().unwrap_err();
+        assert_eq!(err.to_string(), "timed out after 5s");
+        assert_eq!(plugin.flush(None, None).unwrap_err().to_string(), "unsupported");
+
+        // An `Error` isn't converted to a string, so that its causes are logged
+        struct Reader;
+
+        impl Plugin for Reader {
+            type Error = Error;
+
+            fn read_values(&mut self) -> Result<(), Error> {
+                Err(Error::from(io::Error::new(io::ErrorKind::NotFound, "no /proc/loadavg")))
+            }
+        }
+
+        let mut plugin: Box<dyn DynPlugin> = Box::new(Reader);
+        assert!(plugin.read_values().unwrap_err().is::<io::Error>());
+        assert!(plugin.flush(None, None).unwrap_err().is::<NotImplemented>());
+    }
+
+    #[test]
+    fn test_write_errors_summarized() {
+        use stub;
+
+        struct Writer;
+
+        impl Plugin for Writer {
+            type Error = Error;
+
+            fn capabilities(&self) -> PluginCapabilities {
+                PluginCapabilities::WRITE | PluginCapabilities::SUMMARIZE_WRITE_ERRORS
+            }
+
+            fn write_errors_summarized(&self) -> u64 {
+                3
+            }
+        }
+
+        stub::reset();
+        let plugin = RegisteredPlugin::new("myplugin", Box::new(Writer), None);
+        let write = |res: Result<(), Error>| {
+            plugin.finish(PluginCapabilities::WRITE, "write_values", "writing error", res)
+        };
+
+        for _ in 0..7 {
+            assert_eq!(write(Err(Error::from("connection refused"))), -1);
+        }
+        assert_eq!(write(Ok(())), 0);
+        assert_eq!(write(Err(Error::from("connection reset"))), -1);
+
+        let logged: Vec<String> = stub::logged().into_iter().map(|x| x.message).collect();
+        assert_eq!(
+            logged,
+            vec![
+                "writing error: connection refused",
+                "writing error: connection refused (3 consecutive errors)",
+                "writing error: connection refused (6 consecutive errors)",
+                "myplugin: writing recovered after 7 consecutive errors",
+                "writing error: connection reset",
+            ]
+        );
+
+        // Other callbacks and plugins without the capability log every error
+        stub::reset();
+        let read = plugin.finish(
+            PluginCapabilities::READ,
+            "read_values",
+            "read error",
+            Err(Error::from("timed out")),
+        );
+        assert_eq!(read, -1);
+        assert_eq!(stub::logged().len(), 1);
+        stub::reset();
+    }
+
+    #[test]
+    fn test_registration_from_closure() {
+        struct Reader;
+
+        impl Plugin for Reader {
+            type Error = Error;
+
+            fn capabilities(&self) -> PluginCapabilities {
+                PluginCapabilities::READ
+            }
+        }
+
+        let items = vec![
+            ConfigItem {
+                key: "Host",
+                values: vec![],
+                children: vec![],
+            },
+        ];
+
+        let registration = registration_from(Some(&items), |config| {
+            assert_eq!(config.map(|x| x.len()), Some(1));
+            Ok(Reader)
+        });
+        match registration.unwrap() {
+            PluginRegistration::Single(plugin) => assert!(plugin.capabilities().has_read()),
+            PluginRegistration::Multiple(_) => panic!("expected a single plugin"),
+        }
+
+        let failed = registration_from(None, |_| -> Result<Reader, Error> {
+            Err(Error::from("MYPLUGIN_SOCKET is not set"))
+        });
+        assert!(failed.is_err());
+
+        let multiple = registration_from(None, |_| {
+            let reader: Box<dyn DynPlugin> = Box::new(Reader);
+            Ok(PluginRegistration::Multiple(vec![(String::from("first"), reader)]))
+        });
+        assert!(multiple.is_ok());
+    }
 }
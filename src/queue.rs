@@ -0,0 +1,114 @@
+//! collectd queues dispatched values for its write threads, and once the queue grows past
+//! `WriteQueueLimitLow` it starts dropping values at random. A read plugin that can choose how
+//! much it collects (eg: how many targets it polls) is better off shedding load itself. collectd
+//! doesn't expose the queue, but with `CollectInternalStats true` (collectd 5.5 and later) it
+//! dispatches the queue's length and its drops every interval, which `WriteQueueStats` reads back
+//! from collectd's cache.
+
+use api::from_array;
+use bindings::{hostname_g, uc_get_value_by_name, value_t};
+use std::ffi::CString;
+use std::os::raw::c_void;
+use std::ptr;
+
+extern "C" {
+    fn free(ptr: *mut c_void);
+}
+
+/// collectd's write queue as of the latest internal statistics, which are dispatched once per
+/// interval, so this is an estimate of the current queue
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct WriteQueueStats {
+    /// The number of value lists waiting for a write thread
+    pub length: u64,
+
+    /// The number of value lists dropped because the queue was over its limit since collectd
+    /// started, if collectd reported it
+    pub dropped: Option<u64>,
+
+    /// The number of identifiers in collectd's cache, if collectd reported it
+    pub cache_size: Option<u64>,
+}
+
+impl WriteQueueStats {
+    /// Reads the latest statistics from collectd's cache, or `None` if collectd isn't collecting
+    /// its internal statistics
+    pub fn current() -> Option<WriteQueueStats> {
+        let host = unsafe { from_array(&hostname_g) }.unwrap_or("");
+        let gauge = |id: &str| unsafe { cached(&format!("{}/{}", host, id)).map(|x| x.gauge) };
+        let derive = |id: &str| unsafe { cached(&format!("{}/{}", host, id)).map(|x| x.derive) };
+
+        let length = gauge("collectd-write_queue/queue_length")?;
+        Some(WriteQueueStats {
+            length: length.max(0.0) as u64,
+            dropped: derive("collectd-write_queue/derive-dropped").map(|x| x.max(0) as u64),
+            cache_size: gauge("collectd-cache/cache_size").map(|x| x.max(0.0) as u64),
+        })
+    }
+
+    /// Returns if the queue holds at least `limit` value lists. Pass the daemon's
+    /// `WriteQueueLimitLow`, above which collectd drops values, or a fraction of it to back off
+    /// before values are dropped.
+    pub fn is_saturated(&self, limit: u64) -> bool {
+        self.length >= limit
+    }
+}
+
+/// The first value that collectd's cache holds for the identifier
+unsafe fn cached(name: &str) -> Option<value_t> {
+    let name = CString::new(name).ok()?;
+    let mut values: *mut value_t = ptr::null_mut();
+    let mut len = 0;
+    if uc_get_value_by_name(name.as_ptr(), &mut values, &mut len) != 0 || values.is_null() {
+        return None;
+    }
+
+    let value = if len > 0 { Some(*values) } else { None };
+    free(values as *mut c_void);
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{Value, ValueListBuilder};
+    use stub;
+
+    #[test]
+    fn test_write_queue_stats() {
+        stub::reset();
+        assert_eq!(WriteQueueStats::current(), None);
+
+        // The stub only caches values with collectd 5.7
+        if cfg!(not(collectd_57)) {
+            return;
+        }
+
+        let submit = |plugin_instance, type_, type_instance: Option<&str>, value| {
+            let values = [value];
+            let builder = ValueListBuilder::new("collectd", type_)
+                .plugin_instance(plugin_instance)
+                .values(&values);
+            match type_instance {
+                Some(x) => builder.type_instance(x).submit(),
+                None => builder.submit(),
+            }
+        };
+        submit("write_queue", "queue_length", None, Value::Gauge(1500.0)).unwrap();
+        submit("write_queue", "derive", Some("dropped"), Value::Derive(3)).unwrap();
+        submit("cache", "cache_size", None, Value::Gauge(40.0)).unwrap();
+
+        let stats = WriteQueueStats::current().unwrap();
+        assert_eq!(
+            stats,
+            WriteQueueStats {
+                length: 1500,
+                dropped: Some(3),
+                cache_size: Some(40),
+            }
+        );
+        assert!(stats.is_saturated(1000));
+        assert!(!stats.is_saturated(2000));
+        stub::reset();
+    }
+}
@@ -0,0 +1,173 @@
+//! Plugins that read raw counters but need to report rates (eg: as gauges to a system that
+//! doesn't understand counters) have to remember the previous value of each counter.
+//! `RateTracker` does this, computing rates the same way as collectd's value cache so that the
+//! results line up with what the daemon itself would report.
+
+use api::{RecvValueList, Value};
+use identifier::Identifier;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// The increase from `old` to `new`, assuming the counter wrapped if it decreased. Counters that
+/// were below 2^32 are assumed to be 32 bit counters, like collectd does.
+pub fn counter_diff(old: u64, new: u64) -> u64 {
+    if old <= new {
+        new - old
+    } else if old <= u64::from(u32::MAX) {
+        (u64::from(u32::MAX) - old) + new + 1
+    } else {
+        (u64::MAX - old) + new + 1
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Previous {
+    time: SystemTime,
+    values: Vec<Value>,
+}
+
+/// Remembers the last values of each identifier to compute per second rates
+#[derive(Debug, Clone, Default)]
+pub struct RateTracker {
+    previous: HashMap<Identifier, Previous>,
+}
+
+impl RateTracker {
+    pub fn new() -> Self {
+        RateTracker::default()
+    }
+
+    /// Records the values for the identifier and returns the rate of each. Gauges are their own
+    /// rate, counters and derives are the change since the last values divided by the elapsed
+    /// time, and absolutes (which are reset when read) are divided by the elapsed time.
+    ///
+    /// Rates are NaN when there are no previous values to compare against, which is the case for
+    /// the first values of an identifier or when its number or kinds of values change. Values that
+    /// aren't newer than the previous values are ignored and `None` is returned.
+    pub fn rates(
+        &mut self,
+        id: &Identifier,
+        time: SystemTime,
+        values: &[Value],
+    ) -> Option<Vec<f64>> {
+        let rates = match self.previous.get(id) {
+            Some(prev) if time <= prev.time => return None,
+            Some(prev) => {
+                let elapsed = time.duration_since(prev.time).unwrap_or(Duration::from_secs(0));
+                let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+                if prev.values.len() == values.len() {
+                    prev.values
+                        .iter()
+                        .zip(values)
+                        .map(|(old, new)| rate(old, new, secs))
+                        .collect()
+                } else {
+                    values.iter().map(first_rate).collect()
+                }
+            }
+            None => values.iter().map(first_rate).collect(),
+        };
+
+        self.previous.insert(
+            id.clone(),
+            Previous {
+                time: time,
+                values: values.to_vec(),
+            },
+        );
+        Some(rates)
+    }
+
+    /// Records the values of a received value list, identified by its host, plugin, and type
+    pub fn rates_of(&mut self, list: &RecvValueList) -> Option<Vec<f64>> {
+        let values: Vec<Value> = list.values.iter().map(|x| x.value).collect();
+        self.rates(&Identifier::from(list), list.time, &values)
+    }
+
+    /// Forgets an identifier, so its next values are treated as the first
+    pub fn remove(&mut self, id: &Identifier) {
+        self.previous.remove(id);
+    }
+
+    /// Forgets identifiers whose last values are older than the given time. Call periodically
+    /// so identifiers that disappear (eg: a removed network interface) don't accumulate.
+    pub fn expire(&mut self, before: SystemTime) {
+        self.previous.retain(|_, prev| prev.time >= before);
+    }
+
+    /// Number of tracked identifiers
+    pub fn len(&self) -> usize {
+        self.previous.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.previous.is_empty()
+    }
+}
+
+fn first_rate(value: &Value) -> f64 {
+    match *value {
+        Value::Gauge(x) => x,
+        _ => f64::NAN,
+    }
+}
+
+fn rate(old: &Value, new: &Value, secs: f64) -> f64 {
+    match (*old, *new) {
+        (_, Value::Gauge(x)) => x,
+        (Value::Counter(old), Value::Counter(new)) => counter_diff(old, new) as f64 / secs,
+        (Value::Derive(old), Value::Derive(new)) => new.wrapping_sub(old) as f64 / secs,
+        (_, Value::Absolute(x)) => x as f64 / secs,
+        _ => f64::NAN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn id(s: &str) -> Identifier {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_counter_diff() {
+        assert_eq!(counter_diff(10, 25), 15);
+        assert_eq!(counter_diff(u64::from(u32::MAX) - 4, 5), 10);
+        assert_eq!(counter_diff(u64::MAX - 4, 5), 10);
+    }
+
+    #[test]
+    fn test_rates() {
+        let mut tracker = RateTracker::new();
+        let a = id("localhost/interface-eth0/if_octets");
+        let b = id("localhost/interface-eth1/if_octets");
+        let values = [Value::Counter(100), Value::Derive(50), Value::Gauge(1.5)];
+        let rates = tracker.rates(&a, at(10), &values).unwrap();
+        assert!(rates[0].is_nan() && rates[1].is_nan());
+        assert_eq!(rates[2], 1.5);
+
+        let values = [Value::Counter(300), Value::Derive(30), Value::Gauge(2.5)];
+        assert_eq!(tracker.rates(&a, at(20), &values), Some(vec![20.0, -2.0, 2.5]));
+
+        // Stale values are ignored and don't replace the previous values
+        assert_eq!(tracker.rates(&a, at(20), &values), None);
+        let values = [Value::Counter(400), Value::Derive(30), Value::Gauge(2.5)];
+        assert_eq!(tracker.rates(&a, at(30), &values), Some(vec![10.0, 0.0, 2.5]));
+
+        // A 32 bit counter that wraps
+        tracker.rates(&b, at(0), &[Value::Counter(u64::from(u32::MAX) - 9)]);
+        assert_eq!(tracker.rates(&b, at(5), &[Value::Counter(40)]), Some(vec![10.0]));
+
+        assert_eq!(tracker.len(), 2);
+        tracker.expire(at(10));
+        assert_eq!(tracker.len(), 1);
+        tracker.remove(&a);
+        assert!(tracker.is_empty());
+    }
+}
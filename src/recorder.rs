@@ -0,0 +1,266 @@
+//! A recorder for the [metrics](https://crates.io/crates/metrics) facade that keeps the latest
+//! value of each metric and submits them to collectd on demand (typically from a read callback),
+//! so libraries instrumented with `metrics` report through the plugin unchanged.
+
+use api::{Value, ValueListBuilder};
+use errors::Error;
+use metrics::{self, Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Recorder,
+              SetRecorderError, SharedString, Unit};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Summary of the values a histogram recorded since the last submission
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+#[derive(Debug, Default)]
+struct Summary(Mutex<Stats>);
+
+impl Summary {
+    fn take(&self) -> Stats {
+        let mut stats = self.0.lock().unwrap();
+        ::std::mem::replace(&mut *stats, Stats::default())
+    }
+}
+
+impl HistogramFn for Summary {
+    fn record(&self, value: f64) {
+        let mut stats = self.0.lock().unwrap();
+        if stats.count == 0 || value < stats.min {
+            stats.min = value;
+        }
+        if stats.count == 0 || value > stats.max {
+            stats.max = value;
+        }
+        stats.count += 1;
+        stats.sum += value;
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Arc<AtomicU64>),
+    Gauge(Arc<AtomicU64>),
+    Histogram(Arc<Summary>),
+}
+
+type Registry = Mutex<HashMap<Key, Metric>>;
+
+/// Implements `metrics::Recorder` by storing metrics until a `RecorderHandle` submits them
+#[derive(Debug, Default)]
+pub struct CollectdRecorder {
+    registry: Arc<Registry>,
+}
+
+/// Submits the metrics of a `CollectdRecorder`, which may be installed as the global recorder.
+/// Each metric becomes a value list of the plugin, with the metric's name as the type instance
+/// and its labels (as `key=value` pairs sorted by key) as the plugin instance:
+///
+/// - Counters are `derive` values
+/// - Gauges are `gauge` values
+/// - Histograms are summarized with the `gauge` value lists `<name>_count`, `<name>_sum`,
+///   `<name>_min`, and `<name>_max` for the values recorded since the last submission. Nothing
+///   is submitted for an interval without values.
+#[derive(Debug, Clone)]
+pub struct RecorderHandle {
+    registry: Arc<Registry>,
+}
+
+impl CollectdRecorder {
+    pub fn new() -> Self {
+        CollectdRecorder::default()
+    }
+
+    pub fn handle(&self) -> RecorderHandle {
+        RecorderHandle {
+            registry: self.registry.clone(),
+        }
+    }
+
+    /// Installs this recorder as the global recorder. As the `metrics` crate allows only one
+    /// global recorder per process, this can fail if another recorder has already been installed
+    pub fn install(self) -> Result<RecorderHandle, SetRecorderError> {
+        let handle = self.handle();
+        metrics::set_boxed_recorder(Box::new(self))?;
+        Ok(handle)
+    }
+
+    fn register(&self, key: &Key, create: fn() -> Metric) -> Metric {
+        let mut registry = self.registry.lock().unwrap();
+        registry.entry(key.clone()).or_insert_with(create).clone()
+    }
+}
+
+/// Installs a collectd recorder as the global `metrics` recorder, returning the handle that
+/// submits the metrics
+///
+/// ```ignore
+/// let handle = collectd_plugin::install_recorder()?;
+/// // and then within the plugin's read callback
+/// handle.submit("myplugin")?;
+/// ```
+pub fn install_recorder() -> Result<RecorderHandle, SetRecorderError> {
+    CollectdRecorder::new().install()
+}
+
+impl Recorder for CollectdRecorder {
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+    fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {
+    }
+
+    fn register_counter(&self, key: &Key) -> Counter {
+        match self.register(key, || Metric::Counter(Arc::new(AtomicU64::new(0)))) {
+            Metric::Counter(x) => Counter::from_arc(x),
+            _ => Counter::noop(),
+        }
+    }
+
+    fn register_gauge(&self, key: &Key) -> Gauge {
+        match self.register(key, || Metric::Gauge(Arc::new(AtomicU64::new(0)))) {
+            Metric::Gauge(x) => Gauge::from_arc(x),
+            _ => Gauge::noop(),
+        }
+    }
+
+    fn register_histogram(&self, key: &Key) -> Histogram {
+        match self.register(key, || Metric::Histogram(Arc::new(Summary::default()))) {
+            Metric::Histogram(x) => Histogram::from_arc(x),
+            _ => Histogram::noop(),
+        }
+    }
+}
+
+/// Formats the metric's labels as the plugin instance
+fn plugin_instance(key: &Key) -> String {
+    let mut labels: Vec<String> = key.labels()
+        .map(|x| format!("{}={}", x.key(), x.value()))
+        .collect();
+    labels.sort();
+    labels.join(",")
+}
+
+fn submit(
+    plugin: &str,
+    instance: &str,
+    type_: &str,
+    name: &str,
+    value: Value,
+) -> Result<(), Error> {
+    let values = [value];
+    let mut builder = ValueListBuilder::new(plugin, type_)
+        .type_instance(name)
+        .values(&values);
+    if !instance.is_empty() {
+        builder = builder.plugin_instance(instance);
+    }
+
+    builder.submit()
+}
+
+impl RecorderHandle {
+    /// Submits every registered metric under the given plugin name
+    pub fn submit(&self, plugin: &str) -> Result<(), Error> {
+        let mut metrics: Vec<(String, String, Metric)> = {
+            let registry = self.registry.lock().unwrap();
+            registry
+                .iter()
+                .map(|(key, metric)| {
+                    let name = String::from(key.name());
+                    (name, plugin_instance(key), metric.clone())
+                })
+                .collect()
+        };
+        metrics.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+        for (name, instance, metric) in metrics {
+            match metric {
+                Metric::Counter(x) => {
+                    let value = Value::Derive(x.load(Ordering::Acquire) as i64);
+                    submit(plugin, &instance, "derive", &name, value)?;
+                }
+                Metric::Gauge(x) => {
+                    let value = Value::Gauge(f64::from_bits(x.load(Ordering::Acquire)));
+                    submit(plugin, &instance, "gauge", &name, value)?;
+                }
+                Metric::Histogram(x) => {
+                    let stats = x.take();
+                    if stats.count == 0 {
+                        continue;
+                    }
+
+                    let summary = [
+                        ("count", stats.count as f64),
+                        ("sum", stats.sum),
+                        ("min", stats.min),
+                        ("max", stats.max),
+                    ];
+                    for &(stat, value) in summary.iter() {
+                        let name = format!("{}_{}", name, stat);
+                        submit(plugin, &instance, "gauge", &name, Value::Gauge(value))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::Label;
+    use testing::{self, assert_submitted};
+
+    #[test]
+    fn test_submit() {
+        testing::reset();
+        let recorder = CollectdRecorder::new();
+        let labels = vec![Label::new("route", "/"), Label::new("method", "GET")];
+        let requests = recorder.register_counter(&Key::from_parts("requests", labels));
+        requests.increment(3);
+        requests.increment(2);
+        recorder
+            .register_gauge(&Key::from_name("connections"))
+            .set(4.0);
+        let latency = recorder.register_histogram(&Key::from_name("latency"));
+        latency.record(0.5);
+        latency.record(1.5);
+
+        recorder.handle().submit("app").unwrap();
+        let dispatched = testing::dispatched();
+        let names: Vec<_> = dispatched
+            .iter()
+            .map(|x| (x.type_.as_str(), x.type_instance.as_ref().unwrap().as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("gauge", "connections"),
+                ("gauge", "latency_count"),
+                ("gauge", "latency_sum"),
+                ("gauge", "latency_min"),
+                ("gauge", "latency_max"),
+                ("derive", "requests"),
+            ]
+        );
+        assert_eq!(
+            dispatched[5].plugin_instance,
+            Some(String::from("method=GET,route=/"))
+        );
+        assert_submitted("app", "derive", &[Value::Derive(5)]);
+        assert_submitted("app", "gauge", &[Value::Gauge(4.0)]);
+
+        // Histograms only report values recorded since the last submission
+        testing::reset();
+        recorder.handle().submit("app").unwrap();
+        assert_eq!(testing::dispatched().len(), 2);
+    }
+}
@@ -0,0 +1,349 @@
+//! collectd doesn't retry a write that failed: the values are lost, and the write callback is
+//! simply invoked with the next value list. Write plugins for remote backends therefore buffer
+//! values and retry sending them, each with its own backoff. `RetryPolicy` describes jittered
+//! exponential backoff with a bounded number of attempts, and `Retrier` applies a policy from a
+//! callback that collectd invokes regularly (eg: flush), so that no write thread sleeps between
+//! attempts.
+
+use api::NotificationBuilder;
+use errors::Error;
+use std::collections::hash_map::RandomState;
+use std::error;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Returned once an operation has failed on every attempt that its policy allows
+#[derive(Debug)]
+pub struct GaveUp {
+    pub attempts: u32,
+
+    /// The error of the last attempt
+    pub error: Error,
+}
+
+impl fmt::Display for GaveUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "giving up after {} attempts: {}", self.attempts, self.error)
+    }
+}
+
+impl error::Error for GaveUp {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+/// How many times to attempt an operation and how long to wait between attempts
+#[derive(Debug, PartialEq, Clone)]
+pub struct RetryPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: f64,
+    max_attempts: u32,
+    notify: Option<String>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new()
+    }
+}
+
+impl RetryPolicy {
+    /// Five attempts, waiting one second after the first failure and twice as long after each
+    /// subsequent one (up to five minutes), give or take 20%
+    pub fn new() -> Self {
+        RetryPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: 5,
+            notify: None,
+        }
+    }
+
+    /// The delay after the first failure
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// The longest delay between attempts, however many attempts have failed
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// How much longer each delay is than the last
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Randomizes each delay by up to this fraction in either direction (eg: `0.2` is ±20%), so
+    /// that plugins on many hosts don't retry against the same backend in lockstep
+    pub fn jitter(mut self, fraction: f64) -> Self {
+        self.jitter = fraction.max(0.0).min(1.0);
+        self
+    }
+
+    /// The number of attempts, including the first, before giving up
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Dispatches a failure notification from the named plugin when giving up, so that the lost
+    /// values can be alerted on like any other failure
+    pub fn notify(mut self, plugin: &str) -> Self {
+        self.notify = Some(String::from(plugin));
+        self
+    }
+
+    /// The delay before the next attempt once `failures` consecutive attempts have failed
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(63) as i32;
+        let nominal = secs(self.initial_delay) * self.multiplier.powi(exponent);
+        let jittered = nominal.min(secs(self.max_delay)) * (1.0 + self.jitter * random_sign());
+        Duration::from_nanos((jittered.max(0.0) * 1e9) as u64)
+    }
+
+    /// Attempts the operation until it succeeds or the policy gives up, sleeping between
+    /// attempts. Only call from a thread that may block, like a plugin's own sender thread, as
+    /// collectd's write threads are held up by callbacks that sleep.
+    pub fn retry<T, F>(&self, mut op: F) -> Result<T, GaveUp>
+    where
+        F: FnMut() -> Result<T, Error>,
+    {
+        let mut failures = 0;
+        loop {
+            match op() {
+                Ok(x) => return Ok(x),
+                Err(e) => {
+                    failures += 1;
+                    if failures >= self.max_attempts {
+                        return Err(self.give_up(failures, e));
+                    }
+                    thread::sleep(self.delay(failures));
+                }
+            }
+        }
+    }
+
+    fn give_up(&self, attempts: u32, error: Error) -> GaveUp {
+        let gave_up = GaveUp {
+            attempts: attempts,
+            error: error,
+        };
+
+        if let Some(ref plugin) = self.notify {
+            // The caller learns about the failure from the returned error, even if collectd
+            // doesn't accept the notification
+            let message = gave_up.to_string();
+            let _ = NotificationBuilder::failure(plugin.as_str(), &message).dispatch();
+        }
+        gave_up
+    }
+}
+
+/// The outcome of `Retrier::attempt`
+#[derive(Debug)]
+pub enum Attempt<T> {
+    Succeeded(T),
+
+    /// The operation wasn't attempted as the delay since the last failure hasn't passed
+    Waiting,
+
+    /// The operation failed and will be attempted again after the policy's delay
+    Failed(Error),
+
+    /// The operation failed on its last attempt. The retrier starts over with the next attempt.
+    GaveUp(GaveUp),
+}
+
+/// Tracks the attempts of an operation that is retried from a callback collectd invokes
+/// regularly, like a flush callback that sends buffered values: the callback attempts the send
+/// if the retrier is ready, and keeps the values buffered unless the send succeeded or the
+/// retrier gave up.
+#[derive(Debug, Clone)]
+pub struct Retrier {
+    policy: RetryPolicy,
+    failures: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl Retrier {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Retrier {
+            policy: policy,
+            failures: 0,
+            next_attempt: None,
+        }
+    }
+
+    /// The number of consecutive failed attempts
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Returns if the delay since the last failure has passed
+    pub fn is_ready(&self) -> bool {
+        self.is_ready_at(Instant::now())
+    }
+
+    fn is_ready_at(&self, now: Instant) -> bool {
+        self.next_attempt.map_or(true, |x| now >= x)
+    }
+
+    /// Attempts the operation unless the retrier is waiting out a delay
+    pub fn attempt<T, F>(&mut self, op: F) -> Attempt<T>
+    where
+        F: FnOnce() -> Result<T, Error>,
+    {
+        self.attempt_at(Instant::now(), op)
+    }
+
+    fn attempt_at<T, F>(&mut self, now: Instant, op: F) -> Attempt<T>
+    where
+        F: FnOnce() -> Result<T, Error>,
+    {
+        if !self.is_ready_at(now) {
+            return Attempt::Waiting;
+        }
+
+        match op() {
+            Ok(x) => {
+                self.failures = 0;
+                self.next_attempt = None;
+                Attempt::Succeeded(x)
+            }
+            Err(e) => {
+                self.failures += 1;
+                if self.failures >= self.policy.max_attempts {
+                    let failures = self.failures;
+                    self.failures = 0;
+                    self.next_attempt = None;
+                    return Attempt::GaveUp(self.policy.give_up(failures, e));
+                }
+
+                self.next_attempt = Some(now + self.policy.delay(self.failures));
+                Attempt::Failed(e)
+            }
+        }
+    }
+}
+
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9
+}
+
+/// A number between -1 and 1 that is good enough to spread out retries. Every `RandomState` is
+/// seeded differently, which saves depending on a random number generator.
+fn random_sign() -> f64 {
+    let x = RandomState::new().build_hasher().finish();
+    (x >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use constants::Severity;
+    use stub;
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::new()
+            .initial_delay(Duration::from_secs(2))
+            .max_delay(Duration::from_secs(10))
+            .jitter(0.0);
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(2), Duration::from_secs(4));
+        assert_eq!(policy.delay(3), Duration::from_secs(8));
+        assert_eq!(policy.delay(4), Duration::from_secs(10));
+        assert_eq!(policy.delay(1_000), Duration::from_secs(10));
+
+        let policy = policy.jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(3));
+        }
+    }
+
+    #[test]
+    fn test_retrier() {
+        stub::reset();
+        let policy = RetryPolicy::new()
+            .initial_delay(Duration::from_secs(1))
+            .jitter(0.0)
+            .max_attempts(3)
+            .notify("myplugin");
+        let mut retrier = Retrier::new(policy);
+        let start = Instant::now();
+        let fail = || Err::<(), Error>(Error::from("connection refused"));
+
+        assert!(match retrier.attempt_at(start, fail) {
+            Attempt::Failed(_) => true,
+            _ => false,
+        });
+        assert!(match retrier.attempt_at(start, fail) {
+            Attempt::Waiting => true,
+            _ => false,
+        });
+        assert!(!retrier.is_ready_at(start + Duration::from_millis(999)));
+
+        let later = start + Duration::from_secs(1);
+        assert!(match retrier.attempt_at(later, fail) {
+            Attempt::Failed(_) => true,
+            _ => false,
+        });
+        assert_eq!(retrier.failures(), 2);
+        assert!(stub::notifications().is_empty());
+
+        match retrier.attempt_at(later + Duration::from_secs(2), fail) {
+            Attempt::GaveUp(e) => assert_eq!(e.attempts, 3),
+            x => panic!("expected to give up, got {:?}", x),
+        }
+        let notifications = stub::notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].severity, Severity::Failure);
+        assert_eq!(
+            notifications[0].message,
+            "giving up after 3 attempts: connection refused"
+        );
+
+        // Having given up, the retrier starts over
+        assert_eq!(retrier.failures(), 0);
+        assert!(match retrier.attempt_at(later, || Ok(5)) {
+            Attempt::Succeeded(5) => true,
+            _ => false,
+        });
+        stub::reset();
+    }
+
+    #[test]
+    fn test_retry_blocking() {
+        let policy = RetryPolicy::new()
+            .initial_delay(Duration::from_millis(1))
+            .max_attempts(3);
+
+        let mut calls = 0;
+        let res = policy.retry(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(Error::from("connection refused"))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(res.unwrap(), 3);
+
+        let err = policy
+            .retry(|| Err::<(), Error>(Error::from("connection refused")))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "giving up after 3 attempts: connection refused");
+    }
+}
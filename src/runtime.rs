@@ -0,0 +1,114 @@
+//! collectd copies the user data of every callback it registers, and calls the user data's free
+//! function once per copy: when the callback is unregistered, when it's replaced by a callback of
+//! the same name, and at shutdown (after the shutdown callbacks, with read callbacks freed first
+//! and log callbacks last). A plugin that hands the same data to its read, write, log, and flush
+//! registrations would otherwise be dropped once per registration, and possibly while one of its
+//! other callbacks is still running.
+//!
+//! A `Registrar` owns a value together with the registrations that it handed the value to, so the
+//! value is dropped exactly once: when the last of them is freed, after which collectd won't
+//! invoke a callback with it anymore.
+
+use bindings::user_data_t;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The value behind the user data, and the number of owners (the registrar and each
+/// registration) that haven't released it yet
+struct Shared<T> {
+    owners: AtomicUsize,
+    value: T,
+}
+
+/// Shares ownership of a value with the collectd registrations that its user data is given to
+pub struct Registrar<T> {
+    shared: *mut Shared<T>,
+}
+
+impl<T> Registrar<T> {
+    pub fn new(value: T) -> Self {
+        let shared = Box::new(Shared {
+            owners: AtomicUsize::new(1),
+            value: value,
+        });
+
+        Registrar {
+            shared: Box::into_raw(shared),
+        }
+    }
+
+    /// User data for another registration, which owns the value until collectd frees it. Should
+    /// the registration fail, the value is kept alive instead of released, as collectd frees the
+    /// user data of some failed registrations but not others.
+    pub fn user_data(&self) -> user_data_t {
+        unsafe { (*self.shared).owners.fetch_add(1, Ordering::Relaxed) };
+        user_data_t {
+            data: self.shared as *mut c_void,
+            free_func: Some(release::<T>),
+        }
+    }
+}
+
+impl<T> Drop for Registrar<T> {
+    fn drop(&mut self) {
+        unsafe { release::<T>(self.shared as *mut c_void) };
+    }
+}
+
+/// Borrows the value of user data created by `Registrar::user_data`
+pub unsafe fn borrow<'a, T>(data: *mut c_void) -> Option<&'a mut T> {
+    (data as *mut Shared<T>).as_mut().map(|x| &mut x.value)
+}
+
+/// The free function of user data created by `Registrar::user_data`, which drops the value once
+/// every owner has released it
+pub unsafe extern "C" fn release<T>(data: *mut c_void) {
+    let shared = data as *mut Shared<T>;
+    if shared.is_null() {
+        return;
+    }
+
+    // Like `Arc`, the last owner must observe every other owner's use of the value
+    if (*shared).owners.fetch_sub(1, Ordering::Release) == 1 {
+        (*shared).owners.load(Ordering::Acquire);
+        drop(Box::from_raw(shared));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Tracked(Rc<Cell<u32>>);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_dropped_after_last_release() {
+        let drops = Rc::new(Cell::new(0));
+        let registrar = Registrar::new(Tracked(drops.clone()));
+        let read = registrar.user_data();
+        let write = registrar.user_data();
+        drop(registrar);
+        assert_eq!(drops.get(), 0);
+
+        unsafe {
+            assert!(borrow::<Tracked>(write.data).is_some());
+            (read.free_func.unwrap())(read.data);
+            assert_eq!(drops.get(), 0);
+            (write.free_func.unwrap())(write.data);
+            release::<Tracked>(::std::ptr::null_mut());
+        }
+        assert_eq!(drops.get(), 1);
+
+        // Without any registrations the registrar is the only owner
+        drop(Registrar::new(Tracked(drops.clone())));
+        assert_eq!(drops.get(), 2);
+    }
+}
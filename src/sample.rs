@@ -0,0 +1,189 @@
+//! Operators shouldn't have to read a plugin's source to learn what it can be configured with.
+//! A config struct that implements `CollectdConfig` (usually with `#[derive(CollectdConfig)]`
+//! from the `derive` feature) can render an example `<Plugin>` block describing every option,
+//! which a plugin can log at init or hand out on request.
+
+use api::plugin_name;
+use std::fmt::Write;
+
+/// An option of a plugin's config
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ConfigField {
+    /// The key as written in collectd's config
+    pub key: &'static str,
+
+    /// The field's type as written in Rust (eg: `Option<u16>`)
+    pub type_: &'static str,
+
+    /// From the field's doc comment
+    pub doc: Option<&'static str>,
+
+    /// The value is written as is into the sample config, so strings should be quoted
+    pub default: Option<&'static str>,
+}
+
+impl ConfigField {
+    /// Returns if the option may be left out of the config
+    pub fn is_optional(&self) -> bool {
+        self.type_.starts_with("Option<") || self.default.is_some()
+    }
+
+    /// Returns if the option may be given several times
+    pub fn is_repeated(&self) -> bool {
+        self.type_.starts_with("Vec<")
+    }
+
+    /// A value to show for an option without a default
+    fn placeholder(&self) -> &'static str {
+        let inner = self.type_
+            .trim_start_matches("Option<")
+            .trim_start_matches("Vec<")
+            .trim_end_matches('>');
+        match inner {
+            "bool" => "true",
+            "f32" | "f64" => "0.0",
+            "u8" | "u16" | "u32" | "u64" | "usize" | "i8" | "i16" | "i32" | "i64" | "isize" => "0",
+            _ => "\"...\"",
+        }
+    }
+}
+
+/// Describes a plugin's config struct
+pub trait CollectdConfig {
+    /// The options of the config, in declaration order
+    fn fields() -> Vec<ConfigField>;
+
+    /// An example `<Plugin>` block for the registered plugin, with each option annotated with its
+    /// documentation and type
+    fn sample_config() -> String {
+        render_sample(plugin_name().unwrap_or("myplugin"), &Self::fields())
+    }
+}
+
+/// Renders an example `<Plugin>` block. Options without a default are commented out, so that the
+/// sample can be pasted into collectd's config as is.
+pub fn render_sample(plugin: &str, fields: &[ConfigField]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "<Plugin \"{}\">", plugin);
+    for (i, field) in fields.iter().enumerate() {
+        if i != 0 {
+            out.push('\n');
+        }
+
+        if let Some(doc) = field.doc {
+            let _ = writeln!(out, "  # {}", doc);
+        }
+
+        let mut notes = vec![field.type_.to_string()];
+        if field.is_repeated() {
+            notes.push(String::from("may be repeated"));
+        }
+        notes.push(String::from(if field.is_optional() {
+            "optional"
+        } else {
+            "required"
+        }));
+        let _ = writeln!(out, "  # {}", notes.join(", "));
+
+        match field.default {
+            Some(default) => {
+                let _ = writeln!(out, "  {} {}", field.key, default);
+            }
+            None => {
+                let _ = writeln!(out, "  #{} {}", field.key, field.placeholder());
+            }
+        }
+    }
+    out.push_str("</Plugin>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StatsdConfig;
+
+    impl CollectdConfig for StatsdConfig {
+        fn fields() -> Vec<ConfigField> {
+            vec![
+                ConfigField {
+                    key: "Address",
+                    type_: "String",
+                    doc: Some("Address to listen on"),
+                    default: Some("\"0.0.0.0\""),
+                },
+                ConfigField {
+                    key: "Port",
+                    type_: "u16",
+                    doc: None,
+                    default: None,
+                },
+                ConfigField {
+                    key: "Prefix",
+                    type_: "Vec<String>",
+                    doc: None,
+                    default: None,
+                },
+            ]
+        }
+    }
+
+    #[test]
+    fn test_render_sample() {
+        assert_eq!(
+            render_sample("statsd", &StatsdConfig::fields()),
+            "<Plugin \"statsd\">\n  \
+             # Address to listen on\n  \
+             # String, optional\n  \
+             Address \"0.0.0.0\"\n\n  \
+             # u16, required\n  \
+             #Port 0\n\n  \
+             # Vec<String>, may be repeated, required\n  \
+             #Prefix \"...\"\n\
+             </Plugin>\n"
+        );
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[test]
+    fn test_derive_fields() {
+        #[derive(CollectdConfig, Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        #[allow(dead_code)]
+        struct Config {
+            /// Address to listen on
+            #[collectd(default = "\"0.0.0.0\"")]
+            address: String,
+            #[serde(rename = "ListenPort", default)]
+            port: Option<u16>,
+            #[serde(skip)]
+            cache: Vec<u8>,
+            flush_interval: ::std::collections::HashMap<String, f64>,
+        }
+
+        assert_eq!(
+            Config::fields(),
+            vec![
+                ConfigField {
+                    key: "Address",
+                    type_: "String",
+                    doc: Some("Address to listen on"),
+                    default: Some("\"0.0.0.0\""),
+                },
+                ConfigField {
+                    key: "ListenPort",
+                    type_: "Option<u16>",
+                    doc: None,
+                    default: None,
+                },
+                ConfigField {
+                    key: "FlushInterval",
+                    type_: "::std::collections::HashMap<String, f64>",
+                    doc: None,
+                    default: None,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Parses the statsd line protocol (`name:value|type|@rate|#tags`), so a read plugin can accept
+//! statsd traffic and feed it into collectd without the C `statsd` plugin.
+//!
+//! ```
+//! use collectd_plugin::statsd::{parse_line, StatsdValue};
+//!
+//! let line = parse_line("api.requests:3|c|@0.5|#route:/users").unwrap();
+//! assert_eq!(line.name, "api.requests");
+//! assert_eq!(line.value, StatsdValue::Counter(3.0));
+//! assert_eq!(line.sample_rate, 0.5);
+//! ```
+
+use api::{Value, ValueListBuilder};
+use errors::Error;
+use std::error;
+use std::fmt;
+
+/// The value of a statsd line, by metric type
+#[derive(Debug, PartialEq, Clone)]
+pub enum StatsdValue {
+    /// `c`: an increment, before accounting for the sample rate
+    Counter(f64),
+    /// `g`: the gauge's new value
+    Gauge(f64),
+    /// `g` with a leading sign: a change to the gauge's previous value
+    GaugeDelta(f64),
+    /// `ms` (or `h`): a duration in milliseconds
+    Timer(f64),
+    /// `s`: a member of the set, for counting unique occurrences
+    Set(String),
+}
+
+/// A parsed statsd line
+#[derive(Debug, PartialEq, Clone)]
+pub struct StatsdLine {
+    pub name: String,
+    pub value: StatsdValue,
+    /// The fraction of events that were sent (`@rate`), 1 when omitted
+    pub sample_rate: f64,
+    /// Dogstatsd style tags (`#key:value,flag`)
+    pub tags: Vec<(String, Option<String>)>,
+}
+
+/// Reasons a statsd line was rejected
+#[derive(Debug, PartialEq, Clone)]
+pub enum StatsdError {
+    /// The line has no `name:value` pair
+    MissingValue(String),
+    /// The line has no metric type
+    MissingType(String),
+    InvalidValue(String),
+    InvalidType(String),
+    /// The sample rate isn't within (0, 1]
+    InvalidSampleRate(String),
+}
+
+impl fmt::Display for StatsdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StatsdError::MissingValue(ref line) => write!(f, "statsd line has no value: {}", line),
+            StatsdError::MissingType(ref line) => write!(f, "statsd line has no type: {}", line),
+            StatsdError::InvalidValue(ref x) => write!(f, "invalid statsd value: {}", x),
+            StatsdError::InvalidType(ref x) => write!(f, "invalid statsd type: {}", x),
+            StatsdError::InvalidSampleRate(ref x) => {
+                write!(f, "invalid statsd sample rate: {}", x)
+            }
+        }
+    }
+}
+
+impl error::Error for StatsdError {}
+
+fn parse_number(s: &str) -> Result<f64, StatsdError> {
+    match s.parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(x),
+        _ => Err(StatsdError::InvalidValue(String::from(s))),
+    }
+}
+
+fn parse_tags(s: &str) -> Vec<(String, Option<String>)> {
+    s.split(',')
+        .filter(|x| !x.is_empty())
+        .map(|tag| match tag.find(':') {
+            Some(i) => (String::from(&tag[..i]), Some(String::from(&tag[i + 1..]))),
+            None => (String::from(tag), None),
+        })
+        .collect()
+}
+
+/// Parses a single statsd line. Trailing whitespace (eg: a carriage return) is ignored.
+pub fn parse_line(line: &str) -> Result<StatsdLine, StatsdError> {
+    let line = line.trim_end();
+    let mut fields = line.split('|');
+    let metric = fields.next().unwrap_or("");
+
+    // Names may contain colons, values never do
+    let (name, value) = match metric.rfind(':') {
+        Some(i) if i != 0 => (&metric[..i], &metric[i + 1..]),
+        _ => return Err(StatsdError::MissingValue(String::from(line))),
+    };
+
+    let value = match fields.next() {
+        None | Some("") => return Err(StatsdError::MissingType(String::from(line))),
+        Some("c") => StatsdValue::Counter(parse_number(value)?),
+        Some("g") if value.starts_with('+') || value.starts_with('-') => {
+            StatsdValue::GaugeDelta(parse_number(value)?)
+        }
+        Some("g") => StatsdValue::Gauge(parse_number(value)?),
+        Some("ms") | Some("h") => StatsdValue::Timer(parse_number(value)?),
+        Some("s") => StatsdValue::Set(String::from(value)),
+        Some(x) => return Err(StatsdError::InvalidType(String::from(x))),
+    };
+
+    let mut sample_rate = 1.0;
+    let mut tags = Vec::new();
+    for field in fields {
+        if field.starts_with('@') {
+            sample_rate = match field[1..].parse::<f64>() {
+                Ok(x) if x > 0.0 && x <= 1.0 => x,
+                _ => return Err(StatsdError::InvalidSampleRate(String::from(&field[1..]))),
+            };
+        } else if field.starts_with('#') {
+            tags.extend(parse_tags(&field[1..]));
+        }
+    }
+
+    Ok(StatsdLine {
+        name: String::from(name),
+        value: value,
+        sample_rate: sample_rate,
+        tags: tags,
+    })
+}
+
+/// Parses each non-empty line of a packet. A bad line doesn't affect the others, as statsd
+/// clients batch unrelated metrics into one packet.
+pub fn parse_packet(packet: &str) -> Vec<Result<StatsdLine, StatsdError>> {
+    packet
+        .lines()
+        .filter(|x| !x.trim().is_empty())
+        .map(parse_line)
+        .collect()
+}
+
+impl StatsdLine {
+    /// The collectd type and value for the line, named like the C `statsd` plugin's types:
+    ///
+    /// - Counters are a `count`, scaled up by the sample rate
+    /// - Gauges are a `gauge`
+    /// - Timers are a `latency` in seconds
+    ///
+    /// Gauge deltas and set members depend on earlier lines, so they have no value on their own
+    pub fn value(&self) -> Option<(&'static str, Value)> {
+        match self.value {
+            StatsdValue::Counter(x) => Some(("count", Value::Gauge(x / self.sample_rate))),
+            StatsdValue::Gauge(x) => Some(("gauge", Value::Gauge(x))),
+            StatsdValue::Timer(x) => Some(("latency", Value::Gauge(x / 1000.0))),
+            StatsdValue::GaugeDelta(_) | StatsdValue::Set(_) => None,
+        }
+    }
+
+    /// The tags as `key=value` pairs sorted by key, for use as a plugin instance
+    pub fn plugin_instance(&self) -> String {
+        let mut tags: Vec<String> = self.tags
+            .iter()
+            .map(|&(ref key, ref value)| match *value {
+                Some(ref value) => format!("{}={}", key, value),
+                None => key.clone(),
+            })
+            .collect();
+        tags.sort();
+        tags.join(",")
+    }
+
+    /// Submits the line's value (if it has one) under the plugin, with the metric's name as the
+    /// type instance and its tags as the plugin instance
+    pub fn submit(&self, plugin: &str) -> Result<(), Error> {
+        let (type_, value) = match self.value() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let values = [value];
+        let instance = self.plugin_instance();
+        let mut builder = ValueListBuilder::new(plugin, type_)
+            .type_instance(self.name.as_str())
+            .values(&values);
+        if !instance.is_empty() {
+            builder = builder.plugin_instance(instance.as_str());
+        }
+
+        builder.submit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line("db.query:12.5|ms|#host:web-1,canary\r"),
+            Ok(StatsdLine {
+                name: String::from("db.query"),
+                value: StatsdValue::Timer(12.5),
+                sample_rate: 1.0,
+                tags: vec![
+                    (String::from("host"), Some(String::from("web-1"))),
+                    (String::from("canary"), None),
+                ],
+            })
+        );
+
+        assert_eq!(parse_line("temp:-3|g").unwrap().value, StatsdValue::GaugeDelta(-3.0));
+        assert_eq!(parse_line("temp:3|g").unwrap().value, StatsdValue::Gauge(3.0));
+        assert_eq!(
+            parse_line("users:alice|s").unwrap().value,
+            StatsdValue::Set(String::from("alice"))
+        );
+        assert_eq!(parse_line("a:b:1|c").unwrap().name, "a:b");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_line("requests|c"),
+            Err(StatsdError::MissingValue(String::from("requests|c")))
+        );
+        assert_eq!(
+            parse_line("requests:1"),
+            Err(StatsdError::MissingType(String::from("requests:1")))
+        );
+        assert_eq!(
+            parse_line("requests:one|c"),
+            Err(StatsdError::InvalidValue(String::from("one")))
+        );
+        assert_eq!(
+            parse_line("requests:1|x"),
+            Err(StatsdError::InvalidType(String::from("x")))
+        );
+        assert_eq!(
+            parse_line("requests:1|c|@2"),
+            Err(StatsdError::InvalidSampleRate(String::from("2")))
+        );
+
+        let parsed = parse_packet("a:1|c\n\nb:1|zz\nc:2|g\n");
+        assert_eq!(parsed.len(), 3);
+        assert!(parsed[1].is_err());
+        assert_eq!(parsed[2].as_ref().unwrap().name, "c");
+    }
+
+    #[test]
+    fn test_submit() {
+        testing::reset();
+        let line = parse_line("requests:3|c|@0.5|#route:/,method:GET").unwrap();
+        line.submit("statsd").unwrap();
+        parse_line("temp:+1|g").unwrap().submit("statsd").unwrap();
+        parse_line("query:250|ms").unwrap().submit("statsd").unwrap();
+
+        testing::assert_submitted("statsd", "count", &[Value::Gauge(6.0)]);
+        testing::assert_submitted("statsd", "latency", &[Value::Gauge(0.25)]);
+        let dispatched = testing::dispatched();
+        assert_eq!(dispatched.len(), 2);
+        assert_eq!(
+            dispatched[0].plugin_instance,
+            Some(String::from("method=GET,route=/"))
+        );
+        assert_eq!(dispatched[0].type_instance, Some(String::from("requests")));
+    }
+}
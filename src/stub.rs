@@ -0,0 +1,1517 @@
+//! Recording stand-ins for collectd's symbols, enabled by the `stub` feature (and always enabled
+//! for this crate's own tests). Instead of linking against a collectd daemon, dispatched values,
+//! log messages, and callback registrations are recorded (and metadata lists are kept in memory)
+//! so that plugin code can be compiled and exercised on machines (like CI) without collectd
+//! installed.
+//!
+//! Log messages are also written to stderr with their severity (eg: `[warning] ...`) so that
+//! plugin code ran outside of collectd, like from `cargo run`, is debuggable. Disable this with
+//! `echo_logs(false)`.
+//!
+//! Records are kept per thread, which keeps tests that run in parallel isolated from each other.
+//! Values dispatched from threads spawned by the plugin are recorded on those threads.
+//!
+//! Do not load a library built with the `stub` feature into collectd, as these definitions will
+//! shadow collectd's own.
+
+#![allow(non_upper_case_globals)]
+
+#[cfg(collectd_57)]
+use bindings::uc_iter_t;
+use bindings::{cdtime_t, data_set_t, data_source_t, gauge_t, match_proc_t, meta_data_t,
+               notification_meta_s__bindgen_ty_1, notification_meta_t, notification_t,
+               oconfig_item_t, plugin_ctx_t, plugin_flush_cb, plugin_init_cb, plugin_log_cb,
+               plugin_notification_cb, plugin_read_cb, plugin_shutdown_cb, plugin_write_cb,
+               target_proc_t, user_data_t, value_list_t, value_t, ARR_LENGTH};
+use api::{empty_to_none, from_array, length, to_array_res, MetaValue, RecvNotification};
+use constants::{DataSourceType, Severity, LOG_DEBUG, LOG_ERR, LOG_INFO, LOG_NOTICE, LOG_WARNING,
+                MD_TYPE_BOOLEAN, MD_TYPE_DOUBLE, MD_TYPE_SIGNED_INT, MD_TYPE_STRING,
+                MD_TYPE_UNSIGNED_INT, NM_TYPE_BOOLEAN, NM_TYPE_DOUBLE, NM_TYPE_SIGNED_INT,
+                NM_TYPE_STRING, NM_TYPE_UNSIGNED_INT};
+use std::collections::HashMap;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(collectd_57)]
+type Interval = cdtime_t;
+
+#[cfg(not(collectd_57))]
+type Interval = *const ::bindings::timespec;
+
+#[cfg(collectd_57)]
+type UserData = *const user_data_t;
+
+#[cfg(not(collectd_57))]
+type UserData = *mut user_data_t;
+
+type ComplexConfigCb = Option<unsafe extern "C" fn(arg1: *mut oconfig_item_t) -> c_int>;
+type SimpleConfigCb = Option<unsafe extern "C" fn(*const c_char, *const c_char) -> c_int>;
+
+/// A value list that was passed to `plugin_dispatch_values`. As there is no types.db to consult,
+/// values are kept as their raw 64 bits and can be interpreted with the typed accessors.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DispatchedValueList {
+    pub plugin: String,
+    pub plugin_instance: Option<String>,
+    pub type_: String,
+    pub type_instance: Option<String>,
+    pub host: Option<String>,
+    pub time: cdtime_t,
+    pub interval: cdtime_t,
+    raw: Vec<u64>,
+}
+
+impl DispatchedValueList {
+    /// Number of values in the list
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// Interprets each value as a gauge
+    pub fn gauges(&self) -> Vec<f64> {
+        self.raw.iter().map(|&x| f64::from_bits(x)).collect()
+    }
+
+    /// Interprets each value as a counter or absolute
+    pub fn counters(&self) -> Vec<u64> {
+        self.raw.clone()
+    }
+
+    /// Interprets each value as a derive
+    pub fn derives(&self) -> Vec<i64> {
+        self.raw.iter().map(|&x| x as i64).collect()
+    }
+}
+
+/// A notification that was passed to `plugin_dispatch_notification`
+#[derive(Debug, PartialEq, Clone)]
+pub struct DispatchedNotification {
+    pub severity: Severity,
+    pub message: String,
+    pub plugin: String,
+    pub plugin_instance: Option<String>,
+    pub type_: Option<String>,
+    pub type_instance: Option<String>,
+    pub host: Option<String>,
+    pub time: cdtime_t,
+    pub meta: HashMap<String, MetaValue>,
+}
+
+/// A message that was passed to `plugin_log`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LoggedMessage {
+    pub severity: c_int,
+    pub message: String,
+}
+
+/// The kind of callback that was registered
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CallbackKind {
+    Config,
+    SimpleConfig,
+    Init,
+    Read,
+    Write,
+    Log,
+    Flush,
+    Match,
+    Target,
+    Notification,
+    Shutdown,
+}
+
+#[derive(Clone, Copy)]
+enum Callback {
+    Config(ComplexConfigCb),
+    SimpleConfig(SimpleConfigCb),
+    Init(plugin_init_cb),
+    Read(plugin_read_cb),
+    Write(plugin_write_cb),
+    Log(plugin_log_cb),
+    Flush(plugin_flush_cb),
+    Match(match_proc_t),
+    Target(target_proc_t),
+    Notification(plugin_notification_cb),
+    Shutdown(plugin_shutdown_cb),
+}
+
+struct Registration {
+    name: String,
+    callback: Callback,
+
+    /// Boxed so that, like collectd, callbacks are passed a pointer to the stored user data
+    user_data: Option<Box<user_data_t>>,
+
+    /// The interval of a read callback, or zero for the default interval
+    interval: cdtime_t,
+}
+
+impl Registration {
+    fn kind(&self) -> CallbackKind {
+        match self.callback {
+            Callback::Config(_) => CallbackKind::Config,
+            Callback::SimpleConfig(_) => CallbackKind::SimpleConfig,
+            Callback::Init(_) => CallbackKind::Init,
+            Callback::Read(_) => CallbackKind::Read,
+            Callback::Write(_) => CallbackKind::Write,
+            Callback::Log(_) => CallbackKind::Log,
+            Callback::Flush(_) => CallbackKind::Flush,
+            Callback::Match(_) => CallbackKind::Match,
+            Callback::Target(_) => CallbackKind::Target,
+            Callback::Notification(_) => CallbackKind::Notification,
+            Callback::Shutdown(_) => CallbackKind::Shutdown,
+        }
+    }
+}
+
+/// A type defined with `define_type`, standing in for an entry of collectd's types.db
+struct StubType {
+    set: Box<data_set_t>,
+
+    /// Owns the data sources that `set` points to
+    _sources: Vec<data_source_t>,
+}
+
+/// The latest values dispatched for an identifier, as kept in collectd's cache
+#[cfg(collectd_57)]
+#[derive(Clone)]
+struct CachedList {
+    name: CString,
+    time: cdtime_t,
+    interval: cdtime_t,
+    values: Vec<value_t>,
+}
+
+/// Stand-in for collectd's `uc_iter_t`: a copy of the cache when the iterator was created
+#[cfg(collectd_57)]
+struct StubIter {
+    entries: Vec<CachedList>,
+    current: Option<CachedList>,
+}
+
+#[derive(Default)]
+struct Recorder {
+    dispatched: Vec<DispatchedValueList>,
+    notifications: Vec<DispatchedNotification>,
+    logged: Vec<LoggedMessage>,
+    registrations: Vec<Registration>,
+
+    /// Unregistered callbacks, whose user data is freed on `reset`. collectd defers freeing the
+    /// user data of a read callback that is running when it is unregistered.
+    unregistered: Vec<Registration>,
+
+    types: HashMap<String, StubType>,
+
+    /// The options that each simple config callback registered for
+    config_keys: HashMap<String, Vec<String>>,
+
+    /// The rates that `uc_get_rate` reports, by the name of the value list
+    rates: HashMap<String, Vec<f64>>,
+
+    #[cfg(collectd_57)]
+    cache: ::std::collections::BTreeMap<String, CachedList>,
+}
+
+static ECHO_LOGS: AtomicBool = AtomicBool::new(true);
+
+thread_local! {
+    static RECORDER: RefCell<Recorder> = RefCell::new(Recorder::default());
+    static CONTEXT: RefCell<plugin_ctx_t> = RefCell::new(unsafe { ::std::mem::zeroed() });
+}
+
+/// Value lists dispatched on this thread so far
+pub fn dispatched() -> Vec<DispatchedValueList> {
+    RECORDER.with(|r| r.borrow().dispatched.clone())
+}
+
+/// Notifications dispatched on this thread so far
+pub fn notifications() -> Vec<DispatchedNotification> {
+    RECORDER.with(|r| r.borrow().notifications.clone())
+}
+
+/// Messages logged on this thread so far
+pub fn logged() -> Vec<LoggedMessage> {
+    RECORDER.with(|r| r.borrow().logged.clone())
+}
+
+/// The names and kinds of every callback registered on this thread so far
+pub fn registrations() -> Vec<(String, CallbackKind)> {
+    RECORDER.with(|r| {
+        r.borrow()
+            .registrations
+            .iter()
+            .map(|x| (x.name.clone(), x.kind()))
+            .collect()
+    })
+}
+
+/// Defines a type (as if it was found in types.db) with the given data sources, which are
+/// unbounded. Types are looked up by `plugin_get_ds`, for instance to decode collectd's cache.
+pub fn define_type(name: &str, sources: &[(&str, DataSourceType)]) {
+    let mut sources: Vec<data_source_t> = sources
+        .iter()
+        .map(|&(name, type_)| data_source_t {
+            name: to_array_res(name).expect("data source name"),
+            type_: type_.as_raw(),
+            min: f64::NAN,
+            max: f64::NAN,
+        })
+        .collect();
+
+    let set = Box::new(data_set_t {
+        type_: to_array_res(name).expect("type name"),
+        ds_num: sources.len() as _,
+        ds: sources.as_mut_ptr(),
+    });
+
+    RECORDER.with(|r| {
+        r.borrow_mut().types.insert(
+            String::from(name),
+            StubType {
+                set: set,
+                _sources: sources,
+            },
+        )
+    });
+}
+
+/// Sets the rates that collectd's cache reports for the identifier (eg:
+/// `localhost/interface-eth0/if_octets`). collectd computes rates from the previous value of each
+/// non-gauge data source, and reports gauges as they are.
+pub fn set_rates(identifier: &str, rates: &[f64]) {
+    RECORDER.with(|r| {
+        r.borrow_mut()
+            .rates
+            .insert(String::from(identifier), rates.to_vec())
+    });
+}
+
+/// Sets whether log messages are written to stderr (the default) in addition to being recorded.
+/// Applies to all threads.
+pub fn echo_logs(enabled: bool) {
+    ECHO_LOGS.store(enabled, Ordering::SeqCst);
+}
+
+/// Forgets all dispatched values (including the cache) and log messages, but keeps registrations
+/// and types
+pub fn clear() {
+    RECORDER.with(|r| {
+        let mut r = r.borrow_mut();
+        r.dispatched.clear();
+        r.notifications.clear();
+        r.logged.clear();
+        r.rates.clear();
+        #[cfg(collectd_57)]
+        r.cache.clear();
+    })
+}
+
+/// Forgets everything that has been recorded. Like collectd does on shutdown, the user data of
+/// every registration is freed, even if several registrations were given the same user data.
+pub fn reset() {
+    let registrations = RECORDER.with(|r| {
+        let mut r = r.borrow_mut();
+        r.dispatched.clear();
+        r.notifications.clear();
+        r.logged.clear();
+        r.types.clear();
+        r.config_keys.clear();
+        r.rates.clear();
+        #[cfg(collectd_57)]
+        r.cache.clear();
+        let mut registrations = ::std::mem::replace(&mut r.registrations, Vec::new());
+        registrations.append(&mut r.unregistered);
+        registrations
+    });
+
+    for reg in registrations {
+        if let Some(data) = reg.user_data {
+            if let Some(free) = data.free_func {
+                unsafe { free(data.data) };
+            }
+        }
+    }
+}
+
+fn find(name: &str, kind: CallbackKind) -> Option<(Callback, Option<*mut user_data_t>)> {
+    RECORDER.with(|r| {
+        r.borrow_mut()
+            .registrations
+            .iter_mut()
+            .filter(|x| x.name == name && x.kind() == kind)
+            .map(|x| {
+                let data = x.user_data.as_mut().map(|x| &mut **x as *mut user_data_t);
+                (x.callback, data)
+            })
+            .last()
+    })
+}
+
+/// The interval that the read callback registered under the given name was registered with,
+/// which is zero for collectd's global interval
+pub fn read_interval(name: &str) -> Option<cdtime_t> {
+    RECORDER.with(|r| {
+        r.borrow()
+            .registrations
+            .iter()
+            .filter(|x| x.name == name && x.kind() == CallbackKind::Read)
+            .map(|x| x.interval)
+            .last()
+    })
+}
+
+/// Invokes every registered init callback in registration order and returns their results
+pub fn init() -> Vec<c_int> {
+    let callbacks: Vec<plugin_init_cb> = RECORDER.with(|r| {
+        r.borrow()
+            .registrations
+            .iter()
+            .filter_map(|x| match x.callback {
+                Callback::Init(cb) => Some(cb),
+                _ => None,
+            })
+            .collect()
+    });
+
+    callbacks
+        .into_iter()
+        .filter_map(|cb| cb.map(|f| unsafe { f() }))
+        .collect()
+}
+
+/// Invokes every registered shutdown callback in registration order and returns their results.
+/// User data is freed by `reset`, which collectd does after the shutdown callbacks.
+pub fn shutdown() -> Vec<c_int> {
+    let callbacks: Vec<plugin_shutdown_cb> = RECORDER.with(|r| {
+        r.borrow()
+            .registrations
+            .iter()
+            .filter_map(|x| match x.callback {
+                Callback::Shutdown(cb) => Some(cb),
+                _ => None,
+            })
+            .collect()
+    });
+
+    callbacks
+        .into_iter()
+        .filter_map(|cb| cb.map(|f| unsafe { f() }))
+        .collect()
+}
+
+/// Invokes the read callback registered under the given name, returning `None` if there isn't one
+pub fn read(name: &str) -> Option<c_int> {
+    match find(name, CallbackKind::Read) {
+        Some((Callback::Read(Some(f)), Some(data))) => Some(unsafe { f(data) }),
+        _ => None,
+    }
+}
+
+/// Invokes the write callback registered under the given name, returning `None` if there isn't
+/// one
+pub fn write(name: &str, ds: &data_set_t, vl: &value_list_t) -> Option<c_int> {
+    match find(name, CallbackKind::Write) {
+        Some((Callback::Write(Some(f)), Some(data))) => Some(unsafe { f(ds, vl, data) }),
+        _ => None,
+    }
+}
+
+/// Invokes the log callback registered under the given name, returning `false` if there isn't one
+pub fn log(name: &str, severity: c_int, message: &CStr) -> bool {
+    match find(name, CallbackKind::Log) {
+        Some((Callback::Log(Some(f)), Some(data))) => {
+            unsafe { f(severity, message.as_ptr(), data) };
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Invokes the flush callback registered under the given name, returning `None` if there isn't
+/// one. Like collectd, a null identifier is passed when no identifier is given.
+pub fn flush(name: &str, timeout: cdtime_t, identifier: Option<&CStr>) -> Option<c_int> {
+    match find(name, CallbackKind::Flush) {
+        Some((Callback::Flush(Some(f)), Some(data))) => {
+            let ptr = identifier.map(|x| x.as_ptr()).unwrap_or_else(ptr::null);
+            Some(unsafe { f(timeout, ptr, data) })
+        }
+        _ => None,
+    }
+}
+
+/// Invokes the config callback registered under the given name, returning `None` if there isn't
+/// one
+///
+/// # Safety
+///
+/// The item is decoded by the callback, so its key, values, and children must point to valid
+/// config items, as they do in a block built by collectd.
+pub unsafe fn config(name: &str, item: &oconfig_item_t) -> Option<c_int> {
+    match find(name, CallbackKind::Config) {
+        Some((Callback::Config(Some(f)), _)) => {
+            Some(f(item as *const oconfig_item_t as *mut oconfig_item_t))
+        }
+        _ => None,
+    }
+}
+
+/// Passes an option to the simple config callback registered under the given name, like collectd
+/// does for options of a `<Plugin>` block (whose values collectd joins into one string). Like
+/// collectd, options that the callback didn't register for are rejected with -1.
+pub fn simple_config(name: &str, key: &str, value: &str) -> Option<c_int> {
+    let registered = RECORDER.with(|r| {
+        r.borrow()
+            .config_keys
+            .get(name)
+            .map(|keys| keys.iter().any(|x| x.eq_ignore_ascii_case(key)))
+    });
+
+    match (find(name, CallbackKind::SimpleConfig), registered) {
+        (Some((Callback::SimpleConfig(Some(f)), _)), Some(true)) => {
+            let key = CString::new(key).expect("key to not contain nulls");
+            let value = CString::new(value).expect("value to not contain nulls");
+            Some(unsafe { f(key.as_ptr(), value.as_ptr()) })
+        }
+        (Some(_), _) => Some(-1),
+        (None, _) => None,
+    }
+}
+
+/// Runs a value list through the filter chain match registered under the given name, like
+/// collectd does for a `<Match "name">` block: the match is created from the block, invoked, and
+/// destroyed. Returns `None` if there is no such match, otherwise the result of creation (if it
+/// failed) or of the match.
+///
+/// # Safety
+///
+/// The block and the value list are decoded by the match, so their pointers must be valid, as they
+/// are in the blocks and value lists that collectd passes.
+pub unsafe fn filter_match(
+    name: &str,
+    block: &oconfig_item_t,
+    ds: &data_set_t,
+    vl: &value_list_t,
+) -> Option<c_int> {
+    match find(name, CallbackKind::Match) {
+        Some((Callback::Match(procs), _)) => {
+            let mut data: *mut c_void = ptr::null_mut();
+            let created = procs.create.map(|f| f(block, &mut data)).unwrap_or(0);
+            if created != 0 {
+                return Some(created);
+            }
+
+            let res = procs
+                .match_
+                .map(|f| f(ds, vl, ptr::null_mut(), &mut data))
+                .unwrap_or(-1);
+            if let Some(f) = procs.destroy {
+                f(&mut data);
+            }
+            Some(res)
+        }
+        _ => None,
+    }
+}
+
+/// Passes a value list to the filter chain target registered under the given name, like collectd
+/// does for a `<Target "name">` block: the target is created from the block, invoked, and
+/// destroyed. Returns `None` if there is no such target, otherwise the result of creation (if it
+/// failed) or of the target.
+///
+/// # Safety
+///
+/// The block and the value list are decoded by the target, so their pointers must be valid, as
+/// they are in the blocks and value lists that collectd passes.
+pub unsafe fn filter_target(
+    name: &str,
+    block: &oconfig_item_t,
+    ds: &data_set_t,
+    vl: &mut value_list_t,
+) -> Option<c_int> {
+    match find(name, CallbackKind::Target) {
+        Some((Callback::Target(procs), _)) => {
+            let mut data: *mut c_void = ptr::null_mut();
+            let created = procs.create.map(|f| f(block, &mut data)).unwrap_or(0);
+            if created != 0 {
+                return Some(created);
+            }
+
+            let res = procs
+                .invoke
+                .map(|f| f(ds, vl, ptr::null_mut(), &mut data))
+                .unwrap_or(-1);
+            if let Some(f) = procs.destroy {
+                f(&mut data);
+            }
+            Some(res)
+        }
+        _ => None,
+    }
+}
+
+unsafe fn register(
+    name: *const c_char,
+    callback: Callback,
+    user_data: Option<user_data_t>,
+) -> c_int {
+    let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+    RECORDER.with(|r| {
+        r.borrow_mut().registrations.push(Registration {
+            name: name,
+            callback: callback,
+            user_data: user_data.map(Box::new),
+            interval: 0,
+        })
+    });
+    0
+}
+
+unsafe fn copy_user_data(data: UserData) -> Option<user_data_t> {
+    if data.is_null() {
+        None
+    } else {
+        Some(*data)
+    }
+}
+
+/// Formats a log message like collectd's logfile plugin does with `PrintSeverity true`
+fn stderr_line(level: c_int, message: &str) -> String {
+    let severity = match level {
+        LOG_ERR => "error",
+        LOG_WARNING => "warning",
+        LOG_NOTICE => "notice",
+        LOG_INFO => "info",
+        LOG_DEBUG => "debug",
+        _ => "unknown",
+    };
+
+    format!("[{}] {}", severity, message)
+}
+
+fn text(arr: &[c_char; ARR_LENGTH]) -> String {
+    String::from(from_array(arr).unwrap_or(""))
+}
+
+#[export_name = "hostname_g"]
+pub static mut stub_hostname_g: [c_char; ARR_LENGTH] = [0; ARR_LENGTH];
+
+/// collectd's default interval of 10 seconds
+#[export_name = "interval_g"]
+pub static mut stub_interval_g: cdtime_t = 10 << 30;
+
+/// collectd's default timeout of 2 intervals
+#[export_name = "timeout_g"]
+pub static mut stub_timeout_g: c_int = 2;
+
+#[export_name = "plugin_get_ctx"]
+pub extern "C" fn stub_plugin_get_ctx() -> plugin_ctx_t {
+    CONTEXT.with(|c| *c.borrow())
+}
+
+#[export_name = "plugin_set_ctx"]
+pub extern "C" fn stub_plugin_set_ctx(ctx: plugin_ctx_t) -> plugin_ctx_t {
+    CONTEXT.with(|c| ::std::mem::replace(&mut *c.borrow_mut(), ctx))
+}
+
+/// Like collectd, the interval of the thread's context or else the global interval
+#[export_name = "plugin_get_interval"]
+pub extern "C" fn stub_plugin_get_interval() -> cdtime_t {
+    match stub_plugin_get_ctx().interval {
+        0 => unsafe { stub_interval_g },
+        x => x,
+    }
+}
+
+#[export_name = "plugin_log"]
+pub unsafe extern "C" fn stub_plugin_log(level: c_int, format: *const c_char) {
+    // `collectd_log` passes the message as the format without arguments
+    let message = CStr::from_ptr(format).to_string_lossy().into_owned();
+    if ECHO_LOGS.load(Ordering::SeqCst) {
+        // eprintln (unlike writing to io::stderr) is captured by the test harness
+        eprintln!("{}", stderr_line(level, &message));
+    }
+
+    RECORDER.with(|r| {
+        r.borrow_mut().logged.push(LoggedMessage {
+            severity: level,
+            message: message,
+        })
+    });
+}
+
+#[export_name = "plugin_dispatch_values"]
+pub unsafe extern "C" fn stub_plugin_dispatch_values(vl: *const value_list_t) -> c_int {
+    let list = &*vl;
+    let values: &[value_t] = slice::from_raw_parts(list.values, length(list.values_len));
+    let host = text(&list.host);
+    let entry = DispatchedValueList {
+        plugin: text(&list.plugin),
+        plugin_instance: empty_to_none(&text(&list.plugin_instance)).map(String::from),
+        type_: text(&list.type_),
+        type_instance: empty_to_none(&text(&list.type_instance)).map(String::from),
+        host: empty_to_none(&host).map(String::from),
+        time: list.time,
+        interval: list.interval,
+        raw: values.iter().map(|x| x.counter).collect(),
+    };
+
+    #[cfg(collectd_57)]
+    let cached = CachedList {
+        name: CString::new(cache_name(list)).unwrap_or_default(),
+        time: list.time,
+        interval: list.interval,
+        values: values.to_vec(),
+    };
+
+    RECORDER.with(|r| {
+        let mut r = r.borrow_mut();
+        r.dispatched.push(entry);
+        #[cfg(collectd_57)]
+        {
+            let key = cached.name.to_string_lossy().into_owned();
+            r.cache.insert(key, cached);
+        }
+    });
+    0
+}
+
+/// Like collectd, hands the value list to the write callback registered under the given name,
+/// looking up the data set of the list's type if none is given
+#[export_name = "plugin_write"]
+pub unsafe extern "C" fn stub_plugin_write(
+    plugin: *const c_char,
+    ds: *const data_set_t,
+    vl: *const value_list_t,
+) -> c_int {
+    // collectd returns ENOENT for unknown plugins and types
+    let enoent = 2;
+    if plugin.is_null() || vl.is_null() {
+        return enoent;
+    }
+
+    let ds = if ds.is_null() {
+        stub_plugin_get_ds((*vl).type_.as_ptr())
+    } else {
+        ds
+    };
+
+    let name = CStr::from_ptr(plugin).to_string_lossy().into_owned();
+    match (ds.as_ref(), find(&name, CallbackKind::Write)) {
+        (Some(ds), Some((Callback::Write(Some(f)), Some(data)))) => f(ds, vl, data),
+        _ => enoent,
+    }
+}
+
+/// The name that collectd's cache knows the value list by, which defaults to collectd's hostname
+unsafe fn cache_name(list: &value_list_t) -> String {
+    let mut name = match text(&list.host) {
+        ref host if host.is_empty() => text(&stub_hostname_g),
+        host => host,
+    };
+
+    for &(sep, ref field) in [
+        ("/", &list.plugin),
+        ("-", &list.plugin_instance),
+        ("/", &list.type_),
+        ("-", &list.type_instance),
+    ].iter()
+    {
+        let field = text(field);
+        if !field.is_empty() {
+            name.push_str(sep);
+            name.push_str(&field);
+        }
+    }
+    name
+}
+
+#[export_name = "uc_get_rate"]
+pub unsafe extern "C" fn stub_uc_get_rate(
+    ds: *const data_set_t,
+    vl: *const value_list_t,
+) -> *mut gauge_t {
+    let rates = match RECORDER.with(|r| r.borrow().rates.get(&cache_name(&*vl)).cloned()) {
+        Some(x) => x,
+        None => return ptr::null_mut(),
+    };
+
+    let len = length((*ds).ds_num);
+    let ret = malloc(len.max(1) * ::std::mem::size_of::<gauge_t>()) as *mut gauge_t;
+    for i in 0..len {
+        *ret.add(i) = rates.get(i).cloned().unwrap_or(f64::NAN);
+    }
+    ret
+}
+
+/// Like collectd, the values are copied into memory that the caller frees. Only collectd 5.7's
+/// stub has a cache, so older versions don't find any values.
+#[export_name = "uc_get_value_by_name"]
+pub unsafe extern "C" fn stub_uc_get_value_by_name(
+    name: *const c_char,
+    ret_values: *mut *mut value_t,
+    ret_values_num: *mut usize,
+) -> c_int {
+    #[cfg(collectd_57)]
+    {
+        let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+        let cached = RECORDER.with(|r| r.borrow().cache.get(&name).map(|x| x.values.clone()));
+        let values = match cached {
+            Some(x) => x,
+            None => return -1,
+        };
+
+        let len = values.len();
+        let ret = malloc(len.max(1) * ::std::mem::size_of::<value_t>()) as *mut value_t;
+        ptr::copy_nonoverlapping(values.as_ptr(), ret, len);
+        *ret_values = ret;
+        *ret_values_num = len;
+        0
+    }
+
+    #[cfg(not(collectd_57))]
+    {
+        let _ = (name, ret_values, ret_values_num);
+        -1
+    }
+}
+
+#[export_name = "plugin_get_ds"]
+pub unsafe extern "C" fn stub_plugin_get_ds(name: *const c_char) -> *const data_set_t {
+    let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+    RECORDER.with(|r| match r.borrow().types.get(&name) {
+        Some(t) => &*t.set as *const data_set_t,
+        None => ptr::null(),
+    })
+}
+
+#[cfg(collectd_57)]
+#[export_name = "uc_get_iterator"]
+pub extern "C" fn stub_uc_get_iterator() -> *mut uc_iter_t {
+    let entries = RECORDER.with(|r| r.borrow().cache.values().cloned().collect());
+    Box::into_raw(Box::new(StubIter {
+        entries: entries,
+        current: None,
+    })) as *mut uc_iter_t
+}
+
+#[cfg(collectd_57)]
+unsafe fn stub_iter<'a>(iter: *mut uc_iter_t) -> Option<&'a mut StubIter> {
+    (iter as *mut StubIter).as_mut()
+}
+
+#[cfg(collectd_57)]
+#[export_name = "uc_iterator_next"]
+pub unsafe extern "C" fn stub_uc_iterator_next(
+    iter: *mut uc_iter_t,
+    ret_name: *mut *mut c_char,
+) -> c_int {
+    let iter = match stub_iter(iter) {
+        Some(x) => x,
+        None => return -1,
+    };
+
+    if iter.entries.is_empty() {
+        iter.current = None;
+        return -1;
+    }
+
+    let current = iter.entries.remove(0);
+    if !ret_name.is_null() {
+        *ret_name = current.name.as_ptr() as *mut c_char;
+    }
+    iter.current = Some(current);
+    0
+}
+
+#[cfg(collectd_57)]
+#[export_name = "uc_iterator_destroy"]
+pub unsafe extern "C" fn stub_uc_iterator_destroy(iter: *mut uc_iter_t) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter as *mut StubIter));
+    }
+}
+
+#[cfg(collectd_57)]
+unsafe fn current<'a>(iter: *mut uc_iter_t) -> Option<&'a CachedList> {
+    stub_iter(iter).and_then(|x| x.current.as_ref())
+}
+
+#[cfg(collectd_57)]
+#[export_name = "uc_iterator_get_time"]
+pub unsafe extern "C" fn stub_uc_iterator_get_time(
+    iter: *mut uc_iter_t,
+    ret_time: *mut cdtime_t,
+) -> c_int {
+    match current(iter) {
+        Some(entry) if !ret_time.is_null() => {
+            *ret_time = entry.time;
+            0
+        }
+        _ => -1,
+    }
+}
+
+#[cfg(collectd_57)]
+#[export_name = "uc_iterator_get_interval"]
+pub unsafe extern "C" fn stub_uc_iterator_get_interval(
+    iter: *mut uc_iter_t,
+    ret_interval: *mut cdtime_t,
+) -> c_int {
+    match current(iter) {
+        Some(entry) if !ret_interval.is_null() => {
+            *ret_interval = entry.interval;
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Like collectd, the values are copied into memory that the caller frees
+#[cfg(collectd_57)]
+#[export_name = "uc_iterator_get_values"]
+pub unsafe extern "C" fn stub_uc_iterator_get_values(
+    iter: *mut uc_iter_t,
+    ret_values: *mut *mut value_t,
+    ret_num: *mut usize,
+) -> c_int {
+    let entry = match current(iter) {
+        Some(entry) if !ret_values.is_null() && !ret_num.is_null() => entry,
+        _ => return -1,
+    };
+
+    let len = entry.values.len();
+    let values = malloc(len.max(1) * ::std::mem::size_of::<value_t>()) as *mut value_t;
+    if values.is_null() {
+        return -1;
+    }
+
+    ptr::copy_nonoverlapping(entry.values.as_ptr(), values, len);
+    *ret_values = values;
+    *ret_num = len;
+    0
+}
+
+#[export_name = "plugin_dispatch_notification"]
+pub unsafe extern "C" fn stub_plugin_dispatch_notification(notif: *const notification_t) -> c_int {
+    let n = match RecvNotification::from(&*notif) {
+        Ok(n) => n,
+        Err(_) => return -1,
+    };
+
+    let entry = DispatchedNotification {
+        severity: n.severity,
+        message: String::from(n.message),
+        plugin: String::from(n.plugin),
+        plugin_instance: n.plugin_instance.map(String::from),
+        type_: n.type_.map(String::from),
+        type_instance: n.type_instance.map(String::from),
+        host: empty_to_none(n.host).map(String::from),
+        time: (*notif).time,
+        meta: n.meta,
+    };
+
+    RECORDER.with(|r| r.borrow_mut().notifications.push(entry));
+
+    // Like collectd, every notification callback receives the notification
+    let callbacks: Vec<_> = RECORDER.with(|r| {
+        r.borrow_mut()
+            .registrations
+            .iter_mut()
+            .filter_map(|x| match (x.callback, x.user_data.as_mut()) {
+                (Callback::Notification(Some(f)), Some(data)) => {
+                    Some((f, &mut **data as *mut user_data_t))
+                }
+                _ => None,
+            })
+            .collect()
+    });
+
+    for (f, data) in callbacks {
+        f(notif, data);
+    }
+    0
+}
+
+/// Appends an entry to the notification's metadata list, like collectd's
+/// `plugin_notification_meta_add`
+unsafe fn notification_meta_add(
+    n: *mut notification_t,
+    name: *const c_char,
+    type_: c_int,
+    value: notification_meta_s__bindgen_ty_1,
+) -> c_int {
+    if n.is_null() || name.is_null() {
+        return -1;
+    }
+
+    let mut entry = Box::new(notification_meta_t {
+        name: [0; ARR_LENGTH],
+        type_: type_ as _,
+        nm_value: value,
+        next: ptr::null_mut(),
+    });
+
+    let name = CStr::from_ptr(name).to_bytes();
+    for (dst, &src) in entry.name[..ARR_LENGTH - 1].iter_mut().zip(name) {
+        *dst = src as c_char;
+    }
+
+    let mut tail = &mut (*n).meta;
+    while !tail.is_null() {
+        tail = &mut (**tail).next;
+    }
+    *tail = Box::into_raw(entry);
+    0
+}
+
+#[export_name = "plugin_notification_meta_add_string"]
+pub unsafe extern "C" fn stub_plugin_notification_meta_add_string(
+    n: *mut notification_t,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    if value.is_null() {
+        return -1;
+    }
+
+    let value = notification_meta_s__bindgen_ty_1 {
+        nm_string: strdup(value),
+    };
+    notification_meta_add(n, name, NM_TYPE_STRING, value)
+}
+
+#[export_name = "plugin_notification_meta_add_signed_int"]
+pub unsafe extern "C" fn stub_plugin_notification_meta_add_signed_int(
+    n: *mut notification_t,
+    name: *const c_char,
+    value: i64,
+) -> c_int {
+    let value = notification_meta_s__bindgen_ty_1 {
+        nm_signed_int: value,
+    };
+    notification_meta_add(n, name, NM_TYPE_SIGNED_INT, value)
+}
+
+#[export_name = "plugin_notification_meta_add_unsigned_int"]
+pub unsafe extern "C" fn stub_plugin_notification_meta_add_unsigned_int(
+    n: *mut notification_t,
+    name: *const c_char,
+    value: u64,
+) -> c_int {
+    let value = notification_meta_s__bindgen_ty_1 {
+        nm_unsigned_int: value,
+    };
+    notification_meta_add(n, name, NM_TYPE_UNSIGNED_INT, value)
+}
+
+#[export_name = "plugin_notification_meta_add_double"]
+pub unsafe extern "C" fn stub_plugin_notification_meta_add_double(
+    n: *mut notification_t,
+    name: *const c_char,
+    value: f64,
+) -> c_int {
+    let value = notification_meta_s__bindgen_ty_1 { nm_double: value };
+    notification_meta_add(n, name, NM_TYPE_DOUBLE, value)
+}
+
+#[export_name = "plugin_notification_meta_add_boolean"]
+pub unsafe extern "C" fn stub_plugin_notification_meta_add_boolean(
+    n: *mut notification_t,
+    name: *const c_char,
+    value: bool,
+) -> c_int {
+    let value = notification_meta_s__bindgen_ty_1 { nm_boolean: value };
+    notification_meta_add(n, name, NM_TYPE_BOOLEAN, value)
+}
+
+#[export_name = "plugin_notification_meta_free"]
+pub unsafe extern "C" fn stub_plugin_notification_meta_free(n: *mut notification_meta_t) -> c_int {
+    let mut next = n;
+    while !next.is_null() {
+        let entry = Box::from_raw(next);
+        if entry.type_ as c_int == NM_TYPE_STRING {
+            free(entry.nm_value.nm_string as *mut c_void);
+        }
+        next = entry.next;
+    }
+    0
+}
+
+#[export_name = "plugin_register_complex_config"]
+pub unsafe extern "C" fn stub_plugin_register_complex_config(
+    type_: *const c_char,
+    callback: ComplexConfigCb,
+) -> c_int {
+    register(type_, Callback::Config(callback), None)
+}
+
+#[export_name = "plugin_register_config"]
+pub unsafe extern "C" fn stub_plugin_register_config(
+    name: *const c_char,
+    callback: SimpleConfigCb,
+    keys: *mut *const c_char,
+    keys_num: c_int,
+) -> c_int {
+    let keys = slice::from_raw_parts(keys, keys_num.max(0) as usize)
+        .iter()
+        .map(|x| CStr::from_ptr(*x).to_string_lossy().into_owned())
+        .collect();
+    let plugin = CStr::from_ptr(name).to_string_lossy().into_owned();
+    RECORDER.with(|r| r.borrow_mut().config_keys.insert(plugin, keys));
+    register(name, Callback::SimpleConfig(callback), None)
+}
+
+#[export_name = "plugin_register_init"]
+pub unsafe extern "C" fn stub_plugin_register_init(
+    name: *const c_char,
+    callback: plugin_init_cb,
+) -> c_int {
+    register(name, Callback::Init(callback), None)
+}
+
+#[export_name = "plugin_register_shutdown"]
+pub unsafe extern "C" fn stub_plugin_register_shutdown(
+    name: *const c_char,
+    callback: plugin_shutdown_cb,
+) -> c_int {
+    register(name, Callback::Shutdown(callback), None)
+}
+
+#[export_name = "fc_register_match"]
+pub unsafe extern "C" fn stub_fc_register_match(
+    name: *const c_char,
+    procs: match_proc_t,
+) -> c_int {
+    register(name, Callback::Match(procs), None)
+}
+
+#[export_name = "fc_register_target"]
+pub unsafe extern "C" fn stub_fc_register_target(
+    name: *const c_char,
+    procs: target_proc_t,
+) -> c_int {
+    register(name, Callback::Target(procs), None)
+}
+
+#[export_name = "plugin_register_complex_read"]
+pub unsafe extern "C" fn stub_plugin_register_complex_read(
+    _group: *const c_char,
+    name: *const c_char,
+    callback: plugin_read_cb,
+    interval: Interval,
+    user_data: UserData,
+) -> c_int {
+    #[cfg(collectd_57)]
+    let interval = interval;
+
+    #[cfg(not(collectd_57))]
+    let interval = interval
+        .as_ref()
+        .map(|x| ::std::time::Duration::new(x.tv_sec as u64, x.tv_nsec as u32))
+        .map(|x| ::api::CdTime::from(x).into())
+        .unwrap_or(0);
+
+    register(name, Callback::Read(callback), copy_user_data(user_data));
+    RECORDER.with(|r| {
+        if let Some(reg) = r.borrow_mut().registrations.last_mut() {
+            reg.interval = interval;
+        }
+    });
+    0
+}
+
+#[export_name = "plugin_unregister_read"]
+pub unsafe extern "C" fn stub_plugin_unregister_read(name: *const c_char) -> c_int {
+    let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+    RECORDER.with(|r| {
+        let r = &mut *r.borrow_mut();
+        let position = r.registrations
+            .iter()
+            .position(|x| x.name == name && x.kind() == CallbackKind::Read);
+        match position {
+            Some(idx) => {
+                let reg = r.registrations.remove(idx);
+                r.unregistered.push(reg);
+                0
+            }
+            None => -1,
+        }
+    })
+}
+
+#[export_name = "plugin_register_write"]
+pub unsafe extern "C" fn stub_plugin_register_write(
+    name: *const c_char,
+    callback: plugin_write_cb,
+    user_data: UserData,
+) -> c_int {
+    register(name, Callback::Write(callback), copy_user_data(user_data))
+}
+
+#[export_name = "plugin_register_notification"]
+pub unsafe extern "C" fn stub_plugin_register_notification(
+    name: *const c_char,
+    callback: plugin_notification_cb,
+    user_data: UserData,
+) -> c_int {
+    register(name, Callback::Notification(callback), copy_user_data(user_data))
+}
+
+#[export_name = "plugin_register_log"]
+pub unsafe extern "C" fn stub_plugin_register_log(
+    name: *const c_char,
+    callback: plugin_log_cb,
+    user_data: UserData,
+) -> c_int {
+    register(name, Callback::Log(callback), copy_user_data(user_data))
+}
+
+#[export_name = "plugin_register_flush"]
+pub unsafe extern "C" fn stub_plugin_register_flush(
+    name: *const c_char,
+    callback: plugin_flush_cb,
+    user_data: UserData,
+) -> c_int {
+    register(name, Callback::Flush(callback), copy_user_data(user_data))
+}
+
+/// Stand-in for collectd's `meta_data_t`: entries in insertion order
+struct StubMeta {
+    entries: Vec<(String, MetaValue)>,
+}
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+    fn strdup(s: *const c_char) -> *mut c_char;
+}
+
+unsafe fn stub_meta<'a>(md: *mut meta_data_t) -> Option<&'a mut StubMeta> {
+    (md as *mut StubMeta).as_mut()
+}
+
+unsafe fn meta_key(key: *const c_char) -> String {
+    CStr::from_ptr(key).to_string_lossy().into_owned()
+}
+
+unsafe fn meta_add(md: *mut meta_data_t, key: *const c_char, value: MetaValue) -> c_int {
+    match stub_meta(md) {
+        Some(meta) if !key.is_null() => {
+            let key = meta_key(key);
+            meta.entries.retain(|x| x.0 != key);
+            meta.entries.push((key, value));
+            0
+        }
+        _ => -1,
+    }
+}
+
+unsafe fn meta_get<'a>(md: *mut meta_data_t, key: *const c_char) -> Option<&'a MetaValue> {
+    if key.is_null() {
+        return None;
+    }
+
+    let key = meta_key(key);
+    stub_meta(md).and_then(|meta| meta.entries.iter().find(|x| x.0 == key).map(|x| &x.1))
+}
+
+#[export_name = "meta_data_create"]
+pub extern "C" fn stub_meta_data_create() -> *mut meta_data_t {
+    Box::into_raw(Box::new(StubMeta {
+        entries: Vec::new(),
+    })) as *mut meta_data_t
+}
+
+#[export_name = "meta_data_clone"]
+pub unsafe extern "C" fn stub_meta_data_clone(orig: *mut meta_data_t) -> *mut meta_data_t {
+    match stub_meta(orig) {
+        Some(meta) => Box::into_raw(Box::new(StubMeta {
+            entries: meta.entries.clone(),
+        })) as *mut meta_data_t,
+        None => ptr::null_mut(),
+    }
+}
+
+#[export_name = "meta_data_destroy"]
+pub unsafe extern "C" fn stub_meta_data_destroy(md: *mut meta_data_t) {
+    if !md.is_null() {
+        drop(Box::from_raw(md as *mut StubMeta));
+    }
+}
+
+#[export_name = "meta_data_exists"]
+pub unsafe extern "C" fn stub_meta_data_exists(md: *mut meta_data_t, key: *const c_char) -> c_int {
+    meta_get(md, key).is_some() as c_int
+}
+
+#[export_name = "meta_data_type"]
+pub unsafe extern "C" fn stub_meta_data_type(md: *mut meta_data_t, key: *const c_char) -> c_int {
+    match meta_get(md, key) {
+        Some(&MetaValue::String(_)) => MD_TYPE_STRING,
+        Some(&MetaValue::SignedInt(_)) => MD_TYPE_SIGNED_INT,
+        Some(&MetaValue::UnsignedInt(_)) => MD_TYPE_UNSIGNED_INT,
+        Some(&MetaValue::Double(_)) => MD_TYPE_DOUBLE,
+        Some(&MetaValue::Boolean(_)) => MD_TYPE_BOOLEAN,
+        None => 0,
+    }
+}
+
+#[export_name = "meta_data_toc"]
+pub unsafe extern "C" fn stub_meta_data_toc(
+    md: *mut meta_data_t,
+    toc: *mut *mut *mut c_char,
+) -> c_int {
+    let meta = match stub_meta(md) {
+        Some(meta) => meta,
+        None => return -1,
+    };
+
+    let len = meta.entries.len();
+    let arr = malloc(len.max(1) * ::std::mem::size_of::<*mut c_char>()) as *mut *mut c_char;
+    for (i, &(ref key, _)) in meta.entries.iter().enumerate() {
+        let key = CString::new(key.as_str()).unwrap();
+        *arr.offset(i as isize) = strdup(key.as_ptr());
+    }
+
+    *toc = arr;
+    len as c_int
+}
+
+#[export_name = "meta_data_delete"]
+pub unsafe extern "C" fn stub_meta_data_delete(md: *mut meta_data_t, key: *const c_char) -> c_int {
+    match stub_meta(md) {
+        Some(meta) if !key.is_null() => {
+            let key = meta_key(key);
+            let before = meta.entries.len();
+            meta.entries.retain(|x| x.0 != key);
+            if meta.entries.len() == before {
+                -2
+            } else {
+                0
+            }
+        }
+        _ => -1,
+    }
+}
+
+#[export_name = "meta_data_add_string"]
+pub unsafe extern "C" fn stub_meta_data_add_string(
+    md: *mut meta_data_t,
+    key: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    if value.is_null() {
+        return -1;
+    }
+
+    let value = CStr::from_ptr(value).to_string_lossy().into_owned();
+    meta_add(md, key, MetaValue::String(value))
+}
+
+#[export_name = "meta_data_add_signed_int"]
+pub unsafe extern "C" fn stub_meta_data_add_signed_int(
+    md: *mut meta_data_t,
+    key: *const c_char,
+    value: i64,
+) -> c_int {
+    meta_add(md, key, MetaValue::SignedInt(value))
+}
+
+#[export_name = "meta_data_add_unsigned_int"]
+pub unsafe extern "C" fn stub_meta_data_add_unsigned_int(
+    md: *mut meta_data_t,
+    key: *const c_char,
+    value: u64,
+) -> c_int {
+    meta_add(md, key, MetaValue::UnsignedInt(value))
+}
+
+#[export_name = "meta_data_add_double"]
+pub unsafe extern "C" fn stub_meta_data_add_double(
+    md: *mut meta_data_t,
+    key: *const c_char,
+    value: f64,
+) -> c_int {
+    meta_add(md, key, MetaValue::Double(value))
+}
+
+#[export_name = "meta_data_add_boolean"]
+pub unsafe extern "C" fn stub_meta_data_add_boolean(
+    md: *mut meta_data_t,
+    key: *const c_char,
+    value: bool,
+) -> c_int {
+    meta_add(md, key, MetaValue::Boolean(value))
+}
+
+#[export_name = "meta_data_get_string"]
+pub unsafe extern "C" fn stub_meta_data_get_string(
+    md: *mut meta_data_t,
+    key: *const c_char,
+    value: *mut *mut c_char,
+) -> c_int {
+    match meta_get(md, key) {
+        Some(&MetaValue::String(ref x)) => {
+            let x = CString::new(x.as_str()).unwrap();
+            *value = strdup(x.as_ptr());
+            0
+        }
+        _ => -2,
+    }
+}
+
+#[export_name = "meta_data_get_signed_int"]
+pub unsafe extern "C" fn stub_meta_data_get_signed_int(
+    md: *mut meta_data_t,
+    key: *const c_char,
+    value: *mut i64,
+) -> c_int {
+    match meta_get(md, key) {
+        Some(&MetaValue::SignedInt(x)) => {
+            *value = x;
+            0
+        }
+        _ => -2,
+    }
+}
+
+#[export_name = "meta_data_get_unsigned_int"]
+pub unsafe extern "C" fn stub_meta_data_get_unsigned_int(
+    md: *mut meta_data_t,
+    key: *const c_char,
+    value: *mut u64,
+) -> c_int {
+    match meta_get(md, key) {
+        Some(&MetaValue::UnsignedInt(x)) => {
+            *value = x;
+            0
+        }
+        _ => -2,
+    }
+}
+
+#[export_name = "meta_data_get_double"]
+pub unsafe extern "C" fn stub_meta_data_get_double(
+    md: *mut meta_data_t,
+    key: *const c_char,
+    value: *mut f64,
+) -> c_int {
+    match meta_get(md, key) {
+        Some(&MetaValue::Double(x)) => {
+            *value = x;
+            0
+        }
+        _ => -2,
+    }
+}
+
+#[export_name = "meta_data_get_boolean"]
+pub unsafe extern "C" fn stub_meta_data_get_boolean(
+    md: *mut meta_data_t,
+    key: *const c_char,
+    value: *mut bool,
+) -> c_int {
+    match meta_get(md, key) {
+        Some(&MetaValue::Boolean(x)) => {
+            *value = x;
+            0
+        }
+        _ => -2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{collectd_log, submit_derive, submit_gauge, CdTime, LogLevel, Value,
+              ValueListBuilder};
+    use std::time::Duration;
+
+    #[test]
+    fn test_stub_records_logs() {
+        reset();
+        collectd_log(LogLevel::Warning, "hello");
+        assert_eq!(
+            logged(),
+            vec![
+                LoggedMessage {
+                    severity: LogLevel::Warning.as_raw() as c_int,
+                    message: String::from("hello"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stderr_line() {
+        assert_eq!(stderr_line(LOG_WARNING, "hello"), "[warning] hello");
+        assert_eq!(stderr_line(LOG_DEBUG, "hello"), "[debug] hello");
+        assert_eq!(stderr_line(100, "hello"), "[unknown] hello");
+    }
+
+    #[test]
+    fn test_stub_records_dispatched_values() {
+        reset();
+        let values = [Value::Gauge(15.0), Value::Gauge(10.5)];
+        ValueListBuilder::new("myplugin", "load")
+            .values(&values)
+            .type_instance("relative")
+            .submit()
+            .unwrap();
+
+        let dispatched = dispatched();
+        assert_eq!(dispatched.len(), 1);
+        assert_eq!(dispatched[0].plugin, "myplugin");
+        assert_eq!(dispatched[0].type_, "load");
+        assert_eq!(dispatched[0].type_instance, Some(String::from("relative")));
+        assert_eq!(dispatched[0].plugin_instance, None);
+        assert_eq!(dispatched[0].gauges(), vec![15.0, 10.5]);
+        assert_eq!(dispatched[0].interval, unsafe { stub_interval_g });
+
+        clear();
+        assert!(super::dispatched().is_empty());
+    }
+
+    #[test]
+    fn test_reuse_builder() {
+        reset();
+        let mut builder = ValueListBuilder::new("myplugin", "load").type_instance("relative");
+        for i in 0..3 {
+            builder
+                .clear_values()
+                .push_value(Value::Gauge(f64::from(i)))
+                .push_value(Value::Gauge(1.5))
+                .set_time(CdTime::from(Duration::from_secs(i as u64 + 1)));
+            builder.submit().unwrap();
+        }
+
+        let dispatched = dispatched();
+        assert_eq!(dispatched.len(), 3);
+        assert_eq!(dispatched[2].type_instance, Some(String::from("relative")));
+        assert_eq!(dispatched[2].gauges(), vec![2.0, 1.5]);
+        assert_eq!(dispatched[2].time, CdTime::from(Duration::from_secs(3)).into());
+    }
+
+    #[test]
+    fn test_submit_single_values() {
+        reset();
+        submit_gauge("myplugin", "temperature", Some("cpu0"), 45.5).unwrap();
+        submit_derive("myplugin", "if_octets", None, -3).unwrap();
+
+        let dispatched = dispatched();
+        assert_eq!(dispatched.len(), 2);
+        assert_eq!(dispatched[0].type_, "temperature");
+        assert_eq!(dispatched[0].type_instance, Some(String::from("cpu0")));
+        assert_eq!(dispatched[0].gauges(), vec![45.5]);
+        assert_eq!(dispatched[1].type_instance, None);
+        assert_eq!(dispatched[1].derives(), vec![-3]);
+    }
+}
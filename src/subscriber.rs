@@ -0,0 +1,178 @@
+//! A [tracing](https://crates.io/crates/tracing) layer that routes events into collectd's log. Each
+//! event is rendered with its fields and the chain of spans that it occurred within, so that
+//! instrumented libraries running inside a plugin produce coherent log lines.
+//!
+//! ```text
+//! myplugin: poll{host=db1}: query{table="users"}: slow query elapsed_ms=350
+//! ```
+
+use std::fmt::{self, Write};
+use tracing::{Event, Level, Subscriber};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::SetGlobalDefaultError;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+use api::{collectd_log, LogLevel};
+
+/// Routes tracing events to `collectd_log`, prefixed with the plugin's name
+#[derive(Debug)]
+pub struct CollectdLayer {
+    plugin: &'static str,
+}
+
+impl CollectdLayer {
+    pub fn new(plugin: &'static str) -> Self {
+        CollectdLayer { plugin: plugin }
+    }
+}
+
+/// Installs a `CollectdLayer` on top of a span registry as the global default subscriber
+pub fn install_tracing(plugin: &'static str) -> Result<(), SetGlobalDefaultError> {
+    let subscriber = Registry::default().with(CollectdLayer::new(plugin));
+    tracing::subscriber::set_global_default(subscriber)
+}
+
+/// Maps a tracing level to the closest collectd log level. Collectd has no concept of trace, so
+/// those are logged as debug.
+pub fn tracing_level(lvl: &Level) -> LogLevel {
+    match *lvl {
+        Level::ERROR => LogLevel::Error,
+        Level::WARN => LogLevel::Warning,
+        Level::INFO => LogLevel::Info,
+        Level::DEBUG | Level::TRACE => LogLevel::Debug,
+    }
+}
+
+/// The rendered fields of a span, stored in the span's extensions
+struct SpanFields(String);
+
+/// Renders fields as `key=value` pairs separated by spaces. The `message` field is pulled out
+/// separately as it is the main content of an event.
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: String,
+}
+
+impl FieldVisitor {
+    fn push(&mut self, field: &Field, value: fmt::Arguments) {
+        if field.name() == "message" {
+            let _ = self.message.write_fmt(value);
+        } else {
+            if !self.fields.is_empty() {
+                self.fields.push(' ');
+            }
+            let _ = write!(self.fields, "{}={}", field.name(), value);
+        }
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.push(field, format_args!("{:?}", value));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, format_args!("{:?}", value));
+    }
+}
+
+impl<S> Layer<S> for CollectdLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record, ctx: Context<S>) {
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            let mut extensions = span.extensions_mut();
+            if let Some(fields) = extensions.get_mut::<SpanFields>() {
+                if !fields.0.is_empty() && !visitor.fields.is_empty() {
+                    fields.0.push(' ');
+                }
+                fields.0.push_str(&visitor.fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event, ctx: Context<S>) {
+        let mut line = format!("{}: ", self.plugin);
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                line.push_str(span.name());
+                if let Some(fields) = span.extensions().get::<SpanFields>() {
+                    if !fields.0.is_empty() {
+                        let _ = write!(line, "{{{}}}", fields.0);
+                    }
+                }
+                line.push_str(": ");
+            }
+        }
+
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        line.push_str(&visitor.message);
+        if !visitor.fields.is_empty() {
+            if !visitor.message.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&visitor.fields);
+        }
+
+        // Collectd messages can't contain nulls, so strip them instead of panicking
+        collectd_log(
+            tracing_level(event.metadata().level()),
+            &line.replace('\0', ""),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracing_level_mapping() {
+        assert_eq!(tracing_level(&Level::ERROR), LogLevel::Error);
+        assert_eq!(tracing_level(&Level::WARN), LogLevel::Warning);
+        assert_eq!(tracing_level(&Level::INFO), LogLevel::Info);
+        assert_eq!(tracing_level(&Level::DEBUG), LogLevel::Debug);
+        assert_eq!(tracing_level(&Level::TRACE), LogLevel::Debug);
+    }
+
+    #[test]
+    fn test_event_in_span_is_logged() {
+        use stub;
+
+        stub::reset();
+        let subscriber = Registry::default().with(CollectdLayer::new("myplugin"));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("poll", host = "db1");
+            let _guard = span.enter();
+            tracing::warn!(elapsed_ms = 350, "slow query");
+        });
+
+        let logged = stub::logged();
+        assert_eq!(logged.len(), 1);
+        assert_eq!(logged[0].severity, LogLevel::Warning.as_raw() as i32);
+        assert_eq!(
+            logged[0].message,
+            "myplugin: poll{host=\"db1\"}: slow query elapsed_ms=350"
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! Helpers for unit testing plugins end-to-end without a collectd daemon, enabled by the `stub`
+//! feature. Values dispatched with `ValueListBuilder::submit` and messages logged with
+//! `collectd_log` (and the `collectd_*!` macros) are recorded by the `stub` module, so a test can
+//! invoke a read callback and then assert on what it produced:
+//!
+//! ```ignore
+//! #[test]
+//! fn test_read() {
+//!     collectd_plugin::testing::reset();
+//!     MyPlugin.read_values().unwrap();
+//!     assert_submitted!("myplugin", "gauge", [42.0]);
+//!     assert_logged!(LogLevel::Info, "read 1 value");
+//! }
+//! ```
+//!
+//! As records are kept per thread, tests that run in parallel will not see each other's values.
+//!
+//! Write callbacks and filter chains receive collectd's structures, which `ValueListFixture` and
+//! `ConfigFixture` build from Rust values. These are also suited for benchmarking plugins.
+
+use api::{to_array_res, CdTime, LogLevel, OwnedConfigItem, OwnedConfigValue, RecvValueList,
+          Value};
+use bindings::{data_set_t, data_source_t, oconfig_item_t, oconfig_value_s__bindgen_ty_1,
+               oconfig_value_t, value_list_t, value_t, ARR_LENGTH};
+use constants::{DataSourceType, OCONFIG_TYPE_BOOLEAN, OCONFIG_TYPE_NUMBER, OCONFIG_TYPE_STRING};
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::time::{Duration, SystemTime};
+
+pub use stub::{clear, config, dispatched, init, logged, notifications, read, reset,
+               DispatchedNotification, DispatchedValueList, LoggedMessage};
+
+/// A value that a test expects to have been submitted. Bare floats are expected to be gauges,
+/// other data source types are given as a `Value`.
+pub trait ExpectedValue {
+    fn expected(self) -> Value;
+}
+
+impl ExpectedValue for f64 {
+    fn expected(self) -> Value {
+        Value::Gauge(self)
+    }
+}
+
+impl ExpectedValue for Value {
+    fn expected(self) -> Value {
+        self
+    }
+}
+
+impl DispatchedValueList {
+    /// Interprets the dispatched values as the data source types of the given values, as there
+    /// isn't a types.db to consult
+    pub fn values_like(&self, expected: &[Value]) -> Vec<Value> {
+        let gauges = self.gauges();
+        let counters = self.counters();
+        let derives = self.derives();
+        expected
+            .iter()
+            .enumerate()
+            .take(self.len())
+            .map(|(i, x)| match *x {
+                Value::Gauge(_) => Value::Gauge(gauges[i]),
+                Value::Counter(_) => Value::Counter(counters[i]),
+                Value::Derive(_) => Value::Derive(derives[i]),
+                Value::Absolute(_) => Value::Absolute(counters[i]),
+            })
+            .collect()
+    }
+}
+
+/// Value lists dispatched on this thread for the given plugin and type
+pub fn submitted(plugin: &str, type_: &str) -> Vec<DispatchedValueList> {
+    dispatched()
+        .into_iter()
+        .filter(|x| x.plugin == plugin && x.type_ == type_)
+        .collect()
+}
+
+/// Panics unless a value list with the given plugin, type, and values was dispatched on this
+/// thread. Prefer the `assert_submitted!` macro.
+pub fn assert_submitted(plugin: &str, type_: &str, values: &[Value]) {
+    let candidates = submitted(plugin, type_);
+    let found = candidates
+        .iter()
+        .any(|x| x.len() == values.len() && x.values_like(values) == values);
+
+    if !found {
+        panic!(
+            "no value list with plugin `{}`, type `{}`, and values {:?} was submitted. \
+             Submitted: {:#?}",
+            plugin,
+            type_,
+            values,
+            dispatched()
+        );
+    }
+}
+
+/// Panics unless a message containing the given text was logged at the given level on this
+/// thread. Prefer the `assert_logged!` macro.
+pub fn assert_logged(lvl: LogLevel, text: &str) {
+    let found = logged()
+        .iter()
+        .any(|x| x.severity == lvl.as_raw() as c_int && x.message.contains(text));
+
+    if !found {
+        panic!(
+            "no {:?} message containing `{}` was logged. Logged: {:#?}",
+            lvl,
+            text,
+            logged()
+        );
+    }
+}
+
+fn arr(s: &str) -> [c_char; ARR_LENGTH] {
+    to_array_res(s).expect("fixture string to fit into a collectd field")
+}
+
+/// A data set and value list, as collectd passes to write callbacks, owned by Rust. Data sources
+/// are named `value` for a single value, otherwise `value0`, `value1`, etc.
+pub struct ValueListFixture {
+    // The set and list point into these
+    _sources: Vec<data_source_t>,
+    _values: Vec<value_t>,
+    set: data_set_t,
+    list: value_list_t,
+}
+
+impl ValueListFixture {
+    /// Values from the local host at the current time with a ten second interval
+    pub fn new(plugin: &str, type_: &str, values: &[Value]) -> Self {
+        let mut sources: Vec<data_source_t> = values
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let (kind, min) = match *x {
+                    Value::Gauge(_) => (DataSourceType::Gauge, f64::NAN),
+                    Value::Counter(_) => (DataSourceType::Counter, 0.0),
+                    Value::Derive(_) => (DataSourceType::Derive, 0.0),
+                    Value::Absolute(_) => (DataSourceType::Absolute, 0.0),
+                };
+
+                let name = if values.len() == 1 {
+                    String::from("value")
+                } else {
+                    format!("value{}", i)
+                };
+
+                data_source_t {
+                    name: arr(&name),
+                    type_: kind.as_raw(),
+                    min: min,
+                    max: f64::NAN,
+                }
+            })
+            .collect();
+
+        let mut raw: Vec<value_t> = values.iter().map(|&x| x.into()).collect();
+        let set = data_set_t {
+            type_: arr(type_),
+            ds_num: sources.len() as _,
+            ds: sources.as_mut_ptr(),
+        };
+
+        let list = value_list_t {
+            values: raw.as_mut_ptr(),
+            values_len: raw.len() as _,
+            time: CdTime::from(SystemTime::now()).into(),
+            interval: CdTime::from(Duration::from_secs(10)).into(),
+            host: arr("localhost"),
+            plugin: arr(plugin),
+            plugin_instance: [0; ARR_LENGTH],
+            type_: arr(type_),
+            type_instance: [0; ARR_LENGTH],
+            meta: ptr::null_mut(),
+        };
+
+        ValueListFixture {
+            _sources: sources,
+            _values: raw,
+            set: set,
+            list: list,
+        }
+    }
+
+    pub fn host(mut self, host: &str) -> Self {
+        self.list.host = arr(host);
+        self
+    }
+
+    pub fn plugin_instance(mut self, instance: &str) -> Self {
+        self.list.plugin_instance = arr(instance);
+        self
+    }
+
+    pub fn type_instance(mut self, instance: &str) -> Self {
+        self.list.type_instance = arr(instance);
+        self
+    }
+
+    pub fn data_set(&self) -> &data_set_t {
+        &self.set
+    }
+
+    pub fn value_list(&self) -> &value_list_t {
+        &self.list
+    }
+
+    pub fn value_list_mut(&mut self) -> &mut value_list_t {
+        &mut self.list
+    }
+
+    /// The value list as a write callback would receive it
+    pub fn recv(&self) -> RecvValueList {
+        RecvValueList::from(&self.set, &self.list).expect("fixture to be a valid value list")
+    }
+}
+
+/// A configuration tree, as collectd passes to config callbacks and filter chain constructors,
+/// owned by Rust
+pub struct ConfigFixture {
+    item: oconfig_item_t,
+
+    // The item points into these
+    _key: CString,
+    _strings: Vec<CString>,
+    _values: Vec<oconfig_value_t>,
+    _children: Vec<oconfig_item_t>,
+    _nested: Vec<ConfigFixture>,
+}
+
+impl ConfigFixture {
+    pub fn new(config: &OwnedConfigItem) -> Self {
+        let key = CString::new(config.key.as_str()).expect("fixture key to not contain nulls");
+        let mut strings = Vec::new();
+        let mut values: Vec<oconfig_value_t> = config
+            .values
+            .iter()
+            .map(|x| match *x {
+                OwnedConfigValue::String(ref s) => {
+                    let s = CString::new(s.as_str()).expect("fixture value to not contain nulls");
+                    let value = oconfig_value_t {
+                        value: oconfig_value_s__bindgen_ty_1 {
+                            string: s.as_ptr() as *mut c_char,
+                        },
+                        type_: OCONFIG_TYPE_STRING,
+                    };
+                    strings.push(s);
+                    value
+                }
+                OwnedConfigValue::Number(number) => oconfig_value_t {
+                    value: oconfig_value_s__bindgen_ty_1 { number: number },
+                    type_: OCONFIG_TYPE_NUMBER,
+                },
+                OwnedConfigValue::Boolean(boolean) => oconfig_value_t {
+                    value: oconfig_value_s__bindgen_ty_1 {
+                        boolean: boolean as c_int,
+                    },
+                    type_: OCONFIG_TYPE_BOOLEAN,
+                },
+            })
+            .collect();
+
+        let nested: Vec<ConfigFixture> = config.children.iter().map(ConfigFixture::new).collect();
+        let mut children: Vec<oconfig_item_t> = nested.iter().map(|x| x.item).collect();
+        let item = oconfig_item_t {
+            key: key.as_ptr() as *mut c_char,
+            values: values.as_mut_ptr(),
+            values_num: values.len() as c_int,
+            parent: ptr::null_mut(),
+            children: children.as_mut_ptr(),
+            children_num: children.len() as c_int,
+        };
+
+        ConfigFixture {
+            item: item,
+            _key: key,
+            _strings: strings,
+            _values: values,
+            _children: children,
+            _nested: nested,
+        }
+    }
+
+    pub fn item(&self) -> &oconfig_item_t {
+        &self.item
+    }
+
+    /// Pointer for passing to config callbacks, which don't modify the tree
+    pub fn as_ptr(&self) -> *mut oconfig_item_t {
+        &self.item as *const oconfig_item_t as *mut oconfig_item_t
+    }
+}
+
+/// Asserts that a value list was submitted with the given plugin, type, and values. Floats are
+/// compared as gauges, other data source types can be given as a `Value`.
+///
+/// ```ignore
+/// assert_submitted!("myplugin", "gauge", [42.0]);
+/// assert_submitted!("myplugin", "derive", [Value::Derive(10)]);
+/// ```
+#[macro_export]
+macro_rules! assert_submitted {
+    ($plugin:expr, $type_:expr, [$($value:expr),* $(,)*]) => {
+        $crate::testing::assert_submitted(
+            $plugin,
+            $type_,
+            &[$($crate::testing::ExpectedValue::expected($value)),*],
+        )
+    };
+}
+
+/// Asserts that a message containing the given text was logged at the given level
+///
+/// ```ignore
+/// assert_logged!(LogLevel::Error, "unable to connect");
+/// ```
+#[macro_export]
+macro_rules! assert_logged {
+    ($lvl:expr, $text:expr) => {
+        $crate::testing::assert_logged($lvl, $text)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{collectd_log, ConfigItem, ValueListBuilder};
+
+    #[test]
+    fn test_assert_submitted() {
+        reset();
+        ValueListBuilder::new("myplugin", "gauge")
+            .values(&[Value::Gauge(42.0)])
+            .submit()
+            .unwrap();
+        ValueListBuilder::new("myplugin", "derive")
+            .values(&[Value::Derive(-5), Value::Derive(10)])
+            .submit()
+            .unwrap();
+
+        assert_submitted!("myplugin", "gauge", [42.0]);
+        assert_submitted!("myplugin", "derive", [Value::Derive(-5), Value::Derive(10)]);
+        assert_eq!(submitted("myplugin", "gauge").len(), 1);
+        assert!(submitted("otherplugin", "gauge").is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "no value list")]
+    fn test_assert_submitted_mismatch() {
+        reset();
+        ValueListBuilder::new("myplugin", "gauge")
+            .values(&[Value::Gauge(42.0)])
+            .submit()
+            .unwrap();
+
+        assert_submitted!("myplugin", "gauge", [41.0]);
+    }
+
+    #[test]
+    fn test_assert_logged() {
+        reset();
+        collectd_log(LogLevel::Warning, "myplugin: unable to connect");
+        assert_logged!(LogLevel::Warning, "unable to connect");
+    }
+
+    #[test]
+    fn test_value_list_fixture() {
+        let values = [Value::Gauge(1.5), Value::Derive(-3)];
+        let fixture = ValueListFixture::new("myplugin", "mytype", &values)
+            .host("myhost")
+            .type_instance("idle");
+
+        let list = fixture.recv();
+        assert_eq!(list.plugin, "myplugin");
+        assert_eq!(list.type_, "mytype");
+        assert_eq!(list.type_instance, Some("idle"));
+        assert_eq!(list.plugin_instance, None);
+        assert_eq!(list.host, "myhost");
+        assert_eq!(list.interval, Duration::from_secs(10));
+        assert_eq!(list.values[1].name, "value1");
+        assert_eq!(list.values[1].value, Value::Derive(-3));
+    }
+
+    #[test]
+    fn test_config_fixture() {
+        let config = OwnedConfigItem {
+            key: String::from("Plugin"),
+            values: vec![OwnedConfigValue::String(String::from("myplugin"))],
+            children: vec![
+                OwnedConfigItem {
+                    key: String::from("Ratio"),
+                    values: vec![OwnedConfigValue::Number(0.5)],
+                    children: vec![],
+                },
+                OwnedConfigItem {
+                    key: String::from("Enabled"),
+                    values: vec![OwnedConfigValue::Boolean(true)],
+                    children: vec![],
+                },
+            ],
+        };
+
+        let fixture = ConfigFixture::new(&config);
+        let item = unsafe { ConfigItem::from(fixture.item()) }.unwrap();
+        assert_eq!(item.to_owned_item(), config);
+    }
+}
@@ -0,0 +1,550 @@
+//! A Rust equivalent of collectd's (deprecated) `threshold` plugin. Write plugins feed received
+//! value lists into a `ThresholdEngine`, which tracks the state of every data source and
+//! dispatches a notification when one crosses into (or recovers from) a warning or failure range.
+//!
+//! ```
+//! use collectd_plugin::threshold::{Threshold, ThresholdEngine};
+//!
+//! let engine = ThresholdEngine::new()
+//!     .threshold(Threshold::new("percent").plugin("cpu").warning_max(80.0).failure_max(95.0))
+//!     .threshold(Threshold::new("df_complex").type_instance("free").failure_min(1e9).hits(3));
+//! ```
+//!
+//! Like the `threshold` plugin, counters and derives are checked by their rate, so they need two
+//! value lists before they can cross a threshold.
+
+use api::{CdTime, NotificationBuilder, RecvValueList, Value};
+use constants::Severity;
+use errors::Error;
+use identifier::Identifier;
+use network::Notification;
+use rates::RateTracker;
+use std::collections::HashMap;
+
+/// The thresholds of the value lists that match it. Fields that aren't set (the plugin, host,
+/// instances, and data source) match anything.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Threshold {
+    type_: String,
+    host: Option<String>,
+    plugin: Option<String>,
+    plugin_instance: Option<String>,
+    type_instance: Option<String>,
+    data_source: Option<String>,
+    warning_min: f64,
+    warning_max: f64,
+    failure_min: f64,
+    failure_max: f64,
+    hysteresis: f64,
+    hits: u32,
+    invert: bool,
+    persist: bool,
+    persist_ok: bool,
+}
+
+impl Threshold {
+    /// A threshold for value lists of the given type, without any limits
+    pub fn new(type_: &str) -> Self {
+        Threshold {
+            type_: String::from(type_),
+            host: None,
+            plugin: None,
+            plugin_instance: None,
+            type_instance: None,
+            data_source: None,
+            warning_min: f64::NAN,
+            warning_max: f64::NAN,
+            failure_min: f64::NAN,
+            failure_max: f64::NAN,
+            hysteresis: 0.0,
+            hits: 0,
+            invert: false,
+            persist: false,
+            persist_ok: false,
+        }
+    }
+
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(String::from(host));
+        self
+    }
+
+    pub fn plugin(mut self, plugin: &str) -> Self {
+        self.plugin = Some(String::from(plugin));
+        self
+    }
+
+    pub fn plugin_instance(mut self, instance: &str) -> Self {
+        self.plugin_instance = Some(String::from(instance));
+        self
+    }
+
+    pub fn type_instance(mut self, instance: &str) -> Self {
+        self.type_instance = Some(String::from(instance));
+        self
+    }
+
+    /// Only checks the named data source of the type (eg: `rx` of `if_octets`)
+    pub fn data_source(mut self, name: &str) -> Self {
+        self.data_source = Some(String::from(name));
+        self
+    }
+
+    pub fn warning_min(mut self, min: f64) -> Self {
+        self.warning_min = min;
+        self
+    }
+
+    pub fn warning_max(mut self, max: f64) -> Self {
+        self.warning_max = max;
+        self
+    }
+
+    pub fn failure_min(mut self, min: f64) -> Self {
+        self.failure_min = min;
+        self
+    }
+
+    pub fn failure_max(mut self, max: f64) -> Self {
+        self.failure_max = max;
+        self
+    }
+
+    /// Once in a warning or failure state, the value must be back within the limit by this much
+    /// to leave it, which keeps a value hovering around a limit from flapping
+    pub fn hysteresis(mut self, hysteresis: f64) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+
+    /// Number of consecutive values that must be out of range before the state changes
+    pub fn hits(mut self, hits: u32) -> Self {
+        self.hits = hits;
+        self
+    }
+
+    /// Values within the limits are the problem, instead of those outside of them
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        self
+    }
+
+    /// Sends a notification for every value that is out of range, not just on state changes
+    pub fn persist(mut self, persist: bool) -> Self {
+        self.persist = persist;
+        self
+    }
+
+    /// Along with `persist`, also sends a notification for every value that is in range
+    pub fn persist_ok(mut self, persist_ok: bool) -> Self {
+        self.persist_ok = persist_ok;
+        self
+    }
+
+    /// How specifically the threshold matches the value list (more specific thresholds win), or
+    /// `None` if it doesn't match
+    fn matches(&self, list: &RecvValueList) -> Option<usize> {
+        fn field(expected: &Option<String>, actual: Option<&str>) -> Option<usize> {
+            match *expected {
+                None => Some(0),
+                Some(ref x) if x == actual.unwrap_or("") => Some(1),
+                Some(_) => None,
+            }
+        }
+
+        if self.type_ != list.type_ {
+            return None;
+        }
+
+        Some(
+            field(&self.host, Some(list.host))? + field(&self.plugin, Some(list.plugin))?
+                + field(&self.plugin_instance, list.plugin_instance)?
+                + field(&self.type_instance, list.type_instance)?,
+        )
+    }
+
+    /// The severity of the value and the limit it crossed, given the data source's current state
+    fn severity(&self, value: f64, state: Severity) -> (Severity, Option<Limit>) {
+        let failure_hysteresis = if state == Severity::Failure { self.hysteresis } else { 0.0 };
+        let warning_hysteresis = if state == Severity::Warning { self.hysteresis } else { 0.0 };
+
+        if self.invert {
+            if within(value, self.failure_min, self.failure_max, -failure_hysteresis) {
+                return (Severity::Failure, Some(Limit::Within));
+            }
+            if within(value, self.warning_min, self.warning_max, -warning_hysteresis) {
+                return (Severity::Warning, Some(Limit::Within));
+            }
+            return (Severity::Okay, None);
+        }
+
+        if let Some(limit) = outside(value, self.failure_min, self.failure_max, failure_hysteresis)
+        {
+            return (Severity::Failure, Some(limit));
+        }
+        if let Some(limit) = outside(value, self.warning_min, self.warning_max, warning_hysteresis)
+        {
+            return (Severity::Warning, Some(limit));
+        }
+        (Severity::Okay, None)
+    }
+
+    fn limits(&self, severity: Severity) -> (f64, f64) {
+        match severity {
+            Severity::Failure => (self.failure_min, self.failure_max),
+            _ => (self.warning_min, self.warning_max),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Limit {
+    Below,
+    Above,
+    Within,
+}
+
+/// Which limit (if any) the value is beyond. The limits are narrowed by the hysteresis.
+fn outside(value: f64, min: f64, max: f64, hysteresis: f64) -> Option<Limit> {
+    if !min.is_nan() && value < min + hysteresis {
+        Some(Limit::Below)
+    } else if !max.is_nan() && value > max - hysteresis {
+        Some(Limit::Above)
+    } else {
+        None
+    }
+}
+
+/// Whether the value is within the limits, at least one of which must be set
+fn within(value: f64, min: f64, max: f64, hysteresis: f64) -> bool {
+    (!min.is_nan() || !max.is_nan()) && (min.is_nan() || value >= min + hysteresis)
+        && (max.is_nan() || value <= max - hysteresis)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    severity: Severity,
+    hits: u32,
+}
+
+/// Checks value lists against thresholds, remembering the state of every data source
+#[derive(Debug, Clone, Default)]
+pub struct ThresholdEngine {
+    thresholds: Vec<Threshold>,
+    states: HashMap<(Identifier, usize), State>,
+    rates: RateTracker,
+}
+
+impl ThresholdEngine {
+    pub fn new() -> Self {
+        ThresholdEngine::default()
+    }
+
+    /// Adds a threshold. When several match a value list, the one that names the most fields
+    /// (host, plugin, and instances) is used, with ties going to the first added.
+    pub fn threshold(mut self, threshold: Threshold) -> Self {
+        self.thresholds.push(threshold);
+        self
+    }
+
+    /// Checks the value list and dispatches the resulting notifications to collectd
+    pub fn check(&mut self, list: &RecvValueList) -> Result<(), Error> {
+        for notif in self.evaluate(list) {
+            let plugin = notif.plugin.as_str();
+            let mut builder = NotificationBuilder::new(plugin, notif.severity, &notif.message)
+                .type_(notif.type_.as_str())
+                .host(notif.host.as_str())
+                .time(notif.time);
+            if let Some(ref instance) = notif.plugin_instance {
+                builder = builder.plugin_instance(instance.as_str());
+            }
+            if let Some(ref instance) = notif.type_instance {
+                builder = builder.type_instance(instance.as_str());
+            }
+
+            builder.dispatch()?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the value list and returns the notifications that should be sent, without
+    /// dispatching them. Value lists without a matching threshold are ignored.
+    pub fn evaluate(&mut self, list: &RecvValueList) -> Vec<Notification> {
+        let threshold = {
+            let mut best: Option<(usize, &Threshold)> = None;
+            for threshold in &self.thresholds {
+                match (threshold.matches(list), best) {
+                    (Some(score), Some((best_score, _))) if score <= best_score => {}
+                    (Some(score), _) => best = Some((score, threshold)),
+                    (None, _) => {}
+                }
+            }
+
+            match best {
+                Some((_, threshold)) => threshold.clone(),
+                None => return Vec::new(),
+            }
+        };
+
+        let id = Identifier::from(list);
+        let values: Vec<Value> = list.values.iter().map(|x| x.value).collect();
+        let rates = match self.rates.rates(&id, list.time, &values) {
+            Some(rates) => rates,
+            None => return Vec::new(),
+        };
+
+        let mut result = Vec::new();
+        for (i, (report, &rate)) in list.values.iter().zip(rates.iter()).enumerate() {
+            if rate.is_nan() {
+                continue;
+            }
+
+            if let Some(ref ds) = threshold.data_source {
+                if ds != report.name {
+                    continue;
+                }
+            }
+
+            let previous = self.states
+                .get(&(id.clone(), i))
+                .cloned()
+                .unwrap_or(State {
+                    severity: Severity::Okay,
+                    hits: 0,
+                });
+
+            let (severity, limit) = threshold.severity(rate, previous.severity);
+            let mut state = State {
+                severity: severity,
+                hits: 0,
+            };
+
+            // A new problem needs enough consecutive hits before it is reported
+            if severity != Severity::Okay && severity != previous.severity {
+                let hits = previous.hits + 1;
+                if hits < threshold.hits {
+                    state = State {
+                        severity: previous.severity,
+                        hits: hits,
+                    };
+                    self.states.insert((id.clone(), i), state);
+                    continue;
+                }
+            }
+
+            self.states.insert((id.clone(), i), state);
+            let changed = severity != previous.severity;
+            let notify = match severity {
+                Severity::Okay => changed || (threshold.persist && threshold.persist_ok),
+                _ => changed || threshold.persist,
+            };
+
+            if notify {
+                let message = message(list, &threshold, report.name, rate, severity, limit);
+                result.push(notification(list, severity, message));
+            }
+        }
+
+        result
+    }
+}
+
+fn notification(list: &RecvValueList, severity: Severity, message: String) -> Notification {
+    let nonempty = |x: Option<&str>| x.filter(|x| !x.is_empty()).map(String::from);
+    Notification {
+        host: String::from(list.host),
+        plugin: String::from(list.plugin),
+        plugin_instance: nonempty(list.plugin_instance),
+        type_: String::from(list.type_),
+        type_instance: nonempty(list.type_instance),
+        time: CdTime::from(list.time),
+        severity: severity,
+        message: message,
+    }
+}
+
+/// Describes the value the same way as the `threshold` plugin
+fn message(
+    list: &RecvValueList,
+    threshold: &Threshold,
+    ds: &str,
+    value: f64,
+    severity: Severity,
+    limit: Option<Limit>,
+) -> String {
+    let mut msg = format!("Host {}, plugin {}", list.host, list.plugin);
+    if let Some(instance) = list.plugin_instance.filter(|x| !x.is_empty()) {
+        msg.push_str(&format!(" (instance {})", instance));
+    }
+    msg.push_str(&format!(" type {}", list.type_));
+    if let Some(instance) = list.type_instance.filter(|x| !x.is_empty()) {
+        msg.push_str(&format!(" (instance {})", instance));
+    }
+
+    let (min, max) = threshold.limits(severity);
+    let kind = if severity == Severity::Failure { "failure" } else { "warning" };
+    match limit {
+        None => msg.push_str(&format!(
+            ": All data sources are within range again. Current value of \"{}\" is {:.6}.",
+            ds, value
+        )),
+        Some(Limit::Within) => msg.push_str(&format!(
+            ": Data source \"{}\" is currently {:.6}. That is within the {} region of {:.6} and \
+             {:.6}.",
+            ds, value, kind, min, max
+        )),
+        Some(limit) => msg.push_str(&format!(
+            ": Data source \"{}\" is currently {:.6}. That is {} the {} threshold of {:.6}.",
+            ds,
+            value,
+            if limit == Limit::Above { "above" } else { "below" },
+            kind,
+            if limit == Limit::Above { max } else { min }
+        )),
+    }
+
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::ValueReport;
+    use std::time::{Duration, UNIX_EPOCH};
+    use testing;
+
+    fn list<'a>(secs: u64, values: Vec<ValueReport<'a>>) -> RecvValueList<'a> {
+        RecvValueList {
+            values: values.into(),
+            plugin_instance: Some("0"),
+            plugin: "cpu",
+            type_: "percent",
+            type_instance: Some("idle"),
+            host: "web-1",
+            time: UNIX_EPOCH + Duration::from_secs(secs),
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    fn gauge(value: f64) -> Vec<ValueReport<'static>> {
+        vec![ValueReport {
+            name: "value",
+            value: Value::Gauge(value),
+            min: 0.0,
+            max: 0.0,
+        }]
+    }
+
+    fn severities(engine: &mut ThresholdEngine, values: &[f64]) -> Vec<Vec<Severity>> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| {
+                engine
+                    .evaluate(&list(i as u64, gauge(x)))
+                    .iter()
+                    .map(|x| x.severity)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_state_changes() {
+        let mut engine = ThresholdEngine::new()
+            .threshold(Threshold::new("percent").warning_max(80.0).failure_max(95.0));
+        let notifs = engine.evaluate(&list(0, gauge(85.0)));
+        assert_eq!(notifs[0].severity, Severity::Warning);
+        assert_eq!(
+            notifs[0].message,
+            "Host web-1, plugin cpu (instance 0) type percent (instance idle): Data source \
+             \"value\" is currently 85.000000. That is above the warning threshold of 80.000000."
+        );
+        assert_eq!(notifs[0].type_instance, Some(String::from("idle")));
+
+        assert_eq!(
+            severities(&mut engine, &[90.0, 99.0, 50.0, 50.0]),
+            vec![
+                vec![],
+                vec![Severity::Failure],
+                vec![Severity::Okay],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hysteresis_hits_and_persist() {
+        let mut engine = ThresholdEngine::new()
+            .threshold(Threshold::new("percent").warning_max(80.0).hysteresis(5.0));
+        assert_eq!(
+            severities(&mut engine, &[81.0, 78.0, 74.0]),
+            vec![vec![Severity::Warning], vec![], vec![Severity::Okay]]
+        );
+
+        let mut engine = ThresholdEngine::new()
+            .threshold(Threshold::new("percent").failure_min(10.0).hits(2));
+        assert_eq!(
+            severities(&mut engine, &[5.0, 20.0, 5.0, 5.0]),
+            vec![vec![], vec![], vec![], vec![Severity::Failure]]
+        );
+
+        let mut engine = ThresholdEngine::new()
+            .threshold(Threshold::new("percent").failure_max(10.0).persist(true));
+        assert_eq!(
+            severities(&mut engine, &[20.0, 20.0, 5.0, 5.0]),
+            vec![
+                vec![Severity::Failure],
+                vec![Severity::Failure],
+                vec![Severity::Okay],
+                vec![],
+            ]
+        );
+
+        let mut engine = ThresholdEngine::new()
+            .threshold(Threshold::new("percent").failure_min(40.0).failure_max(60.0).invert(true));
+        assert_eq!(
+            severities(&mut engine, &[50.0, 70.0]),
+            vec![vec![Severity::Failure], vec![Severity::Okay]]
+        );
+    }
+
+    #[test]
+    fn test_matching_and_rates() {
+        let mut engine = ThresholdEngine::new()
+            .threshold(Threshold::new("percent").failure_max(10.0))
+            .threshold(Threshold::new("percent").plugin("cpu").type_instance("idle"))
+            .threshold(Threshold::new("percent").plugin("memory").failure_max(0.0));
+
+        // The most specific threshold has no limits
+        assert!(engine.evaluate(&list(0, gauge(50.0))).is_empty());
+
+        let counter = |value| {
+            vec![ValueReport {
+                name: "rx",
+                value: Value::Derive(value),
+                min: 0.0,
+                max: 0.0,
+            }]
+        };
+        let mut engine = ThresholdEngine::new()
+            .threshold(Threshold::new("if_octets").data_source("rx").failure_max(100.0));
+        let mut octets = list(0, counter(0));
+        octets.type_ = "if_octets";
+        assert!(engine.evaluate(&octets).is_empty());
+
+        octets.time += Duration::from_secs(10);
+        octets.values = counter(5000).into();
+        assert_eq!(engine.evaluate(&octets)[0].severity, Severity::Failure);
+
+        testing::reset();
+        octets.time += Duration::from_secs(10);
+        octets.values = counter(5000).into();
+        engine.check(&octets).unwrap();
+        let notifs = testing::notifications();
+        assert_eq!(notifs[0].severity, Severity::Okay);
+        assert_eq!(notifs[0].type_, Some(String::from("if_octets")));
+        assert_eq!(notifs[0].plugin_instance, Some(String::from("0")));
+    }
+}
@@ -0,0 +1,171 @@
+//! Read callbacks that fail tend to fail the same way every interval ("connection refused"), which
+//! can flood collectd's log. `ThrottledLogger` logs the first occurrence of a message, suppresses
+//! identical messages for a window of time, and then logs how many were suppressed.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use api::{collectd_log, LogLevel};
+
+#[derive(Debug, Clone, Copy)]
+struct Occurrence {
+    logged_at: Instant,
+    suppressed: u64,
+}
+
+/// Suppresses repeated identical log messages and periodically summarizes them
+#[derive(Debug, Clone)]
+pub struct ThrottledLogger {
+    window: Duration,
+    seen: HashMap<(LogLevel, String), Occurrence>,
+}
+
+impl ThrottledLogger {
+    /// Identical messages logged within `window` of each other are suppressed
+    pub fn new(window: Duration) -> Self {
+        ThrottledLogger {
+            window: window,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Logs the message to collectd unless an identical message was logged within the window
+    pub fn log(&mut self, lvl: LogLevel, msg: &str) {
+        if let Some(line) = self.filter(lvl, msg, Instant::now()) {
+            collectd_log(lvl, &line);
+        }
+    }
+
+    /// Logs a summary for every message whose window has lapsed with suppressed occurrences and
+    /// forgets about messages that haven't been seen for a window. Call periodically (eg: at the
+    /// end of every read) so that summaries aren't delayed until the next identical message.
+    pub fn flush(&mut self) {
+        for (lvl, line) in self.summaries(Instant::now()) {
+            collectd_log(lvl, &line);
+        }
+    }
+
+    /// Decides if a message should be logged at the given instant, and if so, the line to log
+    pub fn filter(&mut self, lvl: LogLevel, msg: &str, now: Instant) -> Option<String> {
+        let window = self.window;
+        let key = (lvl, String::from(msg));
+        if let Some(occurrence) = self.seen.get_mut(&key) {
+            if now.duration_since(occurrence.logged_at) < window {
+                occurrence.suppressed += 1;
+                return None;
+            }
+
+            let line = summarize(msg, occurrence.suppressed, window);
+            occurrence.logged_at = now;
+            occurrence.suppressed = 0;
+            return Some(line);
+        }
+
+        self.seen.insert(
+            key,
+            Occurrence {
+                logged_at: now,
+                suppressed: 0,
+            },
+        );
+        Some(String::from(msg))
+    }
+
+    /// Returns the summary lines that are due at the given instant
+    pub fn summaries(&mut self, now: Instant) -> Vec<(LogLevel, String)> {
+        let window = self.window;
+        let mut result = Vec::new();
+        for (&(lvl, ref msg), occurrence) in &mut self.seen {
+            if now.duration_since(occurrence.logged_at) >= window && occurrence.suppressed > 0 {
+                result.push((lvl, summarize(msg, occurrence.suppressed, window)));
+                occurrence.logged_at = now;
+                occurrence.suppressed = 0;
+            }
+        }
+
+        self.seen.retain(|_, occurrence| {
+            occurrence.suppressed > 0 || now.duration_since(occurrence.logged_at) < window
+        });
+        result
+    }
+}
+
+fn summarize(msg: &str, suppressed: u64, window: Duration) -> String {
+    if suppressed == 0 {
+        String::from(msg)
+    } else {
+        format!(
+            "{} (repeated {} times in the last {}s)",
+            msg,
+            suppressed,
+            window.as_secs()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_suppresses_within_window() {
+        let mut logger = ThrottledLogger::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let msg = "connection refused";
+        assert_eq!(
+            logger.filter(LogLevel::Error, msg, start),
+            Some(String::from(msg))
+        );
+        assert_eq!(
+            logger.filter(LogLevel::Error, msg, start + Duration::from_secs(10)),
+            None
+        );
+        assert_eq!(
+            logger.filter(LogLevel::Error, msg, start + Duration::from_secs(20)),
+            None
+        );
+        assert_eq!(
+            logger.filter(LogLevel::Error, msg, start + Duration::from_secs(61)),
+            Some(String::from(
+                "connection refused (repeated 2 times in the last 60s)"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_throttle_distinguishes_levels_and_messages() {
+        let mut logger = ThrottledLogger::new(Duration::from_secs(60));
+        let start = Instant::now();
+        assert!(logger.filter(LogLevel::Error, "a", start).is_some());
+        assert!(logger.filter(LogLevel::Warning, "a", start).is_some());
+        assert!(logger.filter(LogLevel::Error, "b", start).is_some());
+    }
+
+    #[test]
+    fn test_throttle_summaries() {
+        let mut logger = ThrottledLogger::new(Duration::from_secs(60));
+        let start = Instant::now();
+        logger.filter(LogLevel::Error, "a", start);
+        logger.filter(LogLevel::Error, "a", start + Duration::from_secs(1));
+        logger.filter(LogLevel::Info, "b", start);
+
+        assert!(logger.summaries(start + Duration::from_secs(30)).is_empty());
+
+        let summaries = logger.summaries(start + Duration::from_secs(60));
+        assert_eq!(
+            summaries,
+            vec![
+                (
+                    LogLevel::Error,
+                    String::from("a (repeated 1 times in the last 60s)")
+                ),
+            ]
+        );
+
+        // "b" was never repeated so it is forgotten and will be logged again
+        assert!(
+            logger
+                .filter(LogLevel::Info, "b", start + Duration::from_secs(61))
+                .is_some()
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use super::{flush_command, getval_command, parse_getval, parse_listval, parse_status,
+            putnotif_command, putval_command, trim_line, Identifier, UnixsockError};
+use api::CdTime;
+use network::{Notification, ValueList};
+use std::future::Future;
+use std::io;
+use std::os::unix::net;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncWrite, BufReader};
+use tokio::net::UnixStream;
+
+/// The async counterpart to `UnixsockClient`, for services running on tokio. It must be created
+/// within a tokio runtime. Each command is a future that must complete before the next command
+/// is sent.
+///
+/// ```ignore
+/// let mut client = AsyncUnixsockClient::connect("/var/run/collectd-unixsock")?;
+/// let values = client.getval(&"localhost/load/load".parse()?).await?;
+/// ```
+#[derive(Debug)]
+pub struct AsyncUnixsockClient {
+    stream: BufReader<UnixStream>,
+}
+
+impl AsyncUnixsockClient {
+    /// Connecting to a unix socket doesn't wait on the daemon, so this isn't a future
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self, UnixsockError> {
+        let stream = net::UnixStream::connect(path)?;
+        stream.set_nonblocking(true)?;
+        Ok(AsyncUnixsockClient {
+            stream: BufReader::new(UnixStream::from_std(stream)?),
+        })
+    }
+
+    /// Current values of the value list, by data source name. Rates are returned instead of
+    /// counter or derive values.
+    pub fn getval(&mut self, id: &Identifier) -> Request<Vec<(String, f64)>> {
+        self.request(getval_command(id), parse_getval)
+    }
+
+    /// Identifiers of all value lists in the daemon's cache, with when they were last updated
+    pub fn listval(&mut self) -> Request<Vec<(CdTime, Identifier)>> {
+        self.request(String::from("LISTVAL"), parse_listval)
+    }
+
+    /// Dispatches the value list within the daemon
+    pub fn putval(&mut self, list: &ValueList) -> Request<()> {
+        self.request(putval_command(list), |_| Ok(()))
+    }
+
+    /// Dispatches the notification within the daemon
+    pub fn putnotif(&mut self, notif: &Notification) -> Request<()> {
+        self.request(putnotif_command(notif), |_| Ok(()))
+    }
+
+    /// Asks the plugins (or all plugins, if empty) to flush data older than the timeout,
+    /// optionally limited to the given identifiers
+    pub fn flush(
+        &mut self,
+        timeout: Option<Duration>,
+        plugins: &[&str],
+        identifiers: &[Identifier],
+    ) -> Request<()> {
+        self.request(flush_command(timeout, plugins, identifiers), |_| Ok(()))
+    }
+
+    fn request<T>(
+        &mut self,
+        cmd: String,
+        parse: fn(Vec<String>) -> Result<T, UnixsockError>,
+    ) -> Request<T> {
+        let mut cmd = cmd.into_bytes();
+        cmd.push(b'\n');
+        Request {
+            stream: &mut self.stream,
+            cmd: cmd,
+            written: 0,
+            flushed: false,
+            line: Vec::new(),
+            count: None,
+            lines: Vec::new(),
+            parse: parse,
+        }
+    }
+}
+
+/// Future that sends a command and parses the daemon's response
+#[derive(Debug)]
+pub struct Request<'a, T> {
+    stream: &'a mut BufReader<UnixStream>,
+    cmd: Vec<u8>,
+    written: usize,
+    flushed: bool,
+
+    /// The line being read, which may arrive over several reads
+    line: Vec<u8>,
+
+    /// Lines that follow the status line, once the status line is read
+    count: Option<usize>,
+    lines: Vec<String>,
+    parse: fn(Vec<String>) -> Result<T, UnixsockError>,
+}
+
+impl<'a, T> Request<'a, T> {
+    fn poll_write(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while self.written < self.cmd.len() {
+            let stream = Pin::new(&mut *self.stream);
+            match stream.poll_write(cx, &self.cmd[self.written..]) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(0)) => {
+                    let err = io::Error::new(io::ErrorKind::WriteZero, "unixsock closed");
+                    return Poll::Ready(Err(err));
+                }
+                Poll::Ready(Ok(n)) => self.written += n,
+            }
+        }
+
+        if !self.flushed {
+            match Pin::new(&mut *self.stream).poll_flush(cx) {
+                Poll::Ready(Ok(())) => self.flushed = true,
+                x => return x,
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Reads the next line of the response
+    fn poll_line(&mut self, cx: &mut Context) -> Poll<Result<String, UnixsockError>> {
+        loop {
+            let (done, used) = {
+                let buf = match Pin::new(&mut *self.stream).poll_fill_buf(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(UnixsockError::Io(e))),
+                    Poll::Ready(Ok(buf)) => buf,
+                };
+
+                if buf.is_empty() {
+                    let err = io::Error::new(io::ErrorKind::UnexpectedEof, "unixsock closed");
+                    return Poll::Ready(Err(UnixsockError::Io(err)));
+                }
+
+                match buf.iter().position(|&x| x == b'\n') {
+                    Some(idx) => {
+                        self.line.extend_from_slice(&buf[..idx + 1]);
+                        (true, idx + 1)
+                    }
+                    None => {
+                        self.line.extend_from_slice(buf);
+                        (false, buf.len())
+                    }
+                }
+            };
+
+            Pin::new(&mut *self.stream).consume(used);
+            if done {
+                let mut line = String::from_utf8_lossy(&self.line).into_owned();
+                self.line.clear();
+                trim_line(&mut line);
+                return Poll::Ready(Ok(line));
+            }
+        }
+    }
+}
+
+impl<'a, T> Future for Request<'a, T> {
+    type Output = Result<T, UnixsockError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let me = self.get_mut();
+        match me.poll_write(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(UnixsockError::Io(e))),
+            Poll::Ready(Ok(())) => {}
+        }
+
+        loop {
+            if me.count == Some(me.lines.len()) {
+                let lines = ::std::mem::replace(&mut me.lines, Vec::new());
+                return Poll::Ready((me.parse)(lines));
+            }
+
+            let line = match me.poll_line(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(line)) => line,
+            };
+
+            if me.count.is_none() {
+                match parse_status(&line) {
+                    Ok(count) => me.count = Some(count),
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            } else {
+                me.lines.push(line);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::listen;
+    use super::*;
+    use std::fs;
+    use tokio::runtime;
+
+    #[test]
+    fn test_async_requests() {
+        let rt = runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        let _guard = rt.enter();
+
+        let (path, handle) = listen(
+            "async",
+            &[
+                "2 Values found\nrx=1.500000e+00\ntx=2.000000e+00\n",
+                "-1 No such value\n",
+                "0 Done: 1 successful, 0 errors\n",
+            ],
+        );
+
+        let mut client = AsyncUnixsockClient::connect(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let id: Identifier = "localhost/if-eth0/if_octets".parse().unwrap();
+        let values = rt.block_on(client.getval(&id)).unwrap();
+        assert_eq!(
+            values,
+            vec![(String::from("rx"), 1.5), (String::from("tx"), 2.0)]
+        );
+
+        match rt.block_on(client.getval(&id)) {
+            Err(UnixsockError::Command(ref msg)) => assert_eq!(msg, "No such value"),
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        rt.block_on(client.flush(None, &[], &[])).unwrap();
+        assert_eq!(
+            handle.join().unwrap(),
+            vec![
+                "GETVAL localhost/if-eth0/if_octets",
+                "GETVAL localhost/if-eth0/if_octets",
+                "FLUSH",
+            ]
+        );
+    }
+}
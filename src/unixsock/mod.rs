@@ -0,0 +1,409 @@
+//! A client for the plain-text protocol of collectd's `unixsock` plugin, for reading the daemon's
+//! current values and injecting values or notifications over its socket. Each command is a single
+//! line, answered by a status line (negative on error) that says how many lines follow.
+//!
+//! Like the `network` module this doesn't call into collectd, so it can be used from standalone
+//! tools as well as from plugins that need to query another daemon.
+
+use api::CdTime;
+use identifier::ParseIdentifierError;
+use network::{Notification, ValueList};
+use plaintext::{putnotif_command, putval_command, quote};
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+pub use identifier::Identifier;
+
+#[cfg(feature = "async")]
+mod async_client;
+
+#[cfg(feature = "async")]
+pub use self::async_client::{AsyncUnixsockClient, Request};
+
+#[derive(Debug)]
+pub enum UnixsockError {
+    /// Reading from or writing to the socket failed
+    Io(io::Error),
+
+    /// The daemon rejected the command, with the message it responded with
+    Command(String),
+
+    /// The daemon's response (or an identifier) couldn't be parsed
+    Parse(String),
+}
+
+impl fmt::Display for UnixsockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnixsockError::Io(_) => write!(f, "unable to communicate with unixsock"),
+            UnixsockError::Command(ref msg) => write!(f, "command failed: {}", msg),
+            UnixsockError::Parse(ref line) => write!(f, "unable to parse: {}", line),
+        }
+    }
+}
+
+impl From<ParseIdentifierError> for UnixsockError {
+    fn from(err: ParseIdentifierError) -> UnixsockError {
+        UnixsockError::Parse(err.0)
+    }
+}
+
+impl error::Error for UnixsockError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            UnixsockError::Io(ref e) => Some(e),
+            UnixsockError::Command(_) | UnixsockError::Parse(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for UnixsockError {
+    fn from(err: io::Error) -> UnixsockError {
+        UnixsockError::Io(err)
+    }
+}
+
+/// Parses epoch seconds, which the daemon formats with a fractional part
+fn parse_time(s: &str) -> Result<CdTime, UnixsockError> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|x| *x >= 0.0)
+        .map(|x| CdTime((x * 1e9) as u64))
+        .ok_or_else(|| UnixsockError::Parse(String::from(s)))
+}
+
+/// Parses a gauge as formatted by the daemon, which writes `nan` for unknown values
+fn parse_gauge(s: &str) -> Result<f64, UnixsockError> {
+    if s.eq_ignore_ascii_case("nan") {
+        Ok(f64::NAN)
+    } else {
+        s.parse::<f64>().map_err(|_| UnixsockError::Parse(String::from(s)))
+    }
+}
+
+fn getval_command(id: &Identifier) -> String {
+    format!("GETVAL {}", quote(&id.to_string()))
+}
+
+fn flush_command(
+    timeout: Option<Duration>,
+    plugins: &[&str],
+    identifiers: &[Identifier],
+) -> String {
+    let mut cmd = String::from("FLUSH");
+    if let Some(timeout) = timeout {
+        cmd.push_str(&format!(" timeout={}", timeout.as_secs()));
+    }
+
+    for plugin in plugins {
+        cmd.push_str(&format!(" plugin={}", quote(plugin)));
+    }
+
+    for id in identifiers {
+        cmd.push_str(&format!(" identifier={}", quote(&id.to_string())));
+    }
+
+    cmd
+}
+
+/// Parses the number of lines that follow a status line, or the error that it reports
+fn parse_status(status: &str) -> Result<usize, UnixsockError> {
+    let mut parts = status.splitn(2, ' ');
+    let count = parts
+        .next()
+        .and_then(|x| x.parse::<i64>().ok())
+        .ok_or_else(|| UnixsockError::Parse(String::from(status)))?;
+
+    if count < 0 {
+        let msg = parts.next().unwrap_or("");
+        Err(UnixsockError::Command(String::from(msg)))
+    } else {
+        Ok(count as usize)
+    }
+}
+
+fn parse_getval(lines: Vec<String>) -> Result<Vec<(String, f64)>, UnixsockError> {
+    lines
+        .into_iter()
+        .map(|line| {
+            let value = {
+                let mut parts = line.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) => Some((String::from(name), parse_gauge(value)?)),
+                    _ => None,
+                }
+            };
+            value.ok_or_else(|| UnixsockError::Parse(line))
+        })
+        .collect()
+}
+
+fn parse_listval(lines: Vec<String>) -> Result<Vec<(CdTime, Identifier)>, UnixsockError> {
+    lines
+        .into_iter()
+        .map(|line| {
+            let value = {
+                let mut parts = line.splitn(2, ' ');
+                match (parts.next(), parts.next()) {
+                    (Some(time), Some(id)) => Some((parse_time(time)?, id.parse()?)),
+                    _ => None,
+                }
+            };
+            value.ok_or_else(|| UnixsockError::Parse(line))
+        })
+        .collect()
+}
+
+/// Strips the line ending from a line of the response
+fn trim_line(line: &mut String) {
+    let len = line.trim_end_matches(|c| c == '\n' || c == '\r').len();
+    line.truncate(len);
+}
+
+/// Connection to the socket of collectd's `unixsock` plugin
+///
+/// ```no_run
+/// use collectd_plugin::unixsock::UnixsockClient;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut client = UnixsockClient::connect("/var/run/collectd-unixsock")?;
+/// for (_, id) in client.listval()? {
+///     println!("{}: {:?}", id, client.getval(&id)?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct UnixsockClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl UnixsockClient {
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self, UnixsockError> {
+        let stream = UnixStream::connect(path)?;
+        Ok(UnixsockClient {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    /// Gives up on reads and writes that take longer than the timeout
+    pub fn timeout(&self, timeout: Option<Duration>) -> Result<(), UnixsockError> {
+        self.writer.set_read_timeout(timeout)?;
+        self.writer.set_write_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Current values of the value list, by data source name. Rates are returned instead of
+    /// counter or derive values.
+    pub fn getval(&mut self, id: &Identifier) -> Result<Vec<(String, f64)>, UnixsockError> {
+        let lines = self.request(&getval_command(id))?;
+        parse_getval(lines)
+    }
+
+    /// Identifiers of all value lists in the daemon's cache, with when they were last updated
+    pub fn listval(&mut self) -> Result<Vec<(CdTime, Identifier)>, UnixsockError> {
+        let lines = self.request("LISTVAL")?;
+        parse_listval(lines)
+    }
+
+    /// Dispatches the value list within the daemon. A time of zero is sent as `N` so that the
+    /// daemon uses the current time, and an interval of zero uses the daemon's interval.
+    pub fn putval(&mut self, list: &ValueList) -> Result<(), UnixsockError> {
+        self.request(&putval_command(list)).map(|_| ())
+    }
+
+    /// Dispatches the notification within the daemon
+    pub fn putnotif(&mut self, notif: &Notification) -> Result<(), UnixsockError> {
+        self.request(&putnotif_command(notif)).map(|_| ())
+    }
+
+    /// Asks the plugins (or all plugins, if empty) to flush data older than the timeout,
+    /// optionally limited to the given identifiers
+    pub fn flush(
+        &mut self,
+        timeout: Option<Duration>,
+        plugins: &[&str],
+        identifiers: &[Identifier],
+    ) -> Result<(), UnixsockError> {
+        self.request(&flush_command(timeout, plugins, identifiers)).map(|_| ())
+    }
+
+    /// Sends the command and returns the lines that follow the status line
+    fn request(&mut self, cmd: &str) -> Result<Vec<String>, UnixsockError> {
+        self.writer.write_all(cmd.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+
+        let count = parse_status(&self.read_line()?)?;
+        (0..count).map(|_| self.read_line()).collect()
+    }
+
+    fn read_line(&mut self) -> Result<String, UnixsockError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            let err = io::Error::new(io::ErrorKind::UnexpectedEof, "unixsock closed");
+            return Err(UnixsockError::Io(err));
+        }
+
+        trim_line(&mut line);
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::Value;
+    use constants::Severity;
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::process;
+    use std::thread;
+
+    /// Serves the canned responses in order to the first connection on the returned path. The
+    /// commands that were received are returned once all responses are sent.
+    pub fn listen(name: &str, responses: &[&str]) -> (PathBuf, thread::JoinHandle<Vec<String>>) {
+        let path = ::std::env::temp_dir().join(format!("unixsock-{}-{}", name, process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let responses: Vec<String> = responses.iter().map(|x| String::from(*x)).collect();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut commands = Vec::new();
+            for response in responses {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                commands.push(String::from(line.trim_end()));
+                writer.write_all(response.as_bytes()).unwrap();
+            }
+            commands
+        });
+
+        (path, handle)
+    }
+
+    fn serve(name: &str, responses: &[&str]) -> (UnixsockClient, thread::JoinHandle<Vec<String>>) {
+        let (path, handle) = listen(name, responses);
+        let client = UnixsockClient::connect(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        (client, handle)
+    }
+
+    fn id(s: &str) -> Identifier {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_identifier() {
+        let ident = id("localhost/cpu-0/percent-idle-time");
+        assert_eq!(ident.plugin_instance, Some(String::from("0")));
+        assert_eq!(ident.type_, "percent");
+        assert_eq!(ident.type_instance, Some(String::from("idle-time")));
+        assert_eq!(ident.to_string(), "localhost/cpu-0/percent-idle-time");
+
+        assert_eq!(id("localhost/load/load").plugin_instance, None);
+        assert!("localhost/load".parse::<Identifier>().is_err());
+        assert!("localhost//load".parse::<Identifier>().is_err());
+    }
+
+    #[test]
+    fn test_getval_listval() {
+        let (mut client, handle) = serve(
+            "getval",
+            &[
+                "2 Values found\nrx=1.500000e+00\ntx=nan\n",
+                "2 Values found\n1500000000.500 localhost/load/load\n\
+                 1500000001.000 localhost/if-eth0/if_octets\n",
+                "-1 No such value\n",
+            ],
+        );
+
+        let values = client.getval(&id("localhost/if-eth0/if_octets")).unwrap();
+        assert_eq!(values[0], (String::from("rx"), 1.5));
+        assert!(values[1].1.is_nan());
+
+        let lists = client.listval().unwrap();
+        assert_eq!(
+            lists,
+            vec![
+                (CdTime(1_500_000_000_500_000_000), id("localhost/load/load")),
+                (CdTime(1_500_000_001_000_000_000), id("localhost/if-eth0/if_octets")),
+            ]
+        );
+
+        match client.getval(&id("localhost/load/missing")) {
+            Err(UnixsockError::Command(ref msg)) => assert_eq!(msg, "No such value"),
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        assert_eq!(
+            handle.join().unwrap(),
+            vec![
+                "GETVAL localhost/if-eth0/if_octets",
+                "LISTVAL",
+                "GETVAL localhost/load/missing",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_putval_putnotif_flush() {
+        let (mut client, handle) = serve(
+            "putval",
+            &[
+                "0 Success: 1 value has been dispatched.\n",
+                "0 Success\n",
+                "0 Done: 1 successful, 0 errors\n",
+            ],
+        );
+
+        let list = ValueList {
+            host: String::from("localhost"),
+            plugin: String::from("disk"),
+            plugin_instance: Some(String::from("sda")),
+            type_: String::from("disk_octets"),
+            type_instance: None,
+            time: CdTime(1_500_000_000_250_000_000),
+            interval: CdTime::from(Duration::from_secs(10)),
+            values: vec![Value::Derive(10), Value::Gauge(f64::NAN)],
+        };
+        client.putval(&list).unwrap();
+
+        let notif = Notification {
+            host: String::from("localhost"),
+            plugin: String::from("disk"),
+            plugin_instance: None,
+            type_: String::from("disk_octets"),
+            type_instance: None,
+            time: CdTime(0),
+            severity: Severity::Warning,
+            message: String::from("disk is slow"),
+        };
+        client.putnotif(&notif).unwrap();
+
+        client
+            .flush(Some(Duration::from_secs(5)), &["rrdtool"], &[Identifier::of(&list)])
+            .unwrap();
+
+        assert_eq!(
+            handle.join().unwrap(),
+            vec![
+                "PUTVAL localhost/disk-sda/disk_octets interval=10.000000000 \
+                 1500000000.250000000:10:U",
+                "PUTNOTIF severity=warning time=N host=localhost plugin=disk type=disk_octets \
+                 message=\"disk is slow\"",
+                "FLUSH timeout=5 plugin=rrdtool identifier=localhost/disk-sda/disk_octets",
+            ]
+        );
+    }
+}
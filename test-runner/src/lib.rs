@@ -0,0 +1,474 @@
+//! Runs a compiled plugin inside of a real collectd daemon (either a local binary or one inside a
+//! docker container) for a number of intervals and captures the values that collectd's csv plugin
+//! wrote as well as collectd's log. This catches ABI and registration bugs that the `stub`
+//! feature can't, as the plugin is loaded exactly like it would be in production.
+//!
+//! ```ignore
+//! extern crate collectd_test_runner;
+//!
+//! use collectd_test_runner::Runner;
+//!
+//! #[test]
+//! #[ignore]
+//! fn test_in_collectd() {
+//!     let output = Runner::new("loadrust", Runner::cdylib("loadrust"))
+//!         .config("ReportRelative true")
+//!         .intervals(2)
+//!         .run()
+//!         .unwrap();
+//!
+//!     assert!(output.errors().is_empty(), "{}", output.log);
+//!     assert!(!output.csv("loadrust", "load").is_empty());
+//! }
+//! ```
+
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Directories that distributions install collectd's plugins to
+const PLUGIN_DIRS: &[&str] = &[
+    "/usr/lib/collectd",
+    "/usr/lib64/collectd",
+    "/usr/lib/x86_64-linux-gnu/collectd",
+    "/usr/local/lib/collectd",
+    "/opt/collectd/lib/collectd",
+];
+
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Where the collectd daemon comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Daemon {
+    /// A collectd binary on this machine
+    Local(PathBuf),
+
+    /// A docker image with collectd installed and on the path. The run's directory is mounted at
+    /// the same location within the container.
+    Docker(String),
+}
+
+#[derive(Debug)]
+pub enum RunError {
+    Io(io::Error),
+
+    /// Collectd exited before it ran for the requested number of intervals. Contains collectd's
+    /// log (and stderr).
+    Exited(ExitStatus, String),
+
+    /// None of the standard plugin directories exist, so `plugin_dir` must be given
+    NoPluginDir,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RunError::Io(ref e) => write!(f, "unable to run collectd: {}", e),
+            RunError::Exited(status, ref log) => {
+                write!(f, "collectd exited early ({}):\n{}", status, log)
+            }
+            RunError::NoPluginDir => write!(f, "unable to locate collectd's plugin directory"),
+        }
+    }
+}
+
+impl error::Error for RunError {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            RunError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RunError {
+    fn from(e: io::Error) -> Self {
+        RunError::Io(e)
+    }
+}
+
+/// The values from one of the csv plugin's files
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvFile {
+    /// The plugin and plugin instance (eg: `cpu-0`)
+    pub plugin: String,
+
+    /// The type and type instance (eg: `percent-idle`)
+    pub type_: String,
+
+    /// Column names after the epoch (eg: `value` or `shortterm,midterm,longterm`)
+    pub columns: Vec<String>,
+
+    /// The epoch and values of every row
+    pub rows: Vec<(f64, Vec<f64>)>,
+}
+
+/// What collectd produced during a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub csv: Vec<CsvFile>,
+
+    /// Collectd's log, written with severities (eg: `[error] ...`)
+    pub log: String,
+}
+
+impl Output {
+    /// Files written for the given plugin (including any instance) and type (including any
+    /// instance)
+    pub fn csv(&self, plugin: &str, type_: &str) -> Vec<&CsvFile> {
+        self.csv
+            .iter()
+            .filter(|x| starts_with_name(&x.plugin, plugin) && starts_with_name(&x.type_, type_))
+            .collect()
+    }
+
+    /// Log lines logged at the error level
+    pub fn errors(&self) -> Vec<&str> {
+        self.log.lines().filter(|x| x.contains("[error]")).collect()
+    }
+}
+
+fn starts_with_name(actual: &str, name: &str) -> bool {
+    actual == name || actual.starts_with(&format!("{}-", name))
+}
+
+/// Writes a collectd.conf that loads a plugin and collectd's csv plugin, and then runs collectd
+#[derive(Debug, Clone)]
+pub struct Runner {
+    name: String,
+    library: PathBuf,
+    config: String,
+    daemon: Daemon,
+    plugin_dir: Option<PathBuf>,
+    types_db: Option<PathBuf>,
+    interval: Duration,
+    intervals: u32,
+}
+
+impl Runner {
+    /// Runs the plugin named `name` (the name given to `LoadPlugin`) from a compiled library.
+    /// Defaults to a local `collectd` on the path running for three one second intervals.
+    pub fn new<P: Into<PathBuf>>(name: &str, library: P) -> Self {
+        Runner {
+            name: String::from(name),
+            library: library.into(),
+            config: String::new(),
+            daemon: Daemon::Local(PathBuf::from("collectd")),
+            plugin_dir: None,
+            types_db: None,
+            interval: Duration::from_secs(1),
+            intervals: 3,
+        }
+    }
+
+    /// The location of a cdylib built in the same target directory as the currently running test
+    /// (eg: `target/debug/libloadrust.so` when ran from `target/debug/deps/`)
+    pub fn cdylib(name: &str) -> PathBuf {
+        let exe = env::current_exe().expect("to locate the test executable");
+        let mut dir = exe.parent().expect("test executable to be in a directory");
+        if dir.ends_with("deps") {
+            dir = dir.parent().expect("deps to be in a directory");
+        }
+
+        dir.join(format!(
+            "{}{}{}",
+            env::consts::DLL_PREFIX,
+            name,
+            env::consts::DLL_SUFFIX
+        ))
+    }
+
+    /// Contents of the plugin's `<Plugin>` block
+    pub fn config(mut self, config: &str) -> Self {
+        self.config = String::from(config);
+        self
+    }
+
+    pub fn daemon(mut self, daemon: Daemon) -> Self {
+        self.daemon = daemon;
+        self
+    }
+
+    /// The directory (in the container, if using docker) that contains collectd's csv and
+    /// logfile plugins
+    pub fn plugin_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.plugin_dir = Some(dir.into());
+        self
+    }
+
+    /// Uses the given types.db instead of collectd's default
+    pub fn types_db<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.types_db = Some(path.into());
+        self
+    }
+
+    /// Collectd's global interval. Must be at least a second.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// The number of intervals to run collectd for
+    pub fn intervals(mut self, intervals: u32) -> Self {
+        self.intervals = intervals;
+        self
+    }
+
+    /// Runs collectd in a fresh directory under the system's temp directory, which is removed
+    /// after the output is captured
+    pub fn run(&self) -> Result<Output, RunError> {
+        let dir = env::temp_dir().join(format!(
+            "collectd-test-runner-{}-{}",
+            std::process::id(),
+            RUNS.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let res = self.run_in(&dir);
+        let _ = fs::remove_dir_all(&dir);
+        res
+    }
+
+    fn run_in(&self, dir: &Path) -> Result<Output, RunError> {
+        // Collectd only loads plugins from a single directory, so the plugin and the plugins it
+        // needs are gathered into one. Links may dangle on the host when using docker.
+        let plugins = dir.join("plugins");
+        fs::create_dir_all(&plugins)?;
+        fs::copy(&self.library, plugins.join(format!("{}.so", self.name)))?;
+
+        let system = self.system_plugin_dir()?;
+        for plugin in &["csv", "logfile"] {
+            let file = format!("{}.so", plugin);
+            symlink(&system.join(&file), &plugins.join(&file))?;
+        }
+
+        let conf = dir.join("collectd.conf");
+        File::create(&conf)?.write_all(self.conf(dir).as_bytes())?;
+
+        let container = format!("collectd-test-runner-{}", dir_name(dir));
+        let mut cmd = match self.daemon {
+            Daemon::Local(ref bin) => {
+                let mut cmd = Command::new(bin);
+                cmd.arg("-f").arg("-C").arg(&conf);
+                cmd
+            }
+            Daemon::Docker(ref image) => {
+                let mut cmd = Command::new("docker");
+                cmd.args(&["run", "--rm", "--name", &container, "-v"])
+                    .arg(format!("{}:{}", dir.display(), dir.display()))
+                    .arg(image)
+                    .args(&["collectd", "-f", "-C"])
+                    .arg(&conf);
+                cmd
+            }
+        };
+
+        let stderr = File::create(dir.join("stderr.log"))?;
+        let mut child = cmd.stdout(Stdio::null()).stderr(stderr).spawn()?;
+
+        // Give collectd a moment to start on top of the requested intervals so that the last
+        // interval is written
+        thread::sleep(self.interval * self.intervals + Duration::from_millis(500));
+
+        if let Some(status) = child.try_wait()? {
+            return Err(RunError::Exited(status, self.log(dir)));
+        }
+
+        if let Daemon::Docker(_) = self.daemon {
+            let _ = Command::new("docker")
+                .args(&["stop", "-t", "1", &container])
+                .stdout(Stdio::null())
+                .status();
+        }
+
+        let _ = child.kill();
+        let _ = child.wait();
+
+        Ok(Output {
+            csv: read_csv(&dir.join("csv"))?,
+            log: self.log(dir),
+        })
+    }
+
+    fn system_plugin_dir(&self) -> Result<PathBuf, RunError> {
+        if let Some(ref dir) = self.plugin_dir {
+            return Ok(dir.clone());
+        }
+
+        match self.daemon {
+            // Both Debian and Alpine based images use the first directory
+            Daemon::Docker(_) => Ok(PathBuf::from(PLUGIN_DIRS[0])),
+            Daemon::Local(_) => PLUGIN_DIRS
+                .iter()
+                .map(PathBuf::from)
+                .find(|x| x.join("csv.so").is_file())
+                .ok_or(RunError::NoPluginDir),
+        }
+    }
+
+    fn conf(&self, dir: &Path) -> String {
+        let mut conf = String::new();
+        conf.push_str("Hostname \"localhost\"\n");
+        conf.push_str("FQDNLookup false\n");
+        conf.push_str(&format!("BaseDir \"{}\"\n", dir.display()));
+        conf.push_str(&format!("PIDFile \"{}\"\n", dir.join("collectd.pid").display()));
+        conf.push_str(&format!("PluginDir \"{}\"\n", dir.join("plugins").display()));
+        conf.push_str(&format!("Interval {}\n", self.interval.as_secs().max(1)));
+        if let Some(ref types) = self.types_db {
+            conf.push_str(&format!("TypesDB \"{}\"\n", types.display()));
+        }
+
+        // The logfile plugin is loaded first so that it captures the other plugins loading
+        conf.push_str("LoadPlugin logfile\n");
+        conf.push_str("<Plugin logfile>\n");
+        conf.push_str("  LogLevel debug\n");
+        conf.push_str(&format!("  File \"{}\"\n", dir.join("collectd.log").display()));
+        conf.push_str("  PrintSeverity true\n");
+        conf.push_str("</Plugin>\n");
+        conf.push_str("LoadPlugin csv\n");
+        conf.push_str("<Plugin csv>\n");
+        conf.push_str(&format!("  DataDir \"{}\"\n", dir.join("csv").display()));
+        conf.push_str("  StoreRates false\n");
+        conf.push_str("</Plugin>\n");
+        conf.push_str(&format!("LoadPlugin {}\n", self.name));
+        conf.push_str(&format!("<Plugin {}>\n{}\n</Plugin>\n", self.name, self.config));
+        conf
+    }
+
+    fn log(&self, dir: &Path) -> String {
+        let mut log = read_to_string(&dir.join("collectd.log"));
+        log.push_str(&read_to_string(&dir.join("stderr.log")));
+        log
+    }
+}
+
+fn dir_name(dir: &Path) -> String {
+    dir.file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn read_to_string(path: &Path) -> String {
+    let mut contents = String::new();
+    if let Ok(mut f) = File::open(path) {
+        let _ = f.read_to_string(&mut contents);
+    }
+    contents
+}
+
+#[cfg(unix)]
+fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(src, dst)
+}
+
+#[cfg(not(unix))]
+fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::copy(src, dst).map(|_| ())
+}
+
+/// Reads every file the csv plugin wrote, which are laid out as
+/// `<host>/<plugin>-<instance>/<type>-<instance>-<date>`
+fn read_csv(dir: &Path) -> Result<Vec<CsvFile>, RunError> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+
+    for host in fs::read_dir(dir)? {
+        for plugin in fs::read_dir(host?.path())? {
+            let plugin = plugin?.path();
+            for file in fs::read_dir(&plugin)? {
+                let file = file?.path();
+                files.push(parse_csv(&dir_name(&plugin), &dir_name(&file), &read_to_string(&file)));
+            }
+        }
+    }
+
+    files.sort_by(|a, b| (&a.plugin, &a.type_).cmp(&(&b.plugin, &b.type_)));
+    Ok(files)
+}
+
+fn parse_csv(plugin: &str, file: &str, contents: &str) -> CsvFile {
+    let mut lines = contents.lines();
+    let columns = lines
+        .next()
+        .map(|x| x.split(',').skip(1).map(String::from).collect())
+        .unwrap_or_default();
+
+    let rows = lines
+        .filter_map(|line| {
+            let mut fields = line.split(',').map(|x| x.parse::<f64>().unwrap_or(::std::f64::NAN));
+            fields.next().map(|epoch| (epoch, fields.collect()))
+        })
+        .collect();
+
+    CsvFile {
+        plugin: String::from(plugin),
+        type_: strip_date(file),
+        columns: columns,
+        rows: rows,
+    }
+}
+
+/// Removes the `-YYYY-MM-DD` suffix the csv plugin appends to file names
+fn strip_date(file: &str) -> String {
+    let bytes = file.as_bytes();
+    let date = bytes.len() >= 11 && bytes[bytes.len() - 11] == b'-'
+        && bytes[bytes.len() - 10..]
+            .iter()
+            .enumerate()
+            .all(|(i, &x)| if i == 4 || i == 7 { x == b'-' } else { x.is_ascii_digit() });
+
+    if date {
+        String::from(&file[..file.len() - 11])
+    } else {
+        String::from(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let contents = "epoch,shortterm,midterm,longterm\n1513000000.000,0.1,0.2,nan\n";
+        let file = parse_csv("loadrust", "load-2017-12-11", contents);
+        assert_eq!(file.plugin, "loadrust");
+        assert_eq!(file.type_, "load");
+        assert_eq!(file.columns, vec!["shortterm", "midterm", "longterm"]);
+        assert_eq!(file.rows.len(), 1);
+        assert_eq!(file.rows[0].0, 1513000000.0);
+        assert_eq!(&file.rows[0].1[..2], &[0.1, 0.2]);
+        assert!(file.rows[0].1[2].is_nan());
+    }
+
+    #[test]
+    fn test_output_helpers() {
+        let output = Output {
+            csv: vec![parse_csv("cpu-0", "percent-idle-2017-12-11", "epoch,value\n")],
+            log: String::from("[info] plugin_load: loaded\n[error] oh no\n"),
+        };
+
+        assert_eq!(output.csv("cpu", "percent").len(), 1);
+        assert_eq!(output.csv("cpu", "percent-idle").len(), 1);
+        assert!(output.csv("cp", "percent").is_empty());
+        assert_eq!(output.errors(), vec!["[error] oh no"]);
+    }
+
+    #[test]
+    fn test_conf_loads_plugin() {
+        let runner = Runner::new("myplugin", "libmyplugin.so").config("Key \"value\"");
+        let conf = runner.conf(Path::new("/tmp/run"));
+        assert!(conf.contains("PluginDir \"/tmp/run/plugins\"\n"));
+        assert!(conf.contains("LoadPlugin myplugin\n<Plugin myplugin>\nKey \"value\"\n</Plugin>"));
+    }
+}
@@ -1,15 +1,24 @@
 #include <dlfcn.h>
 #include <stdlib.h>
 
-#ifdef COLLECTD_55
-    #include <collectd/liboconfig/oconfig.h>
-    #include <collectd/core/daemon/plugin.h>
-#endif
+#ifdef COLLECTD_SRC
+    // Headers are taken from a collectd source tree given by COLLECTD_PATH
+    #ifdef COLLECTD_54
+        #include <plugin.h>
+    #else
+        #include <daemon/plugin.h>
+    #endif
+#else
+    #ifdef COLLECTD_55
+        #include <collectd/liboconfig/oconfig.h>
+        #include <collectd/core/daemon/plugin.h>
+    #endif
 
-#ifdef COLLECTD_54
-    #include <collectd/core/plugin.h>
-#endif
+    #ifdef COLLECTD_54
+        #include <collectd/core/plugin.h>
+    #endif
 
-#ifdef COLLECTD_57
-    #include <collectd/core/daemon/plugin.h>
+    #ifdef COLLECTD_57
+        #include <collectd/core/daemon/plugin.h>
+    #endif
 #endif